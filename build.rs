@@ -0,0 +1,20 @@
+//! Сохраняет сведения о сборке в переменных окружения времени компиляции:
+//! *  `BANSHEE_GIT_COMMIT` - короткий хэш текущего коммита git или `unknown`
+//! *  `BANSHEE_BUILD_TIME` - момент сборки в формате RFC 3339 (UTC)
+
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=BANSHEE_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=BANSHEE_BUILD_TIME={}", chrono::Utc::now().to_rfc3339());
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
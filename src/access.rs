@@ -0,0 +1,64 @@
+//! Контроль допустимости входящих подключений по IP-адресу удаленной стороны.
+//!
+//! Применяется ко всем принимаемым приложением подключениям до чтения из них каких-либо данных.
+//! Настройки `[access]`:
+//! *  `deny` - диапазоны адресов, подключения из которых отклоняются всегда
+//! *  `allow` - если список не пуст, принимаются подключения только из перечисленных диапазонов
+
+use std::net::IpAddr;
+
+use crate::config::{Cidr, SharedConfig};
+
+/// Списки разрешенных и запрещенных диапазонов адресов
+#[derive(Clone)]
+pub struct AccessList {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>
+}
+
+impl AccessList {
+
+    pub fn new(cfg: &SharedConfig) -> AccessList {
+        AccessList {
+            allow: cfg.access_allow(),
+            deny: cfg.access_deny()
+        }
+    }
+
+    /// Проверяет, допустимо ли подключение с адреса `ip`. Запрет имеет приоритет над разрешением
+    pub fn permits(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    fn list(sets: &[&str]) -> AccessList {
+        AccessList::new(&Config::from_sources(&[], sets).unwrap())
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn permits_all_by_default() {
+        assert!(list(&[]).permits(&ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn the_deny_overrides_the_allow() {
+        let l = list(&["access.allow=10.0.0.0/8, 127.0.0.1", "access.deny=10.9.0.0/16"]);
+        assert!(l.permits(&ip("10.1.2.3")));
+        assert!(l.permits(&ip("127.0.0.1")));
+        assert!(!l.permits(&ip("10.9.0.1")));
+        assert!(!l.permits(&ip("192.168.0.1")));
+    }
+}
@@ -8,48 +8,698 @@
 //! *  контролировать суммарный размер буфера
 //! *  контролировать время жизни частично полученных объектов
 //! *  передать извлеченный из буфера готовый объект в обработчик (object processor)
+//!
+//! Готовые объекты передаются в обработчик посылками до `[collector] batch` штук, посылка ожидает заполнения
+//! не дольше `batch_timeout`
+//!
+//! В сеансы собираются только фрагменты со звуком. Фрагменты прочих типов смешанного потока по настройке
+//! `[collector] non_audio` отбрасываются или сохраняются отдельно от результатов
+//!
+//! Фрагменты склеиваются в порядке поля заголовка `order_by`: номеров либо меток времени системы сопряжения.
+//! Готовность сеанса и пропуски в нем определяются по номерам при любом порядке
+//!
+//! Параметры звука сеанса устанавливает первый полученный фрагмент со звуком. Фрагмент с другими параметрами
+//! по настройке `on_mismatch` отбрасывается либо приводит к отбрасыванию всего сеанса.
+//!
+//! Фрагмент со звуком без данных, кроме признака окончания, по настройке `on_empty` только продлевает ожидание
+//! начатого сеанса либо заканчивает его. Сеанс без единого фрагмента со звуком не создается: окончание сеанса,
+//! для которого звук не получен, отбрасывается
+//!
+//! Идентификатор сеанса дополняется префиксом, заданным для системы сопряжения (`<addr>:<port>/session_prefix=<name>`),
+//! и с ним попадает в имена файлов и журнал результатов
+//!
+//! При разрыве соединения с системой сопряжения ее незавершенные сеансы по настройке `on_disconnect`
+//! ожидают оставшиеся фрагменты после переподключения в пределах `session_timeout` либо сразу передаются
+//! на обработку незавершенными. Сеанс определяется номером системы сопряжения и идентификатором абонента,
+//! поэтому сеанс, прерванный кратким разрывом, продолжается фрагментами, переданными по новому соединению
+//!
+//! По настройке `session_key = silence_gap` непрерывный поток абонента разделяется на сеансы-высказывания
+//! паузами в звуке не короче `silence_gap`. Высказывание передается на обработку, как только пауза набрана,
+//! следующее начинается с очередного фрагмента со звуком, а номер высказывания попадает в сведения о сеансе
+//!
+//! Сеанс, не получивший недостающих фрагментов в пределах `session_timeout`, передается на обработку незавершенным,
+//! а пропущенные номера фрагментов записываются в журнал работы и в журнал событий для разбора потерь на стороне
+//! системы сопряжения. Повторная передача фрагментов не запрашивается
+//!
+//! Фрагмент сеанса, переданного на обработку не ранее `late_window` назад, отбрасывается как опоздавший,
+//! а не начинает новый сеанс. Новым сеансом того же абонента считается только начинающийся с первого фрагмента
+//!
+//! Если канал фрагментов закрыт без команды на завершение (подсистема input прекратила работу), все накопленные
+//! сеансы передаются на обработку незавершенными, после чего коллектор завершает работу: закрытый канал
+//! не может получить новых писателей, а input не перезапускается без перезапуска приложения
 
-use crate::config::SharedConfig;
-use crate::data::{Fragment, Session};
+mod chunk;
+mod closed;
+mod nonaudio;
+mod outbox;
+mod partial;
+mod pcm;
+mod silence;
 
-use log::{info, error};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::{ByteOrder, Disconnect, EmptyFragment, GapFill, Mismatch, OrderBy, SampleFormat, SharedConfig};
+use crate::stats::SharedStats;
+use crate::timer::Jittered;
+use crate::trace::SharedTracer;
+use crate::tracker::TaskGuard;
+use crate::data::{AudioParams, Fragment, FragmentKind, Metadata, Session};
+use crate::events;
+use crate::memory;
+use self::closed::Closed;
+use self::nonaudio::NonAudioRoute;
+use self::outbox::Outbox;
+use self::partial::Partial;
+use self::pcm::Pcm;
+use self::silence::{Splitter, Verdict};
+
+use log::{debug, info, error, warn};
+use serde_json::json;
 use tokio::sync::mpsc::{Sender, Receiver};
+use tokio::task::JoinHandle;
+use tokio::time::delay_until;
+
+/// Период проверки сеансов на истечение таймаута
+const SWEEP_PERIOD: Duration = Duration::from_millis(500);
+
+/// Ключ сеанса: порядковый номер источника и идентификатор абонента
+type Key = (usize, u32);
+
+/// Параметры сборки сеанса из фрагментов и его разбиения на части
+struct Assembly {
+    gap_fill: GapFill,
+    order_by: OrderBy,
+    /// Наибольший заполняемый тишиной пропуск, мс: `session_timeout` при запуске
+    max_gap_ms: u64,
+    /// Частота дискретизации сеанса, не объявившего свою, Гц
+    rate: u32,
+    /// Длительность части сеанса и перекрытие соседних частей, мс, 0 - сеанс не разбивается
+    chunk_ms: usize,
+    overlap_ms: usize,
+    /// Срок обработки сеанса от момента его передачи, None - не ограничен
+    deadline: Option<Duration>,
+    /// Префиксы идентификаторов сеансов по порядковому номеру системы сопряжения
+    prefixes: Vec<Arc<str>>,
+    /// Адреса систем сопряжения по порядковому номеру для сведений о сеансе
+    sources: Vec<String>,
+    /// Формат и порядок байтов отсчетов систем сопряжения по порядковому номеру
+    formats: Vec<(SampleFormat, ByteOrder)>
+}
 
 /// Запускает в асинхронном режиме подсистему получения фрагментов от входного контура для сборки готовых к обработке сессий
 /// 
 /// Параметры:
 /// 
 /// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `stats` - счетчики работы подсистем
+/// * `tracer` - регистратор интервалов трасс сеансов
 /// * `rx_frag` - межпоточный канал получения из входного модуля получаемых фрагментов, читатель
 /// * `tx_sess` - межпоточный канал передачи готовых к обработке сессий в процессор, писатель
 /// 
-/// Возвращает Future для ожидания в вызывающем коде
-pub async fn run(_cfg: SharedConfig, mut rx_frag: Receiver<Fragment>, _tx_sess: Sender<Session>) {
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, tracer: SharedTracer, mut rx_frag: Receiver<Fragment>, tx_sess: Sender<Session>) -> JoinHandle<()> {
     info!("start collector");
 
+    let gap_fill = cfg.gap_fill();
+    let order_by = cfg.order_by();
+    let assembly = Assembly {
+        gap_fill,
+        order_by,
+        max_gap_ms: cfg.collector_session_timeout().as_millis() as u64,
+        rate: cfg.sample_rate(),
+        chunk_ms: cfg.chunk_ms() as usize,
+        overlap_ms: cfg.chunk_overlap_ms() as usize,
+        deadline: Some(cfg.deadline()).filter(|d| *d > Duration::from_secs(0)),
+        prefixes: cfg.peers().iter().map(|p| Arc::from(p.session_prefix())).collect(),
+        sources: cfg.peers().iter().map(|p| format!("{}:{}", p.addr(), p.port())).collect(),
+        formats: cfg.peers().iter().map(|p| (p.sample_format().unwrap_or(cfg.sample_format()), p.endian().unwrap_or(cfg.sample_endian()))).collect()
+    };
+    info!("collector: session timeout {:?}, gap fill {}, fragments ordered by {}", cfg.collector_session_timeout(), gap_fill, order_by);
+    if assembly.chunk_ms > 0 {
+        info!("collector: sessions are split into {} ms chunks, overlap {} ms", cfg.chunk_ms(), cfg.chunk_overlap_ms());
+    }
+    let max_buffer = cfg.max_buffer_bytes();
+    let jitter = cfg.timer_jitter();
+    let mut closed = Closed::new(cfg.late_window());
+    let on_mismatch = cfg.on_mismatch();
+    let on_disconnect = cfg.on_disconnect();
+    let on_empty = cfg.on_empty();
+    let mut splitter = Splitter::build(&cfg);
+    if splitter.is_some() {
+        info!("collector: streams are split into utterances by {:?} of silence below {} of full scale", cfg.silence_gap(), cfg.silence_level());
+    }
+    let non_audio = NonAudioRoute::new(&cfg, stats.clone());
+    let mut outbox = Outbox::new(tx_sess, stats.clone(), cfg.collector_batch(), cfg.collector_batch_timeout());
+    if cfg.collector_batch() > 1 {
+        info!("collector: sessions are passed by {} within {:?}", cfg.collector_batch(), cfg.collector_batch_timeout());
+    }
+
     tokio::spawn(async move {
+        let _guard = guard;
 
+        let mut partial: HashMap<Key, Partial> = HashMap::new();
+        // the fragments of all incomplete sessions, bytes
+        let mut buffered = 0usize;
+        let mut sweep = Jittered::new(SWEEP_PERIOD, jitter);
+        // the sessions left on exit are passed incomplete for this reason, "stop" - a regular shutdown
+        let mut reason = "stop";
         loop {
-            match rx_frag.recv().await {
-                None => {
-                    error!("fragments input channel is broken");
-                    break;
+            // the batch wait is not armed without pending sessions, the far wake up is never reached
+            let flush_at = outbox.deadline().unwrap_or_else(|| Instant::now() + SWEEP_PERIOD);
+            // the utterances ended by a pause, their streams go on
+            let mut utterances: Vec<(Key, Partial)> = Vec::new();
+            let ready = tokio::select! {
+                f = rx_frag.recv() => match f {
+                    None => {
+                        error!("fragments input channel is broken, {} pending sessions are passed as is", partial.len());
+                        reason = "input_closed";
+                        break;
+                    },
+                    Some(Fragment::Stop) => {
+                        info!("stop collector");
+                        break;
+                    },
+                    Some(Fragment::Disconnected { peer }) => {
+                        let keys: Vec<Key> = partial.keys().filter(|k| k.0 == peer).copied().collect();
+                        if keys.is_empty() {
+                            continue;
+                        }
+                        if on_disconnect == Disconnect::Keep {
+                            debug!("collector: peer {} is disconnected, {} incomplete sessions wait for the reconnect", peer, keys.len());
+                            for k in &keys {
+                                if let Some(p) = partial.get_mut(k) {
+                                    p.interrupt();
+                                }
+                            }
+                            continue;
+                        }
+                        debug!("collector: peer {} is disconnected, {} incomplete sessions are passed as is", peer, keys.len());
+                        for k in &keys {
+                            if let Some(p) = partial.get(k) {
+                                incomplete(*k, p, "disconnect");
+                            }
+                        }
+                        keys
+                    },
+                    Some(Fragment::Data { peer, kind, id, seq, duration_ms, timestamp_us, audio, last, value }) => {
+                        stats.collector.received();
+                        // charged by input while in the channel, a buffered fragment is charged by its session
+                        memory::release(value.len());
+                        if let FragmentKind::Other(t) = kind {
+                            non_audio.route(peer, id, seq, t, &value);
+                            continue;
+                        }
+                        let now = Instant::now();
+                        let key = (peer, id);
+                        if !partial.contains_key(&key) && closed.is_late(key, seq, now) {
+                            debug!("collector: fragment {} of session {} of peer {} is late, the session is passed already", seq, id, peer);
+                            events::emit("fragment_late", json!({ "peer": peer, "id": id, "seq": seq }));
+                            stats.collector.late();
+                            continue;
+                        }
+                        // a fragment without audio but the end marker is a keepalive or the end by on_empty
+                        if value.is_empty() && !last && on_empty == EmptyFragment::Keepalive {
+                            if let Some(p) = partial.get_mut(&key) {
+                                p.touch(now);
+                            }
+                            continue;
+                        }
+                        let last = last || value.is_empty();
+                        if value.is_empty() && !partial.contains_key(&key) {
+                            // nothing is received to make a session of
+                            debug!("collector: end {} of session {} of peer {} without audio is dropped", seq, id, peer);
+                            if let Some(s) = splitter.as_mut() {
+                                // the stream ends within a pause
+                                s.forget(key);
+                                closed.close(key, true, now);
+                            }
+                            continue;
+                        }
+                        // the end marker carries no audio, its parameters don't matter
+                        let expected = partial.get(&key).and_then(|p| p.audio()).filter(|_| !value.is_empty());
+                        if let Some(expected) = expected.filter(|e| *e != audio) {
+                            events::emit("fragment_mismatch", json!({
+                                "peer": peer,
+                                "id": id,
+                                "seq": seq,
+                                "action": if on_mismatch == Mismatch::Drop { "drop" } else { "fail" },
+                                "expected": expected.to_string(),
+                                "got": audio.to_string()
+                            }));
+                            stats.collector.dropped();
+                            if on_mismatch == Mismatch::Drop {
+                                warn!("collector: fragment {} of session {} of peer {} is dropped, {} instead of {}", seq, id, peer, audio, expected);
+                            } else if let Some(p) = partial.remove(&key) {
+                                warn!("collector: session {} of peer {} is dropped, fragment {} has {} instead of {}", id, peer, seq, audio, expected);
+                                buffered -= p.bytes();
+                                closed.close(key, p.has_first(), now);
+                            }
+                            continue;
+                        }
+                        let verdict = match splitter.as_mut() {
+                            None => Verdict::Keep,
+                            Some(s) => {
+                                let mut verdict = s.observe(key, &value, duration_ms, timestamp_us, now);
+                                if verdict == Verdict::EndBefore {
+                                    if let Some(p) = partial.remove(&key) {
+                                        buffered -= p.bytes();
+                                        utterances.push((key, p));
+                                    }
+                                    s.next(key);
+                                    verdict = s.observe(key, &value, duration_ms, timestamp_us, now);
+                                }
+                                verdict
+                            }
+                        };
+                        if verdict == Verdict::Skip {
+                            if last {
+                                if let Some(s) = splitter.as_mut() {
+                                    // the stream ends within a pause
+                                    s.forget(key);
+                                    closed.close(key, true, now);
+                                }
+                            }
+                            // the fragment is skipped, the utterance ended before it is passed all the same
+                            Vec::new()
+                        } else {
+                            let fresh = !partial.contains_key(&key);
+                            let p = partial.entry(key).or_insert_with(|| Partial::new(now, SystemTime::now(), tracer.begin(SystemTime::now())));
+                            if let Some(s) = splitter.as_ref().filter(|_| fresh) {
+                                p.begin(seq, s.utterance(key));
+                            }
+                            if p.resume() {
+                                info!("collector: session {} of peer {} continues after the reconnect", id, peer);
+                                events::emit("session_resumed", json!({ "peer": peer, "id": id, "seq": seq }));
+                            }
+                            let before = p.bytes();
+                            if !value.is_empty() {
+                                p.establish(audio);
+                            }
+                            if p.push(seq, duration_ms, timestamp_us, last, value, now) {
+                                events::emit("fragment_duplicate", json!({ "peer": peer, "id": id, "seq": seq }));
+                            }
+                            buffered = buffered - before + p.bytes();
+                            let mut ready = if p.is_complete() { vec![key] } else { Vec::new() };
+                            if verdict == Verdict::EndAfter && ready.is_empty() {
+                                if let Some(p) = partial.remove(&key) {
+                                    buffered -= p.bytes();
+                                    utterances.push((key, p));
+                                }
+                                if let Some(s) = splitter.as_mut() {
+                                    s.next(key);
+                                }
+                            }
+                            if max_buffer > 0 && buffered > max_buffer {
+                                ready.extend(oldest(&partial, &ready, buffered, max_buffer));
+                            }
+                            ready
+                        }
+                    }
                 },
-                Some(f) => {
-                    match f {
-                        Fragment::Stop => {
-                            info!("stop collector");
-                            break;
+                _ = sweep.tick() => {
+                    let now = Instant::now();
+                    // read by every sweep, the timeout is changed by a config reload
+                    let timeout = cfg.collector_session_timeout();
+                    closed.prune(now);
+                    if let Some(s) = splitter.as_mut() {
+                        s.prune(now, timeout);
+                    }
+                    partial.iter()
+                        .filter(|(_, p)| now.duration_since(p.touched()) >= timeout)
+                        .map(|(k, p)| {
+                            incomplete(*k, p, "timeout");
+                            missing(*k, p);
+                            *k
+                        })
+                        .collect()
+                },
+                _ = delay_until(flush_at.into()), if outbox.deadline().is_some() => {
+                    if !outbox.flush().await {
+                        error!("sessions output channel is broken");
+                        return;
+                    }
+                    Vec::new()
+                }
+            };
+            for (key, p) in utterances {
+                debug!("collector: utterance {} of session {} of peer {} is passed", p.utterance().unwrap_or(0), key.1, key.0);
+                if !emit(&mut outbox, &stats, &tracer, key, p, &assembly).await {
+                    error!("sessions output channel is broken");
+                    return;
+                }
+            }
+            for key in ready {
+                if let Some(p) = partial.remove(&key) {
+                    buffered -= p.bytes();
+                    match splitter.as_mut() {
+                        // the stream goes on after an utterance passed by timeout or pressure, only its end closes it
+                        Some(s) if !p.is_complete() => s.next(key),
+                        Some(s) => {
+                            s.forget(key);
+                            closed.close(key, p.has_first(), Instant::now());
                         },
-                        _ => {
-                            //todo: handle fragment
-                            info!("collector: handling fragments is not implemented yet");
-                        }
-                    }                    
+                        None => closed.close(key, p.has_first(), Instant::now())
+                    }
+                    if !p.is_complete() {
+                        debug!("collector: session {} of peer {} is passed incomplete", key.1, key.0);
+                    }
+                    if !emit(&mut outbox, &stats, &tracer, key, p, &assembly).await {
+                        error!("sessions output channel is broken");
+                        return;
+                    }
                 }
             }
         }    
+        // pass what is already received further, the downstream may be stopped already
+        for (key, p) in partial.drain() {
+            if !p.is_complete() {
+                incomplete(key, &p, reason);
+            }
+            if !emit(&mut outbox, &stats, &tracer, key, p, &assembly).await {
+                break;
+            }
+        }
+        outbox.flush().await;
+        // the distributions tell the typical sessions apart: short bursts or long streams, the timeouts are tuned by them
+        info!("collector: fragments per session {}", stats.session_fragments.summary());
+        info!("collector: session assembly ms {}", stats.session_assembly_ms.summary());
         info!("collector is stopped");
 
-    });
+    })
+}
+
+fn incomplete(key: Key, p: &Partial, reason: &str) {
+    events::emit("session_incomplete", json!({
+        "peer": key.0,
+        "id": key.1,
+        "reason": reason,
+        "fragments": p.fragments(),
+        "bytes": p.bytes()
+    }));
+}
+
+// reports the fragments which the session timeout has given up waiting for, the upstream loss, not a retransmit request
+fn missing(key: Key, p: &Partial) {
+    let ranges = p.missing();
+    if ranges.is_empty() {
+        return;
+    }
+    let list: Vec<String> = ranges.iter()
+        .map(|(from, to)| if from == to { from.to_string() } else { format!("{}-{}", from, to) })
+        .collect();
+    warn!("collector: session {} of peer {} is timed out missing fragments {}", key.1, key.0, list.join(", "));
+    for (from, to) in ranges {
+        events::emit("fragment_missing", json!({ "peer": key.0, "id": key.1, "from": from, "to": to }));
+    }
+}
+
+// the oldest incomplete sessions to pass right away to bring the buffer within the limit, `ready` are passed already
+fn oldest(partial: &HashMap<Key, Partial>, ready: &[Key], buffered: usize, max_buffer: usize) -> Vec<Key> {
+    let mut left = buffered - ready.iter().filter_map(|k| partial.get(k)).map(|p| p.bytes()).sum::<usize>();
+    let mut candidates: Vec<(&Key, &Partial)> = partial.iter().filter(|(k, _)| !ready.contains(k)).collect();
+    candidates.sort_by_key(|(_, p)| p.started());
+    let mut out = Vec::new();
+    for (k, p) in candidates {
+        if left <= max_buffer {
+            break;
+        }
+        warn!("collector: buffer of {} bytes exceeds {}, session {} of peer {} is flushed", left, max_buffer, k.1, k.0);
+        left -= p.bytes();
+        incomplete(*k, p, "pressure");
+        out.push(*k);
+    }
+    out
+}
+
+// assembles the session, splits it into chunks and passes them to the processor, returns false if the channel is broken
+async fn emit(outbox: &mut Outbox, stats: &SharedStats, tracer: &SharedTracer, key: Key, p: Partial, a: &Assembly) -> bool {
+    let (started, trace, peer_time_us, audio) = (p.started(), p.trace(), p.peer_time_us(), p.audio());
+    stats.session_fragments.record(p.fragments() as u64);
+    let prefix = a.prefixes.get(key.0).cloned().unwrap_or_else(|| Arc::from(""));
+    let metadata = Arc::new(metadata(&p, key.0, a));
+    let pcm = layout(audio, key.0, a);
+    let value = p.assemble(a.order_by, a.gap_fill, &pcm, a.max_gap_ms);
+    stats.session_ms.record(pcm.us(value.len()) / 1000);
+    let assembly = SystemTime::now().duration_since(started).unwrap_or_default();
+    stats.session_assembly_ms.record(assembly.as_millis() as u64);
+    // whole frames, a rate below 1 kHz may round the overlap up to the chunk
+    let size = pcm.bytes(a.chunk_ms as u64);
+    let overlap = Some(pcm.bytes(a.overlap_ms as u64)).filter(|o| *o < size).unwrap_or(0);
+    let chunks = chunk::split(value, size, overlap);
+    let count = chunks.len();
+    // each next chunk starts later by the chunk length less the overlap
+    let deadline = a.deadline.map(|d| Instant::now() + d);
+    let step_us = pcm.us(size - overlap);
+    for (i, value) in chunks.into_iter().enumerate() {
+        let bytes = value.len();
+        tracer.stage(&trace, "collector", started, key.1, i as u32);
+        let s = Session::Data {
+            peer: key.0,
+            id: key.1,
+            prefix: prefix.clone(),
+            metadata: metadata.clone(),
+            chunk: i as u32,
+            trace,
+            peer_time_us: peer_time_us.map(|t| t + i as u64 * step_us),
+            deadline,
+            last: i + 1 == count,
+            audio,
+            value
+        };
+        // the session is held until the processor takes it
+        memory::charge(bytes);
+        if !outbox.push(s).await {
+            return false;
+        }
+    }
+    if count > 1 {
+        debug!("collector: session {} of peer {} is split into {} chunks", key.1, key.0, count);
+    }
+    true
+}
+
+// the session audio as declared by its fragments, the samples are of the peer format
+fn layout(audio: Option<AudioParams>, peer: usize, a: &Assembly) -> Pcm {
+    let format = a.formats.get(peer).map_or(SampleFormat::S16, |f| f.0);
+    match audio.filter(|p| p.rate > 0) {
+        Some(p) => Pcm::new(p.rate, usize::from(p.channels), format),
+        None => Pcm::new(a.rate, 1, format)
+    }
+}
+
+// the session as told by the headers of its fragments, all the chunks share it
+fn metadata(p: &Partial, peer: usize, a: &Assembly) -> Metadata {
+    let mut m = Metadata::new();
+    if let Some(source) = a.sources.get(peer) {
+        m.insert("source".to_string(), source.clone());
+    }
+    if let Some(audio) = p.audio() {
+        m.insert("rate".to_string(), audio.rate.to_string());
+        m.insert("channels".to_string(), audio.channels.to_string());
+        m.insert("format".to_string(), audio.format.to_string());
+    }
+    m.insert("fragments".to_string(), p.fragments().to_string());
+    if let Some((format, order)) = a.formats.get(peer) {
+        m.insert("sample_format".to_string(), format.to_string());
+        m.insert("endian".to_string(), order.to_string());
+    }
+    if let Some(u) = p.utterance() {
+        m.insert("utterance".to_string(), u.to_string());
+    }
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::config::Config;
+    use crate::data::{AudioParams, FragmentKind};
+    use crate::stats::Stats;
+    use crate::trace::{Trace, Tracer};
+    use crate::tracker::TaskTracker;
+    use tokio::sync::mpsc;
+
+    fn audio(rate: u32) -> AudioParams {
+        AudioParams { rate, channels: 1, format: 0 }
+    }
+
+    fn fragment(id: u32, seq: u32, audio: AudioParams, last: bool, value: &[u8]) -> Fragment {
+        Fragment::Data { peer: 0, kind: FragmentKind::Audio, id, seq, duration_ms: 0, timestamp_us: 0, audio, last, value: value.to_vec() }
+    }
+
+    // the sessions the collector passes on for the fragments, the rest is passed on the stop
+    async fn collect(sets: &[&str], mut fragments: Vec<Fragment>) -> Vec<(u32, Vec<u8>)> {
+        fragments.push(Fragment::Stop);
+        collect_until_closed(sets, fragments).await
+    }
+
+    // the sessions the collector passes on for the fragments till the channel is closed
+    async fn collect_until_closed(sets: &[&str], fragments: Vec<Fragment>) -> Vec<(u32, Vec<u8>)> {
+        sessions(sets, fragments).await.0.into_iter()
+            .filter_map(|s| match s {
+                Session::Data { id, value, .. } => Some((id, value)),
+                _ => None
+            })
+            .collect()
+    }
+
+    // the sessions as passed on, batches unpacked, and the stats of the collector
+    async fn sessions(sets: &[&str], fragments: Vec<Fragment>) -> (Vec<Session>, SharedStats) {
+        let mut sets = sets.to_vec();
+        sets.push("input.peers=127.0.0.1:12000");
+        let cfg = Config::from_sources(&[], &sets).unwrap();
+        let (mut tx_frag, rx_frag) = mpsc::channel(16);
+        let (tx_sess, mut rx_sess) = mpsc::channel(16);
+        let stats = Stats::new(&cfg.peers());
+        let task = run(cfg.clone(), TaskTracker::new().track("collector"), stats.clone(), Tracer::new(&cfg), rx_frag, tx_sess).await;
+        for f in fragments {
+            assert!(tx_frag.send(f).await.is_ok());
+        }
+        drop(tx_frag);
+        task.await.unwrap();
+        let mut sessions = Vec::new();
+        while let Some(s) = rx_sess.recv().await {
+            match s {
+                Session::Batch(b) => sessions.extend(b),
+                s => sessions.push(s)
+            }
+        }
+        (sessions, stats)
+    }
+
+    // a session started `age` seconds ago with fragments of `bytes`
+    fn partial(age: u64, bytes: usize) -> Partial {
+        let now = Instant::now();
+        let mut p = Partial::new(now, SystemTime::now() - Duration::from_secs(age), Trace::default());
+        p.push(0, 1, 0, false, vec![0; bytes], now);
+        p
+    }
+
+    #[test]
+    fn flushes_the_oldest_sessions_over_the_limit() {
+        let mut sessions = HashMap::new();
+        sessions.insert((0, 1), partial(10, 40));
+        sessions.insert((0, 2), partial(30, 40));
+        sessions.insert((1, 3), partial(20, 40));
+        assert_eq!(oldest(&sessions, &[], 120, 50), vec![(0, 2), (1, 3)]);
+        assert_eq!(oldest(&sessions, &[], 120, 120), Vec::<Key>::new());
+        // a ready session leaves the buffer anyway
+        assert_eq!(oldest(&sessions, &[(0, 2)], 120, 50), vec![(1, 3)]);
+    }
+
+    #[tokio::test]
+    async fn drops_the_fragment_of_other_audio() {
+        let fragments = vec![fragment(1, 0, audio(8000), false, b"aa"), fragment(1, 1, audio(16000), false, b"bb"), fragment(1, 2, audio(8000), false, b"cc")];
+        assert_eq!(collect(&["collector.on_mismatch=drop"], fragments).await, vec![(1, b"aacc".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn fails_the_session_with_other_audio() {
+        let fragments = vec![fragment(1, 0, audio(8000), false, b"aa"), fragment(1, 1, audio(16000), false, b"bb"),
+            fragment(2, 0, audio(8000), true, b"xx")];
+        assert_eq!(collect(&["collector.on_mismatch=fail"], fragments).await, vec![(2, b"xx".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn passes_the_pending_sessions_when_the_channel_closes() {
+        let fragments = vec![fragment(1, 0, audio(8000), false, b"aa"), fragment(2, 0, audio(8000), false, b"bb")];
+        let mut sessions = collect_until_closed(&[], fragments).await;
+        sessions.sort();
+        assert_eq!(sessions, vec![(1, b"aa".to_vec()), (2, b"bb".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_other_fragments_out_of_the_sessions() {
+        let other = Fragment::Data { peer: 0, kind: FragmentKind::Other(9), id: 1, seq: 1, duration_ms: 0, timestamp_us: 0,
+            audio: audio(0), last: false, value: b"meta".to_vec() };
+        let fragments = vec![fragment(1, 0, audio(8000), false, b"aa"), other, fragment(1, 2, audio(8000), true, b"cc")];
+        assert_eq!(collect(&["collector.non_audio=drop"], fragments).await, vec![(1, b"aacc".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn describes_the_session_by_its_fragments() {
+        let fragments = vec![fragment(1, 0, audio(8000), false, b"aa"), fragment(1, 1, audio(8000), true, b"bb"), Fragment::Stop];
+        match sessions(&[], fragments).await.0.as_slice() {
+            [Session::Data { metadata, .. }] => {
+                let m: Vec<(&str, &str)> = ["source", "rate", "channels", "format", "fragments"].iter()
+                    .map(|k| (*k, metadata.get(*k).map(|v| v.as_str()).unwrap_or("")))
+                    .collect();
+                assert_eq!(m, vec![("source", "127.0.0.1:12000"), ("rate", "8000"), ("channels", "1"), ("format", "0"), ("fragments", "2")]);
+            },
+            _ => panic!("a session is expected")
+        }
+    }
+
+    #[tokio::test]
+    async fn records_the_fragments_per_session() {
+        let fragments = vec![fragment(1, 0, audio(8000), false, b"aa"), fragment(1, 1, audio(8000), false, b"bb"),
+            fragment(1, 2, audio(8000), true, b"cc"), fragment(2, 0, audio(8000), true, b"dd"), Fragment::Stop];
+        let (_, stats) = sessions(&[], fragments).await;
+        let h = &stats.snapshot()["histograms"];
+        assert_eq!((&h["session_fragments"]["count"], &h["session_fragments"]["sum"]), (&json!(2), &json!(4)));
+        assert_eq!(h["session_assembly_ms"]["count"], 2);
+    }
+
+    #[tokio::test]
+    async fn the_session_continues_after_the_reconnect() {
+        let fragments = || vec![fragment(1, 0, audio(8000), false, b"aa"), Fragment::Disconnected { peer: 0 },
+            fragment(1, 1, audio(8000), true, b"bb")];
+        assert_eq!(collect(&[], fragments()).await, vec![(1, b"aabb".to_vec())]);
+        // the rest of the flushed session is late, the session is passed already
+        assert_eq!(collect(&["collector.on_disconnect=flush"], fragments()).await, vec![(1, b"aa".to_vec())]);
+        // the other peer's sessions are not touched
+        let other = vec![fragment(1, 0, audio(8000), false, b"aa"), Fragment::Disconnected { peer: 3 },
+            fragment(1, 1, audio(8000), true, b"bb")];
+        assert_eq!(collect(&["collector.on_disconnect=flush"], other).await, vec![(1, b"aabb".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn splits_the_stream_into_utterances() {
+        let f = |seq, last, value: &[u8]| Fragment::Data { peer: 0, kind: FragmentKind::Audio, id: 1, seq, duration_ms: 40, timestamp_us: 0,
+            audio: audio(8000), last, value: value.to_vec() };
+        let (voice, silence) = (&[0x00, 0x40][..], &[0x00, 0x00][..]);
+        let fragments = vec![f(0, false, silence), f(1, false, voice), f(2, false, silence), f(3, false, silence),
+            f(4, false, silence), f(5, true, voice), Fragment::Stop];
+        let passed: Vec<(Vec<u8>, Option<String>)> = sessions(&["collector.session_key=silence_gap", "collector.silence_gap=100ms",
+            "output.metadata=utterance"], fragments).await.0.into_iter()
+            .filter_map(|s| match s {
+                Session::Data { value, metadata, .. } => Some((value, metadata.get("utterance").cloned())),
+                _ => None
+            })
+            .collect();
+        assert_eq!(passed, vec![([voice, silence, silence, silence].concat(), Some("0".to_string())), (voice.to_vec(), Some("1".to_string()))]);
+        // the pause by the timestamps ends the utterance ahead of a silent fragment, the fragment is skipped
+        let at = |seq, timestamp_us, last, value: &[u8]| Fragment::Data { peer: 0, kind: FragmentKind::Audio, id: 1, seq, duration_ms: 40,
+            timestamp_us, audio: audio(8000), last, value: value.to_vec() };
+        let fragments = vec![at(0, 1_000_000, false, voice), at(1, 1_040_000, false, voice), at(2, 3_000_000, false, silence),
+            at(3, 3_040_000, false, voice), at(4, 3_080_000, true, voice), Fragment::Stop];
+        let passed: Vec<Vec<u8>> = sessions(&["collector.session_key=silence_gap", "collector.silence_gap=100ms"], fragments).await.0
+            .into_iter()
+            .filter_map(|s| match s {
+                Session::Data { value, .. } => Some(value),
+                _ => None
+            })
+            .collect();
+        assert_eq!(passed, vec![[voice, voice].concat(), [voice, voice].concat()]);
+    }
+
+    #[tokio::test]
+    async fn the_empty_fragment_keeps_the_session_or_ends_it() {
+        let fragments = || vec![fragment(1, 0, audio(8000), false, b"aa"), fragment(1, 1, audio(8000), false, b""),
+            fragment(1, 1, audio(8000), true, b"bb")];
+        assert_eq!(collect_until_closed(&[], fragments()).await, vec![(1, b"aabb".to_vec())]);
+        // the end ahead of the rest, the rest is late then
+        assert_eq!(collect_until_closed(&["collector.on_empty=end"], fragments()).await, vec![(1, b"aa".to_vec())]);
+        // no session is made of no audio
+        for on_empty in &["keepalive", "end"] {
+            let empty = vec![fragment(2, 0, audio(8000), false, b""), fragment(2, 1, audio(8000), true, b"")];
+            assert!(collect(&[&format!("collector.on_empty={}", on_empty)], empty).await.is_empty(), "{}", on_empty);
+        }
+    }
 }
@@ -0,0 +1,40 @@
+//! Разбиение длинного сеанса на части ограниченной длительности.
+
+/// Разбивает упакованный сеанс на части по `chunk` байтов, каждая следующая часть начинается на `overlap` байтов
+/// раньше окончания предыдущей. Последняя часть может быть короче. Сеанс не длиннее `chunk` возвращается целиком.
+/// `chunk` должен быть больше `overlap`
+pub fn split(value: Vec<u8>, chunk: usize, overlap: usize) -> Vec<Vec<u8>> {
+    if chunk == 0 || value.len() <= chunk {
+        return vec![value];
+    }
+    let step = chunk - overlap;
+    let mut out = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = (start + chunk).min(value.len());
+        out.push(value[start..end].to_vec());
+        if end == value.len() {
+            break;
+        }
+        start += step;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_a_short_session_whole() {
+        assert_eq!(split(b"abc".to_vec(), 3, 1), vec![b"abc".to_vec()]);
+        assert_eq!(split(b"abc".to_vec(), 0, 0), vec![b"abc".to_vec()]);
+    }
+
+    #[test]
+    fn overlaps_the_chunks() {
+        assert_eq!(split(b"abcdefg".to_vec(), 4, 1), vec![b"abcd".to_vec(), b"defg".to_vec()]);
+        assert_eq!(split(b"abcdefgh".to_vec(), 4, 1), vec![b"abcd".to_vec(), b"defg".to_vec(), b"gh".to_vec()]);
+        assert_eq!(split(b"abcdef".to_vec(), 2, 0), vec![b"ab".to_vec(), b"cd".to_vec(), b"ef".to_vec()]);
+    }
+}
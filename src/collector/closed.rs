@@ -0,0 +1,112 @@
+//! Недавно переданные на обработку сеансы для отсева опоздавших фрагментов.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::Key;
+
+/// Сеансы, переданные на обработку в течение последних `window`.
+/// Фрагмент такого сеанса считается опоздавшим, кроме первого по порядку фрагмента сеанса,
+/// первый фрагмент которого был получен: им начинается новый сеанс того же абонента
+pub struct Closed {
+    window: Duration,
+    /// Момент закрытия и признак получения первого фрагмента сеанса
+    sessions: HashMap<Key, (Instant, bool)>,
+    order: VecDeque<(Key, Instant)>
+}
+
+impl Closed {
+
+    pub fn new(window: Duration) -> Closed {
+        Closed {
+            window,
+            sessions: HashMap::new(),
+            order: VecDeque::new()
+        }
+    }
+
+    /// Отмечает сеанс `key` переданным на обработку, `started` - получен его первый фрагмент
+    pub fn close(&mut self, key: Key, started: bool, now: Instant) {
+        if self.window == Duration::from_secs(0) {
+            return;
+        }
+        self.sessions.insert(key, (now, started));
+        self.order.push_back((key, now));
+    }
+
+    /// Проверяет, опоздал ли фрагмент `seq` сеанса `key`. Новый сеанс абонента снимает отметку закрытого
+    pub fn is_late(&mut self, key: Key, seq: u32, now: Instant) -> bool {
+        let (at, started) = match self.sessions.get(&key) {
+            Some(s) => *s,
+            None => return false
+        };
+        if now.duration_since(at) >= self.window || (seq == 0 && started) {
+            self.sessions.remove(&key);
+            return false;
+        }
+        true
+    }
+
+    /// Забывает сеансы, закрытые ранее `window`
+    pub fn prune(&mut self, now: Instant) {
+        while let Some((key, at)) = self.order.front().copied() {
+            if now.duration_since(at) < self.window {
+                break;
+            }
+            self.order.pop_front();
+            // the session may be closed again later, then its newer mark stays
+            if self.sessions.get(&key).is_some_and(|(t, _)| *t == at) {
+                self.sessions.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_fragment_of_a_closed_session_is_late() {
+        let now = Instant::now();
+        let mut c = Closed::new(Duration::from_secs(10));
+        c.close((0, 1), true, now);
+        c.close((0, 2), false, now);
+        assert!(c.is_late((0, 1), 3, now));
+        assert!(!c.is_late((1, 1), 3, now));
+        // the session lacking its first fragment still waits for it
+        assert!(c.is_late((0, 2), 0, now));
+        assert!(!c.is_late((0, 1), 3, now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn the_first_fragment_starts_a_new_session() {
+        let now = Instant::now();
+        let mut c = Closed::new(Duration::from_secs(10));
+        c.close((0, 1), true, now);
+        assert!(!c.is_late((0, 1), 0, now));
+        assert!(!c.is_late((0, 1), 1, now));
+    }
+
+    #[test]
+    fn forgets_the_sessions_past_the_window() {
+        let now = Instant::now();
+        let mut c = Closed::new(Duration::from_secs(10));
+        c.close((0, 1), true, now);
+        c.close((0, 2), true, now + Duration::from_secs(5));
+        // closed once more, the newer mark stays past the older one
+        c.close((0, 1), true, now + Duration::from_secs(6));
+        c.prune(now + Duration::from_secs(12));
+        assert_eq!(c.sessions.len(), 2);
+        c.prune(now + Duration::from_secs(16));
+        assert!(c.sessions.is_empty() && c.order.is_empty());
+    }
+
+    #[test]
+    fn nothing_is_late_without_the_window() {
+        let now = Instant::now();
+        let mut c = Closed::new(Duration::from_secs(0));
+        c.close((0, 1), true, now);
+        assert!(!c.is_late((0, 1), 3, now));
+    }
+}
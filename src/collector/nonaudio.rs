@@ -0,0 +1,80 @@
+//! Отделение фрагментов без звука от собираемых в сеансы.
+
+use std::path::PathBuf;
+
+use log::{debug, error, info};
+
+use crate::config::{NonAudio, SharedConfig};
+use crate::stats::SharedStats;
+
+/// Отбрасывает фрагменты без звука или сохраняет каждый из них в отдельный файл
+/// `<non_audio_dir>/type_<тип>/<peer>_<id>_<seq>.bin` по настройке `[collector] non_audio`
+pub struct NonAudioRoute {
+    action: NonAudio,
+    dir: PathBuf,
+    stats: SharedStats
+}
+
+impl NonAudioRoute {
+
+    pub fn new(cfg: &SharedConfig, stats: SharedStats) -> NonAudioRoute {
+        let action = cfg.non_audio();
+        if action == NonAudio::Store {
+            info!("collector: non-audio fragments are stored to {}", cfg.non_audio_dir());
+        }
+        NonAudioRoute {
+            action,
+            dir: PathBuf::from(cfg.non_audio_dir()),
+            stats
+        }
+    }
+
+    /// Передает фрагмент `seq` типа `kind` абонента `id` системы сопряжения `peer` по настройке
+    pub fn route(&self, peer: usize, id: u32, seq: u32, kind: u8, value: &[u8]) {
+        if self.action == NonAudio::Drop {
+            debug!("collector: fragment {} of type {} of {} of peer {} is dropped", seq, kind, id, peer);
+            self.stats.non_audio.dropped();
+            return;
+        }
+        let dir = self.dir.join(format!("type_{}", kind));
+        let path = dir.join(format!("{}_{}_{}.bin", peer, id, seq));
+        match std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, value)) {
+            Ok(()) => self.stats.non_audio.stored(),
+            Err(e) => {
+                error!("collector: failed to store fragment {} of type {} of {} of peer {}: {}", seq, kind, id, peer, e);
+                self.stats.non_audio.dropped();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+
+    #[test]
+    fn drops_the_fragment() {
+        let stats = Stats::new(&[]);
+        let route = NonAudioRoute::new(&Config::from_sources(&[], &["collector.non_audio=drop"]).unwrap(), stats.clone());
+        route.route(0, 7, 1, 9, b"data");
+        assert_eq!(stats.snapshot()["non_audio"], json!({ "stored": 0, "dropped": 1 }));
+    }
+
+    #[test]
+    fn stores_the_fragment_by_its_type() {
+        let dir = std::env::temp_dir().join(format!("banshee-nonaudio-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg = Config::from_sources(&[], &["collector.non_audio=store", &format!("collector.non_audio_dir={}", dir.display())]).unwrap();
+        let stats = Stats::new(&[]);
+        let route = NonAudioRoute::new(&cfg, stats.clone());
+        route.route(1, 7, 3, 9, b"data");
+        assert_eq!(std::fs::read(dir.join("type_9").join("1_7_3.bin")).unwrap(), b"data");
+        assert_eq!(stats.snapshot()["non_audio"], json!({ "stored": 1, "dropped": 0 }));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Передача готовых сеансов в процессор посылками.
+
+use std::time::{Duration, Instant};
+
+use crate::data::Session;
+use crate::stats::SharedStats;
+
+use log::debug;
+use tokio::sync::mpsc::Sender;
+
+/// Накапливает готовые сеансы и передает их в процессор посылкой `Session::Batch`,
+/// когда накоплено `batch` сеансов или истекло `timeout` после появления первого из них.
+/// При `batch` не больше 1 каждый сеанс передается сразу
+pub struct Outbox {
+    tx_sess: Sender<Session>,
+    stats: SharedStats,
+    batch: usize,
+    timeout: Duration,
+    pending: Vec<Session>,
+    deadline: Option<Instant>
+}
+
+impl Outbox {
+
+    pub fn new(tx_sess: Sender<Session>, stats: SharedStats, batch: usize, timeout: Duration) -> Outbox {
+        Outbox {
+            tx_sess,
+            stats,
+            batch,
+            timeout,
+            pending: Vec::new(),
+            deadline: None
+        }
+    }
+
+    /// Момент, к которому должна быть передана накопленная посылка, None - посылка пуста
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Добавляет сеанс в посылку, передает ее при заполнении. Возвращает false, если канал разрушен
+    pub async fn push(&mut self, s: Session) -> bool {
+        if self.batch <= 1 {
+            return self.send(s, 1).await;
+        }
+        self.pending.push(s);
+        if self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + self.timeout);
+        }
+        if self.pending.len() >= self.batch {
+            return self.flush().await;
+        }
+        true
+    }
+
+    /// Передает накопленную посылку независимо от ее заполнения. Возвращает false, если канал разрушен
+    pub async fn flush(&mut self) -> bool {
+        self.deadline = None;
+        let mut pending = std::mem::take(&mut self.pending);
+        let count = pending.len();
+        match count {
+            0 => true,
+            1 => self.send(pending.remove(0), 1).await,
+            _ => {
+                debug!("collector: batch of {} sessions", count);
+                self.send(Session::Batch(pending), count).await
+            }
+        }
+    }
+
+    async fn send(&mut self, s: Session, count: usize) -> bool {
+        if self.tx_sess.send(s).await.is_err() {
+            return false;
+        }
+        self.stats.collector.add_sent(count);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::stats::Stats;
+    use tokio::sync::mpsc;
+
+    fn session(id: u32) -> Session {
+        Session::Data { peer: 0, id, prefix: "".into(), metadata: Default::default(), chunk: 0, trace: Default::default(),
+            peer_time_us: None, deadline: None, last: true, audio: None, value: Vec::new() }
+    }
+
+    fn ids(s: Session) -> Vec<u32> {
+        match s {
+            Session::Data { id, .. } => vec![id],
+            Session::Batch(b) => b.into_iter().flat_map(ids).collect(),
+            Session::Stop => Vec::new()
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_full_batch() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let stats = Stats::new(&[]);
+        let mut o = Outbox::new(tx, stats.clone(), 2, Duration::from_secs(30));
+        assert!(o.push(session(1)).await);
+        assert!(o.deadline().is_some());
+        assert!(rx.try_recv().is_err());
+        assert!(o.push(session(2)).await);
+        assert!(o.deadline().is_none());
+        let s = rx.recv().await.unwrap();
+        assert!(matches!(s, Session::Batch(_)));
+        assert_eq!(ids(s), vec![1, 2]);
+        assert_eq!(stats.collector.totals().1, 2);
+    }
+
+    #[tokio::test]
+    async fn flushes_a_single_session_as_is() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut o = Outbox::new(tx, Stats::new(&[]), 4, Duration::from_secs(30));
+        assert!(o.flush().await);
+        assert!(rx.try_recv().is_err());
+        o.push(session(3)).await;
+        assert!(o.flush().await);
+        assert!(matches!(rx.recv().await.unwrap(), Session::Data { id: 3, .. }));
+    }
+
+    #[tokio::test]
+    async fn sends_at_once_without_batching() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut o = Outbox::new(tx, Stats::new(&[]), 1, Duration::from_secs(30));
+        assert!(o.push(session(5)).await);
+        assert!(o.deadline().is_none());
+        assert_eq!(ids(rx.recv().await.unwrap()), vec![5]);
+        drop(rx);
+        assert!(!o.push(session(6)).await);
+    }
+}
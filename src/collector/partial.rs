@@ -0,0 +1,356 @@
+//! Частично полученный сеанс абонента и его сборка из фрагментов.
+
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime};
+
+use crate::config::{GapFill, OrderBy};
+use crate::data::AudioParams;
+use crate::memory;
+use crate::trace::Trace;
+
+use log::warn;
+
+use super::pcm::Pcm;
+
+/// Полученный фрагмент сеанса
+struct Part {
+    duration_ms: u32,
+    timestamp_us: u64,
+    value: Vec<u8>
+}
+
+/// Буфер фрагментов одного сеанса, упорядоченных по порядковому номеру
+pub struct Partial {
+    parts: BTreeMap<u32, Part>,
+    /// Суммарный объем полученных фрагментов
+    bytes: usize,
+    /// Параметры звука первого полученного фрагмента со звуком
+    audio: Option<AudioParams>,
+    last_seq: Option<u32>,
+    touched: Instant,
+    started: SystemTime,
+    trace: Trace,
+    /// Соединение с системой сопряжения разорвано после последнего полученного фрагмента
+    interrupted: bool,
+    /// Номер первого фрагмента: высказывание непрерывного потока начинается не с 0
+    base: u32,
+    /// Номер высказывания в непрерывном потоке абонента, None - сеанс не разделяется на высказывания
+    utterance: Option<u32>
+}
+
+impl Partial {
+
+    pub fn new(now: Instant, started: SystemTime, trace: Trace) -> Partial {
+        Partial {
+            parts: BTreeMap::new(),
+            bytes: 0,
+            audio: None,
+            last_seq: None,
+            touched: now,
+            started,
+            trace,
+            interrupted: false,
+            base: 0,
+            utterance: None
+        }
+    }
+
+    /// Начинает сеанс как высказывание `utterance` непрерывного потока абонента с фрагмента `seq`
+    pub fn begin(&mut self, seq: u32, utterance: u32) {
+        self.base = seq;
+        self.utterance = Some(utterance);
+    }
+
+    /// Номер высказывания в непрерывном потоке абонента
+    pub fn utterance(&self) -> Option<u32> {
+        self.utterance
+    }
+
+    /// Помещает фрагмент в буфер, повторно полученный фрагмент заменяет ранее полученный.
+    /// Возвращает true, если фрагмент с таким номером уже был получен
+    pub fn push(&mut self, seq: u32, duration_ms: u32, timestamp_us: u64, last: bool, value: Vec<u8>, now: Instant) -> bool {
+        self.bytes += value.len();
+        memory::charge(value.len());
+        let old = self.parts.insert(seq, Part { duration_ms, timestamp_us, value });
+        if let Some(old) = old.as_ref() {
+            self.bytes -= old.value.len();
+            memory::release(old.value.len());
+        }
+        if last {
+            self.last_seq = Some(seq);
+        }
+        self.touched = now;
+        old.is_some()
+    }
+
+    /// Продлевает ожидание оставшихся фрагментов, как если бы фрагмент был получен в `now`
+    pub fn touch(&mut self, now: Instant) {
+        self.touched = now;
+    }
+
+    /// Отмечает разрыв соединения, по которому получались фрагменты сеанса
+    pub fn interrupt(&mut self) {
+        self.interrupted = true;
+    }
+
+    /// Снимает отметку о разрыве соединения при получении очередного фрагмента.
+    /// Возвращает true, если сеанс был прерван разрывом, т.е. продолжается после переподключения
+    pub fn resume(&mut self) -> bool {
+        std::mem::replace(&mut self.interrupted, false)
+    }
+
+    /// Параметры звука сеанса, None - еще не получено ни одного фрагмента со звуком
+    pub fn audio(&self) -> Option<AudioParams> {
+        self.audio
+    }
+
+    /// Устанавливает параметры звука сеанса по первому полученному фрагменту со звуком
+    pub fn establish(&mut self, audio: AudioParams) {
+        self.audio.get_or_insert(audio);
+    }
+
+    /// Количество полученных фрагментов
+    pub fn fragments(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Получен ли первый по порядку фрагмент сеанса, первый фрагмент потока предшествовал высказыванию не с начала потока
+    pub fn has_first(&self) -> bool {
+        self.base > 0 || self.parts.contains_key(&0)
+    }
+
+    /// Суммарный объем полученных фрагментов, байтов
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Момент поступления последнего фрагмента
+    pub fn touched(&self) -> Instant {
+        self.touched
+    }
+
+    /// Момент поступления первого фрагмента
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    /// Контекст трассы сеанса
+    pub fn trace(&self) -> Trace {
+        self.trace
+    }
+
+    /// Момент начала сеанса по часам системы сопряжения, мкс: метка первого по порядку фрагмента, для которого она задана
+    pub fn peer_time_us(&self) -> Option<u64> {
+        self.parts.values().map(|p| p.timestamp_us).find(|t| *t > 0)
+    }
+
+    /// Сеанс готов к обработке, если получен последний фрагмент и все предшествующие ему
+    pub fn is_complete(&self) -> bool {
+        match self.last_seq {
+            None => false,
+            Some(last) => self.parts.len() as u64 + self.base as u64 == last as u64 + 1
+        }
+    }
+
+    /// Пропущенные номера фрагментов от первого до последнего, или до наибольшего полученного, пока последний
+    /// не получен, в виде диапазонов `(с, по)` включительно
+    pub fn missing(&self) -> Vec<(u32, u32)> {
+        let end = match self.last_seq.or_else(|| self.parts.keys().next_back().copied()) {
+            Some(end) => end,
+            None => return Vec::new()
+        };
+        let mut out = Vec::new();
+        let mut next = self.base;
+        for seq in self.parts.keys().copied().chain(std::iter::once(end.saturating_add(1))) {
+            if seq > next && next <= end {
+                out.push((next, seq.min(end.saturating_add(1)) - 1));
+            }
+            next = next.max(seq.saturating_add(1));
+        }
+        out
+    }
+
+    /// Склеивает полученные фрагменты в порядке `order`: номеров или меток времени, при равных метках - номеров.
+    /// Сеанс с фрагментом без метки времени склеивается в порядке номеров.
+    /// В режиме `GapFill::Silence` каждый пропущенный номер заменяется отсчетами тишины формата сеанса
+    /// длительностью соседнего полученного фрагмента в раскладке звука сеанса `pcm`,
+    /// что сохраняет соответствие положения звука в сеансе реальному времени. По меткам времени
+    /// пропуском считается промежуток между фрагментами, в который помещается целое число фрагментов
+    /// длительностью предыдущего, он заменяется тишиной этой длительности.
+    /// Пропуск с неизвестной длительностью или длительностью больше `max_gap_ms` отбрасывается
+    pub fn assemble(self, order: OrderBy, gap_fill: GapFill, pcm: &Pcm, max_gap_ms: u64) -> Vec<u8> {
+        if order == OrderBy::Timestamp && self.parts.values().all(|p| p.timestamp_us > 0) {
+            return self.assemble_by_time(gap_fill, pcm, max_gap_ms);
+        }
+        let total: usize = self.parts.values().map(|p| p.value.len()).sum();
+        let mut out = Vec::with_capacity(total);
+        let mut next = self.base;
+        let mut prev_ms = 0u32;
+        for (seq, part) in self.parts.iter() {
+            if gap_fill == GapFill::Silence && *seq > next {
+                // the gap takes the duration of the neighbour fragments
+                let ms = if prev_ms > 0 { prev_ms } else { part.duration_ms };
+                fill(&mut out, pcm, u64::from(*seq - next) * u64::from(ms), max_gap_ms);
+            }
+            out.extend_from_slice(&part.value);
+            prev_ms = part.duration_ms;
+            next = seq.saturating_add(1);
+        }
+        out
+    }
+
+    // the fragments in the order of the peer clock, each one is expected at the end of the previous one
+    fn assemble_by_time(mut self, gap_fill: GapFill, pcm: &Pcm, max_gap_ms: u64) -> Vec<u8> {
+        let total: usize = self.parts.values().map(|p| p.value.len()).sum();
+        let mut out = Vec::with_capacity(total);
+        let mut parts: Vec<(u32, Part)> = std::mem::take(&mut self.parts).into_iter().collect();
+        parts.sort_by_key(|(seq, p)| (p.timestamp_us, *seq));
+        let mut prev: Option<(u64, u32)> = None;
+        for (_, part) in parts {
+            if let (GapFill::Silence, Some((end_us, ms))) = (gap_fill, prev) {
+                // the clock jitter shorter than a fragment is no loss
+                if ms > 0 && part.timestamp_us > end_us {
+                    let missing = (part.timestamp_us - end_us) / (ms as u64 * 1000);
+                    fill(&mut out, pcm, missing.saturating_mul(ms as u64), max_gap_ms);
+                }
+            }
+            out.extend_from_slice(&part.value);
+            prev = Some((part.timestamp_us.saturating_add(part.duration_ms as u64 * 1000), part.duration_ms));
+        }
+        out
+    }
+}
+
+// fills the gap of `ms` with silence unless it is longer than `max_ms`: a bogus number or a clock jump
+// would take gigabytes of it
+fn fill(out: &mut Vec<u8>, pcm: &Pcm, ms: u64, max_ms: u64) {
+    if ms > max_ms {
+        warn!("collector: a gap of {} ms is longer than {} ms, it is not filled", ms, max_ms);
+        return;
+    }
+    pcm.fill(out, ms);
+}
+
+impl Drop for Partial {
+
+    // the fragments leave the budget with the buffer, the assembled session is charged by the caller
+    fn drop(&mut self) {
+        memory::release(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::SampleFormat;
+
+    // a u8 session of 1 kHz: a byte per ms, the silence is 0x80
+    fn pcm() -> Pcm {
+        Pcm::new(1000, 1, SampleFormat::U8)
+    }
+
+    const MAX_GAP_MS: u64 = 5000;
+
+    fn partial(parts: &[(u32, &[u8], bool)]) -> Partial {
+        let now = Instant::now();
+        let mut p = Partial::new(now, SystemTime::now(), Trace::default());
+        for (seq, value, last) in parts {
+            p.push(*seq, value.len() as u32, 0, *last, value.to_vec(), now);
+        }
+        p
+    }
+
+    #[test]
+    fn completes_with_every_fragment_up_to_the_last() {
+        let mut p = partial(&[(2, b"c", true), (0, b"a", false)]);
+        assert!(!p.is_complete());
+        assert!(p.push(0, 1, 0, false, b"a".to_vec(), Instant::now()));
+        assert_eq!((p.fragments(), p.bytes()), (2, 2));
+        p.push(1, 1, 0, false, b"b".to_vec(), Instant::now());
+        assert!(p.is_complete());
+        assert_eq!(p.assemble(OrderBy::Sequence, GapFill::Drop, &pcm(), MAX_GAP_MS), b"abc");
+    }
+
+    #[test]
+    fn drops_the_gaps() {
+        let p = partial(&[(3, b"dd", true), (0, b"aa", false)]);
+        assert_eq!(p.assemble(OrderBy::Sequence, GapFill::Drop, &pcm(), MAX_GAP_MS), b"aadd");
+    }
+
+    #[test]
+    fn fills_the_gaps_with_silence() {
+        // two fragments of 2 ms are missing after the first one
+        let p = partial(&[(3, b"dd", true), (0, b"aa", false)]);
+        assert_eq!(p.assemble(OrderBy::Sequence, GapFill::Silence, &pcm(), MAX_GAP_MS), b"aa\x80\x80\x80\x80dd");
+        // a missing first fragment takes the duration of the next one
+        let p = partial(&[(1, b"bbb", true)]);
+        assert_eq!(p.assemble(OrderBy::Sequence, GapFill::Silence, &pcm(), MAX_GAP_MS), b"\x80\x80\x80bbb");
+    }
+
+    #[test]
+    fn the_peer_time_is_of_the_first_fragment_that_has_it() {
+        let now = Instant::now();
+        let mut p = Partial::new(now, SystemTime::now(), Trace::default());
+        assert_eq!(p.peer_time_us(), None);
+        p.push(2, 1, 3000, true, b"c".to_vec(), now);
+        p.push(1, 1, 2000, false, b"b".to_vec(), now);
+        p.push(0, 1, 0, false, b"a".to_vec(), now);
+        assert_eq!(p.peer_time_us(), Some(2000));
+    }
+
+    #[test]
+    fn a_repeated_fragment_replaces_its_bytes() {
+        let mut p = partial(&[(0, b"aaa", false)]);
+        p.push(1, 1, 0, false, b"b".to_vec(), Instant::now());
+        p.push(0, 1, 0, false, b"a".to_vec(), Instant::now());
+        assert_eq!(p.bytes(), 2);
+    }
+
+    #[test]
+    fn lists_the_missing_ranges() {
+        let p = partial(&[(0, b"a", false), (2, b"c", false), (5, b"f", false), (8, b"i", true)]);
+        assert_eq!(p.missing(), vec![(1, 1), (3, 4), (6, 7)]);
+        // the last fragment is not known yet, nothing is missing past the highest one
+        assert_eq!(partial(&[(1, b"b", false), (3, b"d", false)]).missing(), vec![(0, 0), (2, 2)]);
+        assert_eq!(partial(&[(3, b"d", true)]).missing(), vec![(0, 2)]);
+        assert!(partial(&[(0, b"a", false), (1, b"b", true)]).missing().is_empty());
+        assert!(partial(&[]).missing().is_empty());
+    }
+
+    #[test]
+    fn orders_the_fragments_by_the_peer_time() {
+        let timed = |first_us| {
+            let now = Instant::now();
+            let mut p = Partial::new(now, SystemTime::now(), Trace::default());
+            p.push(0, 2, 3000, false, b"aa".to_vec(), now);
+            p.push(1, 2, first_us, false, b"bb".to_vec(), now);
+            p.push(2, 2, 9000, true, b"cc".to_vec(), now);
+            p
+        };
+        assert_eq!(timed(1000).assemble(OrderBy::Timestamp, GapFill::Drop, &pcm(), MAX_GAP_MS), b"bbaacc");
+        // two fragments of 2 ms fit between the end of the second one and the third
+        assert_eq!(timed(1000).assemble(OrderBy::Timestamp, GapFill::Silence, &pcm(), MAX_GAP_MS), b"bbaa\x80\x80\x80\x80cc");
+        // the equal times keep the numbers
+        assert_eq!(timed(3000).assemble(OrderBy::Timestamp, GapFill::Drop, &pcm(), MAX_GAP_MS), b"aabbcc");
+        // a fragment of no time puts the session back to the numbers
+        assert_eq!(timed(0).assemble(OrderBy::Timestamp, GapFill::Drop, &pcm(), MAX_GAP_MS), b"aabbcc");
+        assert_eq!(timed(1000).assemble(OrderBy::Sequence, GapFill::Drop, &pcm(), MAX_GAP_MS), b"aabbcc");
+    }
+
+    #[test]
+    fn leaves_the_gap_past_the_limit_unfilled() {
+        // a bogus number would take some 4e9 ms of silence
+        let p = partial(&[(0, b"a", false), (4_000_000_000, b"b", true)]);
+        assert_eq!(p.assemble(OrderBy::Sequence, GapFill::Silence, &pcm(), MAX_GAP_MS), b"ab");
+        let p = partial(&[(0, b"a", false), (3, b"d", true)]);
+        assert_eq!(p.assemble(OrderBy::Sequence, GapFill::Silence, &pcm(), 1), b"ad");
+        assert_eq!(partial(&[(0, b"a", false), (3, b"d", true)]).assemble(OrderBy::Sequence, GapFill::Silence, &pcm(), 2), b"a\x80\x80d");
+        // so does a clock jump
+        let now = Instant::now();
+        let mut p = Partial::new(now, SystemTime::now(), Trace::default());
+        p.push(0, 1, 1000, false, b"a".to_vec(), now);
+        p.push(1, 1, u64::MAX - 1, true, b"b".to_vec(), now);
+        assert_eq!(p.assemble(OrderBy::Timestamp, GapFill::Silence, &pcm(), MAX_GAP_MS), b"ab");
+    }
+}
@@ -0,0 +1,77 @@
+//! Соответствие длительности звука сеанса и его объема в байтах.
+
+use std::convert::TryFrom;
+
+use crate::config::SampleFormat;
+use crate::processor;
+
+/// Раскладка звука сеанса: частота дискретизации, количество чередующихся каналов и размер отсчета.
+/// Объемы считаются целыми кадрами - по отсчету каждого канала
+pub struct Pcm {
+    rate: u64,
+    /// Кадр тишины: отсчет тишины формата в каждом канале
+    silent: Vec<u8>
+}
+
+impl Pcm {
+
+    /// Раскладка звука с частотой `rate`, `channels` каналами и отсчетами формата `format`
+    pub fn new(rate: u32, channels: usize, format: SampleFormat) -> Pcm {
+        Pcm {
+            rate: u64::from(rate),
+            silent: processor::silence(format).repeat(channels.max(1))
+        }
+    }
+
+    /// Объем звука длительностью `ms` мс в байтах, округленный вниз до целого кадра, не более `usize::MAX`
+    pub fn bytes(&self, ms: u64) -> usize {
+        let frames = ms.saturating_mul(self.rate) / 1000;
+        usize::try_from(frames).unwrap_or(usize::MAX).saturating_mul(self.silent.len())
+    }
+
+    /// Длительность звука объемом `bytes` в мкс, 0 при неизвестной частоте
+    pub fn us(&self, bytes: usize) -> u64 {
+        ((bytes / self.silent.len()) as u64 * 1_000_000).checked_div(self.rate).unwrap_or(0)
+    }
+
+    /// Дополняет `out` тишиной формата отсчетов длительностью `ms` мс
+    pub fn fill(&self, out: &mut Vec<u8>, ms: u64) {
+        let frames = self.bytes(ms) / self.silent.len();
+        out.reserve(frames * self.silent.len());
+        for _ in 0..frames {
+            out.extend_from_slice(&self.silent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_the_audio_by_whole_frames() {
+        // 16 kHz stereo 16-bit: 4 bytes a frame, 64 bytes a ms
+        let p = Pcm::new(16000, 2, SampleFormat::S16);
+        assert_eq!(p.bytes(10), 640);
+        assert_eq!(p.us(640), 10_000);
+        assert_eq!(p.us(6), 62);
+        // no rate, no duration
+        assert_eq!(Pcm::new(0, 1, SampleFormat::S16).us(640), 0);
+    }
+
+    #[test]
+    fn fills_with_the_silence_of_the_format() {
+        let mut out = vec![1];
+        Pcm::new(1000, 2, SampleFormat::Mulaw).fill(&mut out, 2);
+        assert_eq!(out, vec![1, 0xff, 0xff, 0xff, 0xff]);
+        let mut out = Vec::new();
+        Pcm::new(1000, 1, SampleFormat::S16).fill(&mut out, 1);
+        assert_eq!(out, vec![0, 0]);
+    }
+
+    #[test]
+    fn the_size_does_not_overflow() {
+        // the frames saturate at u64::MAX before the rate divides them
+        assert_eq!(Pcm::new(48000, 2, SampleFormat::S32).bytes(u64::MAX), (u64::MAX / 1000) as usize * 8);
+    }
+}
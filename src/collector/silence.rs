@@ -0,0 +1,183 @@
+//! Разделение непрерывного потока звука абонента на сеансы-высказывания по паузам.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::config::{ByteOrder, SampleFormat, SessionKey, SharedConfig};
+use crate::processor;
+
+use super::Key;
+
+/// Решение о фрагменте потока
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Verdict {
+    /// Фрагмент паузы до начала высказывания не сохраняется
+    Skip,
+    /// Фрагмент продолжает высказывание или начинает новое
+    Keep,
+    /// Фрагмент продолжает паузу, которой высказывание заканчивается: после него высказывание передается на обработку
+    EndAfter,
+    /// Между предыдущим фрагментом и этим по меткам времени прошло не меньше паузы:
+    /// высказывание передается на обработку, а фрагмент решается заново как первый фрагмент следующего
+    EndBefore
+}
+
+/// Состояние потока абонента
+struct Stream {
+    /// Номер текущего высказывания в потоке, начиная с 0
+    utterance: u32,
+    /// Звук в текущем высказывании уже был
+    voiced: bool,
+    /// Длительность паузы в конце текущего высказывания, мс
+    silent_ms: u64,
+    /// Окончание звука последнего фрагмента по часам системы сопряжения, мкс, None - не известно
+    end_us: Option<u64>,
+    touched: Instant
+}
+
+/// Разделяет поток абонента на высказывания по настройке `[collector] session_key = silence_gap`:
+/// высказывание заканчивается, когда пауза в звуке длится не меньше `silence_gap`. Пауза - фрагменты,
+/// пиковый уровень которых ниже `silence_level` полной шкалы, и промежутки между фрагментами по их меткам времени.
+/// Отсчеты читаются в формате и порядке байтов системы сопряжения потока, как их читает этап `convert`.
+/// Пауза до начала высказывания не сохраняется, пауза, которой оно закончилось, остается в нем
+pub struct Splitter {
+    /// Доля полной шкалы, ниже которой пиковый уровень фрагмента считается паузой
+    level: f32,
+    gap_ms: u64,
+    /// Формат и порядок байтов отсчетов систем сопряжения по порядковому номеру
+    formats: Vec<(SampleFormat, ByteOrder)>,
+    streams: HashMap<Key, Stream>
+}
+
+impl Splitter {
+
+    /// Создает разделение по настройкам, None - сеансы определяются только идентификатором абонента
+    pub fn build(cfg: &SharedConfig) -> Option<Splitter> {
+        if cfg.session_key() != SessionKey::SilenceGap {
+            return None;
+        }
+        Some(Splitter {
+            level: cfg.silence_level().clamp(0.0, 1.0),
+            gap_ms: cfg.silence_gap().as_millis() as u64,
+            formats: cfg.peers().iter().map(|p| (p.sample_format().unwrap_or(cfg.sample_format()), p.endian().unwrap_or(cfg.sample_endian()))).collect(),
+            streams: HashMap::new()
+        })
+    }
+
+    /// Решает, как поступить с фрагментом потока `key` длительностью `duration_ms`, начатым в `timestamp_us`
+    pub fn observe(&mut self, key: Key, value: &[u8], duration_ms: u32, timestamp_us: u64, now: Instant) -> Verdict {
+        let gap_ms = self.gap_ms;
+        let silent = self.is_silent(key.0, value);
+        let s = self.streams.entry(key).or_insert(Stream { utterance: 0, voiced: false, silent_ms: 0, end_us: None, touched: now });
+        s.touched = now;
+        // the time uncovered by the fragments is a pause too
+        let lost_ms = match (s.end_us, timestamp_us) {
+            (Some(end), t) if t > end => (t - end) / 1000,
+            _ => 0
+        };
+        if timestamp_us > 0 {
+            s.end_us = Some(timestamp_us + u64::from(duration_ms) * 1000);
+        }
+        if s.voiced && s.silent_ms + lost_ms >= gap_ms {
+            // the end of the fragment is already noted, so deciding it again after next() finds no pause before it
+            return Verdict::EndBefore;
+        }
+        if !silent {
+            s.voiced = true;
+            s.silent_ms = 0;
+            return Verdict::Keep;
+        }
+        if !s.voiced {
+            return Verdict::Skip;
+        }
+        s.silent_ms += lost_ms + u64::from(duration_ms);
+        if s.silent_ms >= gap_ms { Verdict::EndAfter } else { Verdict::Keep }
+    }
+
+    /// Номер текущего высказывания потока `key`
+    pub fn utterance(&self, key: Key) -> u32 {
+        self.streams.get(&key).map_or(0, |s| s.utterance)
+    }
+
+    /// Отмечает окончание высказывания потока `key`: следующий фрагмент со звуком начинает новое
+    pub fn next(&mut self, key: Key) {
+        if let Some(s) = self.streams.get_mut(&key) {
+            s.utterance += 1;
+            s.voiced = false;
+            s.silent_ms = 0;
+        }
+    }
+
+    /// Забывает поток `key`, получивший последний фрагмент
+    pub fn forget(&mut self, key: Key) {
+        self.streams.remove(&key);
+    }
+
+    /// Забывает потоки без фрагментов дольше `timeout`
+    pub fn prune(&mut self, now: Instant, timeout: Duration) {
+        self.streams.retain(|_, s| now.duration_since(s.touched) < timeout);
+    }
+
+    // the samples of the peer format below the level, the end marker without audio is a pause
+    fn is_silent(&self, peer: usize, value: &[u8]) -> bool {
+        let (format, order) = self.formats.get(peer).copied().unwrap_or((SampleFormat::S16, ByteOrder::Little));
+        value.chunks_exact(format.width()).all(|b| processor::decode(b, format, order).abs() < self.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    const VOICE: &[u8] = &[0x00, 0x40];
+    const SILENCE: &[u8] = &[0x01, 0x00];
+
+    fn splitter() -> Splitter {
+        let cfg = Config::from_sources(&[], &["input.peers=127.0.0.1:12000", "collector.session_key=silence_gap",
+            "collector.silence_gap=100ms"]).unwrap();
+        Splitter::build(&cfg).unwrap()
+    }
+
+    #[test]
+    fn ends_the_utterance_by_the_pause() {
+        let (mut s, now, key) = (splitter(), Instant::now(), (0, 1));
+        let verdicts: Vec<Verdict> = [SILENCE, VOICE, SILENCE, SILENCE, SILENCE].iter()
+            .map(|v| s.observe(key, v, 40, 0, now))
+            .collect();
+        assert_eq!(verdicts, vec![Verdict::Skip, Verdict::Keep, Verdict::Keep, Verdict::Keep, Verdict::EndAfter]);
+        assert_eq!(s.utterance(key), 0);
+        s.next(key);
+        // the pause after the end is not kept before the next utterance
+        assert_eq!(s.observe(key, SILENCE, 40, 0, now), Verdict::Skip);
+        assert_eq!(s.observe(key, VOICE, 40, 0, now), Verdict::Keep);
+        assert_eq!(s.utterance(key), 1);
+    }
+
+    #[test]
+    fn the_time_uncovered_by_the_fragments_is_a_pause() {
+        let (mut s, now, key) = (splitter(), Instant::now(), (0, 1));
+        assert_eq!(s.observe(key, VOICE, 40, 1_000_000, now), Verdict::Keep);
+        assert_eq!(s.observe(key, VOICE, 40, 1_040_000, now), Verdict::Keep);
+        assert_eq!(s.observe(key, VOICE, 40, 1_200_000, now), Verdict::EndBefore);
+        s.next(key);
+        assert_eq!(s.observe(key, VOICE, 40, 1_200_000, now), Verdict::Keep);
+        assert_eq!(s.utterance(key), 1);
+    }
+
+    #[test]
+    fn the_silent_streams_are_forgotten() {
+        let (mut s, now) = (splitter(), Instant::now());
+        s.observe((0, 1), VOICE, 40, 0, now);
+        s.next((0, 1));
+        s.observe((0, 2), VOICE, 40, 0, now + Duration::from_secs(5));
+        s.prune(now + Duration::from_secs(10), Duration::from_secs(8));
+        assert_eq!((s.utterance((0, 1)), s.utterance((0, 2))), (0, 0));
+        assert_eq!(s.streams.len(), 1);
+        s.forget((0, 2));
+        assert!(s.streams.is_empty());
+        let cfg = Config::from_sources(&[], &[]).unwrap();
+        assert!(Splitter::build(&cfg).is_none());
+    }
+}
@@ -2,51 +2,1074 @@
 //! 
 //! Задачи:
 //! *  построить конфигурацию при запуске программы
-//! *  обновлять конфигурацию с заданным периодом в процессе работы
+//! *  перечитывать конфигурацию из тех же источников в процессе работы, команда `reload` управления:
+//!    изменения ключей, применяемых на ходу, вступают в силу сразу, остальные - после перезапуска
 //! *  собирать конфигурацию из источников (в порядке уменьшения приоритета):
-//!       * командная строка
-//!       * конфиг. файл
-//!       * переменные окружения
+//!       * командная строка, `--set <section>.<key>=<value>`
+//!       * переменные окружения, `BANSHEE_<SECTION>__<KEY>=<value>`
+//!       * конфиг. файлы, `--config <path>`, могут отсутствовать. Несколько файлов объединяются по порядку,
+//!         значения следующего файла переопределяют значения предыдущих
 //!       * значения по-умолчанию, заданы в коде программы
+//! *  подставлять значения, заданные косвенно: `<key>_file = <path>` - из файла, `<key>_env = <NAME>` - из переменной
+//!    окружения, чтобы секреты не хранились в файлах конфигурации
 
 use clap::{Arg, App, ArgMatches};
-use log::LevelFilter;
-use std::sync::{Arc, RwLock};
+use ini::Ini;
+use log::{warn, LevelFilter};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::sync::watch;
 
+mod cidr;
 mod core;
+mod directive;
 mod endpoint;
+mod options;
+mod route;
 use self::core::ConfigCore;
 
 pub struct Config {
-    core: RwLock<ConfigCore>
+    core: RwLock<ConfigCore>,
+    /// Набор значений всех источников, по которому построены текущие настройки
+    running: Mutex<Ini>,
+    /// Источники для перечитывания: файлы конфигурации и выражения командной строки
+    pathnames: Vec<String>,
+    sets: Vec<String>,
+    notes: Vec<String>,
+    /// Ключи с косвенно заданными значениями текущих настроек, обновляются вместе с ними при перезагрузке
+    secrets: RwLock<Vec<String>>,
+    validate_model: Option<String>,
+    replay_deadletter: Option<String>,
+    /// Количество примененных перезагрузок конфигурации с запуска для извещения подсистем
+    reloads: (watch::Sender<u64>, watch::Receiver<u64>)
 }
 
 pub type SharedConfig = Arc<Config>;
+
+/// Итог перечитывания конфигурации
+#[derive(Debug, Default)]
+pub struct Reload {
+    /// Измененные ключи `<section>.<key>`, примененные без перезапуска
+    pub applied: Vec<String>,
+    /// Измененные ключи, которые вступят в силу только после перезапуска
+    pub pending: Vec<String>,
+    /// Измененные неизвестные ключи, они игнорируются
+    pub unknown: Vec<String>
+}
+pub type Cidr = cidr::Cidr;
 pub type Endpoint = endpoint::Endpoint;
+pub type LogDirective = directive::LogDirective;
+pub type Route = route::Route;
+pub type RouteMatch = route::RouteMatch;
+pub type OutputMode = options::OutputMode;
+pub type NoReader = options::NoReader;
+pub type GapFill = options::GapFill;
+pub type OrderBy = options::OrderBy;
+pub type Unavailable = options::Unavailable;
+pub type BelowThreshold = options::BelowThreshold;
+pub type Oversize = options::Oversize;
+pub type RateMismatch = options::RateMismatch;
+pub type FullPolicy = options::FullPolicy;
+pub type OutputLost = options::OutputLost;
+pub type InputFull = options::InputFull;
+pub type Mismatch = options::Mismatch;
+pub type EmptyFragment = options::EmptyFragment;
+pub type Disconnect = options::Disconnect;
+pub type SessionKey = options::SessionKey;
+pub type Malformed = options::Malformed;
+pub type ChannelSelect = options::ChannelSelect;
+pub type NonAudio = options::NonAudio;
+pub type HashAlg = options::HashAlg;
+pub type ByteOrder = options::ByteOrder;
+pub type SampleFormat = options::SampleFormat;
+pub type BatchPriority = options::BatchPriority;
+pub type Compression = options::Compression;
+pub type FanoutPolicy = options::FanoutPolicy;
+pub type TimestampSource = options::TimestampSource;
+pub type TimestampPrecision = options::TimestampPrecision;
+pub type Validation = options::Validation;
 
 impl Config{
 
     /// Создает копируемый между всеми компонентами приложения указатель на объект конфигурации.
     /// Создание объекта конфигурации должно предшествовать созданию всех остальных подмодулей
+    /// Некорректная конфигурация является фатальной ошибкой: сообщение выводится в stderr, и приложение завершается
     pub fn new() -> SharedConfig {
         let args = init_args();
-        let pathname = args.value_of("config").unwrap_or("banshee.ini");
-        // init from file
-        let inst = ConfigCore::new(pathname);
-        // override values by args
-        // todo ...
+        let pathnames: Vec<String> = args.values_of("config").map(|v| v.map(|s| s.to_string()).collect())
+            .unwrap_or_else(|| vec!["banshee.ini".to_string()]);
+        let sets: Vec<String> = args.values_of("set").map(|v| v.map(|s| s.to_string()).collect()).unwrap_or_default();
+        let validate_model = args.value_of("validate-model").map(|s| s.to_string());
+        let replay_deadletter = args.value_of("replay-deadletter").map(|s| s.to_string());
         // ready
-        Arc::<Config>::new(Config {
-            core: RwLock::new(inst)
+        Arc::<Config>::new(Config::assemble(pathnames, sets, validate_model, replay_deadletter).unwrap_or_else(|e| {
+            eprintln!("banshee: {}", e);
+            std::process::exit(2);
+        }))
+    }
+
+    /// Создает объект конфигурации по файлам `pathnames` и выражениям `<section>.<key>=<value>` `sets`
+    /// без разбора командной строки
+    #[cfg(test)]
+    pub fn from_sources(pathnames: &[&str], sets: &[&str]) -> Result<SharedConfig, String> {
+        let owned = |v: &[&str]| v.iter().map(|s| s.to_string()).collect();
+        Config::assemble(owned(pathnames), owned(sets), None, None).map(Arc::new)
+    }
+
+    fn assemble(pathnames: Vec<String>, sets: Vec<String>, validate_model: Option<String>, replay_deadletter: Option<String>)
+                -> Result<Config, String> {
+        let mut notes = Vec::new();
+        let mut secrets = Vec::new();
+        let ini = build(&pathnames, &sets, &mut notes, &mut secrets)?;
+        let inst = ConfigCore::new(&ini)?;
+        Ok(Config {
+            core: RwLock::new(inst),
+            running: Mutex::new(ini),
+            pathnames,
+            sets,
+            notes,
+            secrets: RwLock::new(secrets),
+            validate_model,
+            replay_deadletter,
+            reloads: watch::channel(0)
         })
     }
 
+    /// Доступ на чтение к текущим настройкам.
+    /// Отравление блокировки паникой пишущего потока не распространяется на читателей:
+    /// настройки остаются в последнем записанном состоянии, признак отравления сбрасывается с предупреждением
+    fn core(&self) -> RwLockReadGuard<'_, ConfigCore> {
+        self.core.read().unwrap_or_else(|poisoned| {
+            warn!("config: lock is poisoned by a panic during update, keep using the last written settings");
+            self.core.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+
+    /// Перечитывает конфигурацию из источников, по которым она построена при запуске. Изменения ключей
+    /// [`core::RELOADABLE`] вступают в силу сразу, остальные ключи сохраняют прежние значения до перезапуска.
+    /// Некорректная конфигурация не применяется, ошибка возвращается, текущие настройки не меняются
+    pub fn reload(&self) -> Result<Reload, String> {
+        // the notes are written to the log at the start only, the changed keys are reported instead
+        let mut notes = Vec::new();
+        let mut secrets = Vec::new();
+        let fresh = build(&self.pathnames, &self.sets, &mut notes, &mut secrets)?;
+        let mut running = self.running.lock().unwrap_or_else(|p| p.into_inner());
+        // a restart-only value must be valid too, else the next restart fails
+        let mut unknown = ConfigCore::new(&fresh)?.unknown_keys().to_vec();
+        // a removed unknown key is no setting either, it is not kept until a restart
+        unknown.extend(self.unknown_keys());
+        let mut next = fresh.clone();
+        let mut reload = Reload::default();
+        for key in core::changed(&running, &fresh) {
+            if unknown.contains(&key) {
+                reload.unknown.push(key);
+            } else if core::RELOADABLE.contains(&key.as_str()) {
+                reload.applied.push(key);
+            } else {
+                // the running pipeline is built by the old value, keep it until a restart
+                core::restore(&mut next, &running, &key);
+                reload.pending.push(key);
+            }
+        }
+        let inst = ConfigCore::new(&next)?;
+        // a key kept until a restart keeps its running value, secret or not
+        let mut was = self.secrets.write().unwrap_or_else(|p| p.into_inner());
+        secrets.retain(|k| !reload.pending.contains(k));
+        secrets.extend(was.iter().filter(|k| reload.pending.contains(k)).cloned());
+        *self.core.write().unwrap_or_else(|p| p.into_inner()) = inst;
+        *was = secrets;
+        *running = next;
+        // the borrow is let go before the broadcast takes the value
+        let count = *self.reloads.1.borrow() + 1;
+        let _ = self.reloads.0.broadcast(count);
+        Ok(reload)
+    }
+
+    /// Извещения о примененных перезагрузках конфигурации: значение - их количество с запуска
+    pub fn reloaded(&self) -> watch::Receiver<u64> {
+        self.reloads.1.clone()
+    }
+
+    /// Сообщения, накопленные при построении конфигурации до инициализации логирования
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    /// Заданные в конфигурации неизвестные ключи `<section>.<key>`, например опечатки. Они игнорируются
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let c = self.core();
+        c.unknown_keys().to_vec()
+    }
+
+    /// Задано ли значение настройки `<section>.<key>` косвенно, через `_file` или `_env`.
+    /// Такое значение считается секретом и не выводится в лог
+    pub fn is_secret(&self, key: &str) -> bool {
+        self.secrets.read().unwrap_or_else(|p| p.into_inner()).iter().any(|s| s == key)
+    }
+
+    /// Файл модели, которую нужно проверить вместо запуска конвейера, `--validate-model <path>`
+    pub fn validate_model(&self) -> Option<String> {
+        self.validate_model.clone()
+    }
+
+    /// Каталог результатов, которые нужно повторно передать в приемники `[output]` вместо запуска конвейера,
+    /// `--replay-deadletter <dir>`
+    pub fn replay_deadletter(&self) -> Option<String> {
+        self.replay_deadletter.clone()
+    }
+
     /// Список точек подключения к копиям системы сопряжения для получения входных данных
     pub fn peers(&self) -> Vec<Endpoint> {
-        let c = self.core.read().unwrap();
+        let c = self.core();
         c.peers().clone()
     }
 
+    /// Предельное время корректного завершения работы после получения сигнала остановки,
+    /// по его истечении приложение завершается принудительно
+    pub fn shutdown_timeout(&self) -> Duration {
+        let c = self.core();
+        c.shutdown_timeout()
+    }
+
+    /// Количество потоков исполнения асинхронных задач, 0 - по количеству процессоров
+    pub fn worker_threads(&self) -> usize {
+        let c = self.core();
+        c.worker_threads()
+    }
+
+    /// Наибольшее количество потоков для блокирующих и вычислительно емких операций
+    pub fn blocking_threads(&self) -> usize {
+        let c = self.core();
+        c.blocking_threads()
+    }
+
+    /// Наибольшее случайное отклонение интервалов периодических таймеров (проверка таймаутов сеансов,
+    /// сброс файлов на диск) от их периода, в процентах. 0 - без разброса
+    pub fn timer_jitter(&self) -> u32 {
+        let c = self.core();
+        c.timer_jitter()
+    }
+
+    /// Маска прав создаваемых файлов процесса (unix), None - наследуется при запуске
+    pub fn umask(&self) -> Option<u32> {
+        let c = self.core();
+        c.umask()
+    }
+
+    /// Наибольший объем данных, удерживаемых конвейером от collector до inference, байтов,
+    /// при его достижении input приостанавливает прием фрагментов, 0 - не ограничен
+    pub fn memory_budget_bytes(&self) -> u64 {
+        let c = self.core();
+        c.memory_budget_bytes()
+    }
+
+    /// Уровни логирования отдельных модулей поверх общих уровней логирования в консоль и в файлы
+    pub fn log_targets(&self) -> Vec<LogDirective> {
+        let c = self.core();
+        c.log_targets().clone()
+    }
+
+    /// Наибольшее количество одновременно устанавливаемых подключений к системам сопряжения, 0 - без ограничения
+    pub fn max_concurrent_connects(&self) -> usize {
+        let c = self.core();
+        c.max_concurrent_connects()
+    }
+
+    /// Предельное время установления TCP-подключения к системе сопряжения
+    pub fn connect_timeout(&self) -> Duration {
+        let c = self.core();
+        c.connect_timeout()
+    }
+
+    /// Предельное время обмена приветствиями V-протокола после установления подключения
+    pub fn handshake_timeout(&self) -> Duration {
+        let c = self.core();
+        c.handshake_timeout()
+    }
+
+    /// Наибольшее время получения кадра с его первого байта и ожидания очередного кадра, по истечении которого
+    /// соединение разрывается. Последующие чтения начатого кадра срока не продлевают, так что соединение разрывается,
+    /// даже если система сопряжения понемногу передает данные. 0 - без ограничения
+    pub fn read_timeout(&self) -> Duration {
+        let c = self.core();
+        c.read_timeout()
+    }
+
+    /// Наибольшее время жизни установленного соединения, по истечении которого оно закрывается
+    /// и сразу устанавливается заново. 0 - без ограничения
+    pub fn max_connection_lifetime(&self) -> Duration {
+        let c = self.core();
+        c.max_connection_lifetime()
+    }
+
+    /// Поведение при заполненном канале передачи фрагментов в коллектор: ожидание, отбрасывание фрагмента
+    /// или разрыв соединения, если место не освобождается дольше `full_timeout`
+    pub fn input_on_full(&self) -> InputFull {
+        let c = self.core();
+        c.input_on_full()
+    }
+
+    /// Наибольшее время ожидания места в заполненном канале передачи фрагментов в коллектор
+    /// перед разрывом соединения при `on_full = disconnect`
+    pub fn full_timeout(&self) -> Duration {
+        let c = self.core();
+        c.full_timeout()
+    }
+
+    /// Пауза перед повторным подключением после неудачи или разрыва соединения
+    pub fn reconnect_delay(&self) -> Duration {
+        let c = self.core();
+        c.reconnect_delay()
+    }
+
+    /// Наибольшая случайная задержка первого подключения к каждой системе сопряжения после запуска,
+    /// чтобы подключения ко всем системам не начинались одновременно. 0 - без задержки
+    pub fn connect_stagger(&self) -> Duration {
+        let c = self.core();
+        c.connect_stagger()
+    }
+
+    /// Наибольшее количество неудачных попыток подключения подряд, после которого подключения к системе сопряжения
+    /// прекращаются, 0 - без ограничения
+    pub fn max_reconnects(&self) -> u32 {
+        let c = self.core();
+        c.max_reconnects()
+    }
+
+    /// Наибольшая частота повторных подключений ко всем системам сопряжения вместе, в секунду, 0 - без ограничения
+    pub fn reconnect_rate(&self) -> f64 {
+        let c = self.core();
+        c.reconnect_rate()
+    }
+
+    /// Количество повторных подключений, допустимых подряд без ожидания при ограниченной частоте
+    pub fn reconnect_burst(&self) -> u32 {
+        let c = self.core();
+        c.reconnect_burst()
+    }
+
+    /// Порядок байтов поля длины данных в заголовке кадра V-протокола
+    pub fn vproto_endian(&self) -> ByteOrder {
+        let c = self.core();
+        c.vproto_endian()
+    }
+
+    /// Сжатие потока V-протокола для систем сопряжения, для которых оно не задано в `peers`
+    pub fn compression(&self) -> Compression {
+        let c = self.core();
+        c.compression()
+    }
+
+    /// Строгость проверки кадров систем сопряжения, для которых она не задана в `peers`
+    pub fn validation(&self) -> Validation {
+        let c = self.core();
+        c.validation()
+    }
+
+    /// Размер буфера приема сокета подключения к системам сопряжения, для которых он не задан в `peers`,
+    /// байтов, 0 - по умолчанию ядра
+    pub fn so_rcvbuf(&self) -> usize {
+        let c = self.core();
+        c.so_rcvbuf()
+    }
+
+    /// Размер буфера передачи сокета подключения к системам сопряжения, для которых он не задан в `peers`,
+    /// байтов, 0 - по умолчанию ядра
+    pub fn so_sndbuf(&self) -> usize {
+        let c = self.core();
+        c.so_sndbuf()
+    }
+
+    /// Наименьший объем свободного места в буфере приема кадров перед очередным чтением из соединения, байтов
+    pub fn read_chunk_size(&self) -> usize {
+        let c = self.core();
+        c.read_chunk_size()
+    }
+
+    /// Время без новых фрагментов, по истечении которого незавершенный сеанс передается на обработку в собранном виде
+    pub fn collector_session_timeout(&self) -> Duration {
+        let c = self.core();
+        c.collector_session_timeout()
+    }
+
+    /// Способ заполнения пропущенных фрагментов при сборке сеанса
+    pub fn gap_fill(&self) -> GapFill {
+        let c = self.core();
+        c.gap_fill()
+    }
+
+    /// Поле заголовка фрагмента, по которому фрагменты упорядочиваются при сборке сеанса
+    pub fn order_by(&self) -> OrderBy {
+        let c = self.core();
+        c.order_by()
+    }
+
+    /// Наибольшая длительность части, на которые разбивается длинный сеанс перед обработкой, мс, 0 - не разбивать
+    pub fn chunk_ms(&self) -> u32 {
+        let c = self.core();
+        c.chunk_ms()
+    }
+
+    /// Длительность перекрытия соседних частей сеанса для сохранения контекста, мс
+    pub fn chunk_overlap_ms(&self) -> u32 {
+        let c = self.core();
+        c.chunk_overlap_ms()
+    }
+
+    /// Количество готовых сеансов, передаваемых в процессор одной посылкой, 1 - передавать по одному
+    pub fn collector_batch(&self) -> usize {
+        let c = self.core();
+        c.collector_batch()
+    }
+
+    /// Наибольшее время ожидания накопления посылки сеансов после появления первого из них
+    pub fn collector_batch_timeout(&self) -> Duration {
+        let c = self.core();
+        c.collector_batch_timeout()
+    }
+
+    /// Срок обработки собранного сеанса от момента его передачи в процессор, по истечении которого
+    /// сеанс не обрабатывается и отбрасывается, 0 - не ограничен
+    pub fn deadline(&self) -> Duration {
+        let c = self.core();
+        c.deadline()
+    }
+
+    /// Наибольший суммарный объем фрагментов всех незавершенных сеансов в коллекторе, байтов,
+    /// при превышении старейшие сеансы передаются на обработку незавершенными, 0 - не ограничен
+    pub fn max_buffer_bytes(&self) -> usize {
+        let c = self.core();
+        c.max_buffer_bytes()
+    }
+
+    /// Период после передачи сеанса на обработку, в течение которого его фрагменты отбрасываются как опоздавшие,
+    /// 0 - не отбрасываются
+    pub fn late_window(&self) -> Duration {
+        let c = self.core();
+        c.late_window()
+    }
+
+    /// Поведение для фрагмента, параметры звука которого отличаются от параметров первого фрагмента сеанса
+    pub fn on_mismatch(&self) -> Mismatch {
+        let c = self.core();
+        c.on_mismatch()
+    }
+
+    /// Значение фрагмента со звуком без данных, кроме признака окончания сеанса
+    pub fn on_empty(&self) -> EmptyFragment {
+        let c = self.core();
+        c.on_empty()
+    }
+
+    /// Поведение для незавершенных сеансов системы сопряжения, соединение с которой разорвано
+    pub fn on_disconnect(&self) -> Disconnect {
+        let c = self.core();
+        c.on_disconnect()
+    }
+
+    /// Определение границ сеанса: по идентификатору абонента или по паузам в его непрерывном потоке
+    pub fn session_key(&self) -> SessionKey {
+        let c = self.core();
+        c.session_key()
+    }
+
+    /// Пауза в звуке, заканчивающая высказывание в режиме `session_key = silence_gap`
+    pub fn silence_gap(&self) -> Duration {
+        let c = self.core();
+        c.silence_gap()
+    }
+
+    /// Пиковый уровень звука относительно полной шкалы, ниже которого фрагмент считается паузой
+    pub fn silence_level(&self) -> f32 {
+        let c = self.core();
+        c.silence_level()
+    }
+
+    /// Поведение коллектора для фрагментов без звука (кадров V-протокола прочих типов)
+    pub fn non_audio(&self) -> NonAudio {
+        let c = self.core();
+        c.non_audio()
+    }
+
+    /// Каталог сохранения фрагментов без звука для `non_audio = store`
+    pub fn non_audio_dir(&self) -> String {
+        let c = self.core();
+        c.non_audio_dir().to_string()
+    }
+
+    /// Упорядоченный список имен этапов обработки сессий в процессоре
+    pub fn pipeline(&self) -> Vec<String> {
+        let c = self.core();
+        c.pipeline().clone()
+    }
+
+    /// Частота дискретизации звука в поступающих на обработку сессиях, Гц
+    pub fn sample_rate(&self) -> u32 {
+        let c = self.core();
+        c.sample_rate()
+    }
+
+    /// Формат отсчетов звука систем сопряжения, для которых он не задан в `[input] peers`
+    pub fn sample_format(&self) -> SampleFormat {
+        let c = self.core();
+        c.sample_format()
+    }
+
+    /// Порядок байтов отсчетов звука систем сопряжения, для которых он не задан в `[input] peers`
+    pub fn sample_endian(&self) -> ByteOrder {
+        let c = self.core();
+        c.sample_endian()
+    }
+
+    /// Частота дискретизации, к которой приводится звук на этапе `resample`, Гц
+    pub fn target_rate(&self) -> u32 {
+        let c = self.core();
+        c.target_rate()
+    }
+
+    /// Целевой пиковый уровень сигнала на этапе `normalize`, в диапазоне (0, 1]
+    pub fn normalize_peak(&self) -> f32 {
+        let c = self.core();
+        c.normalize_peak()
+    }
+
+    /// Коэффициент фильтра на этапе `preemphasis`, в диапазоне [0, 1), 0 - сигнал не изменяется
+    pub fn preemphasis(&self) -> f32 {
+        let c = self.core();
+        c.preemphasis()
+    }
+
+    /// Порог уровня сигнала, ниже которого начальная и конечная тишина отбрасывается на этапе `trim`
+    pub fn trim_threshold(&self) -> f32 {
+        let c = self.core();
+        c.trim_threshold()
+    }
+
+    /// Длительность окна анализа на этапе `features`, мс
+    pub fn frame_ms(&self) -> u32 {
+        let c = self.core();
+        c.frame_ms()
+    }
+
+    /// Шаг окна анализа на этапе `features`, мс
+    pub fn hop_ms(&self) -> u32 {
+        let c = self.core();
+        c.hop_ms()
+    }
+
+    /// Количество частотных полос в векторе признаков на этапе `features`
+    pub fn feature_bands(&self) -> usize {
+        let c = self.core();
+        c.feature_bands()
+    }
+
+    /// Количество результатов обработки последних различных сеансов, сохраняемых для повторяющихся сеансов,
+    /// 0 - без сохранения
+    pub fn feature_cache(&self) -> usize {
+        let c = self.core();
+        c.feature_cache()
+    }
+
+    /// Сигнал, получаемый этапом `deinterleave` из каналов многоканального сеанса: среднее или один из каналов
+    pub fn channel(&self) -> ChannelSelect {
+        let c = self.core();
+        c.channel()
+    }
+
+    /// Поведение процессора для сеанса, данные которого не согласуются с объявленными параметрами звука
+    pub fn on_malformed(&self) -> Malformed {
+        let c = self.core();
+        c.on_malformed()
+    }
+
+    /// Имя вычислителя результата в подсистеме inference: `mock` или `tensorrt`
+    pub fn inference_backend(&self) -> String {
+        let c = self.core();
+        c.inference_backend().to_string()
+    }
+
+    /// Путь к файлу модели вычислителя результата
+    pub fn model(&self) -> String {
+        let c = self.core();
+        c.model().to_string()
+    }
+
+    /// Имя построителя сохраняемого результата по выходу модели: `raw` или `argmax`
+    pub fn result_builder(&self) -> String {
+        let c = self.core();
+        c.result_builder().to_string()
+    }
+
+    /// Поведение при недоступности вычислителя результата при запуске
+    pub fn on_unavailable(&self) -> Unavailable {
+        let c = self.core();
+        c.on_unavailable()
+    }
+
+    /// Количество последних рассчитанных сэмплов, для повторов которых результат не рассчитывается заново, 0 - не проверять повторы
+    pub fn dedup_window(&self) -> usize {
+        let c = self.core();
+        c.dedup_window()
+    }
+
+    /// Наименьшая допустимая уверенность результата, т.е. наибольшее из его значений, 0 - не проверять
+    pub fn min_confidence(&self) -> f32 {
+        let c = self.core();
+        c.min_confidence()
+    }
+
+    /// Поведение для результата с уверенностью ниже `min_confidence`
+    pub fn below_threshold(&self) -> BelowThreshold {
+        let c = self.core();
+        c.below_threshold()
+    }
+
+    /// Допустимые длины входа вычислителя в векторах по возрастанию, пусто - длина входа произвольная
+    pub fn input_buckets(&self) -> Vec<usize> {
+        let c = self.core();
+        c.input_buckets().clone()
+    }
+
+    /// Поведение для сэмпла длиннее наибольшей из `input_buckets`
+    pub fn oversize(&self) -> Oversize {
+        let c = self.core();
+        c.oversize()
+    }
+
+    /// Частота дискретизации входа модели вычислителя, Гц, 0 - не проверяется
+    pub fn model_rate(&self) -> u32 {
+        let c = self.core();
+        c.model_rate()
+    }
+
+    /// Поведение для сэмпла с частотой дискретизации, отличной от `model_rate`
+    pub fn on_rate_mismatch(&self) -> RateMismatch {
+        let c = self.core();
+        c.on_rate_mismatch()
+    }
+
+    /// Поведение при заполненном канале передачи результатов в output
+    pub fn on_full(&self) -> FullPolicy {
+        let c = self.core();
+        c.on_full()
+    }
+
+    /// Поведение при разрушении канала передачи результатов в output
+    pub fn on_output_lost(&self) -> OutputLost {
+        let c = self.core();
+        c.on_output_lost()
+    }
+
+    /// Каталог дисковой очереди результатов для `on_full = spill` и `on_output_lost = spill`
+    pub fn spill_dir(&self) -> String {
+        let c = self.core();
+        c.spill_dir().to_string()
+    }
+
+    /// Наибольшее количество сэмплов в пакете расчета
+    pub fn batch_size(&self) -> usize {
+        let c = self.core();
+        c.batch_size()
+    }
+
+    /// Наибольшее время ожидания заполнения пакета расчета после получения первого сэмпла
+    pub fn batch_timeout(&self) -> Duration {
+        let c = self.core();
+        c.batch_timeout()
+    }
+
+    /// Длительность прогрева после запуска, в течение которого используются параметры пакетов прогрева
+    pub fn warmup_period(&self) -> Duration {
+        let c = self.core();
+        c.warmup_period()
+    }
+
+    /// Наибольшее количество сэмплов в пакете расчета в течение прогрева
+    pub fn warmup_batch_size(&self) -> usize {
+        let c = self.core();
+        c.warmup_batch_size()
+    }
+
+    /// Наибольшее время ожидания заполнения пакета расчета в течение прогрева
+    pub fn warmup_batch_timeout(&self) -> Duration {
+        let c = self.core();
+        c.warmup_batch_timeout()
+    }
+
+    /// Рассчитывается ли пакет по истечении `batch_timeout` в любом случае или ожидает минимального заполнения
+    pub fn batch_priority(&self) -> BatchPriority {
+        let c = self.core();
+        c.batch_priority()
+    }
+
+    /// Доля размера пакета, до которой пакет ожидает сэмплы после `batch_timeout` при `batch_priority = efficiency`
+    pub fn min_batch_fill(&self) -> f32 {
+        let c = self.core();
+        c.min_batch_fill()
+    }
+
+    /// Предельное время ожидания заполнения пакета после получения первого сэмпла при `batch_priority = efficiency`
+    pub fn batch_max_wait(&self) -> Duration {
+        let c = self.core();
+        c.batch_max_wait()
+    }
+
+    /// Ядра CPU, на которых рассчитываются пакеты, пустой список - без привязки.
+    /// Действует в сборке с feature `affinity` под Linux
+    pub fn cpu_affinity(&self) -> Vec<usize> {
+        let c = self.core();
+        c.cpu_affinity().clone()
+    }
+
+    /// Подбирается ли размер пакета расчета по глубине очереди сэмплов, начиная с `batch_size`
+    pub fn adaptive_batch(&self) -> bool {
+        let c = self.core();
+        c.adaptive_batch()
+    }
+
+    /// Наименьший размер пакета расчета при подборе
+    pub fn min_batch_size(&self) -> usize {
+        let c = self.core();
+        c.min_batch_size()
+    }
+
+    /// Наибольший размер пакета расчета при подборе
+    pub fn max_batch_size(&self) -> usize {
+        let c = self.core();
+        c.max_batch_size()
+    }
+
+    /// Наибольшее количество сэмплов в работе от передачи processor до окончания расчета, 0 - не ограничено
+    pub fn max_in_flight(&self) -> usize {
+        let c = self.core();
+        c.max_in_flight()
+    }
+
+    /// Имя теневого вычислителя, рассчитывающего результаты для сравнения с основным без их сохранения,
+    /// пустое - сравнение отключено
+    pub fn shadow_backend(&self) -> String {
+        let c = self.core();
+        c.shadow_backend().to_string()
+    }
+
+    /// Путь к файлу модели теневого вычислителя
+    pub fn shadow_model(&self) -> String {
+        let c = self.core();
+        c.shadow_model().to_string()
+    }
+
+    /// Наибольшее расхождение значений результатов основного и теневого вычислителей, при котором они совпадают
+    pub fn shadow_tolerance(&self) -> f32 {
+        let c = self.core();
+        c.shadow_tolerance()
+    }
+
+    /// Количество сэмплов, ожидающих расчета, свыше которого теневой расчет пропускается, 0 - не пропускается
+    pub fn shadow_max_backlog(&self) -> u64 {
+        let c = self.core();
+        c.shadow_max_backlog()
+    }
+
+    /// Наибольшее время расчета пакета вычислителем, 0 - не ограничено
+    pub fn infer_timeout(&self) -> Duration {
+        let c = self.core();
+        c.infer_timeout()
+    }
+
+    /// Количество таймаутов расчета подряд, после которого вычислитель пересоздается, 0 - не пересоздается
+    pub fn reset_after_timeouts(&self) -> u32 {
+        let c = self.core();
+        c.reset_after_timeouts()
+    }
+
+    /// Количество пересозданий вычислителя подряд без успешного расчета, после которого приложение
+    /// считается неработоспособным, 0 - не считается
+    pub fn unhealthy_after_resets(&self) -> u32 {
+        let c = self.core();
+        c.unhealthy_after_resets()
+    }
+
+    /// Передавать ли в output промежуточные результаты потокового вычислителя перед окончательными
+    pub fn partial_results(&self) -> bool {
+        let c = self.core();
+        c.partial_results()
+    }
+
+    /// Каталог, в который подсистема output сохраняет результаты
+    pub fn output_dir(&self) -> String {
+        let c = self.core();
+        c.output_dir().to_string()
+    }
+
+    /// Резервный каталог результатов, используемый, пока основной недоступен, пустой - не используется
+    pub fn fallback_dir(&self) -> String {
+        let c = self.core();
+        c.fallback_dir().to_string()
+    }
+
+    /// Приемники результатов, каждый со своим способом раскладки результатов по файлам.
+    /// Каждый результат записывается во все приемники
+    pub fn output_modes(&self) -> Vec<OutputMode> {
+        let c = self.core();
+        c.output_modes().clone()
+    }
+
+    /// Маршруты результатов в отдельные приемники по признакам результата, по порядку: результат сохраняет
+    /// приемник первого подходящего маршрута, а не подошедший ни одному - приемники `output_modes`
+    pub fn output_routes(&self) -> Vec<Route> {
+        let c = self.core();
+        c.output_routes().clone()
+    }
+
+    /// Итог записи результата в несколько приемников при неудаче в части из них
+    pub fn fanout(&self) -> FanoutPolicy {
+        let c = self.core();
+        c.fanout()
+    }
+
+    /// Количество повторов неудачной записи результата в приемник при нескольких приемниках
+    pub fn write_retries(&self) -> u32 {
+        let c = self.core();
+        c.write_retries()
+    }
+
+    /// Пауза перед первым повтором неудачной записи результата в приемник, каждый следующий повтор ждет вдвое дольше
+    pub fn write_retry_delay(&self) -> Duration {
+        let c = self.core();
+        c.write_retry_delay()
+    }
+
+    /// Время без новых результатов, по истечении которого файл сеанса закрывается в режиме `session`
+    pub fn session_timeout(&self) -> Duration {
+        let c = self.core();
+        c.session_timeout()
+    }
+
+    /// Путь именованного канала (FIFO) для режима `fifo`, создается при отсутствии
+    pub fn fifo(&self) -> String {
+        let c = self.core();
+        c.fifo().to_string()
+    }
+
+    /// Поведение режима `fifo`, когда читатель канала не подключен
+    pub fn fifo_no_reader(&self) -> NoReader {
+        let c = self.core();
+        c.fifo_no_reader()
+    }
+
+    /// Наибольшее время ожидания читателя канала при `fifo_no_reader = block`
+    pub fn fifo_wait(&self) -> Duration {
+        let c = self.core();
+        c.fifo_wait()
+    }
+
+    /// Адрес внешней системы, принимающей результаты режима `http`
+    pub fn ack_url(&self) -> String {
+        let c = self.core();
+        c.ack_url().to_string()
+    }
+
+    /// Наибольшее время ожидания подтверждения получения результата внешней системой
+    pub fn ack_timeout(&self) -> Duration {
+        let c = self.core();
+        c.ack_timeout()
+    }
+
+    /// Количество повторов передачи результата без подтверждения получения, 0 - до подтверждения
+    pub fn ack_retries(&self) -> u32 {
+        let c = self.core();
+        c.ack_retries()
+    }
+
+    /// Пауза перед повтором передачи результата без подтверждения получения
+    pub fn ack_retry_delay(&self) -> Duration {
+        let c = self.core();
+        c.ack_retry_delay()
+    }
+
+    /// Каталог результатов, которые не удалось сохранить, для повторной передачи `--replay-deadletter`,
+    /// пустой - такие результаты теряются
+    pub fn deadletter_dir(&self) -> String {
+        let c = self.core();
+        c.deadletter_dir().to_string()
+    }
+
+    /// Период сброса буферизованных данных открытых файлов результатов, 0 - только при закрытии файлов
+    pub fn flush_interval(&self) -> Duration {
+        let c = self.core();
+        c.flush_interval()
+    }
+
+    /// Периодический сброс также дожидается записи данных на носитель (fsync)
+    pub fn flush_sync(&self) -> bool {
+        let c = self.core();
+        c.flush_sync()
+    }
+
+    /// Наименьшее свободное место в каталогах хранения, байт, при котором принимаются результаты, 0 - без проверки
+    pub fn min_free_bytes(&self) -> u64 {
+        let c = self.core();
+        c.min_free_bytes()
+    }
+
+    /// Наибольший размер файла результата, байт, больший результат сохраняется частями, 0 - без ограничения
+    pub fn max_result_bytes(&self) -> usize {
+        let c = self.core();
+        c.max_result_bytes()
+    }
+
+    /// Путь файла отметки начала работы, пусто - не записывается
+    pub fn run_start_marker(&self) -> String {
+        let c = self.core();
+        c.run_start_marker().to_string()
+    }
+
+    /// Путь файла отметки окончания работы с ее итогами, пусто - не записывается
+    pub fn run_end_marker(&self) -> String {
+        let c = self.core();
+        c.run_end_marker().to_string()
+    }
+
+    /// Наибольший промежуток между проверками свободного места в каталогах хранения
+    pub fn space_check_interval(&self) -> Duration {
+        let c = self.core();
+        c.space_check_interval()
+    }
+
+    /// Имя файла журнала сохраненных файлов в каталоге результатов, пустая строка отключает журнал
+    pub fn manifest(&self) -> String {
+        let c = self.core();
+        c.manifest().to_string()
+    }
+
+    /// Имена сведений о сеансе, записываемых в журнал сохраненных файлов, пусто - не записываются
+    pub fn manifest_metadata(&self) -> Vec<String> {
+        let c = self.core();
+        c.manifest_metadata().clone()
+    }
+
+    /// Алгоритм контрольной суммы сохраняемых файлов
+    pub fn hash(&self) -> HashAlg {
+        let c = self.core();
+        c.hash()
+    }
+
+    /// Сохранять ли контрольную сумму рядом с файлом в `<file>.<hash>` в формате sha256sum
+    pub fn hash_sidecar(&self) -> bool {
+        let c = self.core();
+        c.hash_sidecar()
+    }
+
+    /// Права создаваемых файлов результатов, журнала и контрольных сумм (unix), None - по маске прав процесса
+    pub fn output_file_mode(&self) -> Option<u32> {
+        let c = self.core();
+        c.file_mode()
+    }
+
+    /// Источник времени для имен сохраняемых файлов и журнала сохраненных файлов
+    pub fn timestamp_source(&self) -> TimestampSource {
+        let c = self.core();
+        c.timestamp_source()
+    }
+
+    /// Точность меток времени в именах сохраняемых файлов, журнале сохраненных файлов и журнале событий
+    pub fn timestamp_precision(&self) -> TimestampPrecision {
+        let c = self.core();
+        c.timestamp_precision()
+    }
+
+    /// Адрес прослушивания служебного HTTP-сервера, пустая строка отключает сервер
+    pub fn http_listen(&self) -> String {
+        let c = self.core();
+        c.http_listen().to_string()
+    }
+
+    /// Выдача сводок сохраняемых результатов клиентам WebSocket на `/live` служебного HTTP-сервера
+    pub fn http_live(&self) -> bool {
+        let c = self.core();
+        c.http_live()
+    }
+
+    /// Количество сводок, которые клиент `/live` может не успеть получить, прежде чем старейшие будут пропущены
+    pub fn live_queue(&self) -> usize {
+        let c = self.core();
+        c.live_queue().max(1)
+    }
+
+    /// Снимается ли профиль загрузки CPU по запросу `/debug/pprof/profile`
+    pub fn http_profile(&self) -> bool {
+        let c = self.core();
+        c.http_profile()
+    }
+
+    /// Длительность снятия профиля загрузки CPU, если в запросе она не задана
+    pub fn http_profile_duration(&self) -> Duration {
+        let c = self.core();
+        c.http_profile_duration()
+    }
+
+    /// Принимаются ли пробные сеансы `POST /inject` для проверки конвейера без звука от систем сопряжения
+    pub fn enable_inject(&self) -> bool {
+        let c = self.core();
+        c.enable_inject()
+    }
+
+    /// Адрес приемника трасс OpenTelemetry по OTLP/HTTP, например `http://127.0.0.1:4318`, пустая строка отключает трассировку
+    pub fn trace_endpoint(&self) -> String {
+        let c = self.core();
+        c.trace_endpoint().to_string()
+    }
+
+    /// Адрес управляющего сокета: `<addr>:<port>` для TCP, иначе путь к Unix-сокету, пустая строка отключает сокет
+    pub fn control_listen(&self) -> String {
+        let c = self.core();
+        c.control_listen().to_string()
+    }
+
+    /// Диапазоны адресов, из которых принимаются входящие подключения, пустой список - из любых
+    pub fn access_allow(&self) -> Vec<Cidr> {
+        let c = self.core();
+        c.access_allow().clone()
+    }
+
+    /// Диапазоны адресов, входящие подключения из которых отклоняются
+    pub fn access_deny(&self) -> Vec<Cidr> {
+        let c = self.core();
+        c.access_deny().clone()
+    }
+
+    /// Количество ошибок обработки сеансов системы сопряжения подряд, после которого ее сеансы помещаются в карантин,
+    /// 0 - карантин отключен
+    pub fn quarantine_failures(&self) -> u32 {
+        let c = self.core();
+        c.quarantine_failures()
+    }
+
+    /// Каталог для сохранения сеансов, помещенных в карантин
+    pub fn quarantine_dir(&self) -> String {
+        let c = self.core();
+        c.quarantine_dir().to_string()
+    }
+
+    /// Файл журнала решений конвейера в формате JSON lines, пустой - журнал не ведется
+    pub fn events_path(&self) -> String {
+        let c = self.core();
+        c.events_path().to_string()
+    }
+
+    /// Файл блокировки, захвативший которую экземпляр становится ведущим, пустой - выбор ведущего не выполняется
+    pub fn leader_lock(&self) -> String {
+        let c = self.core();
+        c.leader_lock().to_string()
+    }
+
+    /// Период попыток резервного экземпляра захватить блокировку ведущего
+    pub fn leader_retry(&self) -> Duration {
+        let c = self.core();
+        c.leader_retry()
+    }
+
     /// Заданный в настройках уровень детализации логирования в консоль
     pub fn log_lvl_console(&self) -> LevelFilter {
         // todo: obtain value from config file
@@ -60,16 +1083,179 @@ impl Config{
     }
 }
 
+// collects the values from all the sources by increasing priority
+fn build(pathnames: &[String], sets: &[String], notes: &mut Vec<String>, secrets: &mut Vec<String>) -> Result<Ini, String> {
+    // init from files, the later ones override the earlier
+    // each source resolves its indirect values itself, so a higher one overrides them as any other value
+    let mut ini = Ini::new();
+    for pathname in pathnames {
+        let mut file = core::load(pathname, notes)?;
+        core::resolve_indirect(&mut file, notes, secrets)?;
+        core::merge(&mut ini, file);
+    }
+    // override values by environment
+    let mut env = Ini::new();
+    core::apply_env(&mut env, std::env::vars());
+    core::resolve_indirect(&mut env, notes, secrets)?;
+    core::merge(&mut ini, env);
+    // override values by args
+    let mut args = Ini::new();
+    for s in sets {
+        core::apply_arg(&mut args, s)?;
+    }
+    core::resolve_indirect(&mut args, notes, secrets)?;
+    core::merge(&mut ini, args);
+    Ok(ini)
+}
+
 // command line
 fn init_args() -> ArgMatches<'static> {
     App::new("banshee")
-        .version("0.1")
+        .version(env!("CARGO_PKG_VERSION"))
         .about("sound stream processor")
         .arg(Arg::with_name("config")
             .short("c")
             .long("config")
             .default_value("banshee.ini")
-            .help("pathname to configuration file")
+            .help("pathname to configuration file, defaults are used if the file is absent; \
+                   may be repeated, the values of a later file override the earlier ones")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("validate-model")
+            .long("validate-model")
+            .value_name("PATH")
+            .help("loads the model by the configured inference backend, prints its inputs and outputs, \
+                   runs a self-test and exits without connecting to peers; exit code 1 - the model is unusable")
             .takes_value(true))
+        .arg(Arg::with_name("replay-deadletter")
+            .long("replay-deadletter")
+            .value_name("DIR")
+            .help("re-submits the results dead-lettered to DIR to the configured output sinks in order, \
+                   removing each one once stored, and exits without connecting to peers; \
+                   exit code 1 - some results are left in DIR")
+            .takes_value(true)
+            .conflicts_with("validate-model"))
+        .arg(Arg::with_name("set")
+            .short("s")
+            .long("set")
+            .value_name("SECTION.KEY=VALUE")
+            .help("overrides configuration value, may be repeated")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
         .get_matches()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a config file of the test named `name`, removed by the caller
+    fn file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("banshee-config-{}-{}.ini", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_file() {
+        let path = file("override", "[general]\nshutdown_timeout = 5s\n[processor]\nsample_rate = 16000\n");
+        let cfg = Config::from_sources(&[&path], &["general.shutdown_timeout=7s"]).unwrap();
+        assert_eq!(cfg.shutdown_timeout(), Duration::from_secs(7));
+        assert_eq!(cfg.sample_rate(), 16000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn runs_without_a_file() {
+        let cfg = Config::from_sources(&["/nonexistent/banshee.ini"], &[]).unwrap();
+        assert_eq!(cfg.shutdown_timeout(), Duration::from_secs(10));
+        assert_eq!(cfg.notes().len(), 1);
+        assert!(Config::from_sources(&[], &["processor.sample_rate=fast"]).is_err());
+    }
+
+    #[test]
+    fn a_poisoned_lock_keeps_the_settings() {
+        let cfg = Config::from_sources(&[], &["general.shutdown_timeout=3s"]).unwrap();
+        let poisoner = cfg.clone();
+        let panicked = std::thread::spawn(move || {
+            let _w = poisoner.core.write().unwrap();
+            panic!("update failed");
+        }).join();
+        assert!(panicked.is_err());
+        assert!(cfg.core.is_poisoned());
+        assert_eq!(cfg.shutdown_timeout(), Duration::from_secs(3));
+        assert!(!cfg.core.is_poisoned());
+    }
+
+    #[test]
+    fn the_later_file_overrides_the_earlier() {
+        let base = file("base", "[general]\nshutdown_timeout = 5s\n[processor]\nsample_rate = 16000\n");
+        let site = file("site", "[general]\nshutdown_timeout = 7s\n");
+        let cfg = Config::from_sources(&[&base, &site], &[]).unwrap();
+        assert_eq!(cfg.shutdown_timeout(), Duration::from_secs(7));
+        assert_eq!(cfg.sample_rate(), 16000);
+        let cfg = Config::from_sources(&[&site, &base], &[]).unwrap();
+        assert_eq!(cfg.shutdown_timeout(), Duration::from_secs(5));
+        std::fs::remove_file(&base).unwrap();
+        std::fs::remove_file(&site).unwrap();
+    }
+
+    #[test]
+    fn the_indirect_value_is_resolved_per_source() {
+        let value = file("dir", "/srv/results\n");
+        let path = file("indirect", &format!("[output]\ndir_file = {}\n", value));
+        assert_eq!(Config::from_sources(&[&path], &[]).unwrap().output_dir(), "/srv/results");
+        // a later source sets the key directly over the indirect one
+        assert_eq!(Config::from_sources(&[&path], &["output.dir=/tmp/results"]).unwrap().output_dir(), "/tmp/results");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&value).unwrap();
+    }
+
+    #[test]
+    fn the_shipped_config_has_no_unknown_keys() {
+        let cfg = Config::from_sources(&[concat!(env!("CARGO_MANIFEST_DIR"), "/banshee.ini")], &[]).unwrap();
+        assert_eq!(cfg.unknown_keys(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn the_reload_applies_the_live_keys_only() {
+        let path = file("reload", "[general]\nshutdown_timeout = 5s\n[inference]\nbatch_size = 4\n");
+        let cfg = Config::from_sources(&[&path], &[]).unwrap();
+        let rx = cfg.reloaded();
+        std::fs::write(&path, "[general]\nshutdown_timeout = 7s\n[inference]\nbatch_size = 8\nbatch_sise = 8\n").unwrap();
+        let r = cfg.reload().unwrap();
+        assert_eq!((r.applied, r.pending, r.unknown), (vec!["general.shutdown_timeout".to_string()],
+            vec!["inference.batch_size".to_string()], vec!["inference.batch_sise".to_string()]));
+        assert_eq!((cfg.shutdown_timeout(), cfg.batch_size()), (Duration::from_secs(7), 4));
+        assert_eq!(*rx.borrow(), 1);
+        // the broken config changes nothing
+        std::fs::write(&path, "[general]\nshutdown_timeout = soon\n").unwrap();
+        assert!(cfg.reload().is_err());
+        assert_eq!(cfg.shutdown_timeout(), Duration::from_secs(7));
+        // the pending key is still pending against the running value
+        std::fs::write(&path, "[general]\nshutdown_timeout = 7s\n[inference]\nbatch_size = 8\n").unwrap();
+        assert_eq!(cfg.reload().unwrap().pending, vec!["inference.batch_size".to_string()]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_reload_tells_the_secrets_anew() {
+        let secret = file("secret-value", "3s");
+        let path = file("secret", "[input]\nread_timeout = 2s\n");
+        let cfg = Config::from_sources(&[&path], &[]).unwrap();
+        assert!(!cfg.is_secret("input.read_timeout"));
+        std::fs::write(&path, format!("[input]\nread_timeout_file = {}\n", secret)).unwrap();
+        cfg.reload().unwrap();
+        assert_eq!(cfg.read_timeout(), Duration::from_secs(3));
+        assert!(cfg.is_secret("input.read_timeout"));
+        // the restart-only key keeps its running value, it isn't a secret until then
+        std::fs::write(&path, format!("[inference]\nmodel_file = {}\n", secret)).unwrap();
+        assert_eq!(cfg.reload().unwrap().pending, vec!["inference.model".to_string()]);
+        assert!(!cfg.is_secret("inference.model"));
+        assert!(!cfg.is_secret("input.read_timeout"));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&secret).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::{Display, Formatter, Result};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Диапазон IP-адресов в нотации CIDR
+#[derive(Clone, Copy, Debug)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8
+}
+
+impl Cidr {
+
+    /// Проверяет принадлежность адреса диапазону. IPv4-адрес, отображенный в IPv6, сравнивается как IPv4
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            _ => *ip
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            },
+            _ => false
+        }
+    }
+}
+
+impl Display for Cidr {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Разбирает диапазон в виде `<addr>/<prefix>`, адрес без длины префикса задает единственный адрес
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (addr, prefix) = match s.find('/') {
+            Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+            None => (s, None)
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("invalid address in '{}'", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max,
+            Some(p) => p.parse().ok().filter(|p| *p <= max).ok_or_else(|| format!("invalid prefix length in '{}'", s))?
+        };
+        Ok(Cidr {
+            addr,
+            prefix
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn contains_the_range() {
+        let c: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(c.contains(&ip("10.1.200.3")));
+        assert!(!c.contains(&ip("10.2.0.1")));
+        assert!(c.contains(&ip("::ffff:10.1.0.1")));
+        assert!(!c.contains(&ip("fe80::1")));
+        let c: Cidr = "fd00::/8".parse().unwrap();
+        assert!(c.contains(&ip("fd12::1")));
+        assert!(!c.contains(&ip("10.1.0.1")));
+    }
+
+    #[test]
+    fn a_bare_address_and_the_zero_prefix() {
+        let c: Cidr = "192.168.1.5".parse().unwrap();
+        assert_eq!(c.to_string(), "192.168.1.5/32");
+        assert!(c.contains(&ip("192.168.1.5")));
+        assert!(!c.contains(&ip("192.168.1.6")));
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn rejects_the_invalid_range() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+}
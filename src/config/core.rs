@@ -2,22 +2,1494 @@
 // config the only module that is init prior to logger, so the logger won't work here
 //use log::info;
 
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io::ErrorKind;
+use std::str::FromStr;
+use std::time::Duration;
+
+use ini::Ini;
+
+use crate::data::METADATA_KEYS;
+use crate::config::cidr::Cidr;
+use crate::config::directive::LogDirective;
 use crate::config::endpoint::Endpoint;
+use crate::config::route::Route;
+use crate::config::options::{BatchPriority, BelowThreshold, ByteOrder, ChannelSelect, Compression, Disconnect, EmptyFragment, FanoutPolicy, FullPolicy, GapFill, HashAlg, InputFull, Malformed, Mismatch, NoReader, NonAudio, OrderBy, OutputLost, OutputMode, Oversize, RateMismatch, SampleFormat, SessionKey, TimestampPrecision, TimestampSource, Unavailable, Validation};
+
+/// Префикс имен переменных окружения, переопределяющих настройки
+const ENV_PREFIX: &str = "BANSHEE_";
+/// Окончания имен ключей, значения которых берутся из файла и из переменной окружения
+const FILE_SUFFIX: &str = "_file";
+const ENV_SUFFIX: &str = "_env";
+
+/// Ключи `<section>.<key>`, изменения которых применяются при перечитывании конфигурации без перезапуска:
+/// их значения читаются заново при каждом использовании. Остальные ключи вступают в силу только после перезапуска
+pub const RELOADABLE: &[&str] = &[
+    "general.log_targets",
+    "general.shutdown_timeout",
+    "input.reconnect_delay",
+    "input.max_reconnects",
+    "input.connect_timeout",
+    "input.handshake_timeout",
+    "input.read_timeout",
+    "input.read_chunk_size",
+    "collector.session_timeout"
+];
+
+/// Порядок этапов обработки сессии в процессоре по-умолчанию
+const DEFAULT_PIPELINE: &[&str] = &["convert", "resample", "normalize", "trim", "features"];
 
 pub struct ConfigCore {
-    peers: Vec<Endpoint>
+    peers: Vec<Endpoint>,
+    // [general]
+    shutdown_timeout: Duration,
+    worker_threads: usize,
+    blocking_threads: usize,
+    timer_jitter: u32,
+    umask: Option<u32>,
+    memory_budget_bytes: u64,
+    log_targets: Vec<LogDirective>,
+    // [input]
+    max_concurrent_connects: usize,
+    connect_timeout: Duration,
+    handshake_timeout: Duration,
+    read_timeout: Duration,
+    max_connection_lifetime: Duration,
+    input_on_full: InputFull,
+    full_timeout: Duration,
+    reconnect_delay: Duration,
+    connect_stagger: Duration,
+    vproto_endian: ByteOrder,
+    compression: Compression,
+    validation: Validation,
+    so_rcvbuf: usize,
+    so_sndbuf: usize,
+    read_chunk_size: usize,
+    max_reconnects: u32,
+    reconnect_rate: f64,
+    reconnect_burst: u32,
+    // [collector]
+    collector_session_timeout: Duration,
+    gap_fill: GapFill,
+    order_by: OrderBy,
+    chunk_ms: u32,
+    chunk_overlap_ms: u32,
+    collector_batch: usize,
+    collector_batch_timeout: Duration,
+    deadline: Duration,
+    max_buffer_bytes: usize,
+    late_window: Duration,
+    on_mismatch: Mismatch,
+    on_empty: EmptyFragment,
+    on_disconnect: Disconnect,
+    session_key: SessionKey,
+    silence_gap: Duration,
+    silence_level: f32,
+    non_audio: NonAudio,
+    non_audio_dir: String,
+    // [processor]
+    pipeline: Vec<String>,
+    sample_rate: u32,
+    sample_format: SampleFormat,
+    sample_endian: ByteOrder,
+    target_rate: u32,
+    normalize_peak: f32,
+    preemphasis: f32,
+    trim_threshold: f32,
+    frame_ms: u32,
+    hop_ms: u32,
+    feature_bands: usize,
+    feature_cache: usize,
+    channel: ChannelSelect,
+    on_malformed: Malformed,
+    // [inference]
+    inference_backend: String,
+    result_builder: String,
+    model: String,
+    on_unavailable: Unavailable,
+    dedup_window: usize,
+    min_confidence: f32,
+    below_threshold: BelowThreshold,
+    input_buckets: Vec<usize>,
+    oversize: Oversize,
+    model_rate: u32,
+    on_rate_mismatch: RateMismatch,
+    on_full: FullPolicy,
+    on_output_lost: OutputLost,
+    spill_dir: String,
+    batch_size: usize,
+    batch_timeout: Duration,
+    warmup_period: Duration,
+    warmup_batch_size: usize,
+    warmup_batch_timeout: Duration,
+    batch_priority: BatchPriority,
+    min_batch_fill: f32,
+    batch_max_wait: Duration,
+    cpu_affinity: Vec<usize>,
+    adaptive_batch: bool,
+    min_batch_size: usize,
+    max_batch_size: usize,
+    max_in_flight: usize,
+    shadow_backend: String,
+    shadow_model: String,
+    shadow_tolerance: f32,
+    shadow_max_backlog: u64,
+    infer_timeout: Duration,
+    reset_after_timeouts: u32,
+    unhealthy_after_resets: u32,
+    partial_results: bool,
+    // [output]
+    output_dir: String,
+    fallback_dir: String,
+    output_modes: Vec<OutputMode>,
+    output_routes: Vec<Route>,
+    fanout: FanoutPolicy,
+    write_retries: u32,
+    write_retry_delay: Duration,
+    session_timeout: Duration,
+    fifo: String,
+    fifo_no_reader: NoReader,
+    fifo_wait: Duration,
+    ack_url: String,
+    ack_timeout: Duration,
+    ack_retries: u32,
+    ack_retry_delay: Duration,
+    deadletter_dir: String,
+    flush_interval: Duration,
+    flush_sync: bool,
+    min_free_bytes: u64,
+    max_result_bytes: usize,
+    run_start_marker: String,
+    run_end_marker: String,
+    space_check_interval: Duration,
+    manifest: String,
+    manifest_metadata: Vec<String>,
+    hash: HashAlg,
+    hash_sidecar: bool,
+    file_mode: Option<u32>,
+    timestamp_source: TimestampSource,
+    timestamp_precision: TimestampPrecision,
+    // [http]
+    http_listen: String,
+    http_live: bool,
+    live_queue: usize,
+    http_profile: bool,
+    http_profile_duration: Duration,
+    enable_inject: bool,
+    // [trace]
+    trace_endpoint: String,
+    // [control]
+    control_listen: String,
+    // [access]
+    access_allow: Vec<Cidr>,
+    access_deny: Vec<Cidr>,
+    // [quarantine]
+    quarantine_failures: u32,
+    quarantine_dir: String,
+    // [events]
+    events_path: String,
+    // [leader]
+    leader_lock: String,
+    leader_retry: Duration,
+    /// Заданные, но неизвестные ключи `<section>.<key>`
+    unknown_keys: Vec<String>
 }
 
 impl ConfigCore {
 
-    pub fn new(_pathname: &str) -> ConfigCore {
-        let p = vec![ Endpoint::new("127.0.0.1", 12000) ];
-        ConfigCore {
-            peers: p
+    /// Строит конфигурацию из собранного набора значений, отсутствующие значения заменяются значениями по-умолчанию
+    /// Ключи, заданные в наборе, но не прочитанные ни одной настройкой, считаются неизвестными, например опечатками.
+    /// Они игнорируются с предупреждением, а при `[general] config_strict = true` построение завершается ошибкой
+    pub fn new(ini: &Ini) -> Result<ConfigCore, String> {
+        let ini = &Source::new(ini);
+        let mut p = Vec::new();
+        for peer in list(ini, "input", "peers", &["127.0.0.1:12000"]) {
+            p.push(peer.parse::<Endpoint>().map_err(|e| format!("invalid input.peers: {}", e))?);
+        }
+        let mut t = Vec::new();
+        for directive in list(ini, "general", "log_targets", &[]) {
+            t.push(directive.parse::<LogDirective>().map_err(|e| format!("invalid general.log_targets: {}", e))?);
+        }
+        let mut m = Vec::new();
+        for mode in list(ini, "output", "mode", &["file"]) {
+            m.push(mode.parse::<OutputMode>().map_err(|e| format!("invalid output.mode: {}", e))?);
+        }
+        if m.is_empty() {
+            return Err("output.mode must list at least one sink".to_string());
+        }
+        let mut r = Vec::new();
+        for route in list(ini, "output", "routes", &[]) {
+            r.push(route.parse::<Route>().map_err(|e| format!("invalid output.routes: {}", e))?);
+        }
+        let mut b = Vec::new();
+        for bucket in list(ini, "inference", "input_buckets", &[]) {
+            match bucket.parse::<usize>() {
+                Ok(n) if n > 0 => b.push(n),
+                _ => return Err(format!("invalid inference.input_buckets: '{}' is not a positive number of frames", bucket))
+            }
+        }
+        b.sort_unstable();
+        b.dedup();
+        let manifest_metadata = list(ini, "output", "metadata", &[]);
+        if let Some(k) = manifest_metadata.iter().find(|k| !METADATA_KEYS.contains(&k.as_str())) {
+            return Err(format!("invalid output.metadata: unknown '{}', known are {}", k, METADATA_KEYS.join(", ")));
+        }
+        let chunk_ms = value(ini, "collector", "chunk_ms", 0)?;
+        let chunk_overlap_ms = value(ini, "collector", "chunk_overlap_ms", 0)?;
+        if chunk_ms > 0 && chunk_overlap_ms >= chunk_ms {
+            return Err("collector.chunk_overlap_ms must be less than collector.chunk_ms".to_string());
+        }
+        let timer_jitter = value(ini, "general", "timer_jitter", 0)?;
+        if timer_jitter >= 100 {
+            return Err("general.timer_jitter must be less than 100".to_string());
+        }
+        let full_timeout = duration(ini, "input", "full_timeout", Duration::from_secs(10))?;
+        if full_timeout == Duration::from_secs(0) {
+            return Err("input.full_timeout must be positive".to_string());
+        }
+        let ack_url: String = value(ini, "output", "ack_url", String::new())?;
+        if m.contains(&OutputMode::Http) && ack_url.is_empty() {
+            return Err("output.ack_url must be set for output.mode http".to_string());
+        }
+        // the url of an http route is its path
+        let mut urls: Vec<&str> = r.iter().filter(|route| route.mode() == OutputMode::Http).map(|route| route.path()).collect();
+        if m.contains(&OutputMode::Http) {
+            urls.push(&ack_url);
+        }
+        if let Some(url) = urls.iter().find(|u| !u.starts_with("http://")) {
+            return Err(format!("invalid ack url {}: only http:// is supported", url));
+        }
+        let ack_timeout = duration(ini, "output", "ack_timeout", Duration::from_secs(5))?;
+        if !urls.is_empty() && ack_timeout == Duration::from_secs(0) {
+            return Err("output.ack_timeout must be positive".to_string());
+        }
+        let fifo_wait = duration(ini, "output", "fifo_wait", Duration::from_secs(10))?;
+        if fifo_wait == Duration::from_secs(0) {
+            return Err("output.fifo_wait must be positive".to_string());
+        }
+        let reconnect_rate: f64 = value(ini, "input", "reconnect_rate", 0.0)?;
+        if !(reconnect_rate >= 0.0 && reconnect_rate.is_finite()) {
+            return Err("input.reconnect_rate must not be negative".to_string());
+        }
+        let reconnect_burst = value(ini, "input", "reconnect_burst", 1)?;
+        if reconnect_burst == 0 {
+            return Err("input.reconnect_burst must be positive".to_string());
+        }
+        let batch_timeout = duration(ini, "inference", "batch_timeout", Duration::from_millis(10))?;
+        let min_batch_fill: f32 = value(ini, "inference", "min_batch_fill", 0.5)?;
+        if !(min_batch_fill > 0.0 && min_batch_fill <= 1.0) {
+            return Err("inference.min_batch_fill must be within (0, 1]".to_string());
+        }
+        let batch_priority = value(ini, "inference", "batch_priority", BatchPriority::Latency)?;
+        let batch_max_wait = duration(ini, "inference", "batch_max_wait", Duration::from_millis(100))?;
+        if batch_priority == BatchPriority::Efficiency && batch_max_wait < batch_timeout {
+            return Err(format!("inference.batch_max_wait must be at least batch_timeout {:?}", batch_timeout));
+        }
+        let min_batch_size = value(ini, "inference", "min_batch_size", 1)?;
+        let max_batch_size = value(ini, "inference", "max_batch_size", 64)?;
+        if min_batch_size == 0 || min_batch_size > max_batch_size {
+            return Err(format!("inference.min_batch_size must be positive and at most max_batch_size {}", max_batch_size));
+        }
+        let silence_gap = duration(ini, "collector", "silence_gap", Duration::from_millis(500))?;
+        if silence_gap == Duration::from_secs(0) {
+            return Err("collector.silence_gap must be positive".to_string());
+        }
+        let silence_level: f32 = value(ini, "collector", "silence_level", 0.01)?;
+        if !(0.0..=1.0).contains(&silence_level) {
+            return Err("collector.silence_level must be within [0, 1]".to_string());
         }
+        let preemphasis: f32 = value(ini, "processor", "preemphasis", 0.97)?;
+        if !(0.0..1.0).contains(&preemphasis) {
+            return Err("processor.preemphasis must be within [0, 1)".to_string());
+        }
+        let mut core = ConfigCore {
+            peers: p,
+            shutdown_timeout: duration(ini, "general", "shutdown_timeout", Duration::from_secs(10))?,
+            worker_threads: value(ini, "general", "worker_threads", 0)?,
+            blocking_threads: value(ini, "general", "blocking_threads", 16)?,
+            timer_jitter,
+            umask: mode(ini, "general", "umask")?,
+            memory_budget_bytes: value(ini, "general", "memory_budget_bytes", 0)?,
+            log_targets: t,
+            max_concurrent_connects: value(ini, "input", "max_concurrent_connects", 16)?,
+            connect_timeout: duration(ini, "input", "connect_timeout", Duration::from_secs(5))?,
+            handshake_timeout: duration(ini, "input", "handshake_timeout", Duration::from_secs(5))?,
+            read_timeout: duration(ini, "input", "read_timeout", Duration::from_secs(0))?,
+            max_connection_lifetime: duration(ini, "input", "max_connection_lifetime", Duration::from_secs(0))?,
+            input_on_full: value(ini, "input", "on_full", InputFull::Block)?,
+            full_timeout,
+            reconnect_delay: duration(ini, "input", "reconnect_delay", Duration::from_secs(1))?,
+            connect_stagger: duration(ini, "input", "connect_stagger", Duration::from_secs(0))?,
+            max_reconnects: value(ini, "input", "max_reconnects", 0)?,
+            reconnect_rate,
+            reconnect_burst,
+            vproto_endian: value(ini, "input", "vproto_endian", ByteOrder::Little)?,
+            compression: value(ini, "input", "compression", Compression::None)?,
+            validation: value(ini, "input", "validation", Validation::Lenient)?,
+            so_rcvbuf: value(ini, "input", "so_rcvbuf", 0)?,
+            so_sndbuf: value(ini, "input", "so_sndbuf", 0)?,
+            read_chunk_size: value(ini, "input", "read_chunk_size", 16 * 1024)?,
+            collector_session_timeout: duration(ini, "collector", "session_timeout", Duration::from_secs(5))?,
+            gap_fill: value(ini, "collector", "gap_fill", GapFill::Drop)?,
+            order_by: value(ini, "collector", "order_by", OrderBy::Sequence)?,
+            chunk_ms,
+            chunk_overlap_ms,
+            collector_batch: value(ini, "collector", "batch", 1)?,
+            collector_batch_timeout: duration(ini, "collector", "batch_timeout", Duration::from_millis(10))?,
+            deadline: duration(ini, "collector", "deadline", Duration::from_secs(0))?,
+            max_buffer_bytes: value(ini, "collector", "max_buffer_bytes", 0)?,
+            late_window: duration(ini, "collector", "late_window", Duration::from_secs(10))?,
+            on_mismatch: value(ini, "collector", "on_mismatch", Mismatch::Drop)?,
+            on_empty: value(ini, "collector", "on_empty", EmptyFragment::Keepalive)?,
+            on_disconnect: value(ini, "collector", "on_disconnect", Disconnect::Keep)?,
+            session_key: value(ini, "collector", "session_key", SessionKey::Id)?,
+            silence_gap,
+            silence_level,
+            non_audio: value(ini, "collector", "non_audio", NonAudio::Drop)?,
+            non_audio_dir: value(ini, "collector", "non_audio_dir", "non_audio".to_string())?,
+            pipeline: list(ini, "processor", "pipeline", DEFAULT_PIPELINE),
+            sample_rate: value(ini, "processor", "sample_rate", 8000)?,
+            sample_format: value(ini, "processor", "sample_format", SampleFormat::S16)?,
+            sample_endian: value(ini, "processor", "sample_endian", ByteOrder::Little)?,
+            target_rate: value(ini, "processor", "target_rate", 16000)?,
+            normalize_peak: value(ini, "processor", "normalize_peak", 0.95)?,
+            preemphasis,
+            trim_threshold: value(ini, "processor", "trim_threshold", 0.01)?,
+            frame_ms: value(ini, "processor", "frame_ms", 25)?,
+            hop_ms: value(ini, "processor", "hop_ms", 10)?,
+            feature_bands: value(ini, "processor", "feature_bands", 40)?,
+            feature_cache: value(ini, "processor", "feature_cache", 0)?,
+            channel: value(ini, "processor", "channel", ChannelSelect::Mix)?,
+            on_malformed: value(ini, "processor", "on_malformed", Malformed::Skip)?,
+            inference_backend: value(ini, "inference", "backend", "mock".to_string())?,
+            result_builder: value(ini, "inference", "result_builder", "raw".to_string())?,
+            model: value(ini, "inference", "model", String::new())?,
+            on_unavailable: value(ini, "inference", "on_unavailable", Unavailable::Fail)?,
+            dedup_window: value(ini, "inference", "dedup_window", 0)?,
+            min_confidence: value(ini, "inference", "min_confidence", 0.0)?,
+            below_threshold: value(ini, "inference", "below_threshold", BelowThreshold::Keep)?,
+            input_buckets: b,
+            oversize: value(ini, "inference", "oversize", Oversize::Split)?,
+            model_rate: value(ini, "inference", "model_rate", 0)?,
+            on_rate_mismatch: value(ini, "inference", "on_rate_mismatch", RateMismatch::Reject)?,
+            on_full: value(ini, "inference", "on_full", FullPolicy::Block)?,
+            on_output_lost: value(ini, "inference", "on_output_lost", OutputLost::Stop)?,
+            spill_dir: value(ini, "inference", "spill_dir", "spill".to_string())?,
+            batch_size: value(ini, "inference", "batch_size", 1)?,
+            batch_timeout,
+            warmup_period: duration(ini, "inference", "warmup_period", Duration::from_secs(0))?,
+            warmup_batch_size: value(ini, "inference", "warmup_batch_size", 1)?,
+            warmup_batch_timeout: duration(ini, "inference", "warmup_batch_timeout", Duration::from_millis(0))?,
+            batch_priority,
+            min_batch_fill,
+            batch_max_wait,
+            cpu_affinity: cores(ini, "inference", "cpu_affinity")?,
+            adaptive_batch: value(ini, "inference", "adaptive_batch", false)?,
+            min_batch_size,
+            max_batch_size,
+            max_in_flight: value(ini, "inference", "max_in_flight", 0)?,
+            shadow_backend: value(ini, "inference", "shadow_backend", String::new())?,
+            shadow_model: value(ini, "inference", "shadow_model", String::new())?,
+            shadow_tolerance: value(ini, "inference", "shadow_tolerance", 0.0)?,
+            shadow_max_backlog: value(ini, "inference", "shadow_max_backlog", 0)?,
+            infer_timeout: duration(ini, "inference", "infer_timeout", Duration::from_secs(0))?,
+            reset_after_timeouts: value(ini, "inference", "reset_after_timeouts", 3)?,
+            unhealthy_after_resets: value(ini, "inference", "unhealthy_after_resets", 3)?,
+            partial_results: value(ini, "inference", "partial_results", false)?,
+            output_dir: value(ini, "output", "dir", "output".to_string())?,
+            fallback_dir: value(ini, "output", "fallback_dir", String::new())?,
+            output_modes: m,
+            output_routes: r,
+            fanout: value(ini, "output", "fanout", FanoutPolicy::BestEffort)?,
+            write_retries: value(ini, "output", "write_retries", 0)?,
+            write_retry_delay: duration(ini, "output", "write_retry_delay", Duration::from_millis(100))?,
+            session_timeout: duration(ini, "output", "session_timeout", Duration::from_secs(30))?,
+            fifo: value(ini, "output", "fifo", "banshee.fifo".to_string())?,
+            fifo_no_reader: value(ini, "output", "fifo_no_reader", NoReader::Block)?,
+            fifo_wait,
+            ack_url,
+            ack_timeout,
+            ack_retries: value(ini, "output", "ack_retries", 10)?,
+            ack_retry_delay: duration(ini, "output", "ack_retry_delay", Duration::from_secs(1))?,
+            deadletter_dir: value(ini, "output", "deadletter_dir", String::new())?,
+            flush_interval: duration(ini, "output", "flush_interval", Duration::from_secs(1))?,
+            flush_sync: value(ini, "output", "flush_sync", false)?,
+            min_free_bytes: value(ini, "output", "min_free_bytes", 0)?,
+            max_result_bytes: value(ini, "output", "max_result_bytes", 0)?,
+            run_start_marker: value(ini, "output", "run_start_marker", String::new())?,
+            run_end_marker: value(ini, "output", "run_end_marker", String::new())?,
+            space_check_interval: duration(ini, "output", "space_check_interval", Duration::from_secs(5))?,
+            manifest: value(ini, "output", "manifest", "manifest.jsonl".to_string())?,
+            manifest_metadata,
+            hash: value(ini, "output", "hash", HashAlg::Sha256)?,
+            hash_sidecar: value(ini, "output", "hash_sidecar", false)?,
+            file_mode: mode(ini, "output", "file_mode")?,
+            timestamp_source: value(ini, "output", "timestamp_source", TimestampSource::Local)?,
+            timestamp_precision: value(ini, "output", "timestamp_precision", TimestampPrecision::Ms)?,
+            http_listen: value(ini, "http", "listen", "127.0.0.1:8080".to_string())?,
+            http_live: value(ini, "http", "live", false)?,
+            live_queue: value(ini, "http", "live_queue", 256)?,
+            http_profile: value(ini, "http", "profile", false)?,
+            http_profile_duration: duration(ini, "http", "profile_duration", Duration::from_secs(10))?,
+            enable_inject: value(ini, "http", "enable_inject", false)?,
+            trace_endpoint: value(ini, "trace", "endpoint", String::new())?,
+            control_listen: value(ini, "control", "listen", String::new())?,
+            access_allow: cidrs(ini, "access", "allow")?,
+            access_deny: cidrs(ini, "access", "deny")?,
+            quarantine_failures: value(ini, "quarantine", "failures", 0)?,
+            quarantine_dir: value(ini, "quarantine", "dir", "quarantine".to_string())?,
+            events_path: value(ini, "events", "path", String::new())?,
+            leader_lock: value(ini, "leader", "lock", String::new())?,
+            leader_retry: duration(ini, "leader", "retry", Duration::from_secs(1))?,
+            unknown_keys: Vec::new()
+        };
+        let strict = value(ini, "general", "config_strict", false)?;
+        // every known key is read by now, whatever is left is unknown
+        core.unknown_keys = ini.unread();
+        if strict && !core.unknown_keys.is_empty() {
+            return Err(format!("unknown config keys: {}", core.unknown_keys.join(", ")));
+        }
+        Ok(core)
     }
 
     pub fn peers(&self) -> &Vec<Endpoint> {
         &self.peers
     }
-}
\ No newline at end of file
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    pub fn worker_threads(&self) -> usize {
+        self.worker_threads
+    }
+
+    pub fn blocking_threads(&self) -> usize {
+        self.blocking_threads
+    }
+
+    pub fn timer_jitter(&self) -> u32 {
+        self.timer_jitter
+    }
+
+    pub fn umask(&self) -> Option<u32> {
+        self.umask
+    }
+
+    pub fn memory_budget_bytes(&self) -> u64 {
+        self.memory_budget_bytes
+    }
+
+    pub fn log_targets(&self) -> &Vec<LogDirective> {
+        &self.log_targets
+    }
+
+    pub fn max_concurrent_connects(&self) -> usize {
+        self.max_concurrent_connects
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    pub fn max_connection_lifetime(&self) -> Duration {
+        self.max_connection_lifetime
+    }
+
+    pub fn input_on_full(&self) -> InputFull {
+        self.input_on_full
+    }
+
+    pub fn full_timeout(&self) -> Duration {
+        self.full_timeout
+    }
+
+    pub fn reconnect_delay(&self) -> Duration {
+        self.reconnect_delay
+    }
+
+    pub fn connect_stagger(&self) -> Duration {
+        self.connect_stagger
+    }
+
+    pub fn max_reconnects(&self) -> u32 {
+        self.max_reconnects
+    }
+
+    pub fn reconnect_rate(&self) -> f64 {
+        self.reconnect_rate
+    }
+
+    pub fn reconnect_burst(&self) -> u32 {
+        self.reconnect_burst
+    }
+
+    pub fn vproto_endian(&self) -> ByteOrder {
+        self.vproto_endian
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    pub fn so_rcvbuf(&self) -> usize {
+        self.so_rcvbuf
+    }
+
+    pub fn so_sndbuf(&self) -> usize {
+        self.so_sndbuf
+    }
+
+    pub fn read_chunk_size(&self) -> usize {
+        self.read_chunk_size
+    }
+
+    pub fn collector_session_timeout(&self) -> Duration {
+        self.collector_session_timeout
+    }
+
+    pub fn gap_fill(&self) -> GapFill {
+        self.gap_fill
+    }
+
+    pub fn order_by(&self) -> OrderBy {
+        self.order_by
+    }
+
+    pub fn chunk_ms(&self) -> u32 {
+        self.chunk_ms
+    }
+
+    pub fn chunk_overlap_ms(&self) -> u32 {
+        self.chunk_overlap_ms
+    }
+
+    pub fn collector_batch(&self) -> usize {
+        self.collector_batch
+    }
+
+    pub fn collector_batch_timeout(&self) -> Duration {
+        self.collector_batch_timeout
+    }
+
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    pub fn max_buffer_bytes(&self) -> usize {
+        self.max_buffer_bytes
+    }
+
+    pub fn late_window(&self) -> Duration {
+        self.late_window
+    }
+
+    pub fn on_mismatch(&self) -> Mismatch {
+        self.on_mismatch
+    }
+
+    pub fn on_empty(&self) -> EmptyFragment {
+        self.on_empty
+    }
+
+    pub fn on_disconnect(&self) -> Disconnect {
+        self.on_disconnect
+    }
+
+    pub fn session_key(&self) -> SessionKey {
+        self.session_key
+    }
+
+    pub fn silence_gap(&self) -> Duration {
+        self.silence_gap
+    }
+
+    pub fn silence_level(&self) -> f32 {
+        self.silence_level
+    }
+
+    pub fn non_audio(&self) -> NonAudio {
+        self.non_audio
+    }
+
+    pub fn non_audio_dir(&self) -> &str {
+        &self.non_audio_dir
+    }
+
+    pub fn pipeline(&self) -> &Vec<String> {
+        &self.pipeline
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    pub fn sample_endian(&self) -> ByteOrder {
+        self.sample_endian
+    }
+
+    pub fn target_rate(&self) -> u32 {
+        self.target_rate
+    }
+
+    pub fn normalize_peak(&self) -> f32 {
+        self.normalize_peak
+    }
+
+    pub fn preemphasis(&self) -> f32 {
+        self.preemphasis
+    }
+
+    pub fn trim_threshold(&self) -> f32 {
+        self.trim_threshold
+    }
+
+    pub fn frame_ms(&self) -> u32 {
+        self.frame_ms
+    }
+
+    pub fn hop_ms(&self) -> u32 {
+        self.hop_ms
+    }
+
+    pub fn feature_bands(&self) -> usize {
+        self.feature_bands
+    }
+
+    pub fn feature_cache(&self) -> usize {
+        self.feature_cache
+    }
+
+    pub fn channel(&self) -> ChannelSelect {
+        self.channel
+    }
+
+    pub fn on_malformed(&self) -> Malformed {
+        self.on_malformed
+    }
+
+    pub fn inference_backend(&self) -> &str {
+        &self.inference_backend
+    }
+
+    pub fn result_builder(&self) -> &str {
+        &self.result_builder
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn on_unavailable(&self) -> Unavailable {
+        self.on_unavailable
+    }
+
+    pub fn dedup_window(&self) -> usize {
+        self.dedup_window
+    }
+
+    pub fn min_confidence(&self) -> f32 {
+        self.min_confidence
+    }
+
+    pub fn below_threshold(&self) -> BelowThreshold {
+        self.below_threshold
+    }
+
+    pub fn input_buckets(&self) -> &Vec<usize> {
+        &self.input_buckets
+    }
+
+    pub fn oversize(&self) -> Oversize {
+        self.oversize
+    }
+
+    pub fn model_rate(&self) -> u32 {
+        self.model_rate
+    }
+
+    pub fn on_rate_mismatch(&self) -> RateMismatch {
+        self.on_rate_mismatch
+    }
+
+    pub fn on_full(&self) -> FullPolicy {
+        self.on_full
+    }
+
+    pub fn on_output_lost(&self) -> OutputLost {
+        self.on_output_lost
+    }
+
+    pub fn spill_dir(&self) -> &str {
+        &self.spill_dir
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn batch_timeout(&self) -> Duration {
+        self.batch_timeout
+    }
+
+    pub fn warmup_period(&self) -> Duration {
+        self.warmup_period
+    }
+
+    pub fn warmup_batch_size(&self) -> usize {
+        self.warmup_batch_size
+    }
+
+    pub fn warmup_batch_timeout(&self) -> Duration {
+        self.warmup_batch_timeout
+    }
+
+    pub fn batch_priority(&self) -> BatchPriority {
+        self.batch_priority
+    }
+
+    pub fn min_batch_fill(&self) -> f32 {
+        self.min_batch_fill
+    }
+
+    pub fn batch_max_wait(&self) -> Duration {
+        self.batch_max_wait
+    }
+
+    pub fn cpu_affinity(&self) -> &Vec<usize> {
+        &self.cpu_affinity
+    }
+
+    pub fn adaptive_batch(&self) -> bool {
+        self.adaptive_batch
+    }
+
+    pub fn min_batch_size(&self) -> usize {
+        self.min_batch_size
+    }
+
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    pub fn shadow_backend(&self) -> &str {
+        &self.shadow_backend
+    }
+
+    pub fn shadow_model(&self) -> &str {
+        &self.shadow_model
+    }
+
+    pub fn shadow_tolerance(&self) -> f32 {
+        self.shadow_tolerance
+    }
+
+    pub fn shadow_max_backlog(&self) -> u64 {
+        self.shadow_max_backlog
+    }
+
+    pub fn infer_timeout(&self) -> Duration {
+        self.infer_timeout
+    }
+
+    pub fn reset_after_timeouts(&self) -> u32 {
+        self.reset_after_timeouts
+    }
+
+    pub fn unhealthy_after_resets(&self) -> u32 {
+        self.unhealthy_after_resets
+    }
+
+    pub fn partial_results(&self) -> bool {
+        self.partial_results
+    }
+
+    pub fn output_dir(&self) -> &str {
+        &self.output_dir
+    }
+
+    pub fn fallback_dir(&self) -> &str {
+        &self.fallback_dir
+    }
+
+    pub fn output_modes(&self) -> &Vec<OutputMode> {
+        &self.output_modes
+    }
+
+    pub fn output_routes(&self) -> &Vec<Route> {
+        &self.output_routes
+    }
+
+    pub fn fanout(&self) -> FanoutPolicy {
+        self.fanout
+    }
+
+    pub fn write_retries(&self) -> u32 {
+        self.write_retries
+    }
+
+    pub fn write_retry_delay(&self) -> Duration {
+        self.write_retry_delay
+    }
+
+    pub fn session_timeout(&self) -> Duration {
+        self.session_timeout
+    }
+
+    pub fn fifo(&self) -> &str {
+        &self.fifo
+    }
+
+    pub fn fifo_no_reader(&self) -> NoReader {
+        self.fifo_no_reader
+    }
+
+    pub fn fifo_wait(&self) -> Duration {
+        self.fifo_wait
+    }
+
+    pub fn ack_url(&self) -> &str {
+        &self.ack_url
+    }
+
+    pub fn ack_timeout(&self) -> Duration {
+        self.ack_timeout
+    }
+
+    pub fn ack_retries(&self) -> u32 {
+        self.ack_retries
+    }
+
+    pub fn ack_retry_delay(&self) -> Duration {
+        self.ack_retry_delay
+    }
+
+    pub fn deadletter_dir(&self) -> &str {
+        &self.deadletter_dir
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    pub fn flush_sync(&self) -> bool {
+        self.flush_sync
+    }
+
+    pub fn min_free_bytes(&self) -> u64 {
+        self.min_free_bytes
+    }
+
+    pub fn max_result_bytes(&self) -> usize {
+        self.max_result_bytes
+    }
+
+    pub fn run_start_marker(&self) -> &str {
+        &self.run_start_marker
+    }
+
+    pub fn run_end_marker(&self) -> &str {
+        &self.run_end_marker
+    }
+
+    pub fn space_check_interval(&self) -> Duration {
+        self.space_check_interval
+    }
+
+    pub fn manifest(&self) -> &str {
+        &self.manifest
+    }
+
+    pub fn manifest_metadata(&self) -> &Vec<String> {
+        &self.manifest_metadata
+    }
+
+    pub fn hash(&self) -> HashAlg {
+        self.hash
+    }
+
+    pub fn hash_sidecar(&self) -> bool {
+        self.hash_sidecar
+    }
+
+    pub fn file_mode(&self) -> Option<u32> {
+        self.file_mode
+    }
+
+    pub fn timestamp_source(&self) -> TimestampSource {
+        self.timestamp_source
+    }
+
+    pub fn timestamp_precision(&self) -> TimestampPrecision {
+        self.timestamp_precision
+    }
+
+    pub fn http_listen(&self) -> &str {
+        &self.http_listen
+    }
+
+    pub fn http_live(&self) -> bool {
+        self.http_live
+    }
+
+    pub fn live_queue(&self) -> usize {
+        self.live_queue
+    }
+
+    pub fn http_profile(&self) -> bool {
+        self.http_profile
+    }
+
+    pub fn http_profile_duration(&self) -> Duration {
+        self.http_profile_duration
+    }
+
+    pub fn enable_inject(&self) -> bool {
+        self.enable_inject
+    }
+
+    pub fn trace_endpoint(&self) -> &str {
+        &self.trace_endpoint
+    }
+
+    pub fn control_listen(&self) -> &str {
+        &self.control_listen
+    }
+
+    pub fn access_allow(&self) -> &Vec<Cidr> {
+        &self.access_allow
+    }
+
+    pub fn access_deny(&self) -> &Vec<Cidr> {
+        &self.access_deny
+    }
+
+    pub fn quarantine_failures(&self) -> u32 {
+        self.quarantine_failures
+    }
+
+    pub fn quarantine_dir(&self) -> &str {
+        &self.quarantine_dir
+    }
+
+    pub fn events_path(&self) -> &str {
+        &self.events_path
+    }
+
+    pub fn leader_lock(&self) -> &str {
+        &self.leader_lock
+    }
+
+    pub fn leader_retry(&self) -> Duration {
+        self.leader_retry
+    }
+
+    pub fn unknown_keys(&self) -> &[String] {
+        &self.unknown_keys
+    }
+}
+
+/// Читает файл конфигурации. Отсутствие файла не является ошибкой: возвращается пустой набор значений
+/// и сообщение для журнала, которое записывается после инициализации логирования.
+/// Существующий, но некорректный файл является ошибкой
+pub fn load(pathname: &str, notes: &mut Vec<String>) -> Result<Ini, String> {
+    match Ini::load_from_file(pathname) {
+        Ok(ini) => Ok(ini),
+        Err(ini::ini::Error::Io(ref e)) if e.kind() == ErrorKind::NotFound => {
+            notes.push(format!("config file {} is not found, defaults, environment and command line are used", pathname));
+            Ok(Ini::new())
+        },
+        Err(e) => Err(format!("failed to read config file {}: {}", pathname, e))
+    }
+}
+
+/// Переопределяет значения `ini` значениями `over`, прочитанными из следующего по порядку файла конфигурации.
+/// Значения, заданные только в `ini`, сохраняются
+pub fn merge(ini: &mut Ini, over: Ini) {
+    for (section, props) in over.iter() {
+        for (key, v) in props.iter() {
+            ini.set_to(section, key.to_string(), v.to_string());
+        }
+    }
+}
+
+/// Переопределяет значения переменными окружения вида `BANSHEE_<SECTION>__<KEY>`,
+/// например `BANSHEE_GENERAL__SHUTDOWN_TIMEOUT=5s` задает `[general] shutdown_timeout`
+pub fn apply_env<I: Iterator<Item = (String, String)>>(ini: &mut Ini, vars: I) {
+    for (name, v) in vars {
+        if let Some(rest) = name.strip_prefix(ENV_PREFIX) {
+            if let Some(pos) = rest.find("__") {
+                let section = rest[..pos].to_lowercase();
+                let key = rest[pos + 2..].to_lowercase();
+                ini.set_to(Some(section), key, v);
+            }
+        }
+    }
+}
+
+/// Заменяет косвенные значения собранного набора: `<key>_file = <path>` задает `<key>` содержимым файла
+/// без завершающего перевода строки, `<key>_env = <NAME>` - значением переменной окружения `NAME`.
+/// Так секреты не хранятся в файлах конфигурации. Сами значения в сообщения для журнала не попадают.
+/// Применяется к каждому источнику отдельно. Ключ, заданный в одном источнике одновременно
+/// непосредственно и косвенно, является ошибкой. Заданные косвенно ключи `<section>.<key>` добавляются в `secrets`
+pub fn resolve_indirect(ini: &mut Ini, notes: &mut Vec<String>, secrets: &mut Vec<String>) -> Result<(), String> {
+    let mut resolved = Vec::new();
+    for (owner, props) in ini.iter() {
+        let section = owner.unwrap_or_default();
+        for (name, source) in props.iter() {
+            let (key, v) = if let Some(key) = name.strip_suffix(FILE_SUFFIX) {
+                let v = std::fs::read_to_string(source.trim())
+                    .map_err(|e| format!("invalid {}.{}: failed to read {}: {}", section, name, source, e))?;
+                notes.push(format!("config: {}.{} is read from file {}", section, key, source));
+                (key, v.trim_end_matches(['\r', '\n']).to_string())
+            } else if let Some(key) = name.strip_suffix(ENV_SUFFIX) {
+                let v = std::env::var(source.trim())
+                    .map_err(|e| format!("invalid {}.{}: variable {}: {}", section, name, source, e))?;
+                notes.push(format!("config: {}.{} is read from variable {}", section, key, source));
+                (key, v)
+            } else {
+                continue;
+            };
+            if props.contains_key(key) {
+                return Err(format!("{}.{} is set both directly and by {}", section, key, name));
+            }
+            secrets.push(format!("{}.{}", section, key));
+            resolved.push((owner.map(|s| s.to_string()), name.to_string(), key.to_string(), v));
+        }
+    }
+    for (owner, name, key, v) in resolved {
+        ini.delete_from(owner.as_deref(), &name);
+        ini.set_to(owner, key, v);
+    }
+    Ok(())
+}
+
+/// Переопределяет значение выражением `<section>.<key>=<value>` из командной строки
+pub fn apply_arg(ini: &mut Ini, arg: &str) -> Result<(), String> {
+    let eq = arg.find('=').ok_or_else(|| format!("expected section.key=value, got '{}'", arg))?;
+    let (name, v) = (&arg[..eq], &arg[eq + 1..]);
+    let dot = name.find('.').ok_or_else(|| format!("expected section.key=value, got '{}'", arg))?;
+    ini.set_to(Some(name[..dot].trim().to_string()), name[dot + 1..].trim().to_string(), v.trim().to_string());
+    Ok(())
+}
+
+/// Ключи `<section>.<key>`, значения которых различаются в наборах `old` и `new`, в том числе заданные только в одном из них
+pub fn changed(old: &Ini, new: &Ini) -> Vec<String> {
+    let mut keys = Vec::new();
+    for (a, b) in [(old, new), (new, old)] {
+        for (section, props) in a.iter() {
+            for (key, v) in props.iter() {
+                if b.get_from(section, key) != Some(v) {
+                    let name = match section {
+                        Some(s) if !s.is_empty() => format!("{}.{}", s, key),
+                        _ => key.to_string()
+                    };
+                    if !keys.contains(&name) {
+                        keys.push(name);
+                    }
+                }
+            }
+        }
+    }
+    keys
+}
+
+/// Возвращает ключу `name` вида `<section>.<key>` в наборе `ini` значение из набора `from`, удаляя его, если там он не задан
+pub fn restore(ini: &mut Ini, from: &Ini, name: &str) {
+    let (section, key) = match name.find('.') {
+        Some(dot) => (Some(&name[..dot]), &name[dot + 1..]),
+        None => (None, name)
+    };
+    match from.get_from(section, key) {
+        Some(v) => {
+            ini.set_to(section, key.to_string(), v.to_string());
+        },
+        None => {
+            ini.delete_from(section, key);
+        }
+    }
+}
+
+/// Собранный набор значений, запоминающий прочитанные ключи
+struct Source<'a> {
+    ini: &'a Ini,
+    read: RefCell<HashSet<(String, String)>>
+}
+
+impl<'a> Source<'a> {
+
+    fn new(ini: &'a Ini) -> Source<'a> {
+        Source {
+            ini,
+            read: RefCell::new(HashSet::new())
+        }
+    }
+
+    fn get(&self, section: &str, key: &str) -> Option<&'a str> {
+        self.read.borrow_mut().insert((section.to_string(), key.to_string()));
+        self.ini.get_from(Some(section), key)
+    }
+
+    // the keys set but never read as `<section>.<key>`, in the order of the set
+    fn unread(&self) -> Vec<String> {
+        let read = self.read.borrow();
+        let mut keys = Vec::new();
+        for (section, props) in self.ini.iter() {
+            let section = section.unwrap_or_default();
+            for (key, _) in props.iter() {
+                if !read.contains(&(section.to_string(), key.to_string())) {
+                    keys.push(if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) });
+                }
+            }
+        }
+        keys
+    }
+}
+
+// the cpu core numbers listed by single numbers and ranges `<first>-<last>`, in ascending order without repeats
+fn cores(ini: &Source, section: &str, key: &str) -> Result<Vec<usize>, String> {
+    // the cores beyond the system cpu set are not addressable
+    const MAX_CORE: usize = 1023;
+    let mut out = Vec::new();
+    for item in list(ini, section, key, &[]) {
+        let (first, last) = match item.find('-') {
+            Some(pos) => (item[..pos].trim(), item[pos + 1..].trim()),
+            None => (item.as_str(), item.as_str())
+        };
+        let first: usize = first.parse().map_err(|_| format!("invalid {}.{}: '{}' is not a core number or range", section, key, item))?;
+        let last: usize = last.parse().map_err(|_| format!("invalid {}.{}: '{}' is not a core number or range", section, key, item))?;
+        if first > last || last > MAX_CORE {
+            return Err(format!("invalid {}.{}: '{}' is out of cores 0-{}", section, key, item, MAX_CORE));
+        }
+        out.extend(first..=last);
+    }
+    out.sort_unstable();
+    out.dedup();
+    Ok(out)
+}
+
+// reads a single typed value from the section, the default is used if the key is absent
+fn value<T>(ini: &Source, section: &str, key: &str, default: T) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display
+{
+    match ini.get(section, key) {
+        None => Ok(default),
+        Some(v) => v.trim().parse().map_err(|e| format!("invalid value '{}' of {}.{}: {}", v, section, key, e))
+    }
+}
+
+// reads a comma separated list from the section, the default is used if the key is absent
+fn list(ini: &Source, section: &str, key: &str, default: &[&str]) -> Vec<String> {
+    match ini.get(section, key) {
+        None => default.iter().map(|s| s.to_string()).collect(),
+        Some(v) => v.split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect()
+    }
+}
+
+// reads a comma separated list of address ranges from the section, empty if the key is absent
+fn cidrs(ini: &Source, section: &str, key: &str) -> Result<Vec<Cidr>, String> {
+    list(ini, section, key, &[]).iter()
+        .map(|s| s.parse().map_err(|e| format!("invalid {}.{}: {}", section, key, e)))
+        .collect()
+}
+
+// reads an octal permission mode such as 0640 from the section, None if the key is absent or empty
+fn mode(ini: &Source, section: &str, key: &str) -> Result<Option<u32>, String> {
+    match ini.get(section, key).map(|v| v.trim()) {
+        None | Some("") => Ok(None),
+        Some(v) => match u32::from_str_radix(v, 8) {
+            Ok(m) if m <= 0o7777 => Ok(Some(m)),
+            _ => Err(format!("invalid mode '{}' of {}.{}: expected octal such as 0640", v, section, key))
+        }
+    }
+}
+
+// reads a duration from the section: a number with optional unit suffix ms, s, m or h, seconds by default
+fn duration(ini: &Source, section: &str, key: &str, default: Duration) -> Result<Duration, String> {
+    match ini.get(section, key) {
+        None => Ok(default),
+        Some(v) => parse_duration(v.trim()).ok_or_else(|| format!("invalid duration '{}' of {}.{}", v, section, key))
+    }
+}
+
+fn parse_duration(v: &str) -> Option<Duration> {
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let n: u64 = v[..split].parse().ok()?;
+    match v[split..].trim() {
+        "ms" => Some(Duration::from_millis(n)),
+        "" | "s" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n * 60)),
+        "h" => Some(Duration::from_secs(n * 3600)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(v: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        v.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn a_missing_file_is_empty() {
+        let mut notes = Vec::new();
+        let ini = load("/nonexistent/banshee.ini", &mut notes).unwrap();
+        assert_eq!(ini.iter().filter(|(_, p)| !p.is_empty()).count(), 0);
+        assert_eq!(notes.len(), 1);
+    }
+
+    #[test]
+    fn a_broken_file_is_an_error() {
+        let path = std::env::temp_dir().join(format!("banshee-core-broken-{}.ini", std::process::id()));
+        std::fs::write(&path, "[general\nshutdown_timeout = 5s\n").unwrap();
+        assert!(load(path.to_str().unwrap(), &mut Vec::new()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_environment_sets_the_section_key() {
+        let mut ini = Ini::new();
+        apply_env(&mut ini, vars(&[("BANSHEE_GENERAL__SHUTDOWN_TIMEOUT", "5s"), ("BANSHEE_NOSECTION", "1"), ("PATH", "/bin")]));
+        assert_eq!(ini.get_from(Some("general"), "shutdown_timeout"), Some("5s"));
+        assert_eq!(ini.iter().map(|(_, p)| p.len()).sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn the_argument_sets_the_section_key() {
+        let mut ini = Ini::new();
+        apply_arg(&mut ini, "processor.sample_rate = 16000").unwrap();
+        apply_arg(&mut ini, "http.listen=").unwrap();
+        assert_eq!(ini.get_from(Some("processor"), "sample_rate"), Some("16000"));
+        assert_eq!(ini.get_from(Some("http"), "listen"), Some(""));
+        assert!(apply_arg(&mut ini, "processor.sample_rate").is_err());
+        assert!(apply_arg(&mut ini, "sample_rate=1").is_err());
+    }
+
+    #[test]
+    fn the_defaults_and_the_invalid_values() {
+        let c = ConfigCore::new(&Ini::new()).unwrap();
+        assert_eq!(c.shutdown_timeout(), Duration::from_secs(10));
+        assert_eq!(c.sample_rate(), 8000);
+        let mut ini = Ini::new();
+        ini.set_to(Some("processor"), "sample_rate".to_string(), "fast".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("processor.sample_rate"));
+        let mut ini = Ini::new();
+        ini.set_to(Some("general"), "shutdown_timeout".to_string(), "5 days".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("general.shutdown_timeout"));
+    }
+
+    #[test]
+    fn parses_the_duration_units() {
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("5 s"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("1d"), None);
+        assert_eq!(parse_duration("ms"), None);
+    }
+
+    #[test]
+    fn the_overlap_is_shorter_than_the_chunk() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("collector"), "chunk_ms".to_string(), "1000".to_string());
+        ini.set_to(Some("collector"), "chunk_overlap_ms".to_string(), "1000".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("chunk_overlap_ms"));
+        ini.set_to(Some("collector"), "chunk_overlap_ms".to_string(), "200".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| (c.chunk_ms(), c.chunk_overlap_ms())).ok(), Some((1000, 200)));
+    }
+
+    #[test]
+    fn the_input_buckets_are_positive() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("inference"), "input_buckets".to_string(), "4, 0".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("inference.input_buckets"));
+        ini.set_to(Some("inference"), "input_buckets".to_string(), "4, 2, 4".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| c.input_buckets().clone()).ok(), Some(vec![2, 4]));
+    }
+
+    #[test]
+    fn merges_the_values_over_the_earlier() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("general"), "shutdown_timeout".to_string(), "5s".to_string());
+        ini.set_to(Some("processor"), "sample_rate".to_string(), "16000".to_string());
+        let mut over = Ini::new();
+        over.set_to(Some("general"), "shutdown_timeout".to_string(), "7s".to_string());
+        merge(&mut ini, over);
+        assert_eq!(ini.get_from(Some("general"), "shutdown_timeout"), Some("7s"));
+        assert_eq!(ini.get_from(Some("processor"), "sample_rate"), Some("16000"));
+    }
+
+    #[test]
+    fn the_preemphasis_is_below_one() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("processor"), "preemphasis".to_string(), "1".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("processor.preemphasis"));
+        ini.set_to(Some("processor"), "preemphasis".to_string(), "-0.1".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("processor.preemphasis"));
+        ini.set_to(Some("processor"), "preemphasis".to_string(), "0".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| c.preemphasis()).ok(), Some(0.0));
+    }
+
+    #[test]
+    fn the_connects_are_not_staggered_by_default() {
+        assert_eq!(ConfigCore::new(&Ini::new()).unwrap().connect_stagger(), Duration::from_secs(0));
+        let mut ini = Ini::new();
+        ini.set_to(Some("input"), "connect_stagger".to_string(), "2s".to_string());
+        assert_eq!(ConfigCore::new(&ini).unwrap().connect_stagger(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn resolves_the_indirect_values() {
+        let path = std::env::temp_dir().join(format!("banshee-core-secret-{}", std::process::id()));
+        std::fs::write(&path, "s3cret\n").unwrap();
+        let mut ini = Ini::new();
+        ini.set_to(Some("http"), "token_file".to_string(), path.display().to_string());
+        ini.set_to(Some("output"), "dir_env".to_string(), "PATH".to_string());
+        let (mut notes, mut secrets) = (Vec::new(), Vec::new());
+        resolve_indirect(&mut ini, &mut notes, &mut secrets).unwrap();
+        assert_eq!(ini.get_from(Some("http"), "token"), Some("s3cret"));
+        assert_eq!(ini.get_from(Some("http"), "token_file"), None);
+        assert_eq!(ini.get_from(Some("output"), "dir").map(|s| s.to_string()), std::env::var("PATH").ok());
+        assert_eq!(notes.len(), 2);
+        assert!(notes.iter().all(|n| !n.contains("s3cret")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_indirect_value_is_set_once() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("http"), "token".to_string(), "a".to_string());
+        ini.set_to(Some("http"), "token_env".to_string(), "PATH".to_string());
+        assert!(resolve_indirect(&mut ini, &mut Vec::new(), &mut Vec::new()).err().unwrap().contains("both directly and by token_env"));
+        let mut ini = Ini::new();
+        ini.set_to(Some("http"), "token_env".to_string(), "BANSHEE_NO_SUCH_VARIABLE".to_string());
+        assert!(resolve_indirect(&mut ini, &mut Vec::new(), &mut Vec::new()).err().unwrap().contains("http.token_env"));
+        let mut ini = Ini::new();
+        ini.set_to(Some("http"), "token_file".to_string(), "/nonexistent/token".to_string());
+        assert!(resolve_indirect(&mut ini, &mut Vec::new(), &mut Vec::new()).err().unwrap().contains("failed to read"));
+    }
+
+    #[test]
+    fn the_full_timeout_is_positive() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("input"), "full_timeout".to_string(), "0s".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("input.full_timeout"));
+    }
+
+    #[test]
+    fn the_timer_jitter_is_below_a_hundred() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("general"), "timer_jitter".to_string(), "100".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("general.timer_jitter"));
+        ini.set_to(Some("general"), "timer_jitter".to_string(), "20".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| c.timer_jitter()).ok(), Some(20));
+    }
+
+    #[test]
+    fn parses_the_octal_modes() {
+        let mut ini = Ini::new();
+        assert_eq!(ConfigCore::new(&ini).map(|c| (c.umask(), c.file_mode())).ok(), Some((None, None)));
+        ini.set_to(Some("general"), "umask".to_string(), "027".to_string());
+        ini.set_to(Some("output"), "file_mode".to_string(), " 0640 ".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| (c.umask(), c.file_mode())).ok(), Some((Some(0o27), Some(0o640))));
+        ini.set_to(Some("output"), "file_mode".to_string(), "0680".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("output.file_mode"));
+        ini.set_to(Some("output"), "file_mode".to_string(), "17777".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("output.file_mode"));
+    }
+
+    #[test]
+    fn the_reconnect_rate_is_not_negative() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("input"), "reconnect_rate".to_string(), "-1".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("input.reconnect_rate"));
+        ini.set_to(Some("input"), "reconnect_rate".to_string(), "0.5".to_string());
+        ini.set_to(Some("input"), "reconnect_burst".to_string(), "0".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("input.reconnect_burst"));
+        ini.set_to(Some("input"), "reconnect_burst".to_string(), "3".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| (c.reconnect_rate(), c.reconnect_burst())).ok(), Some((0.5, 3)));
+    }
+
+    #[test]
+    fn lists_the_unknown_keys() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("input"), "reconect_delay".to_string(), "1s".to_string());
+        ini.set_to(Some("input"), "reconnect_delay".to_string(), "1s".to_string());
+        ini.set_to(Some("outptu"), "dir".to_string(), "x".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| c.unknown_keys().to_vec()).ok(),
+            Some(vec!["input.reconect_delay".to_string(), "outptu.dir".to_string()]));
+        ini.set_to(Some("general"), "config_strict".to_string(), "true".to_string());
+        assert_eq!(ConfigCore::new(&ini).err(), Some("unknown config keys: input.reconect_delay, outptu.dir".to_string()));
+    }
+
+    #[test]
+    fn the_batch_fill_waits_no_less_than_the_timeout() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("inference"), "min_batch_fill".to_string(), "0".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("inference.min_batch_fill"));
+        ini.set_to(Some("inference"), "min_batch_fill".to_string(), "1".to_string());
+        ini.set_to(Some("inference"), "batch_priority".to_string(), "efficiency".to_string());
+        ini.set_to(Some("inference"), "batch_max_wait".to_string(), "5ms".to_string());
+        assert!(ConfigCore::new(&ini).err().unwrap().contains("inference.batch_max_wait"));
+        // the hard max is of no use in the latency mode
+        ini.set_to(Some("inference"), "batch_priority".to_string(), "latency".to_string());
+        assert!(ConfigCore::new(&ini).is_ok());
+    }
+
+    #[test]
+    fn finds_and_restores_the_changed_keys() {
+        let mut old = Ini::new();
+        old.set_to(Some("general"), "shutdown_timeout".to_string(), "5s".to_string());
+        old.set_to(Some("inference"), "batch_size".to_string(), "4".to_string());
+        let mut new = Ini::new();
+        new.set_to(Some("general"), "shutdown_timeout".to_string(), "5s".to_string());
+        new.set_to(Some("inference"), "batch_size".to_string(), "8".to_string());
+        new.set_to(Some("output"), "dir".to_string(), "out".to_string());
+        assert_eq!(changed(&old, &new), vec!["inference.batch_size".to_string(), "output.dir".to_string()]);
+        restore(&mut new, &old, "inference.batch_size");
+        restore(&mut new, &old, "output.dir");
+        assert!(changed(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn lists_the_cpu_cores() {
+        let mut ini = Ini::new();
+        ini.set_to(Some("inference"), "cpu_affinity".to_string(), "8, 0-3,2".to_string());
+        assert_eq!(ConfigCore::new(&ini).map(|c| c.cpu_affinity().clone()).ok(), Some(vec![0, 1, 2, 3, 8]));
+        for v in &["3-1", "0-1024", "a", "1-x"] {
+            ini.set_to(Some("inference"), "cpu_affinity".to_string(), v.to_string());
+            assert!(ConfigCore::new(&ini).err().unwrap().contains("inference.cpu_affinity"), "{}", v);
+        }
+    }
+}
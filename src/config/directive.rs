@@ -0,0 +1,68 @@
+use std::fmt::{Display, Formatter, Result};
+use std::str::FromStr;
+
+use log::LevelFilter;
+
+/// Уровень логирования для отдельного модуля (цели журнала) либо общий уровень, если цель не указана
+#[derive(Clone, Debug)]
+pub struct LogDirective {
+    target: Option<String>,
+    level: LevelFilter
+}
+
+impl LogDirective {
+
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        self.level
+    }
+}
+
+impl Display for LogDirective {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.target() {
+            Some(t) => write!(f, "{}={}", t, self.level),
+            None => write!(f, "{}", self.level)
+        }
+    }
+}
+
+impl FromStr for LogDirective {
+    type Err = String;
+
+    /// Разбирает директиву в стиле env_logger: `<target>=<level>` или только `<level>`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (target, level) = match s.find('=') {
+            Some(pos) => (Some(s[..pos].trim()), s[pos + 1..].trim()),
+            None => (None, s.trim())
+        };
+        if target == Some("") {
+            return Err(format!("empty target in '{}'", s));
+        }
+        let level = level.parse().map_err(|_| format!("invalid level in '{}'", s))?;
+        Ok(LogDirective {
+            target: target.map(|t| t.to_string()),
+            level
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_target_and_level() {
+        let d: LogDirective = "banshee::input = debug".parse().unwrap();
+        assert_eq!((d.target(), d.level()), (Some("banshee::input"), LevelFilter::Debug));
+        assert_eq!(d.to_string(), "banshee::input=DEBUG");
+        let d: LogDirective = "warn".parse().unwrap();
+        assert_eq!((d.target(), d.level()), (None, LevelFilter::Warn));
+        assert!("=debug".parse::<LogDirective>().is_err());
+        assert!("banshee=loud".parse::<LogDirective>().is_err());
+    }
+}
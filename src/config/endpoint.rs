@@ -1,9 +1,19 @@
 use std::fmt::{Display, Formatter, Result};
+use std::str::FromStr;
+
+use super::options::{ByteOrder, Compression, SampleFormat, Validation};
 
 #[derive(Clone)]
 pub struct Endpoint {
     addr: String,
-    port: u16
+    port: u16,
+    compression: Option<Compression>,
+    rcvbuf: Option<usize>,
+    sndbuf: Option<usize>,
+    session_prefix: String,
+    sample_format: Option<SampleFormat>,
+    endian: Option<ByteOrder>,
+    validation: Option<Validation>
 }
 
 impl Endpoint {
@@ -11,17 +21,59 @@ impl Endpoint {
     pub fn new(addr: &str, port: u16) -> Endpoint {
         Endpoint {
             addr: addr.to_string(),
-            port: port
+            port,
+            compression: None,
+            rcvbuf: None,
+            sndbuf: None,
+            session_prefix: String::new(),
+            sample_format: None,
+            endian: None,
+            validation: None
         }
     }
 
-    fn addr(&self) -> &str {
+    pub fn addr(&self) -> &str {
         &self.addr
     }
 
-    fn port(&self) -> u16 {
+    pub fn port(&self) -> u16 {
         self.port
     }
+
+    /// Сжатие потока, заданное для точки подключения, None - по общей настройке
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Размер буфера приема сокета, заданный для точки подключения, None - по общей настройке
+    pub fn rcvbuf(&self) -> Option<usize> {
+        self.rcvbuf
+    }
+
+    /// Размер буфера передачи сокета, заданный для точки подключения, None - по общей настройке
+    pub fn sndbuf(&self) -> Option<usize> {
+        self.sndbuf
+    }
+
+    /// Префикс идентификаторов сеансов системы сопряжения в именах файлов и журнале результатов, пустой - не задан
+    pub fn session_prefix(&self) -> &str {
+        &self.session_prefix
+    }
+
+    /// Формат отсчетов звука, заданный для точки подключения, None - по общей настройке
+    pub fn sample_format(&self) -> Option<SampleFormat> {
+        self.sample_format
+    }
+
+    /// Порядок байтов отсчетов звука, заданный для точки подключения, None - по общей настройке
+    pub fn endian(&self) -> Option<ByteOrder> {
+        self.endian
+    }
+
+    /// Строгость проверки кадров, заданная для точки подключения, None - по общей настройке
+    pub fn validation(&self) -> Option<Validation> {
+        self.validation
+    }
 }
 
 impl Display for Endpoint {
@@ -31,3 +83,99 @@ impl Display for Endpoint {
     }
 
 }
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    /// Разбирает точку подключения в виде `<addr>:<port>[/<option>]...`,
+    /// где option - сжатие, `rcvbuf=<bytes>`, `sndbuf=<bytes>`, `session_prefix=<name>`, `sample_format=<format>`,
+    /// `endian=<le|be>` или `validation=<strict|lenient|permissive>`.
+    /// Префикс попадает в имена файлов, поэтому допускает только буквы, цифры, `-`, `_` и `.`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        let target = parts.next().unwrap_or("");
+        let pos = target.rfind(':').ok_or_else(|| format!("expected addr:port, got '{}'", s))?;
+        let port = target[pos + 1..].parse().map_err(|_| format!("invalid port in '{}'", s))?;
+        let mut e = Endpoint::new(&target[..pos], port);
+        for option in parts {
+            let size = |v: &str| v.parse::<usize>().map_err(|_| format!("invalid buffer size in '{}'", s));
+            if let Some(v) = option.strip_prefix("rcvbuf=") {
+                e.rcvbuf = Some(size(v)?);
+            } else if let Some(v) = option.strip_prefix("sndbuf=") {
+                e.sndbuf = Some(size(v)?);
+            } else if let Some(v) = option.strip_prefix("session_prefix=") {
+                if v.is_empty() || !v.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+                    return Err(format!("invalid session prefix in '{}'", s));
+                }
+                e.session_prefix = v.to_string();
+            } else if let Some(v) = option.strip_prefix("sample_format=") {
+                e.sample_format = Some(v.parse().map_err(|e| format!("invalid sample format in '{}': {}", s, e))?);
+            } else if let Some(v) = option.strip_prefix("endian=") {
+                e.endian = Some(v.parse().map_err(|e| format!("invalid endian in '{}': {}", s, e))?);
+            } else if let Some(v) = option.strip_prefix("validation=") {
+                e.validation = Some(v.parse().map_err(|e| format!("invalid validation in '{}': {}", s, e))?);
+            } else {
+                e.compression = Some(option.parse().map_err(|e| format!("invalid compression in '{}': {}", s, e))?);
+            }
+        }
+        Ok(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_address_and_port() {
+        let e: Endpoint = "10.0.0.1:12000".parse().unwrap();
+        assert_eq!((e.addr(), e.port()), ("10.0.0.1", 12000));
+        assert_eq!(e.to_string(), "10.0.0.1:12000");
+        assert!("10.0.0.1".parse::<Endpoint>().is_err());
+        assert!("10.0.0.1:port".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn parses_the_compression_of_the_peer() {
+        let e: Endpoint = "10.0.0.1:12000/gzip".parse().unwrap();
+        assert_eq!((e.port(), e.compression()), (12000, Some(Compression::Gzip)));
+        assert_eq!("10.0.0.1:12000".parse::<Endpoint>().unwrap().compression(), None);
+        assert!("10.0.0.1:12000/zip".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn parses_the_socket_buffers_of_the_peer() {
+        let e: Endpoint = "10.0.0.1:12000/gzip/rcvbuf=4194304".parse().unwrap();
+        assert_eq!((e.compression(), e.rcvbuf(), e.sndbuf()), (Some(Compression::Gzip), Some(4194304), None));
+        let e: Endpoint = "10.0.0.1:12000/sndbuf=8192".parse().unwrap();
+        assert_eq!((e.compression(), e.rcvbuf(), e.sndbuf()), (None, None, Some(8192)));
+        assert!("10.0.0.1:12000/rcvbuf=big".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn parses_the_session_prefix_of_the_peer() {
+        let e: Endpoint = "10.0.0.1:12000/session_prefix=site-a.1".parse().unwrap();
+        assert_eq!(e.session_prefix(), "site-a.1");
+        assert_eq!("10.0.0.1:12000".parse::<Endpoint>().unwrap().session_prefix(), "");
+        assert!("10.0.0.1:12000/session_prefix=".parse::<Endpoint>().is_err());
+        assert!("10.0.0.1:12000/session_prefix=a b".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn parses_the_sample_format_of_the_peer() {
+        let e: Endpoint = "10.0.0.1:12000/sample_format=mulaw/endian=be".parse().unwrap();
+        assert_eq!((e.sample_format(), e.endian()), (Some(SampleFormat::Mulaw), Some(ByteOrder::Big)));
+        let e: Endpoint = "10.0.0.1:12000".parse().unwrap();
+        assert_eq!((e.sample_format(), e.endian()), (None, None));
+        assert!("10.0.0.1:12000/sample_format=s24".parse::<Endpoint>().is_err());
+        assert!("10.0.0.1:12000/endian=middle".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn parses_the_validation_of_the_peer() {
+        let e: Endpoint = "10.0.0.1:12000/validation=permissive".parse().unwrap();
+        assert_eq!(e.validation(), Some(Validation::Permissive));
+        assert_eq!("10.0.0.1:12000".parse::<Endpoint>().unwrap().validation(), None);
+        assert!("10.0.0.1:12000/validation=none".parse::<Endpoint>().is_err());
+    }
+}
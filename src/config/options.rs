@@ -0,0 +1,928 @@
+//! Перечислимые значения настроек, допускающих выбор одного варианта из нескольких.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Способ раскладки результатов по файлам в подсистеме output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputMode {
+    /// Отдельный файл на каждый результат
+    File,
+    /// Один дописываемый файл на весь сеанс абонента, закрывается по последнему результату или по таймауту
+    Session,
+    /// Записи результатов в именованный канал (FIFO) `[output] fifo` для внешнего процесса
+    Fifo,
+    /// Передача результатов внешней системе запросами HTTP `[output] ack_url` с подтверждением получения
+    Http
+}
+
+impl OutputMode {
+
+    /// Сохраняет ли приемник результаты в каталоге, а не передает их в канал или внешней системе
+    pub fn has_dir(self) -> bool {
+        matches!(self, OutputMode::File | OutputMode::Session)
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(OutputMode::File),
+            "session" => Ok(OutputMode::Session),
+            "fifo" => Ok(OutputMode::Fifo),
+            "http" => Ok(OutputMode::Http),
+            _ => Err("expected file, session, fifo or http".to_string())
+        }
+    }
+}
+
+impl Display for OutputMode {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputMode::File => write!(f, "file"),
+            OutputMode::Session => write!(f, "session"),
+            OutputMode::Fifo => write!(f, "fifo"),
+            OutputMode::Http => write!(f, "http")
+        }
+    }
+}
+
+/// Поведение приемника `fifo`, когда читатель канала не подключен
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoReader {
+    /// Запись ожидает подключения читателя не дольше `[output] fifo_wait`
+    Block,
+    /// Результат сразу считается не сохраненным
+    Skip
+}
+
+impl FromStr for NoReader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(NoReader::Block),
+            "skip" => Ok(NoReader::Skip),
+            _ => Err("expected block or skip".to_string())
+        }
+    }
+}
+
+/// Сигнал, получаемый этапом `deinterleave` из каналов многоканального сеанса
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChannelSelect {
+    /// Среднее всех каналов
+    Mix,
+    /// Канал с заданным номером, начиная с 0
+    Pick(usize)
+}
+
+impl FromStr for ChannelSelect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mix" => Ok(ChannelSelect::Mix),
+            _ => s.parse().map(ChannelSelect::Pick).map_err(|_| "expected mix or a channel number".to_string())
+        }
+    }
+}
+
+/// Поведение процессора для сеанса, данные которого не согласуются с объявленными параметрами звука
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Malformed {
+    /// Сеанс пропускается
+    Skip,
+    /// Сеанс пропускается и сохраняется в карантин для разбора
+    Quarantine
+}
+
+impl FromStr for Malformed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Malformed::Skip),
+            "quarantine" => Ok(Malformed::Quarantine),
+            _ => Err("expected skip or quarantine".to_string())
+        }
+    }
+}
+
+/// Поведение коллектора для фрагмента без звука, кадра V-протокола прочего типа
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NonAudio {
+    /// Фрагмент отбрасывается
+    Drop,
+    /// Фрагмент сохраняется в отдельный файл в каталоге `[collector] non_audio_dir`
+    Store
+}
+
+impl FromStr for NonAudio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(NonAudio::Drop),
+            "store" => Ok(NonAudio::Store),
+            _ => Err("expected drop or store".to_string())
+        }
+    }
+}
+
+/// Способ заполнения пропущенных фрагментов при сборке сеанса в подсистеме collector
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GapFill {
+    /// Полученные фрагменты склеиваются подряд, пропуски отбрасываются
+    Drop,
+    /// Пропущенный фрагмент известной длительности заменяется тишиной той же длительности
+    Silence
+}
+
+impl FromStr for GapFill {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(GapFill::Drop),
+            "silence" => Ok(GapFill::Silence),
+            _ => Err("expected drop or silence".to_string())
+        }
+    }
+}
+
+/// Поле заголовка фрагмента, задающее его положение при сборке сеанса
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OrderBy {
+    /// Порядковый номер фрагмента
+    Sequence,
+    /// Метка времени фрагмента по часам системы сопряжения
+    Timestamp
+}
+
+impl FromStr for OrderBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequence" => Ok(OrderBy::Sequence),
+            "timestamp" => Ok(OrderBy::Timestamp),
+            _ => Err("expected sequence or timestamp".to_string())
+        }
+    }
+}
+
+/// Строгость проверки кадров V-протокола системы сопряжения перед приемом
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Validation {
+    /// Как `Lenient` и, кроме того, размеры звука кадра: частота и количество каналов заданы,
+    /// данные состоят из целых отсчетов всех каналов, их длительность совпадает с заявленной с точностью до 1 мс
+    Strict,
+    /// Сигнатура, версия протокола, контрольная сумма и наибольшая длина данных
+    Lenient,
+    /// Только необходимое для выделения кадров из потока: сигнатура и наибольшая длина данных.
+    /// Версия протокола не проверяется, контрольная сумма не вычисляется
+    Permissive
+}
+
+impl FromStr for Validation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Validation::Strict),
+            "lenient" => Ok(Validation::Lenient),
+            "permissive" => Ok(Validation::Permissive),
+            _ => Err("expected strict, lenient or permissive".to_string())
+        }
+    }
+}
+
+impl Display for Validation {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Validation::Strict => write!(f, "strict"),
+            Validation::Lenient => write!(f, "lenient"),
+            Validation::Permissive => write!(f, "permissive")
+        }
+    }
+}
+
+/// Поведение коллектора для фрагмента, параметры звука которого отличаются от установленных первым фрагментом сеанса
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mismatch {
+    /// Фрагмент отбрасывается, сеанс собирается из остальных
+    Drop,
+    /// Сеанс отбрасывается целиком, его опоздавшие фрагменты - тоже
+    Fail
+}
+
+impl FromStr for Mismatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Mismatch::Drop),
+            "fail" => Ok(Mismatch::Fail),
+            _ => Err("expected drop or fail".to_string())
+        }
+    }
+}
+
+/// Значение фрагмента со звуком без данных, кроме признака окончания сеанса
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EmptyFragment {
+    /// Поддержание сеанса: фрагмент не сохраняется, а продлевает ожидание оставшихся фрагментов начатого сеанса
+    Keepalive,
+    /// Окончание сеанса: фрагмент принимается как признак окончания, как кадр End
+    End
+}
+
+impl FromStr for EmptyFragment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keepalive" => Ok(EmptyFragment::Keepalive),
+            "end" => Ok(EmptyFragment::End),
+            _ => Err("expected keepalive or end".to_string())
+        }
+    }
+}
+
+/// Поведение коллектора для незавершенных сеансов системы сопряжения, соединение с которой разорвано
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Disconnect {
+    /// Сеансы ожидают оставшиеся фрагменты после переподключения в пределах таймаута сеанса
+    Keep,
+    /// Сеансы сразу передаются на обработку незавершенными
+    Flush
+}
+
+impl FromStr for Disconnect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Disconnect::Keep),
+            "flush" => Ok(Disconnect::Flush),
+            _ => Err("expected keep or flush".to_string())
+        }
+    }
+}
+
+/// Определение границ сеанса коллектором
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SessionKey {
+    /// Сеанс - фрагменты абонента от первого до последнего по номерам
+    Id,
+    /// Непрерывный поток абонента разделяется на сеансы-высказывания паузами в звуке
+    SilenceGap
+}
+
+impl FromStr for SessionKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(SessionKey::Id),
+            "silence_gap" => Ok(SessionKey::SilenceGap),
+            _ => Err("expected id or silence_gap".to_string())
+        }
+    }
+}
+
+impl Display for GapFill {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GapFill::Drop => write!(f, "drop"),
+            GapFill::Silence => write!(f, "silence")
+        }
+    }
+}
+
+impl Display for OrderBy {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderBy::Sequence => write!(f, "sequence"),
+            OrderBy::Timestamp => write!(f, "timestamp")
+        }
+    }
+}
+
+/// Поведение подсистемы inference, если вычислитель результата недоступен при запуске
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Unavailable {
+    /// Запуск приложения завершается ошибкой
+    Fail,
+    /// Сэмплы передаются на сохранение без расчета, только для сбора данных
+    Passthrough
+}
+
+impl FromStr for Unavailable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Unavailable::Fail),
+            "passthrough" => Ok(Unavailable::Passthrough),
+            _ => Err("expected fail or passthrough".to_string())
+        }
+    }
+}
+
+/// Поведение подсистемы inference при заполненном канале передачи результатов в output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FullPolicy {
+    /// Расчет ожидает освобождения места в канале, результаты не теряются
+    Block,
+    /// Результат отбрасывается
+    Drop,
+    /// Результат сохраняется в дисковую очередь и передается в output по мере освобождения канала
+    Spill
+}
+
+impl FromStr for FullPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(FullPolicy::Block),
+            "drop" => Ok(FullPolicy::Drop),
+            "spill" => Ok(FullPolicy::Spill),
+            _ => Err("expected block, drop or spill".to_string())
+        }
+    }
+}
+
+/// Поведение подсистемы inference при разрушении канала передачи результатов в output, например при панике output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputLost {
+    /// Потеря output фатальна: приложение останавливается и завершается с ошибкой для перезапуска
+    Stop,
+    /// Результаты сохраняются в дисковую очередь до следующего запуска, экземпляр отмечается неработоспособным
+    Spill
+}
+
+impl FromStr for OutputLost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(OutputLost::Stop),
+            "spill" => Ok(OutputLost::Spill),
+            _ => Err("expected stop or spill".to_string())
+        }
+    }
+}
+
+/// Поведение подсистемы input при заполненном канале передачи фрагментов в коллектор
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputFull {
+    /// Чтение из соединения ожидает освобождения места, фрагменты не теряются,
+    /// но данные могут теряться в буферах системы сопряжения
+    Block,
+    /// Фрагмент отбрасывается, чтение продолжается
+    Drop,
+    /// Чтение ожидает освобождения места, но не дольше `full_timeout`, после чего соединение разрывается
+    /// и восстанавливается, когда в канале появится место
+    Disconnect
+}
+
+impl FromStr for InputFull {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(InputFull::Block),
+            "drop" => Ok(InputFull::Drop),
+            "disconnect" => Ok(InputFull::Disconnect),
+            _ => Err("expected block, drop or disconnect".to_string())
+        }
+    }
+}
+
+/// Поведение подсистемы inference для результата, наибольшая уверенность которого ниже `min_confidence`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BelowThreshold {
+    /// Результат сохраняется как обычно
+    Keep,
+    /// Результат сохраняется с отметкой о низкой уверенности в журнале сохраненных файлов
+    Flag,
+    /// Результат отбрасывается
+    Drop
+}
+
+impl FromStr for BelowThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(BelowThreshold::Keep),
+            "flag" => Ok(BelowThreshold::Flag),
+            "drop" => Ok(BelowThreshold::Drop),
+            _ => Err("expected keep, flag or drop".to_string())
+        }
+    }
+}
+
+/// Поведение подсистемы inference для сэмпла длиннее наибольшей из `input_buckets` длин входа
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Oversize {
+    /// Сэмпл рассчитывается частями наибольшей длины, результаты частей объединяются по порядку
+    Split,
+    /// Сэмпл отбрасывается как не рассчитанный
+    Reject
+}
+
+impl FromStr for Oversize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "split" => Ok(Oversize::Split),
+            "reject" => Ok(Oversize::Reject),
+            _ => Err("expected split or reject".to_string())
+        }
+    }
+}
+
+/// Поведение подсистемы inference для сэмпла с частотой дискретизации, отличной от ожидаемой моделью
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RateMismatch {
+    /// Сигнал передискретизируется к частоте модели, сэмпл признаков отбрасывается
+    Resample,
+    /// Сэмпл отбрасывается как не рассчитанный
+    Reject
+}
+
+impl FromStr for RateMismatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resample" => Ok(RateMismatch::Resample),
+            "reject" => Ok(RateMismatch::Reject),
+            _ => Err("expected resample or reject".to_string())
+        }
+    }
+}
+
+/// Алгоритм контрольной суммы сохраняемых подсистемой output файлов
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HashAlg {
+    /// Контрольная сумма не рассчитывается
+    None,
+    Sha256,
+    Sha512
+}
+
+impl FromStr for HashAlg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(HashAlg::None),
+            "sha256" => Ok(HashAlg::Sha256),
+            "sha512" => Ok(HashAlg::Sha512),
+            _ => Err("expected none, sha256 or sha512".to_string())
+        }
+    }
+}
+
+impl Display for HashAlg {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlg::None => write!(f, "none"),
+            HashAlg::Sha256 => write!(f, "sha256"),
+            HashAlg::Sha512 => write!(f, "sha512")
+        }
+    }
+}
+
+/// Порядок байтов многобайтового поля
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ByteOrder {
+    Little,
+    Big
+}
+
+impl FromStr for ByteOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "le" => Ok(ByteOrder::Little),
+            "be" => Ok(ByteOrder::Big),
+            _ => Err("expected le or be".to_string())
+        }
+    }
+}
+
+impl Display for ByteOrder {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteOrder::Little => write!(f, "le"),
+            ByteOrder::Big => write!(f, "be")
+        }
+    }
+}
+
+/// Приоритет накопления пакета расчета, когда время его ожидания истекло
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BatchPriority {
+    /// Пакет рассчитывается, сколько бы сэмплов в нем ни было
+    Latency,
+    /// Пакет ожидает минимального заполнения до предельного времени ожидания
+    Efficiency
+}
+
+impl FromStr for BatchPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latency" => Ok(BatchPriority::Latency),
+            "efficiency" => Ok(BatchPriority::Efficiency),
+            _ => Err("expected latency or efficiency".to_string())
+        }
+    }
+}
+
+/// Формат отсчетов звука системы сопряжения
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SampleFormat {
+    /// 16-битные целые со знаком
+    S16,
+    /// 32-битные целые со знаком
+    S32,
+    /// 32-битные с плавающей точкой в диапазоне [-1, 1]
+    F32,
+    /// 8-битные целые без знака со смещением 128
+    U8,
+    /// 8-битные G.711 mu-law
+    Mulaw,
+    /// 8-битные G.711 A-law
+    Alaw
+}
+
+impl SampleFormat {
+
+    /// Размер одного отсчета, байтов
+    pub fn width(self) -> usize {
+        match self {
+            SampleFormat::S16 => 2,
+            SampleFormat::S32 | SampleFormat::F32 => 4,
+            SampleFormat::U8 | SampleFormat::Mulaw | SampleFormat::Alaw => 1
+        }
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s16" => Ok(SampleFormat::S16),
+            "s32" => Ok(SampleFormat::S32),
+            "f32" => Ok(SampleFormat::F32),
+            "u8" => Ok(SampleFormat::U8),
+            "mulaw" => Ok(SampleFormat::Mulaw),
+            "alaw" => Ok(SampleFormat::Alaw),
+            _ => Err("expected s16, s32, f32, u8, mulaw or alaw".to_string())
+        }
+    }
+}
+
+impl Display for SampleFormat {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SampleFormat::S16 => write!(f, "s16"),
+            SampleFormat::S32 => write!(f, "s32"),
+            SampleFormat::F32 => write!(f, "f32"),
+            SampleFormat::U8 => write!(f, "u8"),
+            SampleFormat::Mulaw => write!(f, "mulaw"),
+            SampleFormat::Alaw => write!(f, "alaw")
+        }
+    }
+}
+
+/// Сжатие потока V-протокола, получаемого от системы сопряжения
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    None,
+    /// Формат gzip, RFC 1952
+    Gzip,
+    /// Формат zlib, RFC 1950, как deflate в HTTP
+    Deflate
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "deflate" => Ok(Compression::Deflate),
+            _ => Err("expected none, gzip or deflate".to_string())
+        }
+    }
+}
+
+impl Display for Compression {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Deflate => write!(f, "deflate")
+        }
+    }
+}
+
+/// Источник времени, по которому именуются сохраняемые файлы
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimestampSource {
+    /// Часы приложения в момент сохранения
+    Local,
+    /// Метка времени, переданная системой сопряжения в заголовке кадра V-протокола
+    Peer
+}
+
+impl FromStr for TimestampSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(TimestampSource::Local),
+            "peer" => Ok(TimestampSource::Peer),
+            _ => Err("expected local or peer".to_string())
+        }
+    }
+}
+
+impl Display for TimestampSource {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimestampSource::Local => write!(f, "local"),
+            TimestampSource::Peer => write!(f, "peer")
+        }
+    }
+}
+
+/// Точность меток времени в именах файлов результатов, журнале сохраненных файлов и журнале событий
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimestampPrecision {
+    /// Секунды
+    S,
+    /// Миллисекунды
+    Ms,
+    /// Микросекунды
+    Us,
+    /// Наносекунды
+    Ns
+}
+
+impl TimestampPrecision {
+
+    /// Метка времени `ns` наносекунд от начала эпохи Unix в единицах точности
+    pub fn of(self, ns: u64) -> u64 {
+        match self {
+            TimestampPrecision::S => ns / 1_000_000_000,
+            TimestampPrecision::Ms => ns / 1_000_000,
+            TimestampPrecision::Us => ns / 1000,
+            TimestampPrecision::Ns => ns
+        }
+    }
+}
+
+impl FromStr for TimestampPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s" => Ok(TimestampPrecision::S),
+            "ms" => Ok(TimestampPrecision::Ms),
+            "us" => Ok(TimestampPrecision::Us),
+            "ns" => Ok(TimestampPrecision::Ns),
+            _ => Err("expected s, ms, us or ns".to_string())
+        }
+    }
+}
+
+impl Display for TimestampPrecision {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimestampPrecision::S => write!(f, "s"),
+            TimestampPrecision::Ms => write!(f, "ms"),
+            TimestampPrecision::Us => write!(f, "us"),
+            TimestampPrecision::Ns => write!(f, "ns")
+        }
+    }
+}
+
+/// Итог записи результата в несколько приемников при неудаче в части из них
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FanoutPolicy {
+    /// Запись успешна, только если удалась во все приемники
+    AllMustSucceed,
+    /// Запись успешна, если удалась хотя бы в один приемник
+    BestEffort
+}
+
+impl FromStr for FanoutPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all_must_succeed" => Ok(FanoutPolicy::AllMustSucceed),
+            "best_effort" => Ok(FanoutPolicy::BestEffort),
+            _ => Err("expected all_must_succeed or best_effort".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_gap_fill() {
+        assert_eq!("drop".parse::<GapFill>(), Ok(GapFill::Drop));
+        assert_eq!("silence".parse::<GapFill>(), Ok(GapFill::Silence));
+        assert!("zeros".parse::<GapFill>().is_err());
+    }
+
+    #[test]
+    fn parses_the_unavailable_policy() {
+        assert_eq!("fail".parse::<Unavailable>(), Ok(Unavailable::Fail));
+        assert_eq!("passthrough".parse::<Unavailable>(), Ok(Unavailable::Passthrough));
+        assert!("ignore".parse::<Unavailable>().is_err());
+    }
+
+    #[test]
+    fn the_hash_alg_names_the_sidecar() {
+        for alg in &["none", "sha256", "sha512"] {
+            assert_eq!(alg.parse::<HashAlg>().unwrap().to_string(), *alg);
+        }
+        assert!("md5".parse::<HashAlg>().is_err());
+    }
+
+    #[test]
+    fn parses_the_byte_order() {
+        assert_eq!("le".parse::<ByteOrder>(), Ok(ByteOrder::Little));
+        assert_eq!("be".parse::<ByteOrder>(), Ok(ByteOrder::Big));
+        assert!("little".parse::<ByteOrder>().is_err());
+    }
+
+    #[test]
+    fn parses_the_fanout_policy() {
+        assert_eq!("all_must_succeed".parse::<FanoutPolicy>(), Ok(FanoutPolicy::AllMustSucceed));
+        assert_eq!("best_effort".parse::<FanoutPolicy>(), Ok(FanoutPolicy::BestEffort));
+        assert!("any".parse::<FanoutPolicy>().is_err());
+    }
+
+    #[test]
+    fn the_compression_round_trips() {
+        for c in &["none", "gzip", "deflate"] {
+            assert_eq!(c.parse::<Compression>().unwrap().to_string(), *c);
+        }
+        assert!("zstd".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn parses_the_timestamp_source() {
+        assert_eq!("local".parse::<TimestampSource>(), Ok(TimestampSource::Local));
+        assert_eq!("peer".parse::<TimestampSource>(), Ok(TimestampSource::Peer));
+        assert!("remote".parse::<TimestampSource>().is_err());
+    }
+
+    #[test]
+    fn parses_the_oversize_policy() {
+        assert_eq!("split".parse::<Oversize>(), Ok(Oversize::Split));
+        assert_eq!("reject".parse::<Oversize>(), Ok(Oversize::Reject));
+        assert!("truncate".parse::<Oversize>().is_err());
+    }
+
+    #[test]
+    fn parses_the_mismatch_policy() {
+        assert_eq!("drop".parse::<Mismatch>(), Ok(Mismatch::Drop));
+        assert_eq!("fail".parse::<Mismatch>(), Ok(Mismatch::Fail));
+        assert!("keep".parse::<Mismatch>().is_err());
+    }
+
+    #[test]
+    fn parses_the_no_reader_policy() {
+        assert_eq!("block".parse::<NoReader>(), Ok(NoReader::Block));
+        assert_eq!("skip".parse::<NoReader>(), Ok(NoReader::Skip));
+        assert!("wait".parse::<NoReader>().is_err());
+    }
+
+    #[test]
+    fn parses_the_non_audio_action() {
+        assert_eq!("drop".parse::<NonAudio>(), Ok(NonAudio::Drop));
+        assert_eq!("store".parse::<NonAudio>(), Ok(NonAudio::Store));
+        assert!("keep".parse::<NonAudio>().is_err());
+    }
+
+    #[test]
+    fn parses_the_malformed_action() {
+        assert_eq!("skip".parse::<Malformed>(), Ok(Malformed::Skip));
+        assert_eq!("quarantine".parse::<Malformed>(), Ok(Malformed::Quarantine));
+        assert!("drop".parse::<Malformed>().is_err());
+    }
+
+    #[test]
+    fn parses_the_channel_select() {
+        assert_eq!("mix".parse::<ChannelSelect>(), Ok(ChannelSelect::Mix));
+        assert_eq!("1".parse::<ChannelSelect>(), Ok(ChannelSelect::Pick(1)));
+        assert!("left".parse::<ChannelSelect>().is_err());
+    }
+
+    #[test]
+    fn parses_the_input_full_policy() {
+        assert_eq!("block".parse::<InputFull>(), Ok(InputFull::Block));
+        assert_eq!("drop".parse::<InputFull>(), Ok(InputFull::Drop));
+        assert_eq!("disconnect".parse::<InputFull>(), Ok(InputFull::Disconnect));
+        assert!("reset".parse::<InputFull>().is_err());
+    }
+
+    #[test]
+    fn parses_the_output_lost_policy() {
+        assert_eq!("stop".parse::<OutputLost>(), Ok(OutputLost::Stop));
+        assert_eq!("spill".parse::<OutputLost>(), Ok(OutputLost::Spill));
+        assert!("drop".parse::<OutputLost>().is_err());
+    }
+
+    #[test]
+    fn parses_the_disconnect_policy() {
+        assert_eq!("keep".parse::<Disconnect>(), Ok(Disconnect::Keep));
+        assert_eq!("flush".parse::<Disconnect>(), Ok(Disconnect::Flush));
+        assert!("drop".parse::<Disconnect>().is_err());
+    }
+
+    #[test]
+    fn parses_the_session_key() {
+        assert_eq!("id".parse::<SessionKey>(), Ok(SessionKey::Id));
+        assert_eq!("silence_gap".parse::<SessionKey>(), Ok(SessionKey::SilenceGap));
+        assert!("silence".parse::<SessionKey>().is_err());
+    }
+
+    #[test]
+    fn the_sample_format_round_trips() {
+        for (f, width) in &[("s16", 2), ("s32", 4), ("f32", 4), ("u8", 1), ("mulaw", 1), ("alaw", 1)] {
+            let format = f.parse::<SampleFormat>().unwrap();
+            assert_eq!((format.to_string(), format.width()), (f.to_string(), *width));
+        }
+        assert!("s24".parse::<SampleFormat>().is_err());
+    }
+
+    #[test]
+    fn parses_the_batch_priority() {
+        assert_eq!("latency".parse::<BatchPriority>(), Ok(BatchPriority::Latency));
+        assert_eq!("efficiency".parse::<BatchPriority>(), Ok(BatchPriority::Efficiency));
+        assert!("throughput".parse::<BatchPriority>().is_err());
+    }
+
+    #[test]
+    fn parses_the_empty_fragment() {
+        assert_eq!("keepalive".parse::<EmptyFragment>(), Ok(EmptyFragment::Keepalive));
+        assert_eq!("end".parse::<EmptyFragment>(), Ok(EmptyFragment::End));
+        assert!("drop".parse::<EmptyFragment>().is_err());
+    }
+
+    #[test]
+    fn parses_the_fragment_order() {
+        assert_eq!("sequence".parse::<OrderBy>(), Ok(OrderBy::Sequence));
+        assert_eq!("timestamp".parse::<OrderBy>(), Ok(OrderBy::Timestamp));
+        assert!("time".parse::<OrderBy>().is_err());
+    }
+
+    #[test]
+    fn parses_the_timestamp_precision() {
+        for p in ["s", "ms", "us", "ns"] {
+            assert_eq!(p.parse::<TimestampPrecision>().unwrap().to_string(), p);
+        }
+        assert!("min".parse::<TimestampPrecision>().is_err());
+    }
+
+    #[test]
+    fn parses_the_validation() {
+        for v in ["strict", "lenient", "permissive"] {
+            assert_eq!(v.parse::<Validation>().unwrap().to_string(), v);
+        }
+        assert!("none".parse::<Validation>().is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt::{Display, Formatter, Result};
+use std::str::FromStr;
+
+use crate::data::METADATA_KEYS;
+
+use super::options::OutputMode;
+
+/// Признак результата, по которому он направляется в приемник маршрута
+#[derive(Clone, PartialEq, Debug)]
+pub enum RouteMatch {
+    /// Результат с уверенностью ниже `min_confidence`
+    LowConfidence,
+    /// Промежуточный результат потокового вычислителя
+    Partial,
+    /// Результат сеанса системы сопряжения с префиксом идентификаторов сеансов `session_prefix`
+    Prefix(String),
+    /// Результат сеанса со сведением `<имя>` равным значению, см. [`METADATA_KEYS`]
+    Metadata(String, String)
+}
+
+impl Display for RouteMatch {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            RouteMatch::LowConfidence => write!(f, "low_confidence"),
+            RouteMatch::Partial => write!(f, "partial"),
+            RouteMatch::Prefix(p) => write!(f, "prefix={}", p),
+            RouteMatch::Metadata(k, v) => write!(f, "{}={}", k, v)
+        }
+    }
+}
+
+/// Маршрут результатов: результаты с признаком сохраняет отдельный приемник, а не приемники `[output] mode`
+#[derive(Clone, Debug)]
+pub struct Route {
+    matcher: RouteMatch,
+    mode: OutputMode,
+    /// Каталог результатов приемника, для `fifo` - путь именованного канала, для `http` - адрес внешней системы
+    path: String
+}
+
+impl Route {
+
+    pub fn matcher(&self) -> &RouteMatch {
+        &self.matcher
+    }
+
+    pub fn mode(&self) -> OutputMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Display for Route {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{} -> {}:{}", self.matcher, self.mode, self.path)
+    }
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    /// Разбирает маршрут в виде `<признак> -> <mode>:<path>`, где признак - `low_confidence`, `partial`,
+    /// `prefix=<name>` или `<сведение о сеансе>=<значение>`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let pos = s.find("->").ok_or_else(|| format!("expected <match> -> <mode>:<path>, got '{}'", s))?;
+        let (matcher, sink) = (s[..pos].trim(), s[pos + 2..].trim());
+        let matcher = match matcher.find('=') {
+            None if matcher == "low_confidence" => RouteMatch::LowConfidence,
+            None if matcher == "partial" => RouteMatch::Partial,
+            None => return Err(format!("unknown match '{}' in '{}'", matcher, s)),
+            Some(eq) => {
+                let (k, v) = (matcher[..eq].trim(), matcher[eq + 1..].trim());
+                if k == "prefix" {
+                    RouteMatch::Prefix(v.to_string())
+                } else if METADATA_KEYS.contains(&k) {
+                    RouteMatch::Metadata(k.to_string(), v.to_string())
+                } else {
+                    return Err(format!("unknown match '{}' in '{}', known are low_confidence, partial, prefix, {}", k, s, METADATA_KEYS.join(", ")));
+                }
+            }
+        };
+        let colon = sink.find(':').ok_or_else(|| format!("expected <mode>:<path> in '{}'", s))?;
+        let mode = sink[..colon].trim().parse().map_err(|e| format!("invalid mode in '{}': {}", s, e))?;
+        let path = sink[colon + 1..].trim();
+        if path.is_empty() {
+            return Err(format!("empty path in '{}'", s));
+        }
+        Ok(Route {
+            matcher,
+            mode,
+            path: path.to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_route() {
+        let r: Route = " prefix = site-a ->  session : /data/a ".parse().unwrap();
+        assert_eq!((r.matcher(), r.mode(), r.path()), (&RouteMatch::Prefix("site-a".to_string()), OutputMode::Session, "/data/a"));
+        assert_eq!(r.to_string(), "prefix=site-a -> session:/data/a");
+        // the url of an http sink has a colon of its own
+        let r: Route = "partial -> http:http://10.0.0.1:8000/partial".parse().unwrap();
+        assert_eq!((r.matcher(), r.path()), (&RouteMatch::Partial, "http://10.0.0.1:8000/partial"));
+        let r: Route = "rate=8000 -> file:narrow".parse().unwrap();
+        assert_eq!(r.matcher(), &RouteMatch::Metadata("rate".to_string(), "8000".to_string()));
+        assert_eq!("low_confidence -> file:low".parse::<Route>().unwrap().matcher(), &RouteMatch::LowConfidence);
+    }
+
+    #[test]
+    fn rejects_the_malformed_route() {
+        for r in &["low_confidence file:low", "confident -> file:low", "caller=1 -> file:x", "partial -> file", "partial -> disk:x", "partial -> file: "] {
+            assert!(r.parse::<Route>().is_err(), "{}", r);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Управляющий сокет для диагностики работающего приложения операторами.
+//!
+//! Адрес задается в настройках `[control] listen`: `<addr>:<port>` - TCP-сокет, к подключениям применяются
+//! списки доступа `[access]`, иначе - путь к Unix-сокету. Протокол строковый: одна команда в строке запроса,
+//! одна строка ответа.
+//!
+//! Команды:
+//! *  `stats` - снимок счетчиков конвейера в JSON: по подсистемам, заполненность каналов, состояние подключений
+//! *  `reload` - перечитать конфигурацию, ответ в JSON: `applied` - ключи, измененные без перезапуска,
+//!    `restart` - измененные ключи, которые вступят в силу после перезапуска, либо `error`
+
+use std::net::SocketAddr;
+
+use crate::access::AccessList;
+use crate::config::SharedConfig;
+use crate::logger;
+use crate::stats::SharedStats;
+use crate::tracker::TaskGuard;
+
+use log::{debug, error, info, warn};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// Запускает в асинхронном режиме прием команд через управляющий сокет, если в настройках `[control] listen` задан адрес
+///
+/// Параметры:
+///
+/// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `stats` - счетчики работы подсистем
+/// * `rx_stop` - межпоточный канал получения команды на завершение, читатель
+///
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде,
+/// None - подсистема отключена или не запущена
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, rx_stop: oneshot::Receiver<()>) -> Option<JoinHandle<()>> {
+    let listen = cfg.control_listen();
+    if listen.is_empty() {
+        info!("control is disabled");
+        return None;
+    }
+    match listen.parse::<SocketAddr>() {
+        Ok(addr) => {
+            let mut listener = match TcpListener::bind(addr).await {
+                Ok(l) => l,
+                Err(e) => {
+                    error!("control: unable to listen on {}: {}", addr, e);
+                    return None;
+                }
+            };
+            info!("start control on {}", addr);
+            let access = AccessList::new(&cfg);
+            Some(tokio::spawn(async move {
+                let _guard = guard;
+                let accept = async {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, remote)) => {
+                                if !access.permits(&remote.ip()) {
+                                    warn!("control: connection from {} is rejected by access list", remote);
+                                    continue;
+                                }
+                                tokio::spawn(serve(stream, cfg.clone(), stats.clone()));
+                            },
+                            Err(e) => error!("control: failed to accept: {}", e)
+                        }
+                    }
+                };
+                tokio::select! {
+                    _ = accept => {},
+                    _ = rx_stop => info!("stop control")
+                }
+                info!("control is stopped");
+            }))
+        },
+        Err(_) => platform::run_unix(listen, cfg, guard, stats, rx_stop).await
+    }
+}
+
+// serves the commands of one connection until it is closed
+async fn serve<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S, cfg: SharedConfig, stats: SharedStats) {
+    let (rd, mut wr) = tokio::io::split(stream);
+    let mut lines = BufReader::new(rd).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(l)) => l,
+            Ok(None) => break,
+            Err(e) => {
+                debug!("control: connection failed: {}", e);
+                break;
+            }
+        };
+        let reply = command(line.trim(), &cfg, &stats);
+        if wr.write_all(format!("{}\n", reply).as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn command(cmd: &str, cfg: &SharedConfig, stats: &SharedStats) -> String {
+    match cmd {
+        "stats" => stats.snapshot().to_string(),
+        "reload" => reload(cfg),
+        _ => json!({ "error": format!("unknown command '{}'", cmd) }).to_string()
+    }
+}
+
+// rereads the config, the values are not logged as some of them are secrets
+fn reload(cfg: &SharedConfig) -> String {
+    match cfg.reload() {
+        Ok(r) => {
+            if r.applied.iter().any(|k| k == "general.log_targets") {
+                logger::reload(cfg);
+            }
+            for key in &r.pending {
+                warn!("config: {} is changed, the change takes effect after a restart", key);
+            }
+            for key in &r.unknown {
+                warn!("config: unknown key {} is ignored, misspelled?", key);
+            }
+            info!("config: reloaded, applied: [{}]", r.applied.join(", "));
+            json!({ "applied": r.applied, "restart": r.pending }).to_string()
+        },
+        Err(e) => {
+            error!("config: reload failed, the current settings are kept: {}", e);
+            json!({ "error": e }).to_string()
+        }
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use crate::config::SharedConfig;
+    use crate::stats::SharedStats;
+    use crate::tracker::TaskGuard;
+
+    use log::{error, info};
+    use tokio::net::UnixListener;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    pub async fn run_unix(path: String, cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, rx_stop: oneshot::Receiver<()>) -> Option<JoinHandle<()>> {
+        // a socket file left by a previous run prevents binding
+        let _ = std::fs::remove_file(&path);
+        let mut listener = match UnixListener::bind(&path) {
+            Ok(l) => l,
+            Err(e) => {
+                error!("control: unable to listen on {}: {}", path, e);
+                return None;
+            }
+        };
+        info!("start control on {}", path);
+        Some(tokio::spawn(async move {
+            let _guard = guard;
+            let accept = async {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(super::serve(stream, cfg.clone(), stats.clone()));
+                        },
+                        Err(e) => error!("control: failed to accept: {}", e)
+                    }
+                }
+            };
+            tokio::select! {
+                _ = accept => {},
+                _ = rx_stop => info!("stop control")
+            }
+            let _ = std::fs::remove_file(&path);
+            info!("control is stopped");
+        }))
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use crate::config::SharedConfig;
+    use crate::stats::SharedStats;
+    use crate::tracker::TaskGuard;
+
+    use log::error;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    pub async fn run_unix(path: String, _cfg: SharedConfig, _guard: TaskGuard, _stats: SharedStats, _rx_stop: oneshot::Receiver<()>) -> Option<JoinHandle<()>> {
+        error!("control: unix sockets are not supported on this platform, {} is not an addr:port", path);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn serves_a_line_per_command() {
+        let cfg = Config::from_sources(&[], &[]).unwrap();
+        let stats = Stats::new(&[]);
+        stats.output.sent();
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, cfg, stats).await;
+        });
+        let (rd, mut wr) = tokio::io::split(TcpStream::connect(addr).await.unwrap());
+        let mut lines = BufReader::new(rd).lines();
+        wr.write_all(b"stats\n  stats  \nfoo\n").await.unwrap();
+        let mut replies = Vec::new();
+        for _ in 0..3 {
+            let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+            replies.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+        }
+        assert_eq!(replies[0]["stages"]["output"]["sent"], 1);
+        assert_eq!(replies[1]["stages"]["output"]["sent"], 1);
+        assert_eq!(replies[2]["error"], "unknown command 'foo'");
+    }
+}
@@ -5,6 +5,8 @@
 //! вариантами: собственно данными для передачи или признаком команды завершения.
 //! При получении второго варианта на вход каждый компонент должен закрывать соответствующий входной канал и завершать свою работу
 
+use std::collections::HashMap;
+
 /// Фрагмент звука
 mod fragment;
 /// Собранный из фрагментов сеанс
@@ -16,8 +18,20 @@ mod stored_result;
 
 /// Фрагмент звукового сеанса, характеризуется длительностью (20 мс), частотой дискретизации, уровнем сигнала
 pub type Fragment = fragment::Fragment;
+/// Параметры звука фрагмента: частота дискретизации, количество каналов, формат отсчетов
+pub type AudioParams = fragment::AudioParams;
+/// Содержимое фрагмента: звук или прочие данные смешанного потока
+pub type FragmentKind = fragment::FragmentKind;
 /// Звуковой сеанс, собранный из фрагментов, обычно является непрерывной частью разговора
 pub type Session = session::Session;
+/// Сведения о сеансе, передаваемые вместе с его результатами и записываемые в журнал сохраненных файлов:
+/// имя - значение, см. [`METADATA_KEYS`]
+pub type Metadata = HashMap<String, String>;
+/// Имена сведений о сеансе: `source` - адрес системы сопряжения, `rate`, `channels`, `format` - параметры звука,
+/// объявленные фрагментами, `fragments` - количество полученных фрагментов, `utterance` - номер высказывания
+/// в непрерывном потоке абонента, только при разделении потока по паузам, `sample_format`, `endian` - формат
+/// и порядок байтов отсчетов системы сопряжения, по которым processor преобразует звук сеанса
+pub const METADATA_KEYS: [&str; 8] = ["source", "rate", "channels", "format", "fragments", "utterance", "sample_format", "endian"];
 /// Окончательный звуковой образец после всех фильтров, подготовленный для расчета конечного результата
 pub type FinalSample = final_sample::FinalSample;
 /// Сохраняемый результат в системе хранения, содержит результат расчета и связанные признаки разговора
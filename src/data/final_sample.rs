@@ -1,3 +1,9 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::data::Metadata;
+use crate::trace::Trace;
+
 /// Подготовленный к расчету результата сэмпл.
 /// Передается по каналу processor --> inference.
 pub enum FinalSample {
@@ -5,9 +11,29 @@ pub enum FinalSample {
     Stop,
     /// Данные окончательного сэмпла
     Data {
+        /// Порядковый номер системы сопряжения, от которой получен сеанс
+        peer: usize,
         /// Идентификатор абонента
         id: u32,
-        /// Упакованный в байтовый поток сэмпл
-        value: Vec<u8>
+        /// Префикс идентификатора сеанса, заданный для системы сопряжения, пустой - не задан
+        prefix: Arc<str>,
+        /// Сведения о сеансе, собранные из заголовков его фрагментов
+        metadata: Arc<Metadata>,
+        /// Порядковый номер части сеанса, из которой получен сэмпл
+        chunk: u32,
+        /// Контекст трассы сеанса
+        trace: Trace,
+        /// Момент начала звука по часам системы сопряжения, мкс от начала эпохи Unix, None если не передан
+        peer_time_us: Option<u64>,
+        /// Момент, после которого сеанс устарел и не обрабатывается, None - не ограничен
+        deadline: Option<Instant>,
+        /// Упакованный в байтовый поток сэмпл: последовательность векторов f32 little-endian
+        value: Vec<u8>,
+        /// Размерность одного вектора в `value`, 1 если это отсчеты сигнала
+        dim: usize,
+        /// Частота дискретизации сигнала, из которого получен сэмпл, Гц
+        rate: u32,
+        /// Признак последней порции данных сеанса абонента
+        last: bool
     }
 }
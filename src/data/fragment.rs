@@ -1,12 +1,59 @@
+use std::fmt::{Display, Formatter, Result};
+
+/// Параметры звука, объявленные в заголовке фрагмента
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AudioParams {
+    /// Частота дискретизации, Гц
+    pub rate: u32,
+    /// Количество каналов
+    pub channels: u8,
+    /// Формат отсчетов
+    pub format: u8
+}
+
+impl Display for AudioParams {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{} Hz, {} channels, format {}", self.rate, self.channels, self.format)
+    }
+}
+
+/// Содержимое фрагмента по типу кадра V-протокола
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FragmentKind {
+    /// Звук сеанса абонента
+    Audio,
+    /// Прочие данные смешанного потока, тип кадра
+    Other(u8)
+}
+
 /// Полученный от системы сопряжения фрагмент.
 /// Передается по каналу input --> collector.
 pub enum Fragment {
     /// Признак команды закрыть канал и завершить работу 
     Stop,
+    /// Соединение с системой сопряжения `peer` разорвано, все фрагменты, полученные по нему, переданы ранее
+    Disconnected {
+        peer: usize
+    },
     /// Данные фрагмента
     Data {
+        /// Порядковый номер источника (системы сопряжения) в списке подключений
+        peer: usize,
+        /// Содержимое фрагмента, в сеансы собираются только фрагменты со звуком
+        kind: FragmentKind,
         /// Идентификатор абонента
         id: u32,
+        /// Порядковый номер фрагмента в сеансе абонента, начиная с 0
+        seq: u32,
+        /// Длительность звука во фрагменте, мс, 0 если неизвестна
+        duration_ms: u32,
+        /// Момент начала звука во фрагменте по часам системы сопряжения, мкс от начала эпохи Unix, 0 если не задан
+        timestamp_us: u64,
+        /// Параметры звука фрагмента
+        audio: AudioParams,
+        /// Признак последнего фрагмента сеанса
+        last: bool,
         /// Упакованное в байты содержимое фрагмента
         value: Vec<u8>
     }
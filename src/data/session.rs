@@ -1,3 +1,9 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::data::{AudioParams, Metadata};
+use crate::trace::Trace;
+
 /// Собранный из фрагментов звуковой сеанс, готовый для обработки.
 /// Передается по каналу collector --> processor.
 pub enum Session {
@@ -5,9 +11,29 @@ pub enum Session {
     Stop,
     /// Данные готового к обработке сеанса
     Data {
+        /// Порядковый номер системы сопряжения, от которой получен сеанс
+        peer: usize,
         /// Идентификатор абонента
         id: u32,
-        /// Упакованный в байты сеанс, собранный из фрагментов
+        /// Префикс идентификатора сеанса, заданный для системы сопряжения, пустой - не задан
+        prefix: Arc<str>,
+        /// Сведения о сеансе, собранные из заголовков его фрагментов
+        metadata: Arc<Metadata>,
+        /// Порядковый номер части сеанса, 0 если сеанс не разбивался на части
+        chunk: u32,
+        /// Контекст трассы сеанса
+        trace: Trace,
+        /// Момент начала звука по часам системы сопряжения, мкс от начала эпохи Unix, None если не передан
+        peer_time_us: Option<u64>,
+        /// Момент, после которого сеанс устарел и не обрабатывается, None - не ограничен
+        deadline: Option<Instant>,
+        /// Признак последней части сеанса
+        last: bool,
+        /// Параметры звука, объявленные фрагментами сеанса, None - ни один фрагмент не содержал звука
+        audio: Option<AudioParams>,
+        /// Упакованный в байты сеанс или его часть, собранные из фрагментов
         value: Vec<u8>
-    }
+    },
+    /// Несколько готовых сеансов `Data`, переданных одной посылкой
+    Batch(Vec<Session>)
 }
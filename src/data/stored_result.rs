@@ -1,3 +1,20 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use crate::data::Metadata;
+use crate::trace::Trace;
+
+/// Размер заголовка записи результата: id, chunk, признаки, время системы сопряжения.
+/// За ним при признаке `PREFIXED` следуют длина префикса идентификатора сеанса u8 и сам префикс,
+/// затем при признаке `DESCRIBED` - длина сведений о сеансе u32 и сами сведения в виде объекта JSON
+const HEADER: usize = 4 + 4 + 1 + 8;
+/// Признак записи с префиксом идентификатора сеанса, записи без него читаются как прежде
+const PREFIXED: u8 = 8;
+/// Признак записи со сведениями о сеансе
+const DESCRIBED: u8 = 16;
+/// Признак промежуточного результата
+const PARTIAL: u8 = 32;
+
 /// Окончательный результат для передачи в систему хранения.
 /// Передается по каналу inference --> output.
 pub enum StoredResult {
@@ -7,7 +24,146 @@ pub enum StoredResult {
     Data {
         /// Идентификатор абонента
         id: u32,
+        /// Префикс идентификатора сеанса, заданный для системы сопряжения, пустой - не задан
+        prefix: Arc<str>,
+        /// Сведения о сеансе, собранные из заголовков его фрагментов
+        metadata: Arc<Metadata>,
+        /// Порядковый номер части сеанса, к которой относится результат
+        chunk: u32,
+        /// Контекст трассы сеанса
+        trace: Trace,
+        /// Момент начала звука по часам системы сопряжения, мкс от начала эпохи Unix, None если не передан
+        peer_time_us: Option<u64>,
         /// Упакованный в байты embedding
-        value: Vec<u8>
+        value: Vec<u8>,
+        /// Признак результата с уверенностью ниже `min_confidence`
+        low_confidence: bool,
+        /// Признак промежуточного результата потокового вычислителя, за ним следуют другие результаты той же части
+        partial: bool,
+        /// Признак последней порции данных сеанса абонента
+        last: bool
+    }
+}
+
+impl StoredResult {
+
+    /// Запись результата для хранения на диске, для `Stop` - пустая. Контекст трассы не сохраняется
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let StoredResult::Data { id, prefix, metadata, chunk, peer_time_us, value, low_confidence, partial, last, .. } = self {
+            let mut flags = peer_time_us.is_some() as u8 | (*low_confidence as u8) << 1 | (*last as u8) << 2;
+            if *partial {
+                flags |= PARTIAL;
+            }
+            if !prefix.is_empty() {
+                flags |= PREFIXED;
+            }
+            let described = if metadata.is_empty() { Vec::new() } else { serde_json::to_vec(&**metadata).unwrap_or_default() };
+            if !described.is_empty() {
+                flags |= DESCRIBED;
+            }
+            out.reserve(HEADER + 1 + prefix.len() + 4 + described.len() + value.len());
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&chunk.to_le_bytes());
+            out.push(flags);
+            out.extend_from_slice(&peer_time_us.unwrap_or(0).to_le_bytes());
+            if !prefix.is_empty() {
+                // the endpoint option is short, a longer prefix is cut rather than failing the result
+                let p = &prefix.as_bytes()[..prefix.len().min(u8::MAX as usize)];
+                out.push(p.len() as u8);
+                out.extend_from_slice(p);
+            }
+            if !described.is_empty() {
+                out.extend_from_slice(&(described.len() as u32).to_le_bytes());
+                out.extend_from_slice(&described);
+            }
+            out.extend_from_slice(value);
+        }
+        out
+    }
+
+    /// Читает результат из записи [`encode`](StoredResult::encode)
+    pub fn decode(b: &[u8]) -> Result<StoredResult, String> {
+        if b.len() < HEADER {
+            return Err(format!("{} bytes are too short for a result", b.len()));
+        }
+        let flags = b[8];
+        let (prefix, start) = if flags & PREFIXED != 0 {
+            let len = *b.get(HEADER).ok_or("no session prefix length")? as usize;
+            let p = b.get(HEADER + 1..HEADER + 1 + len).ok_or("session prefix is cut")?;
+            let p = std::str::from_utf8(p).map_err(|e| format!("invalid session prefix: {}", e))?;
+            (Arc::from(p), HEADER + 1 + len)
+        } else {
+            (Arc::from(""), HEADER)
+        };
+        let (metadata, start) = if flags & DESCRIBED != 0 {
+            let len = b.get(start..start + 4).ok_or("no session metadata length")?;
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            let m = b.get(start + 4..start + 4 + len).ok_or("session metadata is cut")?;
+            let m: Metadata = serde_json::from_slice(m).map_err(|e| format!("invalid session metadata: {}", e))?;
+            (m, start + 4 + len)
+        } else {
+            (Metadata::new(), start)
+        };
+        Ok(StoredResult::Data {
+            id: u32::from_le_bytes(b[0..4].try_into().unwrap()),
+            prefix,
+            metadata: Arc::new(metadata),
+            chunk: u32::from_le_bytes(b[4..8].try_into().unwrap()),
+            trace: Trace::default(),
+            peer_time_us: Some(u64::from_le_bytes(b[9..17].try_into().unwrap())).filter(|_| flags & 1 != 0),
+            value: b[start..].to_vec(),
+            low_confidence: flags & 2 != 0,
+            partial: flags & PARTIAL != 0,
+            last: flags & 4 != 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(prefix: &str) -> StoredResult {
+        StoredResult::Data { id: 7, prefix: prefix.into(), metadata: Default::default(), chunk: 2, trace: Default::default(),
+            peer_time_us: Some(5), value: vec![1, 2, 3], low_confidence: true, partial: false, last: true }
+    }
+
+    fn prefix(r: StoredResult) -> String {
+        match r {
+            StoredResult::Data { prefix, value, .. } => {
+                assert_eq!(value, vec![1, 2, 3]);
+                prefix.to_string()
+            },
+            StoredResult::Stop => panic!("stop is decoded")
+        }
+    }
+
+    #[test]
+    fn keeps_the_session_prefix() {
+        let b = result("site-a").encode();
+        assert_eq!(b[8] & PREFIXED, PREFIXED);
+        assert_eq!(prefix(StoredResult::decode(&b).unwrap()), "site-a");
+        assert!(StoredResult::decode(&b[..HEADER + 3]).err().unwrap().contains("prefix is cut"));
+    }
+
+    #[test]
+    fn reads_the_record_without_a_prefix() {
+        let b = result("").encode();
+        assert_eq!(b.len(), HEADER + 3);
+        assert_eq!(prefix(StoredResult::decode(&b).unwrap()), "");
+        assert!(StoredResult::decode(&b[..HEADER - 1]).is_err());
+    }
+
+    #[test]
+    fn keeps_the_session_metadata() {
+        let mut m = Metadata::new();
+        m.insert("source".to_string(), "10.0.0.1:12000".to_string());
+        let r = StoredResult::Data { id: 7, prefix: "a".into(), metadata: Arc::new(m.clone()), chunk: 0, trace: Default::default(),
+            peer_time_us: None, value: vec![1, 2, 3], low_confidence: false, partial: false, last: false };
+        match StoredResult::decode(&r.encode()).unwrap() {
+            StoredResult::Data { prefix, metadata, value, .. } => assert_eq!((&*prefix, &*metadata, value), ("a", &m, vec![1, 2, 3])),
+            StoredResult::Stop => panic!("stop is decoded")
+        }
     }
 }
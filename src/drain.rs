@@ -0,0 +1,82 @@
+//! Режим вывода экземпляра из работы для обслуживания, например при поочередном обновлении экземпляров.
+//!
+//! В режиме вывода (`POST /drain` служебного HTTP-сервера) подсистема input закрывает соединения с системами
+//! сопряжения и не подключается к ним, пока режим не снят (`POST /undrain`). Уже полученные данные
+//! обрабатываются и сохраняются как обычно. `GET /health` отвечает в режиме вывода 503, чтобы балансировщик нагрузки
+//! исключил экземпляр, после чего его можно остановить обычным образом
+
+use std::sync::{Mutex, MutexGuard};
+
+use tokio::sync::watch;
+
+/// Признак режима вывода, канал создается при первом обращении
+static STATE: Mutex<Option<(watch::Sender<bool>, watch::Receiver<bool>)>> = Mutex::new(None);
+
+/// Включает (`active`) или снимает режим вывода. Возвращает false, если режим уже был таким
+pub fn set(active: bool) -> bool {
+    let mut s = state();
+    let (tx, rx) = s.get_or_insert_with(|| watch::channel(false));
+    if *rx.borrow() == active {
+        return false;
+    }
+    let _ = tx.broadcast(active);
+    true
+}
+
+/// Включен ли режим вывода
+pub fn is_draining() -> bool {
+    *receiver().borrow()
+}
+
+/// Дожидается включения режима вывода
+pub async fn started() {
+    until(receiver(), true).await
+}
+
+/// Дожидается снятия режима вывода. Вне режима вывода возвращается сразу
+pub async fn resumed() {
+    until(receiver(), false).await
+}
+
+async fn until(mut rx: watch::Receiver<bool>, active: bool) {
+    while *rx.borrow() != active {
+        if rx.recv().await.is_none() {
+            // the sender lives in the static, it is never dropped
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+fn receiver() -> watch::Receiver<bool> {
+    state().get_or_insert_with(|| watch::channel(false)).1.clone()
+}
+
+fn state() -> MutexGuard<'static, Option<(watch::Sender<bool>, watch::Receiver<bool>)>> {
+    STATE.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    // the mode is process wide, the tests of the other modules run along, so it is never turned on here
+    #[tokio::test]
+    async fn out_of_drain_the_input_goes_on() {
+        assert!(!is_draining());
+        assert!(!set(false));
+        resumed().await;
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_mode() {
+        let (tx, rx) = watch::channel(false);
+        until(rx.clone(), false).await;
+        assert!(timeout(Duration::from_millis(10), until(rx.clone(), true)).await.is_err());
+        tx.broadcast(true).unwrap();
+        assert!(timeout(Duration::from_secs(1), until(rx, true)).await.is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+//! Журнал решений, принятых конвейером в отношении отдельных объектов, для аудита и разбора.
+//!
+//! В отличие от журнала работы приложения, каждая запись - объект JSON в отдельной строке файла `[events] path`
+//! с полями `time_ms`, `event` и полями, зависящими от события. Пустой путь отключает журнал.
+//! При точности меток времени `[output] timestamp_precision`, отличной от `ms`, запись несет также
+//! поле `time_<s|us|ns>` с тем же моментом в единицах этой точности
+//! Подсистемы регистрируют события функцией [`emit`](emit) без блокировки, записью в файл занимается отдельный
+//! поток. Если запись не поспевает за событиями, новые события отбрасываются.
+//!
+//! События:
+//! *  `session_incomplete` - незавершенный сеанс передан на обработку (`reason`: `timeout`, `pressure`,
+//!    `stop` - при завершении работы, `input_closed` - при закрытии канала фрагментов,
+//!    `disconnect` - при разрыве соединения с системой сопряжения)
+//! *  `fragment_missing` - сеанс передан на обработку по `session_timeout` без фрагментов с номерами
+//!    от `from` до `to` включительно, по событию на каждый диапазон пропущенных номеров
+//! *  `session_resumed` - сеанс, незавершенный при разрыве соединения, продолжен фрагментом после переподключения
+//! *  `fragment_duplicate` - повторно полученный фрагмент заменил ранее полученный
+//! *  `fragment_late` - отброшен фрагмент уже переданного на обработку сеанса
+//! *  `session_malformed` - сеанс с данными, не согласующимися с параметрами звука, не обработан (`error`)
+//! *  `sample_dedup` - результат сэмпла взят из ранее рассчитанных
+//! *  `deadline_exceeded` - объект отброшен из-за истечения срока обработки (`stage`)
+//! *  `quarantined` - объект сохранен в карантин (`stage`)
+//! *  `inference_failed` - расчет результата завершился ошибкой
+//! *  `backend_reset` - зависший вычислитель создан заново (`attempt`, `ok`, `error`)
+//! *  `low_confidence` - результат ниже порога уверенности отмечен или отброшен (`action`)
+//! *  `shadow_mismatch` - результат теневого вычислителя расходится с основным или рассчитан только одним из них
+//! *  `output_retry` - повтор неудачной записи результата в приемник
+//! *  `result_dropped` - результат отброшен при заполненном канале передачи в output (`reason`: `channel_full`,
+//!    `output_lost`)
+//! *  `output_lost` - канал передачи результатов в output разрушен (`action`: `stop`, `spill`)
+//! *  `result_failed` - результат не удалось сохранить
+
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{SharedConfig, TimestampPrecision};
+use crate::tracker::TaskGuard;
+
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+use tokio::task::{self, JoinHandle};
+
+/// Наибольшее количество событий, ожидающих записи
+const QUEUE: usize = 10_000;
+
+/// Канал передачи событий в поток записи и точность меток времени записей, None - журнал отключен или остановлен
+static SENDER: Mutex<Option<(SyncSender<Value>, TimestampPrecision)>> = Mutex::new(None);
+
+/// Регистрирует событие `event` с полями `fields` (объект JSON). Без включенного журнала ничего не делает
+pub fn emit(event: &str, fields: Value) {
+    let guard = match SENDER.lock() {
+        Ok(g) => g,
+        Err(p) => p.into_inner()
+    };
+    let (tx, precision) = match guard.as_ref() {
+        Some((tx, precision)) => (tx, *precision),
+        None => return
+    };
+    let ns = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    if let Err(TrySendError::Full(_)) = tx.try_send(record(event, fields, ns, precision)) {
+        warn!("events: queue is full, {} event is dropped", event);
+    }
+}
+
+// the record of the event at `ns` from the epoch, the fields follow the time and the event name
+fn record(event: &str, fields: Value, ns: u64, precision: TimestampPrecision) -> Value {
+    let mut record = json!({
+        "time_ms": TimestampPrecision::Ms.of(ns),
+        "event": event
+    });
+    if precision != TimestampPrecision::Ms {
+        record[format!("time_{}", precision)] = json!(precision.of(ns));
+    }
+    if let (Some(r), Value::Object(f)) = (record.as_object_mut(), fields) {
+        r.extend(f);
+    }
+    record
+}
+
+/// Запускает в асинхронном режиме запись журнала событий, если в настройках `[events] path` задан файл
+///
+/// Параметры:
+///
+/// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `rx_stop` - межпоточный канал получения команды на завершение, читатель.
+///   Зарегистрированные к этому моменту события записываются до завершения
+///
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде,
+/// None - журнал отключен или файл не открыт
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, rx_stop: oneshot::Receiver<()>) -> Option<JoinHandle<()>> {
+    let path = cfg.events_path();
+    if path.is_empty() {
+        return None;
+    }
+    let file = match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("events: unable to open {}: {}", path, e);
+            return None;
+        }
+    };
+    info!("start event log to {}", path);
+    let (tx, rx) = sync_channel(QUEUE);
+    *SENDER.lock().unwrap_or_else(|p| p.into_inner()) = Some((tx, cfg.timestamp_precision()));
+
+    // the file is written synchronously, keep it away from the async workers
+    let writer = task::spawn_blocking(move || write_all(rx, BufWriter::new(file)));
+    Some(tokio::spawn(async move {
+        let _guard = guard;
+        let _ = rx_stop.await;
+        info!("stop event log");
+        // the writer ends once the sender is dropped and the queue is drained
+        SENDER.lock().unwrap_or_else(|p| p.into_inner()).take();
+        let _ = writer.await;
+        info!("event log is stopped");
+    }))
+}
+
+fn write_all<W: Write>(rx: Receiver<Value>, mut out: W) {
+    while let Ok(first) = rx.recv() {
+        let mut next = Some(first);
+        // write out what is queued already, then flush once
+        while let Some(record) = next {
+            if let Err(e) = writeln!(out, "{}", record) {
+                error!("events: failed to write: {}", e);
+            }
+            next = rx.try_recv().ok();
+        }
+        if let Err(e) = out.flush() {
+            error!("events: failed to write: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_record_carries_the_time_and_the_fields() {
+        let r = record("quarantined", json!({ "stage": "processor", "id": 7 }), 1_500_000_000, TimestampPrecision::Ms);
+        assert_eq!(r, json!({ "time_ms": 1500, "event": "quarantined", "stage": "processor", "id": 7 }));
+    }
+
+    #[test]
+    fn writes_a_line_per_event() {
+        let (tx, rx) = sync_channel(4);
+        tx.send(json!({ "event": "a" })).unwrap();
+        tx.send(json!({ "event": "b" })).unwrap();
+        drop(tx);
+        let mut out = Vec::new();
+        write_all(rx, &mut out);
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"event\":\"a\"}\n{\"event\":\"b\"}\n");
+    }
+
+    #[test]
+    fn the_record_adds_the_time_of_the_precision() {
+        let r = record("quarantined", json!({}), 1_500_000_123, TimestampPrecision::Us);
+        assert_eq!(r, json!({ "time_ms": 1500, "time_us": 1_500_000, "event": "quarantined" }));
+        assert_eq!(record("a", json!({}), 1_500_000_123, TimestampPrecision::Ns)["time_ns"], 1_500_000_123u64);
+    }
+}
@@ -0,0 +1,35 @@
+//! Признаки неработоспособности подсистем, сообщаемые `GET /health` служебного HTTP-сервера.
+//!
+//! Подсистема, обнаружившая, что не может выполнять свою работу и не восстанавливается сама,
+//! отмечает себя неработоспособной, чтобы балансировщик нагрузки или оператор исключили экземпляр
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Имена неработоспособных подсистем
+static FAILED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Отмечает подсистему `component` неработоспособной (`failed`) или восстановившейся.
+/// Возвращает false, если отметка уже была такой
+pub fn set_failed(component: &'static str, failed: bool) -> bool {
+    let mut f = state();
+    match f.iter().position(|c| *c == component) {
+        Some(i) if !failed => {
+            f.remove(i);
+            true
+        },
+        None if failed => {
+            f.push(component);
+            true
+        },
+        _ => false
+    }
+}
+
+/// Имена неработоспособных подсистем, пусто - все работоспособны
+pub fn failed() -> Vec<&'static str> {
+    state().clone()
+}
+
+fn state() -> MutexGuard<'static, Vec<&'static str>> {
+    FAILED.lock().unwrap_or_else(|p| p.into_inner())
+}
@@ -0,0 +1,269 @@
+//! Служебный HTTP-сервер для контроля работы приложения операторами.
+//!
+//! Точки доступа:
+//! *  `GET /version` - версия приложения, коммит и момент сборки, время работы
+//! *  `GET /health` - состояние для балансировщика нагрузки: 200 `{"status": "ok"}` в работе,
+//!    503 `{"status": "draining"}` в режиме вывода из работы, 503 `{"status": "unhealthy", "failed": [...]}`,
+//!    если подсистемы отмечены неработоспособными ([`health`](crate::health))
+//! *  `POST /drain`, `POST /undrain` - включение и снятие режима вывода экземпляра из работы ([`drain`](crate::drain))
+//! *  `GET /live` - подключение WebSocket, по которому передается сводка каждого сохраненного результата
+//!    в виде объекта JSON, если включено `[http] live`
+//! *  `GET /debug/pprof/profile?seconds=<n>` - профиль загрузки CPU за `n` секунд, по-умолчанию `[http] profile_duration`,
+//!    в свернутом виде для построения flamegraph, если включено `[http] profile` ([`profile`])
+//! *  `POST /inject?id=<n>&rate=<Гц>` - пробный сеанс со звуком из тела запроса передается в processor,
+//!    результат сохраняется как `inject-<id>`, если включено `[http] enable_inject` ([`inject`])
+
+mod inject;
+mod live;
+mod profile;
+mod websocket;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::access::AccessList;
+use crate::config::SharedConfig;
+use crate::data::Session;
+use crate::drain;
+use crate::health;
+use crate::tracker::TaskGuard;
+
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info, warn};
+use serde_json::json;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// Версия приложения
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Короткий хэш коммита, из которого собрано приложение
+pub const GIT_COMMIT: &str = env!("BANSHEE_GIT_COMMIT");
+/// Момент сборки приложения, RFC 3339
+pub const BUILD_TIME: &str = env!("BANSHEE_BUILD_TIME");
+
+/// Общее для всех обработчиков запросов состояние
+struct State {
+    started: Instant,
+    /// Поток сводок сохраняемых результатов, None - выдача отключена
+    live: Option<broadcast::Sender<String>>,
+    /// Длительность снятия профиля CPU по-умолчанию, None - профиль не снимается
+    profile: Option<Duration>,
+    /// Подача пробных сеансов, None - отключена
+    inject: Option<inject::Injector>
+}
+
+/// Запускает в асинхронном режиме служебный HTTP-сервер, если в настройках `[http] listen` задан адрес
+///
+/// Параметры:
+///
+/// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `live` - поток сводок сохраняемых результатов для клиентов `/live`, писатель
+/// * `tx_sess` - межпоточный канал передачи пробных сеансов `/inject` в processor, писатель
+/// * `rx_stop` - межпоточный канал получения команды на завершение, читатель
+///
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде,
+/// None - подсистема отключена или не запущена
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, live: broadcast::Sender<String>, tx_sess: mpsc::Sender<Session>, rx_stop: oneshot::Receiver<()>) -> Option<JoinHandle<()>> {
+    let listen = cfg.http_listen();
+    if listen.is_empty() {
+        info!("http is disabled");
+        return None;
+    }
+    let addr: SocketAddr = match listen.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            error!("http: invalid listen address {}: {}", listen, e);
+            return None;
+        }
+    };
+    let server = match Server::try_bind(&addr) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("http: unable to listen on {}: {}", addr, e);
+            return None;
+        }
+    };
+    info!("start http on {}", addr);
+
+    let state = Arc::new(State {
+        started: Instant::now(),
+        live: if cfg.http_live() { Some(live) } else { None },
+        profile: if cfg.http_profile() { Some(cfg.http_profile_duration()) } else { None },
+        inject: inject::Injector::build(&cfg, tx_sess)
+    });
+    let access = AccessList::new(&cfg);
+
+    Some(tokio::spawn(async move {
+        let _guard = guard;
+
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
+            let state = state.clone();
+            let remote = conn.remote_addr();
+            // a failed service creation makes hyper drop the connection before reading any request
+            let permitted = access.permits(&remote.ip());
+            async move {
+                if !permitted {
+                    warn!("http: connection from {} is rejected by access list", remote);
+                    return Err(format!("{} is not permitted", remote));
+                }
+                Ok(service_fn(move |req| handle(state.clone(), req, remote)))
+            }
+        });
+        let served = server
+            .serve(make_svc)
+            .with_graceful_shutdown(async {
+                let _ = rx_stop.await;
+                info!("stop http");
+            });
+        if let Err(e) = served.await {
+            error!("http: server error: {}", e);
+        }
+        info!("http is stopped");
+
+    }))
+}
+
+async fn handle(state: Arc<State>, req: Request<Body>, remote: SocketAddr) -> Result<Response<Body>, Infallible> {
+    let rsp = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/live") => match state.live.as_ref() {
+            Some(live) => live::upgrade(req, live, remote),
+            None => status_response(StatusCode::NOT_FOUND)
+        },
+        (&Method::GET, "/version") => json_response(json!({
+            "version": VERSION,
+            "git_commit": GIT_COMMIT,
+            "build_time": BUILD_TIME,
+            "uptime_secs": state.started.elapsed().as_secs_f64()
+        })),
+        (&Method::GET, "/health") => health_response(),
+        (&Method::GET, "/debug/pprof/profile") => match state.profile {
+            Some(d) => profile_response(&req, d, remote).await,
+            None => status_response(StatusCode::NOT_FOUND)
+        },
+        (&Method::POST, "/inject") => match state.inject.as_ref() {
+            Some(inject) => inject.accept(req, remote).await,
+            None => status_response(StatusCode::NOT_FOUND)
+        },
+        (&Method::POST, "/drain") => {
+            if drain::set(true) {
+                warn!("http: drain is requested by {}, input is paused", remote);
+            }
+            health_response()
+        },
+        (&Method::POST, "/undrain") => {
+            if drain::set(false) {
+                info!("http: undrain is requested by {}, input is resumed", remote);
+            }
+            health_response()
+        },
+        _ => status_response(StatusCode::NOT_FOUND)
+    };
+    Ok(rsp)
+}
+
+fn json_response(body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// a draining instance is unhealthy for the load balancer to take it out of rotation
+fn health_response() -> Response<Body> {
+    let failed = health::failed();
+    let (status, body) = if !failed.is_empty() {
+        (StatusCode::SERVICE_UNAVAILABLE, json!({ "status": "unhealthy", "failed": failed }))
+    } else if drain::is_draining() {
+        (StatusCode::SERVICE_UNAVAILABLE, json!({ "status": "draining" }))
+    } else {
+        (StatusCode::OK, json!({ "status": "ok" }))
+    };
+    let mut rsp = json_response(body);
+    *rsp.status_mut() = status;
+    rsp
+}
+
+// the profile for `?seconds=<n>` or the default duration; meanwhile the request waits
+async fn profile_response(req: &Request<Body>, default: Duration, remote: SocketAddr) -> Response<Body> {
+    let secs = req.uri().query().unwrap_or("").split('&')
+        .find_map(|p| p.strip_prefix("seconds="))
+        .map(|s| s.parse::<u64>());
+    let duration = match secs {
+        None => default,
+        Some(Ok(s)) if s > 0 => Duration::from_secs(s).min(profile::MAX_DURATION),
+        Some(_) => return status_response(StatusCode::BAD_REQUEST)
+    };
+    info!("http: cpu profile for {:?} is requested by {}", duration, remote);
+    match profile::collect(duration).await {
+        Ok(folded) => Response::builder()
+            .header("Content-Type", "text/plain")
+            .body(Body::from(folded))
+            .unwrap(),
+        Err((status, e)) => {
+            warn!("http: cpu profile is not collected, {}", e);
+            Response::builder()
+                .status(status)
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(status.canonical_reason().unwrap_or("")))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Arc<State> {
+        Arc::new(State { started: Instant::now(), live: None, profile: None, inject: None })
+    }
+
+    async fn get(path: &str) -> Response<Body> {
+        let req = Request::get(path).body(Body::empty()).unwrap();
+        handle(state(), req, "127.0.0.1:1".parse().unwrap()).await.unwrap()
+    }
+
+    async fn json(rsp: Response<Body>) -> serde_json::Value {
+        let body = hyper::body::to_bytes(rsp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_the_version() {
+        let rsp = get("/version").await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(rsp.headers()["Content-Type"], "application/json");
+        let v = json(rsp).await;
+        assert_eq!(v["version"], VERSION);
+        assert_eq!(v["git_commit"], GIT_COMMIT);
+        assert_eq!(v["build_time"], BUILD_TIME);
+        assert!(v["uptime_secs"].as_f64().unwrap() >= 0.0);
+    }
+
+    #[tokio::test]
+    async fn disabled_endpoints_are_not_found() {
+        for path in &["/live", "/debug/pprof/profile", "/unknown"] {
+            assert_eq!(get(path).await.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_working_instance_is_healthy() {
+        let rsp = get("/health").await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(json(rsp).await, json!({ "status": "ok" }));
+        // drain is a post, a get doesn't change the mode
+        assert_eq!(get("/drain").await.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,175 @@
+//! Подача пробного сеанса в конвейер для проверки расчета и сохранения результатов без звука от систем сопряжения.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::SharedConfig;
+use crate::data::{AudioParams, Metadata, Session};
+use crate::memory;
+use crate::shutdown;
+use crate::trace::Trace;
+
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Response, StatusCode};
+use log::{info, warn};
+use serde_json::json;
+use tokio::sync::mpsc::Sender;
+
+/// Наибольший объем звука пробного сеанса
+const MAX_BYTES: usize = 1024 * 1024;
+/// Префикс идентификаторов пробных сеансов, по нему результаты отличаются от результатов реальных сеансов
+pub const PREFIX: &str = "inject";
+
+/// Передает пробные сеансы `POST /inject` в processor, как если бы их собрал collector
+pub struct Injector {
+    tx: Sender<Session>,
+    /// Номер системы сопряжения пробных сеансов, следующий за номерами заданных в `[input] peers`
+    peer: usize,
+    rate: u32,
+    deadline: Option<Duration>,
+    /// Идентификатор следующего сеанса, для которого он не задан в запросе
+    next_id: AtomicU32
+}
+
+impl Injector {
+
+    /// Создает подачу пробных сеансов, если она разрешена настройкой `[http] enable_inject`
+    pub fn build(cfg: &SharedConfig, tx: Sender<Session>) -> Option<Injector> {
+        if !cfg.enable_inject() {
+            return None;
+        }
+        warn!("http: POST /inject is enabled, the injected sessions are stored as {}-<id>", PREFIX);
+        Some(Injector {
+            tx,
+            peer: cfg.peers().len(),
+            rate: cfg.sample_rate(),
+            deadline: Some(cfg.deadline()).filter(|d| *d > Duration::from_secs(0)),
+            next_id: AtomicU32::new(1)
+        })
+    }
+
+    /// Принимает запрос `POST /inject?id=<n>&rate=<Гц>`: тело - звук одного канала в формате
+    /// `[processor] sample_format` и `sample_endian`, не больше 1 МБ. Сеанс передается в processor
+    /// одной последней частью, ответ 202 с идентификатором сеанса, под которым сохраняется результат
+    pub async fn accept(&self, req: Request<Body>, remote: SocketAddr) -> Response<Body> {
+        if shutdown::is_stopping() {
+            return super::status_response(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        let query = req.uri().query().unwrap_or("").to_string();
+        let param = |name: &str| query.split('&').find_map(|p| p.strip_prefix(name).and_then(|v| v.strip_prefix('=')))
+            .map(|v| v.parse::<u32>());
+        let id = match param("id") {
+            None => self.next_id.fetch_add(1, Ordering::Relaxed),
+            Some(Ok(id)) => id,
+            Some(Err(_)) => return super::status_response(StatusCode::BAD_REQUEST)
+        };
+        let rate = match param("rate") {
+            None => self.rate,
+            Some(Ok(r)) if r > 0 => r,
+            Some(_) => return super::status_response(StatusCode::BAD_REQUEST)
+        };
+        let value = match read(req.into_body()).await {
+            Ok(v) if !v.is_empty() => v,
+            Ok(_) => return super::status_response(StatusCode::BAD_REQUEST),
+            Err(status) => return super::status_response(status)
+        };
+        let audio = AudioParams { rate, channels: 1, format: 0 };
+        let mut metadata = Metadata::new();
+        metadata.insert("source".to_string(), format!("inject:{}", remote.ip()));
+        metadata.insert("rate".to_string(), rate.to_string());
+        metadata.insert("channels".to_string(), "1".to_string());
+        let bytes = value.len();
+        let session = Session::Data {
+            peer: self.peer,
+            id,
+            prefix: Arc::from(PREFIX),
+            metadata: Arc::new(metadata),
+            chunk: 0,
+            trace: Trace::default(),
+            peer_time_us: None,
+            deadline: self.deadline.map(|d| Instant::now() + d),
+            last: true,
+            audio: Some(audio),
+            value
+        };
+        let mut tx = self.tx.clone();
+        // charged as a collected session, the processor releases it
+        memory::charge(bytes);
+        if tx.send(session).await.is_err() {
+            memory::release(bytes);
+            return super::status_response(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        info!("http: session {}-{} of {} bytes at {} Hz is injected by {}", PREFIX, id, bytes, rate, remote);
+        let mut rsp = super::json_response(json!({ "session": format!("{}-{}", PREFIX, id), "bytes": bytes }));
+        *rsp.status_mut() = StatusCode::ACCEPTED;
+        rsp
+    }
+}
+
+// the request body within the limit
+async fn read(mut body: Body) -> Result<Vec<u8>, StatusCode> {
+    let mut value = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if value.len() + chunk.len() > MAX_BYTES {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        value.extend_from_slice(&chunk);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    use tokio::sync::mpsc;
+
+    fn post(uri: &str, body: Vec<u8>) -> Request<Body> {
+        Request::post(uri).body(Body::from(body)).unwrap()
+    }
+
+    fn remote() -> SocketAddr {
+        "10.0.0.9:5000".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn passes_the_session_to_the_processor() {
+        let cfg = Config::from_sources(&[], &["http.enable_inject=true", "input.peers=127.0.0.1:12000"]).unwrap();
+        let (tx, mut rx) = mpsc::channel(4);
+        let inject = Injector::build(&cfg, tx).unwrap();
+        let rsp = inject.accept(post("/inject?id=42&rate=16000", vec![1, 2]), remote()).await;
+        assert_eq!(rsp.status(), StatusCode::ACCEPTED);
+        let body = hyper::body::to_bytes(rsp.into_body()).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), json!({ "session": "inject-42", "bytes": 2 }));
+        match rx.recv().await {
+            Some(Session::Data { peer, id, prefix, metadata, last, audio, value, .. }) => {
+                assert_eq!((peer, id, &*prefix, last, value), (1, 42, "inject", true, vec![1, 2]));
+                assert_eq!(audio.map(|a| a.rate), Some(16000));
+                assert_eq!(metadata.get("source").map(|s| s.as_str()), Some("inject:10.0.0.9"));
+            },
+            _ => panic!("no session")
+        }
+        // the id is numbered when not given
+        inject.accept(post("/inject", vec![1, 2]), remote()).await;
+        assert!(matches!(rx.recv().await, Some(Session::Data { id: 1, .. })));
+    }
+
+    #[tokio::test]
+    async fn rejects_the_bad_request() {
+        let cfg = Config::from_sources(&[], &["http.enable_inject=true"]).unwrap();
+        let (tx, _rx) = mpsc::channel(4);
+        let inject = Injector::build(&cfg, tx).unwrap();
+        for (uri, body) in [("/inject?id=x", vec![1]), ("/inject?rate=0", vec![1]), ("/inject", vec![])] {
+            assert_eq!(inject.accept(post(uri, body), remote()).await.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        let rsp = inject.accept(post("/inject", vec![0; MAX_BYTES + 1]), remote()).await;
+        assert_eq!(rsp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let (tx, _) = mpsc::channel(4);
+        assert!(Injector::build(&Config::from_sources(&[], &[]).unwrap(), tx).is_none());
+    }
+}
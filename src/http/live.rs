@@ -0,0 +1,129 @@
+//! Потоковая выдача сводок сохраняемых результатов клиентам WebSocket для наблюдения за работой конвейера.
+
+use std::net::SocketAddr;
+
+use hyper::header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Request, Response, StatusCode};
+use log::{debug, info, warn};
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::sync::mpsc;
+
+use super::websocket::{self, OP_CLOSE, OP_PING, OP_PONG, OP_TEXT};
+
+/// Принимает запрос на подключение клиента WebSocket к потоку сводок `live`.
+/// Клиент получает сводки результатов, сохраненных после подключения. Не успевающий их получать клиент
+/// пропускает сводки, сохранение результатов его не ждет
+pub fn upgrade(req: Request<Body>, live: &broadcast::Sender<String>, remote: SocketAddr) -> Response<Body> {
+    let is_websocket = req.headers().get(UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let key = match (is_websocket, req.headers().get(SEC_WEBSOCKET_KEY).and_then(|v| v.to_str().ok())) {
+        (true, Some(k)) => websocket::accept_key(k),
+        _ => return super::status_response(StatusCode::BAD_REQUEST)
+    };
+    if req.headers().get(SEC_WEBSOCKET_VERSION).map(|v| v.as_bytes()) != Some(b"13") {
+        let mut rsp = super::status_response(StatusCode::UPGRADE_REQUIRED);
+        rsp.headers_mut().insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+        return rsp;
+    }
+    // the summaries produced since the response are all delivered
+    let rx = live.subscribe();
+    tokio::spawn(async move {
+        match req.into_body().on_upgrade().await {
+            Ok(io) => serve(io, rx, remote).await,
+            Err(e) => warn!("http: live client {} failed to upgrade: {}", remote, e)
+        }
+    });
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "Upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, key)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn serve(io: Upgraded, mut rx: broadcast::Receiver<String>, remote: SocketAddr) {
+    info!("http: live client {} is connected", remote);
+    let (mut rd, mut wr) = tokio::io::split(io);
+    // a frame read is not cancel safe, so the client is read apart from the writes
+    let (mut tx_ctl, mut rx_ctl) = mpsc::channel::<(u8, Vec<u8>)>(4);
+    tokio::spawn(async move {
+        loop {
+            let reply = match websocket::read_frame(&mut rd).await {
+                Ok((OP_PING, p)) => (OP_PONG, p),
+                Ok((OP_CLOSE, _)) | Err(_) => (OP_CLOSE, Vec::new()),
+                Ok(_) => continue
+            };
+            let close = reply.0 == OP_CLOSE;
+            if tx_ctl.send(reply).await.is_err() || close {
+                break;
+            }
+        }
+    });
+    let mut skipped = 0u64;
+    let reason = loop {
+        tokio::select! {
+            m = rx.recv() => match m {
+                Ok(summary) => if let Err(e) = websocket::write_frame(&mut wr, OP_TEXT, summary.as_bytes()).await {
+                    break e.to_string();
+                },
+                Err(RecvError::Lagged(n)) => {
+                    debug!("http: live client {} is behind, {} summaries are skipped", remote, n);
+                    skipped += n;
+                },
+                Err(RecvError::Closed) => {
+                    let _ = websocket::write_frame(&mut wr, OP_CLOSE, &[]).await;
+                    break "the server is stopped".to_string();
+                }
+            },
+            c = rx_ctl.recv() => match c {
+                Some((OP_PONG, p)) => if let Err(e) = websocket::write_frame(&mut wr, OP_PONG, &p).await {
+                    break e.to_string();
+                },
+                _ => {
+                    let _ = websocket::write_frame(&mut wr, OP_CLOSE, &[]).await;
+                    break "closed by the client".to_string();
+                }
+            }
+        }
+    };
+    info!("http: live client {} is disconnected: {}, {} summaries skipped", remote, reason, skipped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> Request<Body> {
+        let mut req = Request::builder().uri("/live");
+        for (k, v) in headers {
+            req = req.header(*k, *v);
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    const HANDSHAKE: [(&str, &str); 3] = [("upgrade", "websocket"), ("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="), ("sec-websocket-version", "13")];
+
+    #[tokio::test]
+    async fn accepts_the_websocket_handshake() {
+        let (live, _) = broadcast::channel(4);
+        let remote = "127.0.0.1:5000".parse().unwrap();
+        let rsp = upgrade(request(&HANDSHAKE), &live, remote);
+        assert_eq!(rsp.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(rsp.headers()[SEC_WEBSOCKET_ACCEPT], "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(live.receiver_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_plain_request_or_another_version() {
+        let (live, _) = broadcast::channel(4);
+        let remote = "127.0.0.1:5000".parse().unwrap();
+        assert_eq!(upgrade(request(&[]), &live, remote).status(), StatusCode::BAD_REQUEST);
+        let old = request(&[HANDSHAKE[0], HANDSHAKE[1], ("sec-websocket-version", "8")]);
+        let rsp = upgrade(old, &live, remote);
+        assert_eq!(rsp.status(), StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(rsp.headers()[SEC_WEBSOCKET_VERSION], "13");
+    }
+}
@@ -0,0 +1,452 @@
+//! Профиль загрузки CPU по запросу `GET /debug/pprof/profile?seconds=<n>`, снимаемый без перезапуска приложения.
+//!
+//! Профиль снимается выборкой: SIGPROF приходит 99 раз в секунду процессорного времени процесса, обработчик
+//! запоминает стек прерванного потока, проходя его по указателям кадров от регистров прерванного контекста.
+//! Обработчик только читает память и пишет в канал, проверяя им адреса кадров, все прочее, включая поиск
+//! имен функций, выполняется после выборки. Полные стеки получаются в сборке с указателями кадров
+//! (`RUSTFLAGS="-C force-frame-pointers=yes"`), без них стек обрывается на прерванной функции.
+//! Ответ - стеки в свернутом виде (`main;run;infer 12`, по строке на стек
+//! с количеством попаданий), из которого строится flamegraph (`flamegraph.pl`, `inferno-flamegraph`).
+//! Имена функций берутся из таблицы символов исполняемого файла, для собранного без нее выводятся адреса.
+//! Доступен в сборке с feature `pprof` под Linux с glibc на x86_64 и aarch64, одновременно снимается только один профиль
+
+use std::time::Duration;
+
+use hyper::StatusCode;
+
+/// Наибольшая длительность снятия профиля
+pub const MAX_DURATION: Duration = Duration::from_secs(60);
+
+/// Снимает профиль в течение `duration`, возвращает стеки в свернутом виде
+/// либо код ответа с описанием, почему профиль не снят
+#[cfg(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub async fn collect(duration: Duration) -> Result<String, (StatusCode, String)> {
+    let session = Session::begin()?;
+    sampler::start().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    tokio::time::delay_for(duration.min(MAX_DURATION)).await;
+    sampler::halt();
+    // a handler interrupted by the stop is given the time to finish its stack, without holding the worker
+    tokio::time::delay_for(sampler::SETTLE).await;
+    let (stacks, dropped) = sampler::take();
+    drop(session);
+    if dropped > 0 {
+        log::warn!("http: {} profile samples are dropped, the buffer is full", dropped);
+    }
+    // reading the symbols takes a while, keep it away from the async workers
+    tokio::task::spawn_blocking(move || symbols::fold(&stacks))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("profile failed: {}", e)))
+}
+
+/// Снятие профиля, единственное в процессе: таймер и буфер стеков общие для всего процесса.
+/// Прерванное, например закрытием соединения клиентом, снятие останавливает выборку при освобождении
+#[cfg(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+struct Session;
+
+#[cfg(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+static BUSY: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+impl Session {
+
+    fn begin() -> Result<Session, (StatusCode, String)> {
+        if BUSY.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            return Err((StatusCode::CONFLICT, "a profile is being collected already".to_string()));
+        }
+        Ok(Session)
+    }
+}
+
+#[cfg(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+impl Drop for Session {
+
+    fn drop(&mut self) {
+        sampler::halt();
+        BUSY.store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Заглушка для сборки без feature `pprof`: профиль недоступен
+#[cfg(not(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+pub async fn collect(_duration: Duration) -> Result<String, (StatusCode, String)> {
+    Err((StatusCode::NOT_IMPLEMENTED, "the build has no pprof feature".to_string()))
+}
+
+/// Выборка стеков по сигналу SIGPROF
+#[cfg(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod sampler {
+    use std::mem::{align_of, size_of};
+    use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+
+    use libc::{c_int, c_void};
+
+    /// Частота выборки, раз в секунду процессорного времени
+    const FREQUENCY: i64 = 99;
+    /// Наибольшая глубина стека
+    const DEPTH: usize = 64;
+    /// Наибольшее количество стеков за профиль
+    const CAPACITY: usize = 8192;
+    /// Время, за которое прерванный остановкой обработчик дописывает стек
+    pub const SETTLE: std::time::Duration = std::time::Duration::from_millis(10);
+
+    // the handler can't allocate, the stacks go to the buffer preallocated for the whole profile
+    static FRAMES: [AtomicUsize; CAPACITY * DEPTH] = [const { AtomicUsize::new(0) }; CAPACITY * DEPTH];
+    static LENGTHS: [AtomicUsize; CAPACITY] = [const { AtomicUsize::new(0) }; CAPACITY];
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    static ACTIVE: AtomicBool = AtomicBool::new(false);
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    // the pipe the frame addresses are checked by: a write from unmapped memory fails with EFAULT, not a crash
+    static PROBE: [AtomicI32; 2] = [AtomicI32::new(-1), AtomicI32::new(-1)];
+
+    /// Начинает выборку
+    pub fn start() -> Result<(), String> {
+        // the timer is armed only with the handler in place, the default action of SIGPROF kills the process
+        if !INSTALLED.load(Ordering::SeqCst) {
+            install()?;
+            INSTALLED.store(true, Ordering::SeqCst);
+        }
+        let used = NEXT.swap(0, Ordering::SeqCst).min(CAPACITY);
+        for l in LENGTHS.iter().take(used) {
+            l.store(0, Ordering::SeqCst);
+        }
+        DROPPED.store(0, Ordering::SeqCst);
+        ACTIVE.store(true, Ordering::SeqCst);
+        let period = libc::timeval { tv_sec: 0, tv_usec: (1_000_000 / FREQUENCY) as libc::suseconds_t };
+        set_timer(period).map_err(|e| {
+            ACTIVE.store(false, Ordering::SeqCst);
+            format!("unable to start the profile timer: {}", e)
+        })
+    }
+
+    /// Останавливает выборку, собранные стеки остаются в буфере
+    pub fn halt() {
+        let _ = set_timer(libc::timeval { tv_sec: 0, tv_usec: 0 });
+        ACTIVE.store(false, Ordering::SeqCst);
+    }
+
+    /// Возвращает стеки, собранные остановленной выборкой, вершиной вперед, и количество не поместившихся.
+    /// Вызывается через `SETTLE` после `halt`
+    pub fn take() -> (Vec<Vec<usize>>, usize) {
+        let used = NEXT.load(Ordering::SeqCst).min(CAPACITY);
+        let stacks = (0..used)
+            .map(|i| {
+                let len = LENGTHS[i].load(Ordering::Acquire);
+                (0..len).map(|k| FRAMES[i * DEPTH + k].load(Ordering::Relaxed)).collect()
+            })
+            .filter(|s: &Vec<usize>| !s.is_empty())
+            .collect();
+        (stacks, DROPPED.load(Ordering::SeqCst))
+    }
+
+    // the handler stays for good: a signal pending after the timer is stopped would otherwise kill the process
+    fn install() -> Result<(), String> {
+        let mut fds = [-1; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            return Err(format!("unable to create the profile probe pipe: {}", std::io::Error::last_os_error()));
+        }
+        PROBE[0].store(fds[0], Ordering::SeqCst);
+        PROBE[1].store(fds[1], Ordering::SeqCst);
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGPROF, &action, std::ptr::null_mut()) != 0 {
+                return Err(format!("unable to handle SIGPROF: {}", std::io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_timer(period: libc::timeval) -> std::io::Result<()> {
+        let timer = libc::itimerval { it_interval: period, it_value: period };
+        match unsafe { libc::setitimer(libc::ITIMER_PROF, &timer, std::ptr::null_mut()) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error())
+        }
+    }
+
+    // the interrupted instruction and the frame pointer of the context
+    #[cfg(target_arch = "x86_64")]
+    fn registers(uc: &libc::ucontext_t) -> (usize, usize) {
+        let regs = &uc.uc_mcontext.gregs;
+        (regs[libc::REG_RIP as usize] as usize, regs[libc::REG_RBP as usize] as usize)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn registers(uc: &libc::ucontext_t) -> (usize, usize) {
+        (uc.uc_mcontext.pc as usize, uc.uc_mcontext.regs[29] as usize)
+    }
+
+    // the frame record at `fp`: the frame pointer of the caller and the return address, None if not mapped.
+    // The frame pointer may be a general register in code built without them, the address is checked first
+    fn frame(fp: usize) -> Option<(usize, usize)> {
+        if fp == 0 || !fp.is_multiple_of(align_of::<usize>()) {
+            return None;
+        }
+        let size = 2 * size_of::<usize>();
+        let written = unsafe { libc::write(PROBE[1].load(Ordering::Relaxed), fp as *const c_void, size) };
+        // the probe bytes are drained for the next check, those of a handler on another thread too, only the write tells
+        let mut drain = [0u8; 64];
+        while unsafe { libc::read(PROBE[0].load(Ordering::Relaxed), drain.as_mut_ptr() as *mut c_void, drain.len()) } > 0 {}
+        if written != size as isize {
+            return None;
+        }
+        let words = fp as *const usize;
+        Some(unsafe { (words.read(), words.add(1).read()) })
+    }
+
+    // only async signal safe work here: atomics, reads of the checked memory, write and read of the probe pipe
+    extern "C" fn on_signal(_: c_int, _: *mut libc::siginfo_t, context: *mut c_void) {
+        if !ACTIVE.load(Ordering::Relaxed) || context.is_null() {
+            return;
+        }
+        let i = NEXT.fetch_add(1, Ordering::Relaxed);
+        if i >= CAPACITY {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        // the interrupted code may check errno right after the handler
+        let errno = unsafe { *libc::__errno_location() };
+        let (pc, mut fp) = registers(unsafe { &*(context as *const libc::ucontext_t) });
+        FRAMES[i * DEPTH].store(pc, Ordering::Relaxed);
+        let mut len = 1;
+        while len < DEPTH {
+            let (caller, ret) = match frame(fp) {
+                Some(f) => f,
+                None => break
+            };
+            if ret == 0 {
+                break;
+            }
+            FRAMES[i * DEPTH + len].store(ret, Ordering::Relaxed);
+            len += 1;
+            // the stack grows down, a caller frame below this one is not a frame
+            if caller <= fp {
+                break;
+            }
+            fp = caller;
+        }
+        LENGTHS[i].store(len, Ordering::Release);
+        unsafe { *libc::__errno_location() = errno };
+    }
+}
+
+/// Имена функций по адресам стеков
+#[cfg(all(feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod symbols {
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+    use std::ffi::CStr;
+
+    /// Функция исполняемого файла: адрес начала в памяти, размер, имя
+    type Function = (usize, usize, String);
+
+    /// Сворачивает стеки: по строке `<корень>;...;<вершина> <количество>` на каждый различный стек
+    pub fn fold(stacks: &[Vec<usize>]) -> String {
+        let functions = load();
+        let mut names: HashMap<usize, String> = HashMap::new();
+        let mut folded: HashMap<String, u64> = HashMap::new();
+        for stack in stacks {
+            // a return address points past the call, except the interrupted one on top
+            let addrs: Vec<usize> = stack.iter().enumerate().rev()
+                .map(|(k, a)| if k == 0 { *a } else { a.saturating_sub(1) })
+                .collect();
+            for a in &addrs {
+                names.entry(*a).or_insert_with(|| name(&functions, *a));
+            }
+            let frames: Vec<&str> = addrs.iter().map(|a| names[a].as_str()).collect();
+            *folded.entry(frames.join(";")).or_insert(0) += 1;
+        }
+        let mut lines: Vec<(String, u64)> = folded.into_iter().collect();
+        lines.sort();
+        lines.iter().map(|(s, n)| format!("{} {}\n", s, n)).collect()
+    }
+
+    fn name(functions: &[Function], addr: usize) -> String {
+        let i = functions.partition_point(|f| f.0 <= addr);
+        if i > 0 {
+            let (start, size, name) = &functions[i - 1];
+            if addr < start + (*size).max(1) {
+                return demangle(name);
+            }
+        }
+        // outside the executable: the dynamic symbols of the libraries
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        if unsafe { libc::dladdr(addr as *const libc::c_void, &mut info) } != 0 {
+            if !info.dli_sname.is_null() {
+                return demangle(&unsafe { CStr::from_ptr(info.dli_sname) }.to_string_lossy());
+            }
+            if !info.dli_fname.is_null() {
+                let file = unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy();
+                let file = file.rsplit('/').next().unwrap_or_default().to_string();
+                return format!("[{}+{:#x}]", file, addr - info.dli_fbase as usize);
+            }
+        }
+        format!("[{:#x}]", addr)
+    }
+
+    // the functions of the executable by their addresses in memory, empty if it has no symbol table
+    fn load() -> Vec<Function> {
+        let image = match std::fs::read("/proc/self/exe") {
+            Ok(i) => i,
+            Err(e) => {
+                log::warn!("http: unable to read the executable for the profile symbols: {}", e);
+                return Vec::new();
+            }
+        };
+        // the executable is mapped at the base of the code of this very module
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        let here = load as fn() -> Vec<Function> as *const libc::c_void;
+        if unsafe { libc::dladdr(here, &mut info) } == 0 {
+            return Vec::new();
+        }
+        let mut functions = parse(&image, info.dli_fbase as usize).unwrap_or_default();
+        if functions.is_empty() {
+            log::warn!("http: the executable has no symbol table, the profile shows addresses");
+        }
+        functions.sort_by_key(|f| f.0);
+        functions
+    }
+
+    // the function symbols of an ELF64 little-endian image mapped at `base`
+    fn parse(image: &[u8], base: usize) -> Option<Vec<Function>> {
+        if image.get(..6)? != b"\x7fELF\x02\x01" {
+            return None;
+        }
+        let phoff = u64_at(image, 0x20)? as usize;
+        let shoff = u64_at(image, 0x28)? as usize;
+        let phentsize = u16_at(image, 0x36)? as usize;
+        let phnum = u16_at(image, 0x38)? as usize;
+        let shentsize = u16_at(image, 0x3a)? as usize;
+        let shnum = u16_at(image, 0x3c)? as usize;
+        // the file offset 0 is mapped by the segment at this address, zero for a position independent one
+        let vaddr = (0..phnum)
+            .map(|i| phoff + i * phentsize)
+            .find(|p| u32_at(image, *p) == Some(1) && u64_at(image, p + 0x08) == Some(0))
+            .and_then(|p| u64_at(image, p + 0x10))? as usize;
+        let bias = base.wrapping_sub(vaddr);
+        let sections: Vec<usize> = (0..shnum).map(|i| shoff + i * shentsize).collect();
+        // the full symbol table, the dynamic one of a stripped executable otherwise
+        let symtab = [2, 11].iter()
+            .find_map(|t| sections.iter().find(|s| u32_at(image, **s + 0x04) == Some(*t)))?;
+        let (offset, size) = (u64_at(image, symtab + 0x18)? as usize, u64_at(image, symtab + 0x20)? as usize);
+        let strtab = *sections.get(u32_at(image, symtab + 0x28)? as usize)?;
+        let strings = u64_at(image, strtab + 0x18)? as usize;
+        let mut out = Vec::new();
+        for sym in (offset..offset + size).step_by(24) {
+            let (info, value, len) = (*image.get(sym + 4)?, u64_at(image, sym + 8)?, u64_at(image, sym + 16)?);
+            // functions defined in the image only
+            if info & 0xf != 2 || value == 0 {
+                continue;
+            }
+            let name = strings + u32_at(image, sym)? as usize;
+            let end = name + image.get(name..)?.iter().position(|b| *b == 0)?;
+            let name = String::from_utf8_lossy(&image[name..end]).into_owned();
+            out.push((bias.wrapping_add(value as usize), len as usize, name));
+        }
+        Some(out)
+    }
+
+    // the legacy rust mangling _ZN<len><ident>...17h<hash>E, other names are kept as they are
+    pub fn demangle(name: &str) -> String {
+        let mut rest = match name.strip_prefix("_ZN") {
+            Some(r) => r,
+            None => return name.to_string()
+        };
+        let mut parts = Vec::new();
+        while !rest.starts_with('E') {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let len: usize = match rest[..digits].parse() {
+                Ok(l) if digits + l <= rest.len() => l,
+                _ => return name.to_string()
+            };
+            parts.push(&rest[digits..digits + len]);
+            rest = &rest[digits + len..];
+        }
+        if parts.last().is_some_and(|h| h.len() == 17 && h.starts_with('h')) {
+            parts.pop();
+        }
+        parts.iter().map(|p| unescape(p)).collect::<Vec<_>>().join("::")
+    }
+
+    fn unescape(ident: &str) -> String {
+        let ident = ident.strip_prefix("_$").map(|i| format!("${}", i)).unwrap_or_else(|| ident.to_string());
+        let mut out = String::with_capacity(ident.len());
+        let mut rest = ident.as_str();
+        while let Some(c) = rest.chars().next() {
+            if let (true, Some(end)) = (c == '$', rest[1..].find('$')) {
+                let code = &rest[1..end + 1];
+                let decoded = match code {
+                    "SP" => Some('@'),
+                    "BP" => Some('*'),
+                    "RF" => Some('&'),
+                    "LT" => Some('<'),
+                    "GT" => Some('>'),
+                    "LP" => Some('('),
+                    "RP" => Some(')'),
+                    "C" => Some(','),
+                    _ => code.strip_prefix('u').and_then(|h| u32::from_str_radix(h, 16).ok()).and_then(char::from_u32)
+                };
+                if let Some(d) = decoded {
+                    out.push(d);
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+            if rest.starts_with("..") {
+                out.push_str("::");
+                rest = &rest[2..];
+                continue;
+            }
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        out
+    }
+
+    fn u16_at(b: &[u8], at: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(b.get(at..at + 2)?.try_into().ok()?))
+    }
+
+    fn u32_at(b: &[u8], at: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(b.get(at..at + 4)?.try_into().ok()?))
+    }
+
+    fn u64_at(b: &[u8], at: usize) -> Option<u64> {
+        Some(u64::from_le_bytes(b.get(at..at + 8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(all(test, feature = "pprof", target_os = "linux", target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangles_the_legacy_names() {
+        assert_eq!(symbols::demangle("_ZN7banshee4http7profile7collect17h0123456789abcdefE"), "banshee::http::profile::collect");
+        assert_eq!(symbols::demangle("_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h0123456789abcdefE"),
+            "core::ptr::drop_in_place<alloc::vec::Vec<u8>>");
+        assert_eq!(symbols::demangle("memcpy"), "memcpy");
+        assert_eq!(symbols::demangle("_ZN99broken"), "_ZN99broken");
+    }
+
+    #[test]
+    fn samples_the_busy_thread() {
+        let _session = Session::begin().unwrap();
+        assert!(Session::begin().is_err());
+        sampler::start().unwrap();
+        let started = std::time::Instant::now();
+        let mut x = 0u64;
+        while started.elapsed() < Duration::from_millis(300) {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+        }
+        sampler::halt();
+        std::thread::sleep(sampler::SETTLE);
+        let (stacks, _) = sampler::take();
+        assert!(x != 1);
+        assert!(!stacks.is_empty());
+        assert!(!symbols::fold(&stacks).is_empty());
+    }
+}
@@ -6,48 +6,450 @@
 //! *  запустить TensorRT на сформированных данных
 //! *  получить результат
 //! *  отправить результат в output
+//! 
+//! Вычислитель результата задается в настройках `[inference] backend`, каждый вычислитель реализует
+//! [`InferenceBackend`](backend::InferenceBackend). Если вычислитель недоступен, в зависимости от настройки
+//! `on_unavailable` запуск завершается ошибкой либо сэмплы передаются на сохранение без расчета (passthrough).
+//! Для сэмпла, совпадающего по содержимому с одним из последних `dedup_window` рассчитанных, вычислитель
+//! не вызывается, передается ранее рассчитанный результат.
+//!
+//! Сэмплы рассчитываются пакетами до `batch_size` штук, пакет ожидает заполнения не дольше `batch_timeout`.
+//! В течение `warmup_period` после запуска используются `warmup_batch_size` и `warmup_batch_timeout`,
+//! обычно меньшие, чтобы не задерживать первые сэмплы до выхода на постоянный поток.
+//! При `adaptive_batch` размер пакета после прогрева подбирается по глубине очереди сэмплов
+//! в пределах `min_batch_size..max_batch_size`: растет при глубокой очереди и уменьшается при мелкой.
+//! При `batch_priority = efficiency` пакет, заполненный по истечении `batch_timeout` меньше чем на `min_batch_fill`,
+//! ожидает сэмплы до `batch_max_wait` после первого, сокращая вызовы вычислителя при редком потоке
+//!
+//! Результат, наибольшее значение которого ниже `min_confidence`, в зависимости от настройки `below_threshold`
+//! сохраняется как обычно, сохраняется с отметкой о низкой уверенности либо отбрасывается.
+//! В режиме passthrough уверенность не проверяется
+//!
+//! Для вычислителя с фиксированными длинами входа `input_buckets` сэмпл дополняется нулями до ближайшей
+//! не меньшей длины, вычислителю передается количество значимых векторов. Сэмпл длиннее наибольшей длины
+//! по настройке `oversize` рассчитывается частями с объединением их результатов по порядку либо отбрасывается
+//!
+//! Сэмпл с частотой дискретизации, отличной от ожидаемой моделью `model_rate`, по настройке `on_rate_mismatch`
+//! передискретизируется либо отклоняется с ошибкой. Передискретизировать можно только сигнал, но не признаки
+//!
+//! Сэмплы пакета выбывают из работы по окончании его расчета, освобождая место для следующих сэмплов processor
+//! в пределах `max_in_flight`
+//!
+//! Если канал передачи результатов в output заполнен, по настройке `on_full` расчет ожидает освобождения места,
+//! результат отбрасывается либо сохраняется в дисковую очередь `spill_dir` и передается позже в исходном порядке.
+//! Если канал разрушен, т.е. output завершился, по настройке `on_output_lost` требуется остановка приложения
+//! либо результаты сохраняются в дисковую очередь до следующего запуска. Расчет при этом продолжается до остановки
+//!
+//! Расчет пакета ограничен по времени `infer_timeout`, после `reset_after_timeouts` таймаутов подряд вычислитель
+//! считается зависшим и создается заново. Если пересоздания не помогают, подсистема отмечается неработоспособной
+//!
+//! Выход модели приводится к сохраняемому результату построителем `result_builder`, выбираемым по типу модели:
+//! `raw` сохраняет выход как есть, `argmax` - только лучший класс с его оценкой
+//!
+//! Потоковый вычислитель передает перед окончательным результатом сэмпла промежуточные, по настройке `partial_results`
+//! они передаются в output с отметкой о промежуточном результате либо отбрасываются
+//!
+//! Теневой вычислитель `shadow_backend` рассчитывает те же пакеты после основного в потоке блокирующих операций,
+//! не задерживая основной расчет, его результаты только сравниваются с основными. Пока теневой вычислитель занят
+//! предыдущим пакетом или при очереди сэмплов больше `shadow_max_backlog` теневой расчет пропускается
+//!
+//! Поток расчета пакета по настройке `cpu_affinity` привязывается на время расчета к заданным ядрам CPU,
+//! например одного узла NUMA с GPU ([`affinity`])
+//!
+//! Модель можно проверить без запуска конвейера: `--validate-model <path>` загружает ее вычислителем `backend`,
+//! выводит описание входов и выходов и рассчитывает пробный вход
 
-use crate::config::SharedConfig;
+mod adaptive;
+mod affinity;
+mod backend;
+mod batch;
+mod bucket;
+mod builder;
+mod dedup;
+mod mock;
+mod overflow;
+mod rate;
+mod shadow;
+mod spill;
+mod threshold;
+mod validate;
+mod watchdog;
+
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::{BatchPriority, Oversize, RateMismatch, SharedConfig, Unavailable};
+use crate::quarantine::SharedQuarantine;
+use crate::stats::SharedStats;
+use crate::trace::SharedTracer;
+use crate::tracker::TaskGuard;
 use crate::data::{FinalSample, StoredResult};
+use crate::events;
+use crate::inflight;
+use crate::logger;
+use crate::memory;
+use self::adaptive::Adaptive;
+use self::backend::{InferenceBackend, Input, Partials};
+use self::batch::{BatchParams, Batcher, Efficiency, End, Item};
+use self::bucket::{Buckets, Piece};
+use self::builder::{RawBuilder, ResultBuilder};
+use self::dedup::{Key, ResultCache};
+use self::overflow::Overflow;
+use self::rate::RateCheck;
+use self::shadow::Shadow;
+use self::threshold::{Threshold, Verdict};
+use self::watchdog::Watchdog;
+
+pub use self::validate::validate_model;
 
-use log::{info, error};
+use log::{debug, info, error, warn};
+use serde_json::json;
 use tokio::sync::mpsc::{Sender, Receiver};
+use tokio::task::JoinHandle;
 
 /// Запускает в асинхронном режиме подсистему расчета итогового результата обработки звуковых сэмплов, полученных от процессора
 /// 
 /// Параметры:
 /// 
 /// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
-/// * rx_smpl` - межпоточный канал получения обработанных сэмплов, читатель
-/// * tx_rslt` - межпоточный канал передачи результата в модуль отправки в систему сохранения, писатель
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `stats` - счетчики работы подсистем
+/// * `tracer` - регистратор интервалов трасс сеансов
+/// * `quarantine` - карантин сеансов систем сопряжения с повторяющимися ошибками расчета
+/// * `rx_smpl` - межпоточный канал получения обработанных сэмплов, читатель
+/// * `tx_rslt` - межпоточный канал передачи результата в модуль отправки в систему сохранения, писатель
 /// 
-/// Возвращает Future для ожидания в вызывающем коде
-pub async fn run(_cfg: SharedConfig, mut rx_smpl: Receiver<FinalSample>, _tx_rslt: Sender<StoredResult>) {
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, tracer: SharedTracer, quarantine: SharedQuarantine, mut rx_smpl: Receiver<FinalSample>, tx_rslt: Sender<StoredResult>) -> JoinHandle<()> {
     info!("start inference");
 
+    let backend = open(&cfg).unwrap_or_else(|e| fail(&e));
+    let limit = cfg.infer_timeout();
+    if backend.is_some() && limit > Duration::from_secs(0) {
+        info!("inference: a batch is limited to {:?}, the backend is reset after {} timeouts in a row", limit, cfg.reset_after_timeouts());
+    }
+    let cores = cfg.cpu_affinity();
+    if !cores.is_empty() && !affinity::SUPPORTED {
+        warn!("inference: cpu_affinity {:?} is ignored, the build has no affinity feature or it is not linux", cores);
+    }
+    let mut backend = backend.map(|b| Watchdog::new(&cfg, stats.clone(), b));
+    // the samples passed through aren't model outputs, they are stored as they are
+    let builder: Box<dyn ResultBuilder> = match backend {
+        Some(_) => builder::build(&cfg).unwrap_or_else(|e| fail(&e)),
+        None => Box::new(RawBuilder)
+    };
+    if builder.name() != "raw" {
+        info!("inference: results are built by {}", builder.name());
+    }
+
+    // the shadow compares against the computed results, there is nothing to compare in passthrough mode
+    let mut shadow = backend.as_ref().and_then(|_| Shadow::build(&cfg, stats.clone()));
+    let mut cache = ResultCache::new(cfg.dedup_window());
+    let threshold = Threshold::new(&cfg);
+    let buckets = Buckets::new(&cfg);
+    if !buckets.lengths().is_empty() {
+        info!("inference: inputs are padded to {:?} frames, longer ones are {}", buckets.lengths(),
+            if cfg.oversize() == Oversize::Split { "split" } else { "rejected" });
+    }
+    let mut overflow = Overflow::new(&cfg, tx_rslt, stats.clone());
+    let rate = RateCheck::new(&cfg);
+    if rate.rate() > 0 {
+        info!("inference: the model expects {} Hz, samples at other rates are {}", rate.rate(),
+            if cfg.on_rate_mismatch() == RateMismatch::Resample { "resampled" } else { "rejected" });
+    }
+    let partial_results = cfg.partial_results();
+    if partial_results {
+        info!("inference: partial results of streaming backends are passed on before the final ones");
+    }
+    if cfg.batch_priority() == BatchPriority::Efficiency {
+        info!("inference: batches under {} of their size wait for samples up to {:?}", cfg.min_batch_fill(), cfg.batch_max_wait());
+    }
+    let mut batcher = Batcher::new(
+        BatchParams::new(cfg.batch_size(), cfg.batch_timeout()),
+        BatchParams::new(cfg.warmup_batch_size(), cfg.warmup_batch_timeout()),
+        cfg.warmup_period(),
+        Adaptive::build(&cfg),
+        Efficiency::build(&cfg),
+        Instant::now()
+    );
+
     tokio::spawn(async move {
+        let _guard = guard;
 
         loop {
-            match rx_smpl.recv().await {
-                None => {
-                    error!("final samples input channel is broken");
+            let params = batcher.params(Instant::now(), stats.samples_backlog());
+            stats.inference_metrics.set_batch_limit(params.size);
+            let (items, end) = batch::collect(&mut rx_smpl, params).await;
+            let received = items.len();
+            let bytes: usize = items.iter().map(|i| i.value.len()).sum();
+            stats.inference.add_received(received);
+            let (items, held): (Vec<Item>, Vec<Item>) = items.into_iter().partition(|i| !quarantine.is_held(i.peer));
+            for i in held {
+                quarantine.hold(i.peer, i.id, i.chunk, "inference", &i.value);
+                stats.inference.dropped();
+            }
+            let now = Instant::now();
+            let (items, expired): (Vec<Item>, Vec<Item>) = items.into_iter().partition(|i| i.deadline.is_none_or(|d| now < d));
+            for i in expired {
+                warn!("inference: sample {} is dropped, deadline exceeded", i.id);
+                events::emit("deadline_exceeded", json!({ "stage": "inference", "peer": i.peer, "id": i.id, "chunk": i.chunk }));
+                stats.inference.expired();
+            }
+            // the passthrough stores samples as they are, there is no model to conform to
+            let items: Vec<Item> = items.into_iter()
+                .filter_map(|mut i| match backend.as_ref().map_or(Ok(()), |_| rate.conform(&mut i)) {
+                    Ok(()) => Some(i),
+                    Err(e) => {
+                        error!("inference: sample {} is rejected, {}", i.id, e);
+                        events::emit("inference_failed", json!({ "peer": i.peer, "id": i.id, "chunk": i.chunk, "error": e }));
+                        stats.inference.dropped();
+                        None
+                    }
+                })
+                .collect();
+            if !items.is_empty() {
+                debug!("inference: batch of {} samples", items.len());
+            }
+            let started = SystemTime::now();
+            let (results, partials) = match backend.as_mut() {
+                None => (items.iter().map(|i| Ok(i.value.clone())).collect(), vec![Vec::new(); items.len()]),
+                Some(b) => {
+                    if !items.is_empty() {
+                        stats.inference_metrics.batch(items.len());
+                    }
+                    let r = infer_watched(b, &mut cache, &buckets, &items).await;
+                    if let Some(load) = b.device_load() {
+                        stats.inference_metrics.set_device(load);
+                    }
+                    r
+                }
+            };
+            if let Some(s) = shadow.as_mut() {
+                s.compare(&items, &results);
+            }
+            for ((item, r), partials) in items.into_iter().zip(results).zip(partials) {
+                let value = match r {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("inference: sample {} is dropped, {}", item.id, e);
+                        events::emit("inference_failed", json!({ "peer": item.peer, "id": item.id, "chunk": item.chunk, "error": e }));
+                        stats.inference.dropped();
+                        quarantine.failure(item.peer);
+                        continue;
+                    }
+                };
+                quarantine.success(item.peer);
+                let verdict = if backend.is_some() { threshold.check(&value) } else { Verdict::Pass };
+                match verdict {
+                    Verdict::Pass => {},
+                    Verdict::Flag => {
+                        debug!("inference: result of {} is below the confidence threshold", item.id);
+                        events::emit("low_confidence", json!({ "action": "flag", "peer": item.peer, "id": item.id, "chunk": item.chunk }));
+                        stats.low_confidence.flagged();
+                    },
+                    Verdict::Drop => {
+                        debug!("inference: result of {} is below the confidence threshold and dropped", item.id);
+                        events::emit("low_confidence", json!({ "action": "drop", "peer": item.peer, "id": item.id, "chunk": item.chunk }));
+                        stats.low_confidence.dropped();
+                        stats.inference.dropped();
+                        continue;
+                    }
+                }
+                tracer.stage(&item.trace, "inference", started, item.id, item.chunk);
+                // the partial results of a failed or dropped sample are of no use, they go only ahead of the final one
+                for value in partials.into_iter().filter(|_| partial_results) {
+                    match builder.build(&item, value, false, true) {
+                        Ok(rslt) => overflow.send(rslt).await,
+                        Err(e) => debug!("inference: partial result of {} is dropped, {}", item.id, e)
+                    }
+                }
+                let rslt = match builder.build(&item, value, verdict == Verdict::Flag, false) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!("inference: result of {} is dropped, {}", item.id, e);
+                        events::emit("inference_failed", json!({ "peer": item.peer, "id": item.id, "chunk": item.chunk, "error": e }));
+                        stats.inference.dropped();
+                        continue;
+                    }
+                };
+                overflow.send(rslt).await;
+            }
+            // every sample of the batch is stored or dropped by now
+            inflight::release(received);
+            memory::release(bytes);
+            match end {
+                None => {},
+                Some(End::Stop) => {
+                    info!("stop inference");
                     break;
                 },
-                Some(f) => {
-                    match f {
-                        FinalSample::Stop => {
-                            info!("stop inference");
-                            break;
-                        },
-                        _ => {
-                            //todo: handle sample
-                            info!("inference: handling samples is not implemented yet");
-                        }
-                    }                    
+                Some(End::Broken) => {
+                    error!("final samples input channel is broken");
+                    break;
                 }
             }
-        }    
+        }
+        inflight::close();
         info!("inference is stopped");
 
-    });
+    })
+}
+
+// the backend by `[inference] backend`, None - it is unavailable and the samples are passed through
+fn open(cfg: &SharedConfig) -> Result<Option<Box<dyn InferenceBackend>>, String> {
+    match backend::build(cfg) {
+        Ok(b) => {
+            info!("inference: {} backend", b.name());
+            Ok(Some(b))
+        },
+        Err(e) => match cfg.on_unavailable() {
+            Unavailable::Fail => Err(e),
+            Unavailable::Passthrough => {
+                warn!("inference: {}", e);
+                warn!("inference: !!! PASSTHROUGH MODE, samples are stored as is without inference !!!");
+                Ok(None)
+            }
+        }
+    }
+}
+
+// the start can't go on, it ends as on a config error
+fn fail(e: &str) -> ! {
+    error!("inference: {}", e);
+    // exit skips the destructors, the guard won't flush
+    logger::flush();
+    std::process::exit(2);
+}
+
+// computes the results of the batch, the samples repeating the recent ones are served from the cache
+fn infer(backend: &mut dyn InferenceBackend, cache: &mut ResultCache, buckets: &Buckets, items: &[Item]) -> Vec<Result<Vec<u8>, String>> {
+    let prepared = Prepared::new(cache, buckets, items);
+    let computed = if prepared.is_empty() { Vec::new() } else { backend.infer_batch(&prepared.inputs(items)) };
+    prepared.finish(cache, computed)
+}
+
+// the same by the watched primary backend, the batch fails at once on a timeout.
+// The partial results of a streaming backend are returned by sample
+async fn infer_watched(watchdog: &mut Watchdog, cache: &mut ResultCache, buckets: &Buckets, items: &[Item]) -> (Vec<Result<Vec<u8>, String>>, Vec<Vec<Vec<u8>>>) {
+    let prepared = Prepared::new(cache, buckets, items);
+    let (computed, partials) = if prepared.is_empty() {
+        (Vec::new(), Partials::new())
+    } else {
+        watchdog.infer_batch(&prepared.inputs(items)).await
+    };
+    let partials = prepared.partials(items.len(), partials);
+    (prepared.finish(cache, computed), partials)
+}
+
+// the batch split into the results served from the cache and the pieces of the rest to compute
+struct Prepared<'a> {
+    keys: Vec<Option<Key>>,
+    results: Vec<Option<Result<Vec<u8>, String>>>,
+    pieces: Vec<Piece<'a>>,
+    // the index of a sample and the count of its pieces
+    misses: Vec<(usize, usize)>
+}
+
+impl<'a> Prepared<'a> {
+
+    fn new(cache: &mut ResultCache, buckets: &Buckets, items: &'a [Item]) -> Prepared<'a> {
+        let keys: Vec<_> = items.iter().map(|i| cache.key(&i.value, i.dim)).collect();
+        let mut results: Vec<Option<Result<Vec<u8>, String>>> = keys.iter().zip(items)
+            .map(|(k, i)| {
+                let hit = k.as_ref().and_then(|k| cache.get(k));
+                if hit.is_some() {
+                    debug!("inference: sample {} duplicates a recent one, the result is reused", i.id);
+                    events::emit("sample_dedup", json!({ "peer": i.peer, "id": i.id, "chunk": i.chunk }));
+                }
+                hit.map(Ok)
+            })
+            .collect();
+        // every miss is computed in one or more pieces fitting the input buckets, all in one batch
+        let mut pieces = Vec::new();
+        let mut misses = Vec::new();
+        for n in 0..items.len() {
+            if results[n].is_some() {
+                continue;
+            }
+            let item = &items[n];
+            match buckets.fit(&item.value, item.dim) {
+                Ok(p) => {
+                    let shape: Vec<usize> = p.iter().map(|p| p.value.len() / (4 * item.dim).max(1)).collect();
+                    if shape.iter().sum::<usize>() * 4 * item.dim != item.value.len() || p.len() > 1 {
+                        debug!("inference: sample {} is passed as {:?} frames", item.id, shape);
+                    }
+                    misses.push((n, p.len()));
+                    pieces.extend(p);
+                },
+                Err(e) => results[n] = Some(Err(e))
+            }
+        }
+        Prepared {
+            keys,
+            results,
+            pieces,
+            misses
+        }
+    }
+
+    // nothing to compute
+    fn is_empty(&self) -> bool {
+        self.misses.is_empty()
+    }
+
+    // the backend inputs of the pieces in order
+    fn inputs(&self, items: &[Item]) -> Vec<Input<'_>> {
+        self.pieces.iter().zip(self.misses.iter().flat_map(|(n, count)| std::iter::repeat_n(*n, *count)))
+            .map(|(p, n)| Input { value: &p.value, dim: items[n].dim, frames: p.frames })
+            .collect()
+    }
+
+    // the partial results by sample, a sample computed in several pieces has none: the partial result
+    // of a piece is not one of the sample
+    fn partials(&self, samples: usize, partials: Partials) -> Vec<Vec<Vec<u8>>> {
+        let mut by_input = Vec::new();
+        for (n, count) in &self.misses {
+            by_input.extend(std::iter::repeat_n(Some(*n).filter(|_| *count == 1), *count));
+        }
+        let mut by_sample = vec![Vec::new(); samples];
+        for (i, value) in partials {
+            if let Some(Some(n)) = by_input.get(i) {
+                by_sample[*n].push(value);
+            }
+        }
+        by_sample
+    }
+
+    fn finish(mut self, cache: &mut ResultCache, computed: Vec<Result<Vec<u8>, String>>) -> Vec<Result<Vec<u8>, String>> {
+        let mut computed = computed.into_iter();
+        for (n, count) in self.misses {
+            // the results of the pieces are joined in order, a failed piece fails the sample
+            let parts: Vec<_> = computed.by_ref().take(count).collect();
+            if parts.len() < count {
+                continue;
+            }
+            let r = parts.into_iter().collect::<Result<Vec<_>, _>>().map(|parts| parts.concat());
+            if let (Ok(v), Some(k)) = (&r, self.keys[n]) {
+                cache.put(k, v.clone());
+            }
+            self.results[n] = Some(r);
+        }
+        self.results.into_iter()
+            .map(|r| r.unwrap_or_else(|| Err("no result from the backend".to_string())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    #[test]
+    fn the_unavailable_backend_fails_or_passes_through() {
+        let open = |sets: &[&str]| open(&Config::from_sources(&[], sets).unwrap()).map(|b| b.map(|b| b.name()));
+        assert_eq!(open(&[]), Ok(Some("mock")));
+        assert!(open(&["inference.backend=tensorrt"]).unwrap_err().contains("not available"));
+        assert_eq!(open(&["inference.backend=tensorrt", "inference.on_unavailable=passthrough"]), Ok(None));
+    }
 }
@@ -0,0 +1,104 @@
+//! Подбор размера пакета расчета по глубине очереди сэмплов.
+
+use crate::config::SharedConfig;
+
+use log::debug;
+
+/// Пакетов подряд с глубокой или мелкой очередью, после которых размер пакета меняется
+const STREAK: u32 = 3;
+
+/// Размер пакета, подбираемый по глубине очереди сэмплов в пределах `[inference] min_batch_size..max_batch_size`.
+/// Пока очередь несколько пакетов подряд вмещает еще хотя бы один полный пакет, размер удваивается
+/// ради пропускной способности вычислителя. Пока очередь несколько пакетов подряд меньше половины пакета,
+/// размер уменьшается вдвое, чтобы сэмплы не ожидали заполнения пакета
+pub struct Adaptive {
+    min: usize,
+    max: usize,
+    size: usize,
+    deep: u32,
+    shallow: u32
+}
+
+impl Adaptive {
+
+    /// Создает подбор по настройкам, None - подбор не задан
+    pub fn build(cfg: &SharedConfig) -> Option<Adaptive> {
+        if !cfg.adaptive_batch() {
+            return None;
+        }
+        Some(Adaptive::new(cfg.min_batch_size(), cfg.max_batch_size(), cfg.batch_size()))
+    }
+
+    /// Подбор в пределах `min..max`, начиная с `start`
+    pub fn new(min: usize, max: usize, start: usize) -> Adaptive {
+        Adaptive {
+            min,
+            max,
+            size: start.max(min).min(max),
+            deep: 0,
+            shallow: 0
+        }
+    }
+
+    pub fn min(&self) -> usize {
+        self.min
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Размер следующего пакета при `backlog` сэмплах в очереди
+    pub fn next(&mut self, backlog: u64) -> usize {
+        let size = self.size as u64;
+        if backlog >= size {
+            self.deep += 1;
+            self.shallow = 0;
+        } else if backlog < size / 2 {
+            self.shallow += 1;
+            self.deep = 0;
+        } else {
+            self.deep = 0;
+            self.shallow = 0;
+        }
+        let size = if self.deep >= STREAK {
+            (self.size * 2).min(self.max)
+        } else if self.shallow >= STREAK {
+            (self.size / 2).max(self.min)
+        } else {
+            self.size
+        };
+        if size != self.size {
+            debug!("inference: batch size {} -> {}, {} samples queued", self.size, size, backlog);
+            self.size = size;
+            self.deep = 0;
+            self.shallow = 0;
+        }
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_queue_depth_within_the_limits() {
+        let mut a = Adaptive::new(2, 8, 4);
+        // a single deep queue is not a streak
+        assert_eq!(a.next(4), 4);
+        assert_eq!(a.next(1), 4);
+        assert_eq!((a.next(4), a.next(4), a.next(4)), (4, 4, 8));
+        assert_eq!((a.next(100), a.next(100), a.next(100)), (8, 8, 8));
+        // half a batch and more keeps the size
+        assert_eq!((a.next(4), a.next(4), a.next(4)), (8, 8, 8));
+        assert_eq!((a.next(3), a.next(3), a.next(3)), (8, 8, 4));
+        assert_eq!((a.next(0), a.next(0), a.next(0), a.next(0), a.next(0), a.next(0)), (4, 4, 2, 2, 2, 2));
+    }
+
+    #[test]
+    fn starts_within_the_limits() {
+        assert_eq!(Adaptive::new(2, 8, 1).next(1), 2);
+        assert_eq!(Adaptive::new(2, 8, 64).next(8), 8);
+    }
+}
@@ -0,0 +1,163 @@
+//! Привязка расчета пакета к ядрам CPU `[inference] cpu_affinity`.
+//!
+//! Пакет рассчитывается в потоке блокирующих операций, общем с прочими подсистемами, поэтому поток привязывается
+//! к ядрам только на время расчета, затем его прежняя привязка восстанавливается.
+//! Доступна в сборке с feature `affinity` под Linux, в остальных сборках настройка игнорируется с предупреждением
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{info, warn};
+
+/// Выполняется ли привязка в этой сборке
+pub const SUPPORTED: bool = platform::SUPPORTED;
+
+/// Привязка текущего потока к ядрам, при уничтожении восстанавливает прежнюю
+pub struct Pinned {
+    /// Прежняя привязка, None - поток не привязан
+    saved: Option<platform::Mask>
+}
+
+impl Drop for Pinned {
+
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved.as_ref() {
+            let _ = platform::set(saved);
+        }
+    }
+}
+
+/// Привязывает текущий поток к ядрам `cores` до уничтожения результата, пустой список или сборка
+/// без привязки - поток не привязывается. Первая привязка в процессе записывает в журнал ядра,
+/// о которых сообщает сам поток после привязки: ядра из списка, отсутствующие в системе, в них не попадают
+pub fn pin(cores: &[usize]) -> Pinned {
+    /// Результат привязки уже записан в журнал
+    static REPORTED: AtomicBool = AtomicBool::new(false);
+
+    // the unsupported setting is reported once at the start
+    if cores.is_empty() || !SUPPORTED {
+        return Pinned { saved: None };
+    }
+    let saved = match platform::get() {
+        Ok(s) => s,
+        Err(e) => {
+            if !REPORTED.swap(true, Ordering::Relaxed) {
+                warn!("inference: cpu affinity is not read, batches are computed on any core: {}", e);
+            }
+            return Pinned { saved: None };
+        }
+    };
+    if let Err(e) = platform::set(&platform::mask(cores)) {
+        if !REPORTED.swap(true, Ordering::Relaxed) {
+            warn!("inference: thread is not pinned to cpu cores {:?}, batches are computed on any core: {}", cores, e);
+        }
+        return Pinned { saved: None };
+    }
+    if !REPORTED.swap(true, Ordering::Relaxed) {
+        match platform::get().map(|m| platform::cores(&m)) {
+            Ok(actual) if actual == cores => info!("inference: batches are computed on cpu cores {:?}", actual),
+            Ok(actual) => warn!("inference: batches are computed on cpu cores {:?}, the rest of {:?} are not available", actual, cores),
+            Err(e) => warn!("inference: cpu affinity of the pinned thread is not read: {}", e)
+        }
+    }
+    Pinned { saved: Some(saved) }
+}
+
+/// Привязка потоков системными вызовами Linux
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+mod platform {
+    use std::mem;
+
+    use libc::cpu_set_t;
+
+    pub const SUPPORTED: bool = true;
+
+    /// Набор ядер
+    pub type Mask = cpu_set_t;
+
+    /// Привязка текущего потока
+    pub fn get() -> Result<cpu_set_t, String> {
+        unsafe {
+            let mut set: cpu_set_t = mem::zeroed();
+            if libc::sched_getaffinity(0, mem::size_of::<cpu_set_t>(), &mut set) != 0 {
+                return Err(std::io::Error::last_os_error().to_string());
+            }
+            Ok(set)
+        }
+    }
+
+    /// Привязывает текущий поток
+    pub fn set(set: &cpu_set_t) -> Result<(), String> {
+        if unsafe { libc::sched_setaffinity(0, mem::size_of::<cpu_set_t>(), set) } != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(())
+    }
+
+    /// Набор из ядер `cores`
+    pub fn mask(cores: &[usize]) -> cpu_set_t {
+        unsafe {
+            let mut set: cpu_set_t = mem::zeroed();
+            // a core beyond the set size isn't there anyway
+            for c in cores.iter().filter(|c| **c < libc::CPU_SETSIZE as usize) {
+                libc::CPU_SET(*c, &mut set);
+            }
+            set
+        }
+    }
+
+    /// Ядра набора по возрастанию
+    pub fn cores(set: &cpu_set_t) -> Vec<usize> {
+        (0..libc::CPU_SETSIZE as usize).filter(|c| unsafe { libc::CPU_ISSET(*c, set) }).collect()
+    }
+}
+
+/// Заглушка для сборки без feature `affinity` или не под Linux: поток не привязывается
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+mod platform {
+    pub const SUPPORTED: bool = false;
+
+    pub struct Mask;
+
+    pub fn get() -> Result<Mask, String> {
+        Err("cpu affinity is not supported by this build".to_string())
+    }
+
+    pub fn set(_set: &Mask) -> Result<(), String> {
+        Err("cpu affinity is not supported by this build".to_string())
+    }
+
+    pub fn mask(_cores: &[usize]) -> Mask {
+        Mask
+    }
+
+    pub fn cores(_set: &Mask) -> Vec<usize> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_cores_leave_the_thread_as_is() {
+        assert!(pin(&[]).saved.is_none());
+    }
+
+    #[cfg(all(feature = "affinity", target_os = "linux"))]
+    #[test]
+    fn pins_for_the_batch_and_restores() {
+        let before = platform::cores(&platform::get().unwrap());
+        let first = before[0];
+        let pinned = pin(&[first]);
+        assert_eq!(platform::cores(&platform::get().unwrap()), vec![first]);
+        drop(pinned);
+        assert_eq!(platform::cores(&platform::get().unwrap()), before);
+    }
+
+    #[cfg(not(all(feature = "affinity", target_os = "linux")))]
+    #[test]
+    fn the_build_without_affinity_ignores_the_cores() {
+        assert!(pin(&[0]).saved.is_none());
+    }
+}
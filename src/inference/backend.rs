@@ -0,0 +1,99 @@
+//! Общий интерфейс вычислителя результата и его выбор по конфигурации.
+
+use crate::config::SharedConfig;
+use crate::stats::DeviceLoad;
+
+use super::mock::MockBackend;
+
+/// Вход вычислителя: векторы f32 размерности `dim`, уложенные подряд.
+/// Значимы первые `frames` векторов, остальные - выравнивание нулями до фиксированной длины входа,
+/// которое не должно учитываться в результате
+pub struct Input<'a> {
+    pub value: &'a [u8],
+    pub dim: usize,
+    pub frames: usize
+}
+
+/// Промежуточные результаты пакета по порядку получения: номер входа в пакете и результат
+pub type Partials = Vec<(usize, Vec<u8>)>;
+
+/// Вход или выход модели вычислителя
+pub struct Tensor {
+    pub name: String,
+    /// Размеры по осям, -1 - задается входом при расчете
+    pub shape: Vec<i64>,
+    pub dtype: &'static str
+}
+
+/// Описание входов и выходов модели вычислителя
+pub struct ModelInfo {
+    pub inputs: Vec<Tensor>,
+    pub outputs: Vec<Tensor>
+}
+
+/// Вычислитель итогового результата по подготовленному сэмплу
+pub trait InferenceBackend: Send {
+
+    /// Имя вычислителя, под которым он указывается в настройках
+    fn name(&self) -> &'static str;
+
+    /// Входы и выходы загруженной модели
+    fn model_info(&self) -> ModelInfo;
+
+    /// Рассчитывает результат по входу, возвращает упакованный результат
+    fn infer(&mut self, input: &Input) -> Result<Vec<u8>, String>;
+
+    /// Рассчитывает результаты пакета входов в том же порядке.
+    /// По-умолчанию входы рассчитываются по одному
+    fn infer_batch(&mut self, batch: &[Input]) -> Vec<Result<Vec<u8>, String>> {
+        batch.iter().map(|input| self.infer(input)).collect()
+    }
+
+    /// Рассчитывает результаты пакета входов, как `infer_batch`. Потоковая модель, выдающая результат по частям,
+    /// передает промежуточные результаты по мере получения в `partial` вместе с номером входа в пакете,
+    /// окончательные результаты возвращаются как обычно. По-умолчанию промежуточных результатов нет
+    fn infer_batch_streamed(&mut self, batch: &[Input], _partial: &mut dyn FnMut(usize, Vec<u8>)) -> Vec<Result<Vec<u8>, String>> {
+        self.infer_batch(batch)
+    }
+
+    /// Текущая загрузка GPU, на котором выполняется расчет, None - не выполняется на GPU или неизвестна
+    fn device_load(&self) -> Option<DeviceLoad> {
+        None
+    }
+}
+
+/// Создает вычислитель, заданный в настройках `[inference] backend`.
+/// Ошибка означает, что вычислитель недоступен: неизвестен, не поддерживается сборкой или не найдена модель
+pub fn build(cfg: &SharedConfig) -> Result<Box<dyn InferenceBackend>, String> {
+    create(&cfg.inference_backend(), &cfg.model())
+}
+
+/// Создает вычислитель `name` с моделью из файла `model`
+pub fn create(name: &str, model: &str) -> Result<Box<dyn InferenceBackend>, String> {
+    match name {
+        "mock" => Ok(Box::new(MockBackend::new())),
+        "mock_stream" => Ok(Box::new(MockBackend::streaming())),
+        "tensorrt" => Err(format!("tensorrt backend is not available in this build, model '{}'", model)),
+        b => Err(format!("unknown inference backend '{}'", b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_the_backend_by_name() {
+        assert_eq!(create("mock", "").unwrap().name(), "mock");
+        assert!(create("tensorrt", "model.plan").err().unwrap().contains("not available"));
+        assert!(create("onnx", "").err().unwrap().contains("unknown"));
+    }
+
+    #[test]
+    fn infers_the_batch_in_order() {
+        let (a, b) = (1f32.to_le_bytes(), 3f32.to_le_bytes());
+        let batch = [Input { value: &a, dim: 1, frames: 1 }, Input { value: &b, dim: 1, frames: 1 }];
+        let r = create("mock", "").unwrap().infer_batch(&batch);
+        assert_eq!(r, vec![Ok(a.to_vec()), Ok(b.to_vec())]);
+    }
+}
@@ -0,0 +1,288 @@
+//! Накопление сэмплов в пакеты для совместного расчета.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::timeout;
+
+use crate::config::{BatchPriority, SharedConfig};
+use crate::data::{FinalSample, Metadata};
+use crate::trace::Trace;
+
+use super::adaptive::Adaptive;
+
+/// Сэмпл в составе пакета
+#[derive(Clone)]
+pub struct Item {
+    pub peer: usize,
+    pub id: u32,
+    pub prefix: Arc<str>,
+    pub metadata: Arc<Metadata>,
+    pub chunk: u32,
+    pub trace: Trace,
+    pub peer_time_us: Option<u64>,
+    pub deadline: Option<Instant>,
+    pub value: Vec<u8>,
+    pub dim: usize,
+    pub rate: u32,
+    pub last: bool
+}
+
+/// Причина завершения накопления пакета помимо его заполнения или истечения времени ожидания
+#[derive(PartialEq)]
+pub enum End {
+    /// Получена команда на завершение работы
+    Stop,
+    /// Канал получения сэмплов разрушен
+    Broken
+}
+
+/// Параметры накопления пакета
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BatchParams {
+    /// Наибольшее количество сэмплов в пакете
+    pub size: usize,
+    /// Наибольшее время ожидания заполнения пакета после получения первого сэмпла
+    pub timeout: Duration,
+    /// Сэмплов, меньше которых пакет по истечении `timeout` ожидает еще, 0 - не ожидает
+    pub fill: usize,
+    /// Предельное время ожидания минимального заполнения после получения первого сэмпла
+    pub max_wait: Duration
+}
+
+impl BatchParams {
+
+    /// Параметры пакета, рассчитываемого по истечении `timeout` в любом случае
+    pub fn new(size: usize, timeout: Duration) -> BatchParams {
+        BatchParams {
+            size,
+            timeout,
+            fill: 0,
+            max_wait: timeout
+        }
+    }
+}
+
+/// Ожидание минимального заполнения пакета по настройке `[inference] batch_priority = efficiency`:
+/// пакет, в котором по истечении `batch_timeout` меньше `min_batch_fill` его размера, ожидает сэмплы
+/// до `batch_max_wait` после получения первого
+#[derive(Clone, Copy, Debug)]
+pub struct Efficiency {
+    fill: f32,
+    max_wait: Duration
+}
+
+impl Efficiency {
+
+    /// Создает ожидание по настройкам, None - пакет рассчитывается по истечении `batch_timeout` в любом случае
+    pub fn build(cfg: &SharedConfig) -> Option<Efficiency> {
+        if cfg.batch_priority() != BatchPriority::Efficiency {
+            return None;
+        }
+        Some(Efficiency { fill: cfg.min_batch_fill(), max_wait: cfg.batch_max_wait() })
+    }
+
+    // the parameters holding a batch of `p.size` for the fill
+    fn apply(&self, p: BatchParams) -> BatchParams {
+        BatchParams { fill: (p.size as f32 * self.fill).ceil() as usize, max_wait: self.max_wait, ..p }
+    }
+}
+
+/// Выбирает параметры накопления: в течение прогрева после запуска - параметры прогрева, затем - постоянные,
+/// размер пакета которых при заданном подборе подбирается по глубине очереди, а при заданном ожидании
+/// заполнения пакет ожидает сэмплы и после истечения времени ожидания
+pub struct Batcher {
+    steady: BatchParams,
+    warmup: BatchParams,
+    warmup_until: Instant,
+    warm: bool,
+    adaptive: Option<Adaptive>,
+    efficiency: Option<Efficiency>
+}
+
+impl Batcher {
+
+    pub fn new(steady: BatchParams, warmup: BatchParams, warmup_period: Duration, adaptive: Option<Adaptive>, efficiency: Option<Efficiency>,
+               now: Instant) -> Batcher {
+        Batcher {
+            steady,
+            warmup,
+            warmup_until: now + warmup_period,
+            warm: warmup_period == Duration::from_secs(0),
+            adaptive,
+            efficiency
+        }
+    }
+
+    /// Параметры накопления пакета, начинаемого в момент `now` при `backlog` сэмплах в очереди
+    pub fn params(&mut self, now: Instant, backlog: u64) -> BatchParams {
+        if !self.warm {
+            if now < self.warmup_until {
+                return self.warmup;
+            }
+            self.warm = true;
+            match self.adaptive.as_ref() {
+                None => info!("inference: warmup is over, batches up to {} samples within {:?}", self.steady.size, self.steady.timeout),
+                Some(a) => info!("inference: warmup is over, batches of {}..{} samples by the queue depth within {:?}",
+                    a.min(), a.max(), self.steady.timeout)
+            }
+        }
+        let params = match self.adaptive.as_mut() {
+            None => self.steady,
+            Some(a) => BatchParams { size: a.next(backlog), ..self.steady }
+        };
+        match self.efficiency.as_ref() {
+            None => params,
+            Some(e) => e.apply(params)
+        }
+    }
+}
+
+/// Накапливает пакет: ожидает первый сэмпл, затем добирает до `params.size` сэмплов в пределах `params.timeout`,
+/// а пока в пакете меньше `params.fill` сэмплов - в пределах `params.max_wait`
+pub async fn collect(rx_smpl: &mut Receiver<FinalSample>, params: BatchParams) -> (Vec<Item>, Option<End>) {
+    let mut items = Vec::with_capacity(params.size);
+    let mut first: Option<Instant> = None;
+    while items.len() < params.size.max(1) {
+        let next = match first {
+            None => rx_smpl.recv().await,
+            Some(start) => {
+                let held = items.len() < params.fill;
+                let left = (start + if held { params.max_wait } else { params.timeout }).saturating_duration_since(Instant::now());
+                match timeout(left, rx_smpl.recv()).await {
+                    Ok(n) => n,
+                    Err(_) => {
+                        if held {
+                            debug!("inference: batch of {} samples is computed after {:?}, {} expected", items.len(), params.max_wait, params.fill);
+                        }
+                        break
+                    }
+                }
+            }
+        };
+        match next {
+            None => return (items, Some(End::Broken)),
+            Some(FinalSample::Stop) => return (items, Some(End::Stop)),
+            Some(FinalSample::Data { peer, id, prefix, metadata, chunk, trace, peer_time_us, deadline, value, dim, rate, last }) => {
+                items.push(Item { peer, id, prefix, metadata, chunk, trace, peer_time_us, deadline, value, dim, rate, last });
+                if first.is_none() {
+                    first = Some(Instant::now());
+                }
+            }
+        }
+    }
+    (items, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::mpsc::{channel, Sender};
+
+    fn sample(id: u32) -> FinalSample {
+        FinalSample::Data { peer: 0, id, prefix: "".into(), metadata: Default::default(), chunk: 0, trace: Default::default(),
+            peer_time_us: None, deadline: None, value: vec![0; 4], dim: 1, rate: 16000, last: true }
+    }
+
+    async fn send(tx: &mut Sender<FinalSample>, ids: std::ops::Range<u32>) {
+        for id in ids {
+            tx.send(sample(id)).await.ok().unwrap();
+        }
+    }
+
+    #[test]
+    fn small_batches_while_warming_up() {
+        let now = Instant::now();
+        let steady = BatchParams::new(32, Duration::from_millis(20));
+        let warmup = BatchParams::new(4, Duration::from_millis(5));
+        let mut b = Batcher::new(steady, warmup, Duration::from_secs(10), None, None, now);
+        assert_eq!(b.params(now + Duration::from_secs(9), 0), warmup);
+        assert_eq!(b.params(now + Duration::from_secs(10), 0), steady);
+        // once warm, it stays warm
+        assert_eq!(b.params(now, 0), steady);
+        let mut b = Batcher::new(steady, warmup, Duration::from_secs(0), None, None, now);
+        assert_eq!(b.params(now, 0), steady);
+    }
+
+    #[tokio::test]
+    async fn collects_up_to_the_size() {
+        let (mut tx, mut rx) = channel(10);
+        send(&mut tx, 0..5).await;
+        let (items, end) = collect(&mut rx, BatchParams::new(3, Duration::from_secs(5))).await;
+        assert_eq!(items.iter().map(|i| i.id).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert!(end.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_partial_batch_after_the_timeout() {
+        let (mut tx, mut rx) = channel(10);
+        send(&mut tx, 0..2).await;
+        let (items, end) = collect(&mut rx, BatchParams::new(8, Duration::from_millis(20))).await;
+        assert_eq!(items.len(), 2);
+        assert!(end.is_none());
+    }
+
+    #[tokio::test]
+    async fn the_stop_ends_the_batch() {
+        let (mut tx, mut rx) = channel(10);
+        send(&mut tx, 0..1).await;
+        tx.send(FinalSample::Stop).await.ok().unwrap();
+        let (items, end) = collect(&mut rx, BatchParams::new(8, Duration::from_secs(5))).await;
+        assert_eq!(items.len(), 1);
+        assert!(end == Some(End::Stop));
+        drop(tx);
+        let (items, end) = collect(&mut rx, BatchParams::new(8, Duration::from_secs(5))).await;
+        assert!(items.is_empty() && end == Some(End::Broken));
+    }
+
+    #[test]
+    fn adapts_the_size_once_warm() {
+        let now = Instant::now();
+        let steady = BatchParams::new(4, Duration::from_millis(20));
+        let warmup = BatchParams::new(1, Duration::from_millis(5));
+        let mut b = Batcher::new(steady, warmup, Duration::from_secs(10), Some(Adaptive::new(1, 16, 4)), None, now);
+        for _ in 0..3 {
+            assert_eq!(b.params(now, 100), warmup);
+        }
+        let later = now + Duration::from_secs(10);
+        let sizes: Vec<usize> = (0..3).map(|_| b.params(later, 100).size).collect();
+        assert_eq!(sizes, vec![4, 4, 8]);
+        assert_eq!(b.params(later, 100).timeout, steady.timeout);
+    }
+
+    #[test]
+    fn the_efficiency_holds_the_batch_for_the_fill() {
+        let cfg = crate::config::Config::from_sources(&[], &["inference.batch_priority=efficiency", "inference.min_batch_fill=0.3",
+            "inference.batch_max_wait=200ms"]).unwrap();
+        let steady = BatchParams::new(8, Duration::from_millis(20));
+        let now = Instant::now();
+        let mut b = Batcher::new(steady, steady, Duration::from_secs(0), None, Efficiency::build(&cfg), now);
+        assert_eq!(b.params(now, 0), BatchParams { fill: 3, max_wait: Duration::from_millis(200), ..steady });
+        assert!(Efficiency::build(&crate::config::Config::from_sources(&[], &[]).unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn waits_past_the_timeout_for_the_fill() {
+        let params = BatchParams { fill: 3, max_wait: Duration::from_millis(100), ..BatchParams::new(8, Duration::from_millis(10)) };
+        let (mut tx, mut rx) = channel(10);
+        send(&mut tx, 0..1).await;
+        let mut late = tx.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(30)).await;
+            send(&mut late, 1..2).await;
+        });
+        let started = Instant::now();
+        let (items, end) = collect(&mut rx, params).await;
+        // the fill is not reached, the batch goes at the hard max
+        assert_eq!((items.len(), end.is_none()), (2, true));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        send(&mut tx, 2..5).await;
+        let started = Instant::now();
+        let (items, _) = collect(&mut rx, params).await;
+        assert_eq!(items.len(), 3);
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+}
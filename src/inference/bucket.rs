@@ -0,0 +1,130 @@
+//! Приведение длины сэмплов к фиксированным длинам входа вычислителя.
+
+use std::borrow::Cow;
+
+use crate::config::{Oversize, SharedConfig};
+
+/// Часть сэмпла, подаваемая на вход вычислителя: векторы сэмпла, дополненные нулями до длины входа.
+/// Значимы первые `frames` векторов
+pub struct Piece<'a> {
+    pub value: Cow<'a, [u8]>,
+    pub frames: usize
+}
+
+/// Приводит сэмплы к ближайшей не меньшей из длин `[inference] input_buckets`.
+/// Сэмпл длиннее наибольшей длины делится на части либо отклоняется по настройке `oversize`
+pub struct Buckets {
+    lengths: Vec<usize>,
+    oversize: Oversize
+}
+
+impl Buckets {
+
+    pub fn new(cfg: &SharedConfig) -> Buckets {
+        Buckets {
+            lengths: cfg.input_buckets(),
+            oversize: cfg.oversize()
+        }
+    }
+
+    /// Длины входа по возрастанию, пусто - длина входа не приводится
+    pub fn lengths(&self) -> &[usize] {
+        &self.lengths
+    }
+
+    /// Делит сэмпл из векторов f32 размерности `dim` на части для расчета, результаты частей объединяются по порядку.
+    /// Без заданных длин, как и для сэмпла, не состоящего из целых векторов, возвращает сэмпл как есть.
+    /// Ошибка означает, что сэмпл длиннее наибольшей длины и отклонен
+    pub fn fit<'a>(&self, value: &'a [u8], dim: usize) -> Result<Vec<Piece<'a>>, String> {
+        let vector = dim * 4;
+        let frames = value.len().checked_div(vector).unwrap_or(0);
+        let largest = match self.lengths.last() {
+            Some(l) if frames * vector == value.len() => *l,
+            // the backend reports a malformed sample itself
+            _ => return Ok(vec![Piece { value: Cow::Borrowed(value), frames }])
+        };
+        if frames > largest && self.oversize == Oversize::Reject {
+            return Err(format!("{} frames exceed the largest input bucket of {}", frames, largest));
+        }
+        let mut pieces = Vec::new();
+        let mut rest = value;
+        loop {
+            let n = rest.len() / vector;
+            if n > largest {
+                let (part, tail) = rest.split_at(largest * vector);
+                pieces.push(Piece { value: Cow::Borrowed(part), frames: largest });
+                rest = tail;
+                continue;
+            }
+            // the remainder is the last piece, an empty one is passed only for an empty sample
+            if n > 0 || pieces.is_empty() {
+                pieces.push(self.pad(rest, n, vector));
+            }
+            return Ok(pieces);
+        }
+    }
+
+    // pads `n` vectors to the nearest bucket, `n` never exceeds the largest one
+    fn pad<'a>(&self, value: &'a [u8], n: usize, vector: usize) -> Piece<'a> {
+        let bucket = self.lengths.iter().copied().find(|l| *l >= n).unwrap_or(n);
+        if bucket == n {
+            return Piece { value: Cow::Borrowed(value), frames: n };
+        }
+        let mut padded = Vec::with_capacity(bucket * vector);
+        padded.extend_from_slice(value);
+        padded.resize(bucket * vector, 0);
+        Piece { value: Cow::Owned(padded), frames: n }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    fn buckets(sets: &[&str]) -> Buckets {
+        Buckets::new(&Config::from_sources(&[], sets).unwrap())
+    }
+
+    // `n` vectors of dimension 1, the vector `i` is `i + 1`
+    fn sample(n: usize) -> Vec<u8> {
+        (0..n).flat_map(|i| (i as f32 + 1.0).to_le_bytes().to_vec()).collect()
+    }
+
+    fn shape(pieces: &[Piece<'_>]) -> Vec<(usize, usize)> {
+        pieces.iter().map(|p| (p.value.len() / 4, p.frames)).collect()
+    }
+
+    #[test]
+    fn pads_to_the_nearest_bucket() {
+        let b = buckets(&["inference.input_buckets=8,2,4"]);
+        assert_eq!(b.lengths(), &[2, 4, 8]);
+        let value = sample(3);
+        let pieces = b.fit(&value, 1).unwrap();
+        assert_eq!(shape(&pieces), vec![(4, 3)]);
+        assert_eq!(&pieces[0].value[..12], &value[..]);
+        assert_eq!(&pieces[0].value[12..], &[0; 4]);
+        assert!(matches!(b.fit(&sample(4), 1).unwrap()[0].value, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn splits_or_rejects_the_oversized_sample() {
+        let split = buckets(&["inference.input_buckets=2,4"]);
+        let value = sample(9);
+        let pieces = split.fit(&value, 1).unwrap();
+        assert_eq!(shape(&pieces), vec![(4, 4), (4, 4), (2, 1)]);
+        assert_eq!(&pieces[1].value[..], &value[16..32]);
+        let reject = buckets(&["inference.input_buckets=2,4", "inference.oversize=reject"]);
+        assert!(reject.fit(&value, 1).is_err());
+        assert_eq!(shape(&reject.fit(&sample(4), 1).unwrap()), vec![(4, 4)]);
+    }
+
+    #[test]
+    fn passes_the_sample_as_is_without_buckets() {
+        let value = sample(9);
+        assert_eq!(shape(&buckets(&[]).fit(&value, 1).unwrap()), vec![(9, 9)]);
+        // not a whole number of vectors
+        assert_eq!(shape(&buckets(&["inference.input_buckets=2"]).fit(&value[..5], 1).unwrap()), vec![(1, 1)]);
+    }
+}
@@ -0,0 +1,125 @@
+//! Преобразование выхода модели в сохраняемый результат и выбор преобразования по конфигурации.
+
+use crate::config::SharedConfig;
+use crate::data::StoredResult;
+
+use super::batch::Item;
+
+/// Построитель сохраняемого результата по выходу модели. Модели разных типов выдают выходы разной формы,
+/// построитель приводит выход к сохраняемому виду, не затрагивая цикл расчета
+pub trait ResultBuilder: Send {
+
+    /// Имя построителя, под которым он указывается в настройках
+    fn name(&self) -> &'static str;
+
+    /// Приводит выход модели к сохраняемому значению, ошибка - выход непригоден для построителя
+    fn value(&self, output: Vec<u8>) -> Result<Vec<u8>, String>;
+
+    /// Строит сохраняемый результат сэмпла `item` по выходу модели `output` с отметками
+    /// о низкой уверенности `low_confidence` и о промежуточном результате `partial`
+    fn build(&self, item: &Item, output: Vec<u8>, low_confidence: bool, partial: bool) -> Result<StoredResult, String> {
+        Ok(StoredResult::Data {
+            id: item.id,
+            prefix: item.prefix.clone(),
+            metadata: item.metadata.clone(),
+            chunk: item.chunk,
+            trace: item.trace,
+            peer_time_us: item.peer_time_us,
+            value: self.value(output)?,
+            low_confidence,
+            partial,
+            // a partial result is followed by the final one of the same chunk
+            last: item.last && !partial
+        })
+    }
+}
+
+/// Выход модели сохраняется как есть
+pub struct RawBuilder;
+
+impl ResultBuilder for RawBuilder {
+
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+
+    fn value(&self, output: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(output)
+    }
+}
+
+/// Выход модели классификации - оценки классов f32 little-endian. Сохраняется только лучший класс:
+/// номер класса u32 и его оценка f32, оба little-endian, при равных оценках - класс с меньшим номером
+pub struct ArgmaxBuilder;
+
+impl ResultBuilder for ArgmaxBuilder {
+
+    fn name(&self) -> &'static str {
+        "argmax"
+    }
+
+    fn value(&self, output: Vec<u8>) -> Result<Vec<u8>, String> {
+        if output.is_empty() || !output.chunks_exact(4).remainder().is_empty() {
+            return Err(format!("argmax expects f32 scores, got {} bytes", output.len()));
+        }
+        let (class, score) = output.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .enumerate()
+            .fold((0, f32::NEG_INFINITY), |top, (i, v)| if v > top.1 { (i, v) } else { top });
+        let mut value = Vec::with_capacity(8);
+        value.extend_from_slice(&(class as u32).to_le_bytes());
+        value.extend_from_slice(&score.to_le_bytes());
+        Ok(value)
+    }
+}
+
+/// Создает построитель, заданный в настройках `[inference] result_builder`
+pub fn build(cfg: &SharedConfig) -> Result<Box<dyn ResultBuilder>, String> {
+    create(&cfg.result_builder())
+}
+
+/// Создает построитель `name`
+pub fn create(name: &str) -> Result<Box<dyn ResultBuilder>, String> {
+    match name {
+        "raw" => Ok(Box::new(RawBuilder)),
+        "argmax" => Ok(Box::new(ArgmaxBuilder)),
+        b => Err(format!("unknown result builder '{}'", b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+
+    fn item(last: bool) -> Item {
+        Item { peer: 0, id: 7, prefix: "a".into(), metadata: Default::default(), chunk: 2, trace: Default::default(), peer_time_us: Some(5),
+            deadline: None, value: Vec::new(), dim: 1, rate: 8000, last }
+    }
+
+    #[test]
+    fn the_argmax_keeps_the_top_class() {
+        let mut top = 2u32.to_le_bytes().to_vec();
+        top.extend_from_slice(&0.5f32.to_le_bytes());
+        assert_eq!(ArgmaxBuilder.value(scores(&[0.1, -1.0, 0.5, 0.5])), Ok(top));
+        assert!(ArgmaxBuilder.value(Vec::new()).is_err());
+        assert!(ArgmaxBuilder.value(vec![0; 5]).is_err());
+    }
+
+    #[test]
+    fn the_partial_result_is_not_the_last() {
+        let b = create("raw").unwrap();
+        match b.build(&item(true), vec![1], true, true).unwrap() {
+            StoredResult::Data { id, prefix, chunk, peer_time_us, value, low_confidence, partial, last, .. } => {
+                assert_eq!((id, &*prefix, chunk, peer_time_us, value), (7, "a", 2, Some(5), vec![1]));
+                assert_eq!((low_confidence, partial, last), (true, true, false));
+            },
+            StoredResult::Stop => panic!("stop is built")
+        }
+        assert!(matches!(b.build(&item(true), vec![1], false, false), Ok(StoredResult::Data { last: true, .. })));
+        assert!(create("softmax").is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Исключение повторного расчета результата для одинаковых сэмплов.
+
+use std::collections::{HashMap, VecDeque};
+
+use sha2::{Digest, Sha256};
+
+/// Хэш содержимого сэмпла
+pub type Key = [u8; 32];
+
+/// Результаты расчета последних различных сэмплов, не больше `window` штук.
+/// При переполнении вытесняется дольше всех не использовавшийся результат
+pub struct ResultCache {
+    window: usize,
+    // the result and the stamp of its last use
+    entries: HashMap<Key, (Vec<u8>, u64)>,
+    // uses in order, an item is stale if the entry was used again later
+    order: VecDeque<(Key, u64)>,
+    stamp: u64
+}
+
+impl ResultCache {
+
+    pub fn new(window: usize) -> ResultCache {
+        ResultCache {
+            window,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            stamp: 0
+        }
+    }
+
+    /// Хэш содержимого сэмпла вместе с размерностью его векторов, `None` если повторы не проверяются
+    pub fn key(&self, value: &[u8], dim: usize) -> Option<Key> {
+        if self.window == 0 {
+            return None;
+        }
+        let mut h = Sha256::new();
+        h.update((dim as u64).to_le_bytes());
+        h.update(value);
+        let mut k = [0u8; 32];
+        k.copy_from_slice(&h.finalize());
+        Some(k)
+    }
+
+    /// Возвращает ранее рассчитанный результат для сэмпла, отмечая его использование
+    pub fn get(&mut self, key: &Key) -> Option<Vec<u8>> {
+        self.stamp += 1;
+        let stamp = self.stamp;
+        let (value, used) = self.entries.get_mut(key)?;
+        *used = stamp;
+        self.order.push_back((*key, stamp));
+        let value = value.clone();
+        self.compact();
+        Some(value)
+    }
+
+    /// Запоминает рассчитанный результат сэмпла
+    pub fn put(&mut self, key: Key, value: Vec<u8>) {
+        self.stamp += 1;
+        self.entries.insert(key, (value, self.stamp));
+        self.order.push_back((key, self.stamp));
+        while self.entries.len() > self.window {
+            match self.order.pop_front() {
+                Some((k, s)) => {
+                    if self.entries.get(&k).map(|(_, used)| *used == s).unwrap_or(false) {
+                        self.entries.remove(&k);
+                    }
+                },
+                None => break
+            }
+        }
+        self.compact();
+    }
+
+    // drops the stale uses from the queue once it outgrows the window twice, amortized constant time per use
+    fn compact(&mut self) {
+        if self.order.len() > 2 * self.window.max(1) {
+            let entries = &self.entries;
+            self.order.retain(|(k, s)| entries.get(k).map(|(_, used)| used == s).unwrap_or(false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_key_covers_the_dimension() {
+        let c = ResultCache::new(2);
+        assert_eq!(c.key(b"abcd", 1), c.key(b"abcd", 1));
+        assert_ne!(c.key(b"abcd", 1), c.key(b"abcd", 2));
+        assert_ne!(c.key(b"abcd", 1), c.key(b"abce", 1));
+        assert_eq!(ResultCache::new(0).key(b"abcd", 1), None);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut c = ResultCache::new(2);
+        let (a, b, d) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+        c.put(a, b"a".to_vec());
+        c.put(b, b"b".to_vec());
+        assert_eq!(c.get(&a), Some(b"a".to_vec()));
+        c.put(d, b"d".to_vec());
+        assert_eq!(c.get(&b), None);
+        assert_eq!(c.get(&a), Some(b"a".to_vec()));
+        assert_eq!(c.get(&d), Some(b"d".to_vec()));
+    }
+
+    #[test]
+    fn the_queue_stays_bounded() {
+        let mut c = ResultCache::new(2);
+        let a = [1u8; 32];
+        c.put(a, b"a".to_vec());
+        for _ in 0..100 {
+            assert!(c.get(&a).is_some());
+        }
+        assert!(c.order.len() <= 4);
+        assert_eq!(c.entries.len(), 1);
+    }
+}
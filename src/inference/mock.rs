@@ -0,0 +1,127 @@
+//! Эталонный вычислитель результата на CPU, не требующий GPU и модели.
+
+use super::backend::{InferenceBackend, Input, ModelInfo, Tensor};
+
+/// Количество промежуточных результатов потокового варианта на каждый вход
+const PARTIALS: usize = 2;
+
+/// Рассчитывает в качестве результата среднее по значимым векторам сэмпла, т.е. вектор размерности `dim`.
+/// Потоковый вариант `mock_stream` перед окончательным результатом передает промежуточные -
+/// средние по первой трети и первым двум третям векторов
+pub struct MockBackend {
+    streaming: bool
+}
+
+impl MockBackend {
+
+    pub fn new() -> MockBackend {
+        MockBackend {
+            streaming: false
+        }
+    }
+
+    pub fn streaming() -> MockBackend {
+        MockBackend {
+            streaming: true
+        }
+    }
+}
+
+impl InferenceBackend for MockBackend {
+
+    fn name(&self) -> &'static str {
+        if self.streaming { "mock_stream" } else { "mock" }
+    }
+
+    // the mean of any number of vectors of any dimension
+    fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            inputs: vec![Tensor { name: "samples".to_string(), shape: vec![-1, -1], dtype: "f32" }],
+            outputs: vec![Tensor { name: "mean".to_string(), shape: vec![-1], dtype: "f32" }]
+        }
+    }
+
+    fn infer(&mut self, input: &Input) -> Result<Vec<u8>, String> {
+        mean(input, input.frames)
+    }
+
+    fn infer_batch_streamed(&mut self, batch: &[Input], partial: &mut dyn FnMut(usize, Vec<u8>)) -> Vec<Result<Vec<u8>, String>> {
+        batch.iter().enumerate()
+            .map(|(n, input)| {
+                if self.streaming {
+                    for k in 1..=PARTIALS {
+                        partial(n, mean(input, input.frames * k / (PARTIALS + 1))?);
+                    }
+                }
+                self.infer(input)
+            })
+            .collect()
+    }
+}
+
+// the mean of the first `frames` vectors of the input
+fn mean(input: &Input, frames: usize) -> Result<Vec<u8>, String> {
+    let dim = input.dim;
+    if dim == 0 || !input.value.chunks_exact(4 * dim).remainder().is_empty() {
+        return Err(format!("{} bytes are not vectors of {} f32", input.value.len(), dim));
+    }
+    // the padding is not a part of the sample
+    let value = &input.value[..(frames * dim * 4).min(input.value.len())];
+    let mut sum = vec![0f64; dim];
+    let mut n = 0usize;
+    for (i, b) in value.chunks_exact(4).enumerate() {
+        sum[i % dim] += f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64;
+        if i % dim == 0 {
+            n += 1;
+        }
+    }
+    let mut out = Vec::with_capacity(dim * 4);
+    for s in sum {
+        let mean = if n > 0 { s / n as f64 } else { 0.0 };
+        out.extend_from_slice(&(mean as f32).to_le_bytes());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(v: &[f32]) -> Vec<u8> {
+        v.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn the_mean_of_the_vectors() {
+        let value = pack(&[1.0, 2.0, 3.0, 6.0]);
+        let r = MockBackend::new().infer(&Input { value: &value, dim: 2, frames: 2 }).unwrap();
+        assert_eq!(r, pack(&[2.0, 4.0]));
+    }
+
+    #[test]
+    fn ignores_the_padding() {
+        let value = pack(&[1.0, 2.0, 0.0, 0.0]);
+        let r = MockBackend::new().infer(&Input { value: &value, dim: 2, frames: 1 }).unwrap();
+        assert_eq!(r, pack(&[1.0, 2.0]));
+    }
+
+    #[test]
+    fn rejects_the_partial_vectors() {
+        let value = pack(&[1.0, 2.0, 3.0]);
+        assert!(MockBackend::new().infer(&Input { value: &value, dim: 2, frames: 2 }).is_err());
+        assert!(MockBackend::new().infer(&Input { value: &value, dim: 0, frames: 2 }).is_err());
+    }
+
+    #[test]
+    fn the_streaming_variant_passes_the_partial_results() {
+        let value = pack(&[3.0, 0.0, 6.0]);
+        let batch = [Input { value: &value, dim: 1, frames: 3 }];
+        let mut partials = Vec::new();
+        let r = MockBackend::streaming().infer_batch_streamed(&batch, &mut |n, v| partials.push((n, v)));
+        assert_eq!(r, vec![Ok(pack(&[3.0]))]);
+        assert_eq!(partials, vec![(0, pack(&[3.0])), (0, pack(&[1.5]))]);
+        partials.clear();
+        MockBackend::new().infer_batch_streamed(&batch, &mut |n, v| partials.push((n, v)));
+        assert!(partials.is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+//! Передача результатов в output по настройке `[inference] on_full` при заполненном канале
+//! и `on_output_lost` при разрушенном.
+
+use crate::config::{FullPolicy, OutputLost, SharedConfig};
+use crate::data::StoredResult;
+use crate::events;
+use crate::health;
+use crate::shutdown;
+use crate::stats::SharedStats;
+
+use super::spill::Spill;
+
+use log::{debug, error, info, warn};
+use serde_json::json;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::sync::mpsc::Sender;
+
+/// Передает результаты в канал output. Если канал заполнен, в зависимости от политики ожидает освобождения места,
+/// отбрасывает результат или сохраняет его в дисковую очередь, чтобы медленное сохранение не останавливало расчет.
+/// После разрушения канала требует остановки приложения и отбрасывает результаты до нее
+/// либо сохраняет их в дисковую очередь, передаваемую output при следующем запуске
+pub struct Overflow {
+    policy: FullPolicy,
+    on_lost: OutputLost,
+    tx_rslt: Sender<StoredResult>,
+    spill: Option<Spill>,
+    stats: SharedStats,
+    // the channel is reported full once until it frees up
+    full: bool,
+    // the channel is closed, output is gone for good
+    lost: bool
+}
+
+impl Overflow {
+
+    /// Недоступный каталог дисковой очереди - фатальная ошибка, как и недоступный каталог результатов
+    pub fn new(cfg: &SharedConfig, tx_rslt: Sender<StoredResult>, stats: SharedStats) -> Overflow {
+        let policy = cfg.on_full();
+        let on_lost = cfg.on_output_lost();
+        let spill = match (policy, on_lost) {
+            (FullPolicy::Spill, _) | (_, OutputLost::Spill) => Some(Spill::open(&cfg.spill_dir(), tx_rslt.clone())
+                .unwrap_or_else(|e| panic!("inference: unable to init spill queue in {}: {}", cfg.spill_dir(), e))),
+            _ => None
+        };
+        match policy {
+            FullPolicy::Block => {},
+            FullPolicy::Drop => info!("inference: results are dropped while the output channel is full"),
+            FullPolicy::Spill => info!("inference: results are spilled to {} while the output channel is full", cfg.spill_dir())
+        }
+        Overflow {
+            policy,
+            on_lost,
+            tx_rslt,
+            spill,
+            stats,
+            full: false,
+            lost: false
+        }
+    }
+
+    /// Передает результат и учитывает его как переданный, сохраненный в очередь или отброшенный
+    pub async fn send(&mut self, r: StoredResult) {
+        if self.lost {
+            return self.keep(r);
+        }
+        // the queued results go first, a new one waits behind them to keep the order
+        if self.spill.as_ref().is_some_and(|s| !s.is_empty()) {
+            return self.spill_or_drop(r, true, "channel_full");
+        }
+        if self.policy == FullPolicy::Block {
+            match self.tx_rslt.send(r).await {
+                Ok(()) => self.stats.inference.sent(),
+                Err(SendError(r)) => self.lose(r)
+            }
+            return;
+        }
+        let r = match self.tx_rslt.try_send(r) {
+            Ok(()) => {
+                self.full = false;
+                self.stats.inference.sent();
+                return;
+            },
+            Err(TrySendError::Closed(r)) => return self.lose(r),
+            Err(TrySendError::Full(r)) => r
+        };
+        let spill = self.policy == FullPolicy::Spill;
+        if !self.full {
+            warn!("inference: output channel is full, results are {}", if spill { "spilled" } else { "dropped" });
+            self.full = true;
+        }
+        self.spill_or_drop(r, spill, "channel_full");
+    }
+
+    // the channel is found closed, the policy is applied once and the result is kept by it
+    fn lose(&mut self, r: StoredResult) {
+        self.lost = true;
+        if shutdown::is_stopping() {
+            // output has got its stop ahead of the results still computed, they are only kept by the policy
+            warn!("inference: output is stopped ahead of inference, the rest of the results are {}",
+                if self.on_lost == OutputLost::Spill { "spilled" } else { "dropped" });
+            return self.keep(r);
+        }
+        match self.on_lost {
+            OutputLost::Stop => {
+                error!("inference: !!! STORED RESULTS OUTPUT CHANNEL IS BROKEN, banshee is stopped !!!");
+                shutdown::request("output is lost");
+            },
+            OutputLost::Spill => {
+                error!("inference: !!! STORED RESULTS OUTPUT CHANNEL IS BROKEN, results are spilled to disk until the restart !!!");
+                health::set_failed("output", true);
+            }
+        }
+        events::emit("output_lost", json!({ "action": if self.on_lost == OutputLost::Stop { "stop" } else { "spill" } }));
+        self.keep(r);
+    }
+
+    // with output lost the results are only spilled or dropped
+    fn keep(&mut self, r: StoredResult) {
+        let spill = self.on_lost == OutputLost::Spill;
+        self.spill_or_drop(r, spill, "output_lost");
+    }
+
+    fn spill_or_drop(&mut self, r: StoredResult, spill: bool, reason: &str) {
+        let (id, chunk) = match &r {
+            StoredResult::Data { id, chunk, .. } => (*id, *chunk),
+            StoredResult::Stop => return
+        };
+        if let Some(s) = self.spill.as_ref().filter(|_| spill) {
+            match s.push(&r) {
+                Ok(()) => {
+                    debug!("inference: result of {} is spilled", id);
+                    self.stats.inference.spilled();
+                    return;
+                },
+                Err(e) => error!("inference: failed to spill result of {}: {}", id, e)
+            }
+        }
+        debug!("inference: result of {} is dropped, {}", id, reason);
+        events::emit("result_dropped", json!({ "id": id, "chunk": chunk, "reason": reason }));
+        self.stats.inference.dropped();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+
+    use tokio::sync::mpsc;
+
+    fn result(id: u32) -> StoredResult {
+        StoredResult::Data { id, prefix: "".into(), metadata: Arc::new(HashMap::new()), chunk: 0, trace: Default::default(),
+            peer_time_us: None, value: vec![id as u8], low_confidence: false, partial: false, last: true }
+    }
+
+    fn id(r: Option<StoredResult>) -> u32 {
+        match r {
+            Some(StoredResult::Data { id, .. }) => id,
+            _ => panic!("no result")
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_while_the_channel_is_full() {
+        let cfg = Config::from_sources(&[], &["inference.on_full=drop"]).unwrap();
+        let stats = Stats::new(&[]);
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut o = Overflow::new(&cfg, tx, stats.clone());
+        o.send(result(1)).await;
+        o.send(result(2)).await;
+        assert_eq!(stats.inference.totals(), (0, 1, 1));
+        assert_eq!(id(rx.recv().await), 1);
+        // the channel is free again, the result goes right in
+        o.send(result(3)).await;
+        assert_eq!(id(rx.recv().await), 3);
+        assert_eq!(stats.inference.totals(), (0, 2, 1));
+    }
+
+    #[tokio::test]
+    async fn spills_while_the_channel_is_full_in_order() {
+        let dir = std::env::temp_dir().join(format!("banshee-overflow-{}-spill", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg = Config::from_sources(&[], &["inference.on_full=spill",
+            &format!("inference.spill_dir={}", dir.display())]).unwrap();
+        let stats = Stats::new(&[]);
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut o = Overflow::new(&cfg, tx, stats.clone());
+        o.send(result(1)).await;
+        o.send(result(2)).await;
+        assert_eq!(id(rx.recv().await), 1);
+        // the queue is not passed on yet, the new result waits behind it
+        o.send(result(3)).await;
+        assert_eq!(id(rx.recv().await), 2);
+        assert_eq!(id(rx.recv().await), 3);
+        assert_eq!(stats.inference.totals(), (0, 3, 0));
+        assert_eq!(stats.snapshot()["stages"]["inference"]["spilled"], 2);
+        // the file of the result passed on is removed right after
+        tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // the loss itself stops banshee or fails its health, only the results after it are checked here
+    #[tokio::test]
+    async fn drops_the_results_once_output_is_lost() {
+        let cfg = Config::from_sources(&[], &[]).unwrap();
+        let stats = Stats::new(&[]);
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut o = Overflow::new(&cfg, tx, stats.clone());
+        o.lost = true;
+        o.send(result(1)).await;
+        assert_eq!(stats.inference.totals(), (0, 0, 1));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn spills_the_results_once_output_is_lost() {
+        let dir = std::env::temp_dir().join(format!("banshee-overflow-{}-lost", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg = Config::from_sources(&[], &["inference.on_output_lost=spill",
+            &format!("inference.spill_dir={}", dir.display())]).unwrap();
+        let stats = Stats::new(&[]);
+        let (tx, rx) = mpsc::channel(4);
+        drop(rx);
+        let mut o = Overflow::new(&cfg, tx, stats.clone());
+        o.lost = true;
+        o.send(result(1)).await;
+        o.send(result(2)).await;
+        assert_eq!(stats.inference.totals(), (0, 2, 0));
+        assert_eq!(stats.snapshot()["stages"]["inference"]["spilled"], 2);
+        // kept for the next start
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+//! Приведение частоты дискретизации сэмплов к частоте входа модели.
+
+use crate::config::{RateMismatch, SharedConfig};
+use crate::processor;
+
+use super::batch::Item;
+
+use log::debug;
+
+/// Проверяет частоту дискретизации сэмплов по `[inference] model_rate` и по настройке `on_rate_mismatch`
+/// передискретизирует сигнал либо отклоняет сэмпл. Модель, получившая сигнал другой частоты,
+/// рассчитывает неверный результат без каких-либо признаков ошибки
+pub struct RateCheck {
+    rate: u32,
+    on_mismatch: RateMismatch
+}
+
+impl RateCheck {
+
+    pub fn new(cfg: &SharedConfig) -> RateCheck {
+        RateCheck {
+            rate: cfg.model_rate(),
+            on_mismatch: cfg.on_rate_mismatch()
+        }
+    }
+
+    /// Частота входа модели, Гц, 0 - не проверяется
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Приводит сэмпл к частоте модели. Ошибка означает, что сэмпл не может быть приведен и не рассчитывается
+    pub fn conform(&self, item: &mut Item) -> Result<(), String> {
+        if self.rate == 0 || item.rate == self.rate {
+            return Ok(());
+        }
+        if self.on_mismatch == RateMismatch::Reject || item.rate == 0 {
+            return Err(format!("sample rate is {} Hz, the model expects {} Hz", item.rate, self.rate));
+        }
+        // the features are computed over frames of the signal, they can't be resampled
+        if item.dim != 1 {
+            return Err(format!("feature vectors of {} Hz signal can't be resampled to {} Hz", item.rate, self.rate));
+        }
+        let src: Vec<f32> = item.value.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        let out = processor::interpolate(&src, item.rate, self.rate);
+        debug!("inference: sample {} is resampled from {} Hz to {} Hz", item.id, item.rate, self.rate);
+        item.value = out.iter().flat_map(|v| v.to_le_bytes()).collect();
+        item.rate = self.rate;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    fn check(policy: &str) -> RateCheck {
+        let policy = format!("inference.on_rate_mismatch={}", policy);
+        RateCheck::new(&Config::from_sources(&[], &["inference.model_rate=16000", &policy]).unwrap())
+    }
+
+    fn item(rate: u32, dim: usize, values: &[f32]) -> Item {
+        Item { peer: 0, id: 1, prefix: "".into(), metadata: Default::default(), chunk: 0, trace: Default::default(), peer_time_us: None,
+            deadline: None, value: values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect(), dim, rate, last: true }
+    }
+
+    #[test]
+    fn passes_the_model_rate() {
+        let mut i = item(16000, 1, &[0.5]);
+        check("reject").conform(&mut i).unwrap();
+        assert_eq!(i.value.len(), 4);
+        let mut i = item(8000, 1, &[0.5]);
+        RateCheck::new(&Config::from_sources(&[], &[]).unwrap()).conform(&mut i).unwrap();
+        assert_eq!(i.rate, 8000);
+    }
+
+    #[test]
+    fn resamples_the_signal_to_the_model_rate() {
+        let mut i = item(8000, 1, &[0.0, 1.0]);
+        check("resample").conform(&mut i).unwrap();
+        assert_eq!(i.rate, 16000);
+        let out: Vec<f32> = i.value.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        assert_eq!(out, vec![0.0, 0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn rejects_what_is_not_resampled() {
+        assert!(check("reject").conform(&mut item(8000, 1, &[0.5])).is_err());
+        assert!(check("resample").conform(&mut item(8000, 2, &[0.5, 0.5])).is_err());
+        assert!(check("resample").conform(&mut item(0, 1, &[0.5])).is_err());
+    }
+}
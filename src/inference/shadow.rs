@@ -0,0 +1,227 @@
+//! Теневой расчет результатов вторым вычислителем для сравнения с основным, например при смене модели.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::SharedConfig;
+use crate::events;
+use crate::stats::SharedStats;
+
+use super::affinity;
+use super::backend::{self, InferenceBackend};
+use super::batch::Item;
+use super::bucket::Buckets;
+use super::dedup::ResultCache;
+
+use log::{debug, info, warn};
+use serde_json::json;
+use tokio::task;
+use tokio::time::timeout;
+
+/// Результаты пакета входов
+type Results = Vec<Result<Vec<u8>, String>>;
+
+/// Теневой вычислитель вместе с тем, что нужно для расчета пакета в потоке блокирующих операций
+struct Engine {
+    backend: Box<dyn InferenceBackend>,
+    /// Без повторного использования результатов, каждый сэмпл рассчитывается
+    cache: ResultCache,
+    buckets: Buckets
+}
+
+/// Рассчитывает те же сэмплы теневым вычислителем `[inference] shadow_backend` и сравнивает результаты
+/// с результатами основного. Результаты теневого вычислителя только сравниваются и никуда не передаются.
+/// Пакет рассчитывается в потоке блокирующих операций и не задерживает основной расчет: пока теневой
+/// вычислитель занят предыдущим пакетом, следующие пакеты пропускаются. Пакет, не рассчитанный
+/// в пределах `infer_timeout`, отмечается в журнале, вычислитель остается занятым до окончания его расчета
+pub struct Shadow {
+    /// Свободный вычислитель, None - занят расчетом пакета, по его окончании возвращается сюда же
+    engine: Arc<Mutex<Option<Engine>>>,
+    tolerance: f32,
+    max_backlog: u64,
+    limit: Duration,
+    /// Ядра CPU расчета, как у основного вычислителя
+    cores: Vec<usize>,
+    stats: SharedStats
+}
+
+impl Shadow {
+
+    /// Создает теневой расчет, если он задан в настройках. Недоступный теневой вычислитель
+    /// не мешает работе основного, сравнение тогда отключается
+    pub fn build(cfg: &SharedConfig, stats: SharedStats) -> Option<Shadow> {
+        let name = cfg.shadow_backend();
+        if name.is_empty() {
+            return None;
+        }
+        match backend::create(&name, &cfg.shadow_model()) {
+            Ok(backend) => {
+                info!("inference: {} shadow backend, results differing by more than {} are reported",
+                    backend.name(), cfg.shadow_tolerance());
+                Some(Shadow {
+                    engine: Arc::new(Mutex::new(Some(Engine { backend, cache: ResultCache::new(0), buckets: Buckets::new(cfg) }))),
+                    tolerance: cfg.shadow_tolerance(),
+                    max_backlog: cfg.shadow_max_backlog(),
+                    limit: cfg.infer_timeout(),
+                    cores: cfg.cpu_affinity(),
+                    stats
+                })
+            },
+            Err(e) => {
+                warn!("inference: shadow backend is disabled, {}", e);
+                None
+            }
+        }
+    }
+
+    /// Передает пакет `items` на расчет теневым вычислителем для сравнения с результатами основного `primary`
+    /// и возвращается сразу. Пакет пропускается, если вычислитель занят или очередь сэмплов длиннее `shadow_max_backlog`
+    pub fn compare(&mut self, items: &[Item], primary: &[Result<Vec<u8>, String>]) {
+        if items.is_empty() {
+            return;
+        }
+        // the shadow must not slow the production path down when it falls behind
+        let backlog = self.stats.samples_backlog();
+        if self.max_backlog > 0 && backlog > self.max_backlog {
+            debug!("inference: shadow run of {} samples is skipped, {} samples are waiting", items.len(), backlog);
+            self.stats.shadow.add_skipped(items.len());
+            return;
+        }
+        let mut engine = match lock(&self.engine).take() {
+            Some(e) => e,
+            None => {
+                debug!("inference: shadow run of {} samples is skipped, the shadow backend is busy", items.len());
+                self.stats.shadow.add_skipped(items.len());
+                return;
+            }
+        };
+        let (items, primary) = (items.to_vec(), primary.to_vec());
+        let (slot, cores, stats, tolerance) = (self.engine.clone(), self.cores.clone(), self.stats.clone(), self.tolerance);
+        let call = task::spawn_blocking(move || {
+            let shadow = {
+                let _pinned = affinity::pin(&cores);
+                super::infer(engine.backend.as_mut(), &mut engine.cache, &engine.buckets, &items)
+            };
+            // free for the next batch before the comparison
+            *lock(&slot) = Some(engine);
+            report(&stats, tolerance, &items, &primary, shadow);
+        });
+        let limit = self.limit;
+        tokio::spawn(async move {
+            if limit == Duration::from_secs(0) {
+                let _ = call.await;
+                return;
+            }
+            if timeout(limit, call).await.is_err() {
+                warn!("inference: shadow batch is not computed in {:?}, the next ones are skipped until it is", limit);
+            }
+        });
+    }
+}
+
+fn lock(engine: &Mutex<Option<Engine>>) -> std::sync::MutexGuard<'_, Option<Engine>> {
+    engine.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+// counts and reports the shadow results against the primary ones
+fn report(stats: &SharedStats, tolerance: f32, items: &[Item], primary: &[Result<Vec<u8>, String>], shadow: Results) {
+    for ((item, p), s) in items.iter().zip(primary).zip(shadow) {
+        match (p, s) {
+            (Ok(p), Ok(s)) => match diff(p, &s) {
+                Some(d) if d <= tolerance => stats.shadow.matched(),
+                d => {
+                    let d = d.map_or_else(|| format!("{} vs {} bytes", p.len(), s.len()), |d| d.to_string());
+                    debug!("inference: shadow result of {} chunk {} differs: {}", item.id, item.chunk, d);
+                    events::emit("shadow_mismatch", json!({ "peer": item.peer, "id": item.id, "chunk": item.chunk, "diff": d }));
+                    stats.shadow.mismatched();
+                }
+            },
+            (Err(_), Err(_)) => stats.shadow.matched(),
+            (p, s) => {
+                let error = p.as_ref().err().or(s.as_ref().err()).cloned().unwrap_or_default();
+                debug!("inference: only {} backend computed {} chunk {}: {}",
+                    if p.is_ok() { "primary" } else { "shadow" }, item.id, item.chunk, error);
+                events::emit("shadow_mismatch", json!({
+                    "peer": item.peer, "id": item.id, "chunk": item.chunk,
+                    "failed": if p.is_ok() { "shadow" } else { "primary" }, "error": error
+                }));
+                stats.shadow.failed();
+            }
+        }
+    }
+}
+
+// the largest difference of the f32 values, None if the results are not comparable
+fn diff(a: &[u8], b: &[u8]) -> Option<f32> {
+    if a.len() != b.len() || !a.chunks_exact(4).remainder().is_empty() {
+        return None;
+    }
+    let value = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    Some(a.chunks_exact(4).zip(b.chunks_exact(4)).map(|(a, b)| (value(a) - value(b)).abs()).fold(0.0, f32::max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+
+    fn item(id: u32, values: &[f32]) -> Item {
+        Item { peer: 0, id, prefix: "".into(), metadata: Default::default(), chunk: 0, trace: Default::default(), peer_time_us: None,
+            deadline: None, value: packed(values), dim: values.len(), rate: 8000, last: true }
+    }
+
+    fn packed(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+
+    fn counts(stats: &SharedStats) -> serde_json::Value {
+        stats.snapshot()["shadow"].clone()
+    }
+
+    #[test]
+    fn the_largest_difference_of_the_values() {
+        assert_eq!(diff(&packed(&[1.0, 2.0]), &packed(&[1.5, 1.0])), Some(1.0));
+        assert_eq!(diff(&packed(&[1.0]), &packed(&[1.0, 2.0])), None);
+        assert_eq!(diff(b"abc", b"abd"), None);
+    }
+
+    #[test]
+    fn counts_the_matches_and_failures() {
+        let stats = Stats::new(&[]);
+        let items: Vec<Item> = (0..4).map(|i| item(i, &[1.0])).collect();
+        let primary = vec![Ok(packed(&[1.0])), Ok(packed(&[1.0])), Err("failed".to_string()), Ok(packed(&[1.0]))];
+        let shadow = vec![Ok(packed(&[1.05])), Ok(packed(&[2.0])), Err("failed".to_string()), Err("failed".to_string())];
+        report(&stats, 0.1, &items, &primary, shadow);
+        assert_eq!(counts(&stats), json!({ "matched": 2, "mismatched": 1, "failed": 1, "skipped": 0 }));
+    }
+
+    #[test]
+    fn is_built_only_for_an_available_backend() {
+        let build = |name: &str| Shadow::build(&Config::from_sources(&[], &[&format!("inference.shadow_backend={}", name)]).unwrap(), Stats::new(&[]));
+        assert!(build("").is_none());
+        assert!(build("onnx").is_none());
+        assert!(build("mock").is_some());
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn compares_the_batch_aside() {
+        let cfg = Config::from_sources(&[], &["inference.shadow_backend=mock", "inference.shadow_max_backlog=2"]).unwrap();
+        let stats = Stats::new(&[]);
+        let mut shadow = Shadow::build(&cfg, stats.clone()).unwrap();
+        let items = vec![item(1, &[1.0, 3.0])];
+        // the mock gives the mean of the vectors, that of a single one is the vector itself
+        shadow.compare(&items, &[Ok(packed(&[1.0, 3.0]))]);
+        for _ in 0..100 {
+            if counts(&stats)["matched"] == 1 {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert_eq!(counts(&stats)["matched"], 1);
+        stats.processor.add_sent(3);
+        shadow.compare(&items, &[Ok(packed(&[1.0, 3.0]))]);
+        assert_eq!(counts(&stats)["skipped"], 1);
+    }
+}
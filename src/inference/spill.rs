@@ -0,0 +1,136 @@
+//! Дисковая очередь результатов, не поместившихся в канал передачи в output или не переданных из-за его разрушения.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::data::StoredResult;
+use crate::leader;
+
+use log::{error, info};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+
+/// Расширение файлов результатов в каталоге очереди
+const EXT: &str = "res";
+/// Очередь результатов в каталоге, по файлу `<номер>.res` на результат. Результаты передаются в output
+/// отдельной задачей в порядке добавления, каждый файл удаляется после передачи результата.
+/// Оставшиеся от предыдущего запуска файлы передаются первыми, резервным экземпляром - только после выбора
+/// ведущим. Контекст трассы результата не сохраняется
+pub struct Spill {
+    queue: Arc<Mutex<Queue>>,
+    notify: Arc<Notify>
+}
+
+struct Queue {
+    dir: PathBuf,
+    /// Номер старейшего не переданного результата
+    head: u64,
+    /// Номер следующего добавляемого результата
+    tail: u64
+}
+
+impl Spill {
+
+    /// Открывает очередь в каталоге `dir` и запускает передачу из нее в `tx_rslt`
+    pub fn open(dir: &str, tx_rslt: Sender<StoredResult>) -> io::Result<Spill> {
+        fs::create_dir_all(dir)?;
+        let mut queue = Queue { dir: PathBuf::from(dir), head: 0, tail: 0 };
+        // the new results are numbered after the ones left, these are passed on first
+        queue.recover()?;
+        let queue = Arc::new(Mutex::new(queue));
+        let notify = Arc::new(Notify::new());
+        tokio::spawn(forward(queue.clone(), notify.clone(), tx_rslt));
+        Ok(Spill { queue, notify })
+    }
+
+    /// Признак отсутствия результатов в очереди, включая передаваемый в данный момент
+    pub fn is_empty(&self) -> bool {
+        let q = lock(&self.queue);
+        q.head == q.tail
+    }
+
+    /// Добавляет результат в конец очереди
+    pub fn push(&self, r: &StoredResult) -> io::Result<()> {
+        let mut q = lock(&self.queue);
+        fs::write(q.path(q.tail), r.encode())?;
+        q.tail += 1;
+        drop(q);
+        self.notify.notify();
+        Ok(())
+    }
+}
+
+impl Queue {
+
+    fn path(&self, seq: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", seq, EXT))
+    }
+
+    // takes over the results left in the directory
+    fn recover(&mut self) -> io::Result<()> {
+        let mut seqs: Vec<u64> = fs::read_dir(&self.dir)?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().to_str()
+                .and_then(|n| n.strip_suffix(EXT))
+                .and_then(|n| n.strip_suffix('.'))
+                .and_then(|n| n.parse().ok()))
+            .collect();
+        seqs.sort_unstable();
+        if let (Some(h), Some(t)) = (seqs.first(), seqs.last()) {
+            self.head = *h;
+            self.tail = t + 1;
+        }
+        Ok(())
+    }
+}
+
+fn lock(queue: &Mutex<Queue>) -> MutexGuard<'_, Queue> {
+    queue.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+// passes the queued results on in order, a result leaves the queue once it is in the channel
+async fn forward(queue: Arc<Mutex<Queue>>, notify: Arc<Notify>, mut tx_rslt: Sender<StoredResult>) {
+    // a standby leaves the queue to the leader, what is left by then is taken over once elected
+    leader::elected().await;
+    {
+        let mut q = lock(&queue);
+        if q.head == q.tail {
+            if let Err(e) = q.recover() {
+                error!("inference: failed to read the results left in {}: {}", q.dir.display(), e);
+            }
+        }
+        if q.head < q.tail {
+            info!("inference: {} results left in {} are passed on first", q.tail - q.head, q.dir.display());
+        }
+    }
+    loop {
+        let next = {
+            let q = lock(&queue);
+            if q.head == q.tail { None } else { Some((q.path(q.head), fs::read(q.path(q.head)))) }
+        };
+        let (path, read) = match next {
+            Some(n) => n,
+            None => {
+                notify.notified().await;
+                continue;
+            }
+        };
+        match read.map_err(|e| e.to_string()).and_then(|b| StoredResult::decode(&b)) {
+            Ok(r) => if tx_rslt.send(r).await.is_err() {
+                // the rest stays on disk for the next start
+                return;
+            },
+            Err(e) => error!("inference: spilled result {} is lost: {}", path.display(), e)
+        }
+        let mut q = lock(&queue);
+        if let Err(e) = fs::remove_file(&path) {
+            error!("inference: failed to remove {}: {}", path.display(), e);
+        }
+        q.head += 1;
+        if q.head == q.tail {
+            info!("inference: spilled results are passed on, the queue is empty");
+        }
+    }
+}
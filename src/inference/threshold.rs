@@ -0,0 +1,93 @@
+//! Проверка уверенности рассчитанного результата.
+
+use crate::config::{BelowThreshold, SharedConfig};
+
+/// Решение по результату после проверки его уверенности
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Verdict {
+    /// Результат сохраняется как обычно
+    Pass,
+    /// Результат сохраняется с отметкой о низкой уверенности
+    Flag,
+    /// Результат отбрасывается
+    Drop
+}
+
+/// Сравнивает уверенность результата, т.е. наибольшее из его значений f32, с порогом `[inference] min_confidence`
+pub struct Threshold {
+    min: f32,
+    policy: BelowThreshold
+}
+
+impl Threshold {
+
+    pub fn new(cfg: &SharedConfig) -> Threshold {
+        Threshold {
+            min: cfg.min_confidence(),
+            policy: cfg.below_threshold()
+        }
+    }
+
+    /// Решение по упакованному результату `value`.
+    /// Результат, не являющийся последовательностью f32, не проверяется
+    pub fn check(&self, value: &[u8]) -> Verdict {
+        if self.min <= 0.0 || self.policy == BelowThreshold::Keep {
+            return Verdict::Pass;
+        }
+        match confidence(value) {
+            Some(c) if c < self.min => match self.policy {
+                BelowThreshold::Keep => Verdict::Pass,
+                BelowThreshold::Flag => Verdict::Flag,
+                BelowThreshold::Drop => Verdict::Drop
+            },
+            _ => Verdict::Pass
+        }
+    }
+}
+
+// the top value of the result packed as f32 little-endian
+fn confidence(value: &[u8]) -> Option<f32> {
+    if value.is_empty() || !value.chunks_exact(4).remainder().is_empty() {
+        return None;
+    }
+    value.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .fold(None, |top: Option<f32>, v| Some(top.map_or(v, |t| t.max(v))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    fn threshold(min: &str, policy: &str) -> Threshold {
+        let min = format!("inference.min_confidence={}", min);
+        let policy = format!("inference.below_threshold={}", policy);
+        Threshold::new(&Config::from_sources(&[], &[&min, &policy]).unwrap())
+    }
+
+    fn packed(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn checks_the_top_value() {
+        assert_eq!(confidence(&packed(&[0.1, 0.7, 0.2])), Some(0.7));
+        assert_eq!(confidence(&[]), None);
+        assert_eq!(confidence(&[0; 5]), None);
+    }
+
+    #[test]
+    fn applies_the_policy_below_the_threshold() {
+        let low = packed(&[0.1, 0.3]);
+        let high = packed(&[0.1, 0.9]);
+        assert_eq!(threshold("0.5", "flag").check(&low), Verdict::Flag);
+        assert_eq!(threshold("0.5", "drop").check(&low), Verdict::Drop);
+        assert_eq!(threshold("0.5", "keep").check(&low), Verdict::Pass);
+        assert_eq!(threshold("0.5", "drop").check(&high), Verdict::Pass);
+        // disabled, or not a result of f32
+        assert_eq!(threshold("0", "drop").check(&low), Verdict::Pass);
+        assert_eq!(threshold("0.5", "drop").check(b"abc"), Verdict::Pass);
+    }
+}
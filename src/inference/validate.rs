@@ -0,0 +1,116 @@
+//! Проверка модели вычислителя без запуска конвейера, `--validate-model <path>`.
+
+use crate::config::SharedConfig;
+
+use super::backend::{self, InferenceBackend, Input, Tensor};
+
+/// Количество векторов пробного входа
+const PROBE_FRAMES: usize = 100;
+
+/// Загружает модель `model` вычислителем `[inference] backend`, выводит описание ее входов и выходов
+/// и рассчитывает пробный вход. Ошибка означает, что модель непригодна для работы
+pub fn validate_model(cfg: &SharedConfig, model: &str) -> Result<(), String> {
+    let mut backend = backend::create(&cfg.inference_backend(), model)?;
+    println!("model {} is loaded by {} backend", model, backend.name());
+    let info = backend.model_info();
+    for t in &info.inputs {
+        println!("input  {}", describe(t));
+    }
+    for t in &info.outputs {
+        println!("output {}", describe(t));
+    }
+    let input = info.inputs.first().ok_or("model has no inputs")?;
+    let output = info.outputs.first().ok_or("model has no outputs")?;
+    if input.dtype != "f32" {
+        return Err(format!("input {} is {}, samples are f32", input.name, input.dtype));
+    }
+    self_test(backend.as_mut(), input, output, cfg.feature_bands())
+}
+
+// runs a probe of known values, the dynamic vector size of the input is the configured number of bands
+fn self_test(backend: &mut dyn InferenceBackend, input: &Tensor, output: &Tensor, bands: usize) -> Result<(), String> {
+    let dim = match input.shape.last() {
+        Some(d) if *d > 0 => *d as usize,
+        _ => bands.max(1)
+    };
+    let frames = match input.shape.len() {
+        n if n >= 2 && input.shape[n - 2] > 0 => input.shape[n - 2] as usize,
+        _ => PROBE_FRAMES
+    };
+    let mut value = Vec::with_capacity(frames * dim * 4);
+    for i in 0..frames * dim {
+        value.extend_from_slice(&(((i % dim) as f32 + 1.0) / dim as f32).to_le_bytes());
+    }
+    let result = backend.infer(&Input { value: &value, dim, frames })
+        .map_err(|e| format!("self-test failed: {}", e))?;
+    if !result.chunks_exact(4).remainder().is_empty() {
+        return Err(format!("self-test failed: {} bytes of output are not f32 values", result.len()));
+    }
+    let expected: i64 = output.shape.iter().product();
+    let got = result.len() / 4;
+    if expected > 0 && got as i64 != expected {
+        return Err(format!("self-test failed: {} values of output instead of {}", got, expected));
+    }
+    if result.chunks_exact(4).any(|b| !f32::from_le_bytes([b[0], b[1], b[2], b[3]]).is_finite()) {
+        return Err("self-test failed: output is not finite".to_string());
+    }
+    println!("self-test passed: {} vectors of {} -> {} values", frames, dim, got);
+    Ok(())
+}
+
+fn describe(t: &Tensor) -> String {
+    let dims: Vec<String> = t.shape.iter().map(|d| if *d < 0 { "?".to_string() } else { d.to_string() }).collect();
+    format!("{} [{}] {}", t.name, dims.join(", "), t.dtype)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::inference::backend::ModelInfo;
+
+    // answers any input with the same output
+    struct Fixed(Vec<f32>);
+
+    impl InferenceBackend for Fixed {
+
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn model_info(&self) -> ModelInfo {
+            ModelInfo { inputs: vec![tensor(vec![4, 2])], outputs: vec![tensor(vec![2])] }
+        }
+
+        fn infer(&mut self, input: &Input) -> Result<Vec<u8>, String> {
+            assert_eq!((input.frames, input.dim, input.value.len()), (4, 2, 32));
+            Ok(self.0.iter().flat_map(|v| v.to_le_bytes()).collect())
+        }
+    }
+
+    fn tensor(shape: Vec<i64>) -> Tensor {
+        Tensor { name: "t".to_string(), shape, dtype: "f32" }
+    }
+
+    #[test]
+    fn describes_the_dynamic_axes() {
+        assert_eq!(describe(&tensor(vec![-1, 40])), "t [?, 40] f32");
+    }
+
+    #[test]
+    fn the_mock_model_passes() {
+        let cfg = Config::from_sources(&[], &["inference.backend=mock"]).unwrap();
+        assert_eq!(validate_model(&cfg, ""), Ok(()));
+        let cfg = Config::from_sources(&[], &["inference.backend=tensorrt"]).unwrap();
+        assert!(validate_model(&cfg, "model.plan").err().unwrap().contains("not available"));
+    }
+
+    #[test]
+    fn checks_the_output_of_the_probe() {
+        let (input, output) = (tensor(vec![4, 2]), tensor(vec![2]));
+        assert_eq!(self_test(&mut Fixed(vec![0.5, 1.0]), &input, &output, 40), Ok(()));
+        assert!(self_test(&mut Fixed(vec![0.5]), &input, &output, 40).err().unwrap().contains("1 values of output instead of 2"));
+        assert!(self_test(&mut Fixed(vec![0.5, f32::NAN]), &input, &output, 40).err().unwrap().contains("not finite"));
+    }
+}
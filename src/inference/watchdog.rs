@@ -0,0 +1,248 @@
+//! Ограничение времени расчета пакета и пересоздание зависшего вычислителя.
+
+use std::time::Duration;
+
+use crate::config::SharedConfig;
+use crate::events;
+use crate::health;
+use crate::stats::{DeviceLoad, SharedStats};
+
+use super::affinity;
+use super::backend::{self, InferenceBackend, Input, Partials};
+
+use log::{error, info, warn};
+use serde_json::json;
+use tokio::task::{self, JoinHandle};
+use tokio::time::timeout;
+
+/// Результаты пакета входов
+type Results = Vec<Result<Vec<u8>, String>>;
+/// Расчет пакета в потоке блокирующих операций, возвращающий вычислитель вместе с результатами
+type Call = JoinHandle<(Box<dyn InferenceBackend>, Results, Partials)>;
+
+/// Вычислитель с ограничением времени расчета пакета `[inference] infer_timeout`.
+/// Пакет рассчитывается в потоке блокирующих операций, не уложившийся в таймаут пакет завершается ошибкой,
+/// а вычислитель остается занятым до окончания расчета. После `reset_after_timeouts` таймаутов подряд
+/// вычислитель считается зависшим, например из-за испорченного контекста GPU, брошенный расчет оставляется,
+/// а вычислитель создается заново. После `unhealthy_after_resets` пересозданий подряд, не вернувших успешный расчет,
+/// подсистема отмечается неработоспособной до первого успешного расчета
+pub struct Watchdog {
+    cfg: SharedConfig,
+    stats: SharedStats,
+    limit: Duration,
+    reset_after: u32,
+    unhealthy_after: u32,
+    /// Ядра CPU, к которым привязывается поток на время расчета пакета
+    cores: Vec<usize>,
+    /// Свободный вычислитель, None - занят расчетом `busy` или не создан при пересоздании
+    backend: Option<Box<dyn InferenceBackend>>,
+    /// Расчет, не уложившийся в таймаут, по его окончании вычислитель освобождается
+    busy: Option<Call>,
+    timeouts: u32,
+    resets: u32
+}
+
+impl Watchdog {
+
+    pub fn new(cfg: &SharedConfig, stats: SharedStats, backend: Box<dyn InferenceBackend>) -> Watchdog {
+        Watchdog {
+            cfg: cfg.clone(),
+            stats,
+            limit: cfg.infer_timeout(),
+            reset_after: cfg.reset_after_timeouts(),
+            unhealthy_after: cfg.unhealthy_after_resets(),
+            cores: cfg.cpu_affinity(),
+            backend: Some(backend),
+            busy: None,
+            timeouts: 0,
+            resets: 0
+        }
+    }
+
+    /// Рассчитывает результаты пакета входов в том же порядке вместе с промежуточными результатами потоковой модели.
+    /// По таймауту, как и при недоступности вычислителя, все входы завершаются ошибкой без промежуточных результатов
+    pub async fn infer_batch(&mut self, batch: &[Input<'_>]) -> (Results, Partials) {
+        if self.limit == Duration::from_secs(0) {
+            // without the limit a batch is never abandoned, the backend is always at hand
+            if let Some(b) = self.backend.as_mut() {
+                let _pinned = affinity::pin(&self.cores);
+                let mut partials = Partials::new();
+                let r = b.infer_batch_streamed(batch, &mut |n, v| partials.push((n, v)));
+                return (r, partials);
+            }
+        }
+        let mut backend = match self.free().await {
+            Ok(b) => b,
+            Err(e) => return (failed(batch.len(), e), Partials::new())
+        };
+        let inputs: Vec<(Vec<u8>, usize, usize)> = batch.iter().map(|i| (i.value.to_vec(), i.dim, i.frames)).collect();
+        let cores = self.cores.clone();
+        let mut call = task::spawn_blocking(move || {
+            let _pinned = affinity::pin(&cores);
+            let batch: Vec<Input> = inputs.iter().map(|(value, dim, frames)| Input { value, dim: *dim, frames: *frames }).collect();
+            let mut partials = Partials::new();
+            let r = backend.infer_batch_streamed(&batch, &mut |n, v| partials.push((n, v)));
+            (backend, r, partials)
+        });
+        match timeout(self.limit, &mut call).await {
+            Ok(Ok((b, r, partials))) => {
+                self.backend = Some(b);
+                self.succeeded();
+                (r, partials)
+            },
+            Ok(Err(e)) => {
+                // the backend is lost with the panicked thread, the next batch gets a new one
+                error!("inference: backend failed: {}", e);
+                (failed(batch.len(), format!("backend failed: {}", e)), Partials::new())
+            },
+            Err(_) => {
+                self.timed_out();
+                self.busy = Some(call);
+                (failed(batch.len(), format!("not computed in {:?}", self.limit)), Partials::new())
+            }
+        }
+    }
+
+    /// Текущая загрузка GPU по данным свободного вычислителя
+    pub fn device_load(&self) -> Option<DeviceLoad> {
+        self.backend.as_ref().and_then(|b| b.device_load())
+    }
+
+    // the backend to compute the next batch: the free one, the overrunning one once it is done, or a new one
+    async fn free(&mut self) -> Result<Box<dyn InferenceBackend>, String> {
+        if let Some(b) = self.backend.take() {
+            return Ok(b);
+        }
+        if let Some(mut call) = self.busy.take() {
+            if !self.wedged() {
+                // the batch is given the limit to wait for the overrunning one, its late results are of no use
+                match timeout(self.limit, &mut call).await {
+                    Ok(Ok((b, ..))) => {
+                        info!("inference: backend has finished the overrunning batch");
+                        return Ok(b);
+                    },
+                    Ok(Err(e)) => error!("inference: backend failed: {}", e),
+                    Err(_) => {
+                        self.timed_out();
+                        if !self.wedged() {
+                            self.busy = Some(call);
+                            return Err(format!("backend is busy for over {:?}", self.limit));
+                        }
+                    }
+                }
+            }
+            // dropping the handle abandons the call, its thread is left until the backend returns, if ever
+        }
+        self.reset()
+    }
+
+    fn reset(&mut self) -> Result<Box<dyn InferenceBackend>, String> {
+        if self.unhealthy_after > 0 && self.resets >= self.unhealthy_after && health::set_failed("inference", true) {
+            error!("inference: !!! {} BACKEND RESETS IN A ROW DIDN'T HELP, THE INSTANCE IS UNHEALTHY !!!", self.resets);
+        }
+        self.resets += 1;
+        self.stats.inference_metrics.reset();
+        let reason = match self.timeouts {
+            0 => "IS LOST".to_string(),
+            n => format!("IS WEDGED after {} timeouts in a row", n)
+        };
+        error!("inference: !!! BACKEND {}, it is reset (attempt {}) !!!", reason, self.resets);
+        self.timeouts = 0;
+        match backend::build(&self.cfg) {
+            Ok(b) => {
+                warn!("inference: {} backend is recreated", b.name());
+                events::emit("backend_reset", json!({ "attempt": self.resets, "ok": true }));
+                Ok(b)
+            },
+            Err(e) => {
+                error!("inference: backend reset failed: {}", e);
+                events::emit("backend_reset", json!({ "attempt": self.resets, "ok": false, "error": e }));
+                Err(format!("backend reset failed, {}", e))
+            }
+        }
+    }
+
+    fn timed_out(&mut self) {
+        self.timeouts += 1;
+        self.stats.inference_metrics.timeout();
+        warn!("inference: batch is not computed in {:?} ({} timeouts in a row)", self.limit, self.timeouts);
+    }
+
+    fn succeeded(&mut self) {
+        self.timeouts = 0;
+        self.resets = 0;
+        if health::set_failed("inference", false) {
+            info!("inference: backend is computing again, the instance is healthy");
+        }
+    }
+
+    fn wedged(&self) -> bool {
+        self.reset_after > 0 && self.timeouts >= self.reset_after
+    }
+}
+
+fn failed(n: usize, e: String) -> Results {
+    vec![Err(e); n]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::inference::backend::ModelInfo;
+    use crate::stats::Stats;
+
+    // answers each input with its value after the pause
+    struct Slow(Duration);
+
+    impl InferenceBackend for Slow {
+
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        fn model_info(&self) -> ModelInfo {
+            ModelInfo { inputs: Vec::new(), outputs: Vec::new() }
+        }
+
+        fn infer(&mut self, input: &Input) -> Result<Vec<u8>, String> {
+            std::thread::sleep(self.0);
+            Ok(input.value.to_vec())
+        }
+    }
+
+    fn watchdog(sets: &[&str], pause: Duration) -> (Watchdog, SharedStats) {
+        let cfg = Config::from_sources(&[], sets).unwrap();
+        let stats = Stats::new(&[]);
+        (Watchdog::new(&cfg, stats.clone(), Box::new(Slow(pause))), stats)
+    }
+
+    fn input(v: &[u8]) -> Input<'_> {
+        Input { value: v, dim: 1, frames: 1 }
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn computes_within_the_limit() {
+        let (mut w, _) = watchdog(&["inference.infer_timeout=1s"], Duration::from_millis(0));
+        let v = 1f32.to_le_bytes();
+        assert_eq!(w.infer_batch(&[input(&v)]).await.0, vec![Ok(v.to_vec())]);
+        assert_eq!(w.infer_batch(&[input(&v)]).await.0, vec![Ok(v.to_vec())]);
+        let (mut w, _) = watchdog(&["inference.infer_timeout=0s"], Duration::from_millis(0));
+        assert_eq!(w.infer_batch(&[input(&v)]).await.0, vec![Ok(v.to_vec())]);
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn resets_the_wedged_backend() {
+        let sets = ["inference.backend=mock", "inference.infer_timeout=50ms", "inference.reset_after_timeouts=2", "inference.unhealthy_after_resets=0"];
+        let (mut w, stats) = watchdog(&sets, Duration::from_millis(150));
+        let v = 1f32.to_le_bytes();
+        let (r, _) = w.infer_batch(&[input(&v), input(&v)]).await;
+        assert_eq!(r.len(), 2);
+        assert!(r.iter().all(|r| r.as_ref().err().unwrap().contains("not computed")));
+        // the overrunning batch times out once more, the backend is given up and a new one computes the batch
+        assert_eq!(w.infer_batch(&[input(&v)]).await.0, vec![Ok(v.to_vec())]);
+        let m = &stats.snapshot()["inference"];
+        assert_eq!((&m["timeouts"], &m["resets"]), (&serde_json::json!(2), &serde_json::json!(1)));
+    }
+}
@@ -0,0 +1,152 @@
+//! Ограничение количества сэмплов в работе на границе processor и inference.
+//!
+//! Сэмпл находится в работе с передачи его processor до окончания расчета его пакета в inference,
+//! включая ожидание в канале и накопление пакета. Processor не передает сэмпл, пока их в работе
+//! `[inference] max_in_flight`, так что при всплеске нагрузки память под сэмплы и пакеты остается ограниченной
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+
+/// Сэмплы в работе приложения
+static STATE: InFlight = InFlight::new();
+
+/// Счет сэмплов в работе и разрешения на них
+struct InFlight {
+    /// Разрешения на сэмплы в работе, None - количество не ограничено
+    permits: Mutex<Option<Arc<Semaphore>>>,
+    /// Наибольшее количество сэмплов в работе, 0 - не ограничено
+    limit: AtomicU64,
+    /// Сэмплы в работе
+    current: AtomicU64,
+    /// Наибольшее количество сэмплов в работе с запуска
+    peak: AtomicU64
+}
+
+/// Устанавливает наибольшее количество сэмплов в работе, 0 - не ограничено. Вызывается до запуска подсистем
+pub fn init(limit: usize) {
+    STATE.init(limit);
+}
+
+/// Дожидается разрешения на передачу сэмпла в работу
+pub async fn acquire() {
+    STATE.acquire().await;
+}
+
+/// Возвращает разрешения `n` сэмплов, расчет которых окончен или которые выбыли из работы
+pub fn release(n: usize) {
+    STATE.release(n);
+}
+
+/// Снимает ограничение после завершения inference: разрешения уже не вернутся, а ожидающий их processor
+/// должен обнаружить закрытый канал сэмплов и завершиться
+pub fn close() {
+    STATE.close();
+}
+
+/// Снимок показателей сэмплов в работе в виде JSON
+pub fn snapshot() -> Value {
+    STATE.snapshot()
+}
+
+impl InFlight {
+
+    const fn new() -> InFlight {
+        InFlight {
+            permits: Mutex::new(None),
+            limit: AtomicU64::new(0),
+            current: AtomicU64::new(0),
+            peak: AtomicU64::new(0)
+        }
+    }
+
+    fn init(&self, limit: usize) {
+        self.limit.store(limit as u64, Ordering::Relaxed);
+        *self.permits() = Some(limit).filter(|l| *l > 0).map(|l| Arc::new(Semaphore::new(l)));
+    }
+
+    async fn acquire(&self) {
+        let sem = self.permits().clone();
+        if let Some(sem) = sem {
+            // the permit is returned by release() once the sample is computed, not when this scope ends
+            sem.acquire().await.forget();
+        }
+        let n = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(n, Ordering::Relaxed);
+    }
+
+    fn release(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let _ = self.current.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| Some(c.saturating_sub(n as u64)));
+        if let Some(sem) = self.permits().as_ref() {
+            sem.add_permits(n);
+        }
+    }
+
+    fn close(&self) {
+        if let Some(sem) = self.permits().take() {
+            sem.add_permits(self.limit.load(Ordering::Relaxed) as usize);
+        }
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "samples": self.current.load(Ordering::Relaxed),
+            "peak": self.peak.load(Ordering::Relaxed),
+            "limit": self.limit.load(Ordering::Relaxed)
+        })
+    }
+
+    fn permits(&self) -> MutexGuard<'_, Option<Arc<Semaphore>>> {
+        self.permits.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    // the process-wide state is shared by the tests of processor and inference, these count on their own
+    #[tokio::test]
+    async fn waits_for_the_room() {
+        let f = InFlight::new();
+        f.init(2);
+        f.acquire().await;
+        f.acquire().await;
+        assert!(timeout(Duration::from_millis(20), f.acquire()).await.is_err());
+        f.release(1);
+        assert!(timeout(Duration::from_millis(20), f.acquire()).await.is_ok());
+        f.release(1);
+        assert_eq!(f.snapshot(), json!({ "samples": 1, "peak": 2, "limit": 2 }));
+    }
+
+    #[tokio::test]
+    async fn the_close_lets_the_waiting_go() {
+        let f = InFlight::new();
+        f.init(1);
+        f.acquire().await;
+        f.close();
+        assert!(timeout(Duration::from_millis(20), f.acquire()).await.is_ok());
+        // the late releases find no permits to return to
+        f.release(5);
+        assert_eq!(f.snapshot()["samples"], 0);
+    }
+
+    #[tokio::test]
+    async fn unlimited_by_default() {
+        let f = InFlight::new();
+        f.init(0);
+        for _ in 0..3 {
+            assert!(timeout(Duration::from_millis(20), f.acquire()).await.is_ok());
+        }
+        assert_eq!(f.snapshot(), json!({ "samples": 3, "peak": 3, "limit": 0 }));
+    }
+}
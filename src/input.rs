@@ -1,47 +1,169 @@
 //! Получение входных данных.
 //! 
 //! Задачи:
-//! *  подключиться к пулу подсистем сопряжения по TCP
+//! *  подключиться к пулу подсистем сопряжения по TCP, с заданными размерами буферов сокета (`[input] so_rcvbuf`, `so_sndbuf`)
 //! *  выполнять двусторонний обмен по логике взаимодействия между подсистемами по V-протоколу
-//! *  читать фрагменты объектов по V-протоколу
+//! *  распаковать поток, если система сопряжения передает его сжатым (`[input] compression`)
+//! *  читать фрагменты объектов по V-протоколу, собирая кадры из чтений по `[input] read_chunk_size` байтов
+//! *  проверять кадры со строгостью, заданной для системы сопряжения (`[input] validation`, `/validation=<level>`)
+//! *  разрывать соединение, кадр из которого не получен целиком за `[input] read_timeout` с его первого байта
+//!    или очередной кадр не начат за это время
+//! *  переустанавливать соединение, существующее дольше `[input] max_connection_lifetime`
 //! *  по полю в заголовке фрагмента определить получателя фрагмента
 //! *  передать фрагмент в коллектор
+//!
+//! Если коллектор не успевает принимать фрагменты, по настройке `[input] on_full` чтение из соединения ожидает
+//! (данные могут теряться уже в буферах системы сопряжения), фрагмент отбрасывается или, если место в канале
+//! не освобождается дольше `[input] full_timeout`, соединение разрывается и восстанавливается после освобождения места
+//!
+//! Первые подключения после запуска разносятся во времени: к каждой системе сопряжения приложение подключается
+//! со случайной задержкой не более `[input] connect_stagger`, чтобы все системы не получали подключения одновременно.
+//! Повторные подключения выполняются через `[input] reconnect_delay` без случайной задержки.
+//! Общая для всех систем сопряжения частота повторных подключений ограничивается `[input] reconnect_rate`,
+//! чтобы после восстановления общего кластера сопряжения к нему не переподключались все системы разом
+//!
+//! В режиме вывода экземпляра из работы ([`drain`](crate::drain)) соединения с системами сопряжения закрываются
+//! и не устанавливаются до снятия режима
+//!
+//! Резервный экземпляр (`[leader] lock`) не подключается к системам сопряжения, пока не будет выбран ведущим
+//!
+//! Команда на завершение имеет приоритет над чтением: она проверяется прежде чтения из соединения, поэтому
+//! непрерывно поступающие данные не задерживают завершение
 
-use crate::config::SharedConfig;
+mod connects;
+mod inflate;
+mod peer;
+mod socket;
+mod vproto;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{InputFull, SharedConfig, Validation};
+use crate::leader;
+use crate::timer::jitter;
+use crate::stats::SharedStats;
+use crate::tracker::TaskGuard;
 use crate::data::Fragment;
+use self::connects::Connects;
 
-use log::info;
-use tokio::sync::oneshot;
+use log::{debug, info, warn};
+use tokio::sync::{oneshot, watch};
 use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio::time::delay_for;
 
 /// Запускает в асинхронном режиме подсистему получения входных данных от системы сопряжения комплекса
 /// 
 /// Параметры:
 /// 
 /// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `stats` - счетчики работы подсистем
 /// * `rx_stop` - межпоточный канал получения команды на завершение, читатель
 /// * `tx_frag` - межпоточный канал передачи в коллектор получаемых фрагментов, писатель
 /// 
-/// Возвращает Future для ожидания в вызывающем коде
-pub async fn run(cfg: SharedConfig, rx_stop: oneshot::Receiver<()>, _tx_frag: Sender<Fragment>) {
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, mut rx_stop: oneshot::Receiver<()>, tx_frag: Sender<Fragment>) -> JoinHandle<()> {
     info!("start input");
 
+    let peers = cfg.peers();
+    // the limit applies to connect and handshake only, established connections aren't limited
+    let limit = match cfg.max_concurrent_connects() {
+        0 => peers.len().max(1),
+        n => n
+    };
+    let connects = Arc::new(Connects::new(limit, cfg.reconnect_rate(), cfg.reconnect_burst()));
+    info!("input: {} peers, up to {} connects at once", peers.len(), limit);
+    if cfg.reconnect_rate() > 0.0 {
+        info!("input: up to {} reconnects per second to all peers, bursts of {}", cfg.reconnect_rate(), cfg.reconnect_burst());
+    }
+    if cfg.read_chunk_size() < peer::MIN_READ_CHUNK {
+        warn!("input: read_chunk_size {} is too small, {} is used", cfg.read_chunk_size(), peer::MIN_READ_CHUNK);
+    }
+    match cfg.input_on_full() {
+        InputFull::Block => {},
+        InputFull::Drop => info!("input: fragments are dropped while the collector is full"),
+        InputFull::Disconnect => info!("input: connections are reset when the collector is full for {:?}", cfg.full_timeout())
+    }
+    // lenient is the protocol as specified, only the other levels are worth a line
+    for p in peers.iter() {
+        let level = p.validation().unwrap_or_else(|| cfg.validation());
+        if level != Validation::Lenient {
+            info!("input: frames of {} are validated {}", p, level);
+        }
+    }
+    let stagger = cfg.connect_stagger();
+    if stagger > Duration::from_secs(0) {
+        info!("input: first connects are spread over {:?}", stagger);
+    }
+
     tokio::spawn( async move {
+        let _guard = guard;
 
-        //todo: start all client sockets according to cfg.peers()
-        // print target peers to connect to
-        let peers = cfg.peers();
-        for p in peers {
-            info!("input: unable to connect to {}", p);
+        // a standby instance leaves the peers to the leader
+        tokio::select! {
+            _ = leader::elected() => {},
+            _ = &mut rx_stop => {
+                info!("input is stopped on standby");
+                return;
+            }
         }
 
-        loop {
-            let _ = rx_stop.await;
-            info!("stop input");
-            //todo: stop all client sockets
-            break;
-        }    
+        let (tx_peer_stop, rx_peer_stop) = watch::channel(false);
+        let handles: Vec<_> = peers.into_iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let delay = jitter(stagger);
+                let mut rx_peer_stop = rx_peer_stop.clone();
+                let run = peer::run(cfg.clone(), stats.clone(), i, p, connects.clone(), rx_peer_stop.clone(), tx_frag.clone());
+                tokio::spawn(async move {
+                    if delay > Duration::from_secs(0) {
+                        debug!("input: peer {} first connects in {:?}", i, delay);
+                        tokio::select! {
+                            _ = delay_for(delay) => {},
+                            _ = peer::stopped(&mut rx_peer_stop) => return
+                        }
+                    }
+                    run.await
+                })
+            })
+            .collect();
+
+        let _ = rx_stop.await;
+        info!("stop input");
+        let _ = tx_peer_stop.broadcast(true);
+        for h in handles {
+            let _ = h.await;
+        }
         info!("input is stopped");
 
-    });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+    use crate::tracker::TaskTracker;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn the_staggered_connect_is_stopped_while_it_waits() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer = format!("input.peers={}", listener.local_addr().unwrap());
+        // an hour of spread leaves the connect waiting for the test to be over
+        let cfg = Config::from_sources(&[], &[&peer, "input.connect_stagger=1h"]).unwrap();
+        let (tx_stop, rx_stop) = oneshot::channel();
+        let (tx_frag, _rx_frag) = mpsc::channel(1);
+        let task = run(cfg.clone(), TaskTracker::new().track("input"), Stats::new(&cfg.peers()), rx_stop, tx_frag).await;
+        assert!(timeout(Duration::from_millis(100), listener.accept()).await.is_err());
+        tx_stop.send(()).unwrap();
+        assert!(timeout(Duration::from_secs(1), task).await.is_ok());
+    }
 }
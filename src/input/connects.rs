@@ -0,0 +1,113 @@
+//! Ограничения подключений, общие для всех систем сопряжения.
+
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::delay_for;
+
+/// Общие для всех систем сопряжения ограничения: количество одновременно устанавливаемых подключений
+/// `[input] max_concurrent_connects` и частота повторных подключений `[input] reconnect_rate`.
+/// Частота ограничивается маркерной корзиной емкостью `reconnect_burst`: каждое повторное подключение
+/// забирает маркер, маркеры пополняются с заданной частотой. Когда общая система сопряжения восстанавливается
+/// после отказа, к ней переподключаются не все сразу, а с ограниченной частотой
+pub struct Connects {
+    permits: Semaphore,
+    bucket: Option<Mutex<Bucket>>
+}
+
+/// Маркерная корзина
+struct Bucket {
+    /// Пополнение, маркеров в секунду
+    rate: f64,
+    /// Емкость
+    burst: f64,
+    tokens: f64,
+    /// Момент последнего пополнения
+    filled: Instant
+}
+
+impl Connects {
+
+    /// `limit` - одновременно устанавливаемых подключений, `rate` - повторных подключений в секунду, 0 - без ограничения
+    pub fn new(limit: usize, rate: f64, burst: u32) -> Connects {
+        let bucket = if rate > 0.0 {
+            let burst = f64::from(burst.max(1));
+            Some(Mutex::new(Bucket { rate, burst, tokens: burst, filled: Instant::now() }))
+        } else {
+            None
+        };
+        Connects {
+            permits: Semaphore::new(limit),
+            bucket
+        }
+    }
+
+    /// Разрешение на установку подключения, удерживается до окончания установки
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.permits.acquire().await
+    }
+
+    /// Дожидается разрешения на повторное подключение, возвращает время ожидания
+    pub async fn reconnect(&self) -> Duration {
+        let started = Instant::now();
+        let bucket = match self.bucket.as_ref() {
+            Some(b) => b,
+            None => return Duration::from_secs(0)
+        };
+        loop {
+            // the lock isn't held while waiting, whoever is first to find a token takes it
+            let wait = match lock(bucket).take(Instant::now()) {
+                None => return started.elapsed(),
+                Some(w) => w
+            };
+            delay_for(wait).await;
+        }
+    }
+}
+
+impl Bucket {
+
+    // takes a token, None if taken, otherwise the time until the next one
+    fn take(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.filled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.filled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+}
+
+fn lock(bucket: &Mutex<Bucket>) -> MutexGuard<'_, Bucket> {
+    bucket.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_bucket_refills_at_the_rate() {
+        let now = Instant::now();
+        let mut b = Bucket { rate: 10.0, burst: 2.0, tokens: 2.0, filled: now };
+        assert_eq!(b.take(now), None);
+        assert_eq!(b.take(now), None);
+        assert_eq!(b.take(now), Some(Duration::from_millis(100)));
+        assert_eq!(b.take(now + Duration::from_millis(100)), None);
+        // the idle time fills up to the burst only
+        assert_eq!(b.take(now + Duration::from_secs(10)), None);
+        assert_eq!(b.take(now + Duration::from_secs(10)), None);
+        assert!(b.take(now + Duration::from_secs(10)).is_some());
+    }
+
+    #[tokio::test]
+    async fn the_reconnect_waits_its_turn() {
+        assert_eq!(Connects::new(1, 0.0, 1).reconnect().await, Duration::from_secs(0));
+        let c = Connects::new(1, 50.0, 1);
+        assert!(c.reconnect().await < Duration::from_millis(5));
+        assert!(c.reconnect().await >= Duration::from_millis(15));
+    }
+}
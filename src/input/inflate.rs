@@ -0,0 +1,174 @@
+//! Распаковка сжатого потока V-протокола по мере его получения.
+
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::config::Compression;
+
+use flate2::write::{GzDecoder, ZlibDecoder};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Объем одного чтения сжатых данных из нижележащего потока
+const READ_CHUNK: usize = 16 * 1024;
+
+// the push style decoders accumulate the decompressed bytes in their output vector
+enum Decoder {
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>)
+}
+
+impl Decoder {
+
+    // the decoders keep the decompressed bytes internally until flushed
+    fn feed(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Decoder::Gzip(d) => d.write_all(data).and_then(|_| d.flush()),
+            Decoder::Deflate(d) => d.write_all(data).and_then(|_| d.flush())
+        }
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            Decoder::Gzip(d) => d.get_mut(),
+            Decoder::Deflate(d) => d.get_mut()
+        }
+    }
+}
+
+/// Поток, распаковывающий данные, читаемые из `inner`, в соответствии с заданным сжатием.
+/// Запись передается в `inner` без изменений. Без сжатия чтение также передается без изменений.
+/// Повреждение сжатых данных возвращается при чтении как ошибка `InvalidData`
+pub struct Inflate<R> {
+    inner: R,
+    compression: Compression,
+    decoder: Option<Decoder>,
+    raw: Vec<u8>,
+    // the decompressed bytes are handed out from this position of the decoder output
+    pos: usize
+}
+
+impl<R> Inflate<R> {
+
+    pub fn new(inner: R, compression: Compression) -> Inflate<R> {
+        let decoder = match compression {
+            Compression::None => None,
+            Compression::Gzip => Some(Decoder::Gzip(GzDecoder::new(Vec::new()))),
+            Compression::Deflate => Some(Decoder::Deflate(ZlibDecoder::new(Vec::new())))
+        };
+        Inflate {
+            inner,
+            compression,
+            decoder,
+            raw: vec![0; if compression == Compression::None { 0 } else { READ_CHUNK }],
+            pos: 0
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Inflate<R> {
+
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let decoder = match this.decoder.as_mut() {
+            None => return Pin::new(&mut this.inner).poll_read(cx, buf),
+            Some(d) => d
+        };
+        loop {
+            let out = decoder.output();
+            if this.pos < out.len() {
+                let n = buf.len().min(out.len() - this.pos);
+                buf[..n].copy_from_slice(&out[this.pos..this.pos + n]);
+                this.pos += n;
+                if this.pos == out.len() {
+                    out.clear();
+                    this.pos = 0;
+                }
+                return Poll::Ready(Ok(n));
+            }
+            // a read may carry only a part of a compressed block, so nothing is decompressed yet
+            let n = match Pin::new(&mut this.inner).poll_read(cx, &mut this.raw) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(0)) => return Poll::Ready(Ok(0)),
+                Poll::Ready(Ok(n)) => n
+            };
+            if let Err(e) = decoder.feed(&this.raw[..n]) {
+                let msg = format!("{} stream is corrupt: {}", this.compression, e);
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, msg)));
+            }
+        }
+    }
+}
+
+impl<R: AsyncWrite + Unpin> AsyncWrite for Inflate<R> {
+
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression as Level;
+    use tokio::io::AsyncReadExt;
+
+    const TEXT: &[u8] = b"VPRT frames of the peer, VPRT frames of the peer, VPRT frames of the peer";
+
+    async fn inflate(data: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Inflate::new(data, compression).read_to_end(&mut out).await?;
+        Ok(out)
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), Level::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut e = ZlibEncoder::new(Vec::new(), Level::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn decompresses_the_stream() {
+        assert_eq!(inflate(&gzip(TEXT), Compression::Gzip).await.unwrap(), TEXT);
+        assert_eq!(inflate(&deflate(TEXT), Compression::Deflate).await.unwrap(), TEXT);
+        assert_eq!(inflate(TEXT, Compression::None).await.unwrap(), TEXT);
+    }
+
+    #[tokio::test]
+    async fn hands_out_the_output_in_small_reads() {
+        let data = gzip(TEXT);
+        let mut r = Inflate::new(&data[..], Compression::Gzip);
+        let (mut out, mut buf) = (Vec::new(), [0; 3]);
+        loop {
+            let n = r.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, TEXT);
+    }
+
+    #[tokio::test]
+    async fn a_corrupt_stream_is_invalid_data() {
+        let e = inflate(b"not a gzip stream", Compression::Gzip).await.err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,738 @@
+//! Подключение к одной системе сопряжения и получение от нее фрагментов.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use crate::config::{ByteOrder, Endpoint, InputFull, SharedConfig, Validation};
+use crate::data::{AudioParams, Fragment, FragmentKind};
+use crate::drain;
+use crate::memory;
+use crate::stats::{PeerState, SharedStats};
+use super::connects::Connects;
+use super::inflate::Inflate;
+use super::socket::Buffers;
+use super::vproto::{self, Header, Kind};
+
+use bytes::BytesMut;
+use log::{debug, error, info, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::watch;
+use tokio::task;
+use tokio::time::{delay_for, timeout, timeout_at};
+
+/// Наименьший допустимый объем чтения из соединения `[input] read_chunk_size`, байтов
+pub const MIN_READ_CHUNK: usize = 512;
+
+/// Причина завершения соединения
+enum Closed {
+    /// Соединение закрыто системой сопряжения
+    ByPeer,
+    /// Ошибка подключения или обмена
+    Failed(String),
+    /// Канал передачи фрагментов в коллектор разрушен
+    Downstream,
+    /// Канал передачи фрагментов в коллектор заполнен дольше `[input] full_timeout`
+    Overloaded,
+    /// Соединение существует дольше `[input] max_connection_lifetime`
+    Expired,
+    /// Включен режим вывода экземпляра из работы
+    Drained
+}
+
+/// Поддерживает подключение к системе сопряжения `peer` до получения команды на завершение:
+/// подключается, передает полученные фрагменты в коллектор, после разрыва соединения подключается повторно.
+/// После `[input] max_reconnects` неудачных попыток подряд система сопряжения считается недоступной
+/// и подключения к ней прекращаются до перезагрузки конфигурации командой `reload` или перезапуска приложения.
+/// Одновременно устанавливаемых подключений не больше, чем разрешений в `connects`,
+/// повторные подключения после разрыва или неудачи ожидают разрешения `connects` по общей частоте.
+/// Соединение, разорванное из-за заполненного канала передачи фрагментов, восстанавливается после освобождения места в нем.
+/// Соединение, закрытое по истечении `[input] max_connection_lifetime`, устанавливается заново сразу.
+/// В режиме вывода экземпляра из работы соединение закрывается и не устанавливается до снятия режима
+pub async fn run(cfg: SharedConfig, stats: SharedStats, index: usize, peer: Endpoint, connects: Arc<Connects>,
+                 mut rx_stop: watch::Receiver<bool>, mut tx_frag: Sender<Fragment>) {
+    let ps = stats.peer(index);
+    let mut rx_reload = cfg.reloaded();
+    let mut failures = 0;
+    let mut buffers = Buffers::new(&cfg, &peer);
+    let lifetime = cfg.max_connection_lifetime();
+    loop {
+        if drain::is_draining() {
+            ps.set_state(PeerState::Drained);
+            tokio::select! {
+                _ = drain::resumed() => info!("input: drain is over, connecting to {}", peer),
+                _ = stopped(&mut rx_stop) => {
+                    ps.set_state(PeerState::Stopped);
+                    return;
+                }
+            }
+            ps.set_state(PeerState::Connecting);
+        }
+        let mut established = false;
+        let conn = async {
+            let stream = match connect(&cfg, &stats, index, &peer, &mut buffers, &connects).await {
+                Ok(s) => s,
+                Err(c) => return c
+            };
+            established = true;
+            ps.connected();
+            info!("input: connected to {}", peer);
+            let expired = async {
+                if lifetime == Duration::from_secs(0) {
+                    std::future::pending::<()>().await;
+                }
+                delay_for(lifetime).await
+            };
+            tokio::select! {
+                c = read_loop(&cfg, &stats, index, &peer, stream, &mut tx_frag) => c,
+                _ = expired => Closed::Expired,
+                _ = drain::started() => Closed::Drained
+            }
+        };
+        let closed = match unless_stopped(&mut rx_stop, conn).await {
+            Some(c) => c,
+            None => {
+                ps.set_state(PeerState::Stopped);
+                return;
+            }
+        };
+        if established {
+            // the notice follows the fragments in the channel, it is of no use if the channel is full or broken
+            if tx_frag.try_send(Fragment::Disconnected { peer: index }).is_err() {
+                debug!("input: collector isn't told of the disconnect of {}", peer);
+            }
+        }
+        match closed {
+            Closed::ByPeer => info!("input: {} closed the connection", peer),
+            Closed::Failed(e) => warn!("input: {}: {}", peer, e),
+            Closed::Downstream => {
+                error!("fragments output channel is broken");
+                ps.set_state(PeerState::Stopped);
+                return;
+            },
+            Closed::Overloaded => {
+                warn!("input: collector can't keep up with {} for {:?}, disconnecting", peer, cfg.full_timeout());
+                ps.overloaded();
+                ps.set_state(PeerState::Waiting);
+                let ready = tokio::select! {
+                    r = room(&mut tx_frag) => r,
+                    _ = stopped(&mut rx_stop) => {
+                        ps.set_state(PeerState::Stopped);
+                        return;
+                    }
+                };
+                if !ready {
+                    error!("fragments output channel is broken");
+                    ps.set_state(PeerState::Stopped);
+                    return;
+                }
+                info!("input: collector has room again, reconnecting to {}", peer);
+            },
+            Closed::Drained => {
+                info!("input: connection to {} is closed to drain", peer);
+                failures = 0;
+                continue;
+            },
+            Closed::Expired => {
+                // an intentional reconnect is neither a failure nor paused
+                info!("input: connection to {} is {:?} old, reconnecting", peer, lifetime);
+                ps.set_state(PeerState::Connecting);
+                failures = 0;
+                continue;
+            }
+        }
+        // only the attempts failed in a row count, an established connection resets them
+        failures = if established { 0 } else { failures + 1 };
+        // read by every failure, the limit is changed by a config reload
+        let max_reconnects = cfg.max_reconnects();
+        if max_reconnects > 0 && failures > max_reconnects {
+            error!("input: {} failed {} times in a row, giving up until a config reload", peer, failures);
+            ps.set_state(PeerState::Down);
+            tokio::select! {
+                _ = reloaded(&mut rx_reload) => info!("input: config is reloaded, connecting to {} again", peer),
+                _ = stopped(&mut rx_stop) => {
+                    ps.set_state(PeerState::Stopped);
+                    return;
+                }
+            }
+            failures = 0;
+            ps.set_state(PeerState::Connecting);
+            continue;
+        }
+        // pause before the next attempt
+        ps.set_state(PeerState::Waiting);
+        let pause = async {
+            delay_for(cfg.reconnect_delay()).await;
+            connects.reconnect().await
+        };
+        tokio::select! {
+            held = pause => {
+                if held > Duration::from_secs(0) {
+                    debug!("input: reconnect to {} is held for {:?} by reconnect_rate", peer, held);
+                }
+                ps.set_state(PeerState::Connecting)
+            },
+            _ = stopped(&mut rx_stop) => {
+                ps.set_state(PeerState::Stopped);
+                return;
+            }
+        }
+    }
+}
+
+/// Завершается по получении команды на завершение или при разрушении канала команд
+pub async fn stopped(rx_stop: &mut watch::Receiver<bool>) {
+    while let Some(stop) = rx_stop.recv().await {
+        if stop {
+            return;
+        }
+    }
+}
+
+// waits for the next config reload, the reloads before the call don't count
+async fn reloaded(rx_reload: &mut watch::Receiver<u64>) {
+    let seen = *rx_reload.borrow();
+    while let Some(n) = rx_reload.recv().await {
+        if n != seen {
+            return;
+        }
+    }
+    // the sender lives in the config, it is never dropped
+    std::future::pending::<()>().await;
+}
+
+// runs `work` until the stop command, None if stopped; unlike select! polling the branches in random order
+// the command is checked first on every poll, so the read of a flooded socket, ready all the time, can't put off the stop
+async fn unless_stopped<F: Future>(rx_stop: &mut watch::Receiver<bool>, work: F) -> Option<F::Output> {
+    let stop = stopped(rx_stop);
+    tokio::pin!(stop);
+    tokio::pin!(work);
+    std::future::poll_fn(|cx| {
+        if stop.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        work.as_mut().poll(cx).map(Some)
+    }).await
+}
+
+// connects and greets the peer, the connect permit is held until the handshake is done
+async fn connect(cfg: &SharedConfig, stats: &SharedStats, index: usize, peer: &Endpoint, buffers: &mut Buffers,
+                 connects: &Connects) -> Result<FrameReader<Inflate<TcpStream>>, Closed> {
+    let _permit = connects.acquire().await;
+    stats.peer(index).set_state(PeerState::Connecting);
+    let stream = timeout(cfg.connect_timeout(), buffers.connect(peer))
+        .await
+        .map_err(|_| Closed::Failed("connect timed out".to_string()))?
+        .map_err(|e| Closed::Failed(format!("unable to connect: {}", e)))?;
+    stats.peer(index).set_state(PeerState::Handshaking);
+    // the frames following the hello may arrive in the same read, so the reader outlives the handshake
+    let compression = peer.compression().unwrap_or_else(|| cfg.compression());
+    let chunk = cfg.read_chunk_size().max(MIN_READ_CHUNK);
+    let checks = Checks {
+        level: peer.validation().unwrap_or_else(|| cfg.validation()),
+        width: peer.sample_format().unwrap_or_else(|| cfg.sample_format()).width()
+    };
+    let mut reader = FrameReader::new(Inflate::new(stream, compression), cfg.vproto_endian(), checks, chunk, cfg.read_timeout());
+    timeout(cfg.handshake_timeout(), handshake(&mut reader))
+        .await
+        .map_err(|_| Closed::Failed("handshake timed out".to_string()))??;
+    Ok(reader)
+}
+
+async fn handshake(reader: &mut FrameReader<Inflate<TcpStream>>) -> Result<(), Closed> {
+    let hello = vproto::encode(&Header::empty(Kind::Hello), &[]);
+    reader.inner.write_all(&hello).await.map_err(|e| Closed::Failed(format!("handshake failed: {}", e)))?;
+    let (h, _) = reader.next().await?;
+    if h.kind != Kind::Hello {
+        return Err(Closed::Failed(format!("expected hello, got {:?}", h.kind)));
+    }
+    Ok(())
+}
+
+// waits until the channel has room without taking it, false if the channel is broken
+async fn room(tx: &mut Sender<Fragment>) -> bool {
+    let ready = std::future::poll_fn(|cx| tx.poll_ready(cx)).await.is_ok();
+    tx.disarm();
+    ready
+}
+
+async fn read_loop(cfg: &SharedConfig, stats: &SharedStats, index: usize, peer: &Endpoint, mut reader: FrameReader<Inflate<TcpStream>>,
+                   tx_frag: &mut Sender<Fragment>) -> Closed {
+    let on_full = cfg.input_on_full();
+    let full_timeout = cfg.full_timeout();
+    loop {
+        // the connection isn't read while the pipeline holds the memory budget, the peer is backed up
+        memory::admit().await;
+        let (h, payload) = match reader.next().await {
+            Ok(f) => f,
+            Err(c) => return c
+        };
+        stats.peer(index).frame();
+        stats.input.received();
+        let kind = match h.kind {
+            Kind::Audio | Kind::End => FragmentKind::Audio,
+            // the collector tells what becomes of the rest of the mixed stream
+            Kind::Other(t) => {
+                debug!("input: {} frame of type {} is passed as non-audio", peer, t);
+                FragmentKind::Other(t)
+            },
+            Kind::Hello | Kind::Keepalive => continue
+        };
+        if h.kind == Kind::Audio {
+            stats.fragment_bytes.record(payload.len() as u64);
+        }
+        let bytes = payload.len();
+        let f = Fragment::Data {
+            peer: index,
+            kind,
+            id: h.id,
+            seq: h.seq,
+            duration_ms: h.duration_ms as u32,
+            timestamp_us: h.timestamp_us,
+            audio: AudioParams { rate: h.rate, channels: h.channels, format: h.format },
+            last: h.kind == Kind::End,
+            value: payload
+        };
+        // the fragment is held in the channel until the collector takes it
+        memory::charge(bytes);
+        match on_full {
+            InputFull::Block => if tx_frag.send(f).await.is_err() {
+                memory::release(bytes);
+                return Closed::Downstream;
+            },
+            InputFull::Drop => match tx_frag.try_send(f) {
+                Ok(()) => {},
+                Err(TrySendError::Full(_)) => {
+                    memory::release(bytes);
+                    debug!("input: fragment {} of {} of {} is dropped, collector is full", h.seq, h.id, peer);
+                    stats.input.dropped();
+                    // reading a busy connection is always ready, let the rest of the pipeline catch up
+                    let () = task::yield_now().await;
+                    continue;
+                },
+                Err(TrySendError::Closed(_)) => {
+                    memory::release(bytes);
+                    return Closed::Downstream;
+                }
+            },
+            InputFull::Disconnect => match timeout(full_timeout, tx_frag.send(f)).await {
+                Ok(Ok(())) => {},
+                Ok(Err(_)) => {
+                    memory::release(bytes);
+                    return Closed::Downstream;
+                },
+                Err(_) => {
+                    memory::release(bytes);
+                    stats.input.dropped();
+                    return Closed::Overloaded;
+                }
+            }
+        }
+        stats.input.sent();
+    }
+}
+
+/// Проверка кадров системы сопряжения
+struct Checks {
+    level: Validation,
+    /// Ширина отсчета звука системы сопряжения, байтов, для проверки размеров звука
+    width: usize
+}
+
+/// Накапливает принятые из соединения байты и выделяет из них кадры V-протокола.
+/// Чтение может вернуть любую часть кадра или несколько кадров сразу, кадр разбирается только после получения целиком
+struct FrameReader<R> {
+    inner: R,
+    buf: BytesMut,
+    order: ByteOrder,
+    checks: Checks,
+    /// Наименьший объем свободного места в буфере перед очередным чтением
+    chunk: usize,
+    /// Наибольшее время получения кадра с его первого байта и ожидания следующего кадра, None - без ограничения
+    read_timeout: Option<Duration>,
+    /// Момент получения первого байта кадра, полученного не целиком, None - в буфере нет начатого кадра
+    pending_since: Option<Instant>
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+
+    fn new(inner: R, order: ByteOrder, checks: Checks, chunk: usize, read_timeout: Duration) -> FrameReader<R> {
+        FrameReader {
+            inner,
+            buf: BytesMut::with_capacity(chunk),
+            order,
+            checks,
+            chunk,
+            read_timeout: Some(read_timeout).filter(|t| *t > Duration::from_secs(0)),
+            pending_since: None
+        }
+    }
+
+    async fn next(&mut self) -> Result<(Header, Vec<u8>), Closed> {
+        loop {
+            if let Some((h, payload)) = vproto::decode_frame(&mut self.buf, self.order, self.checks.level).map_err(Closed::Failed)? {
+                if self.checks.level == Validation::Strict {
+                    vproto::check_sizes(&h, &payload, self.checks.width).map_err(Closed::Failed)?;
+                }
+                // the rest of the buffer, if any, came along with the frame, the next one is timed from now
+                self.pending_since = None;
+                return Ok((h, payload));
+            }
+            if self.buf.capacity() - self.buf.len() < self.chunk {
+                self.buf.reserve(self.chunk);
+            }
+            // the reads of a started frame don't put its deadline off, a peer trickling bytes can't hold it forever
+            if self.buf.is_empty() {
+                self.pending_since = None;
+            } else if self.pending_since.is_none() {
+                self.pending_since = Some(Instant::now());
+            }
+            let read = match self.read_timeout {
+                None => self.inner.read_buf(&mut self.buf).await,
+                Some(t) => {
+                    let pending = self.pending_since;
+                    timeout_at((pending.unwrap_or_else(Instant::now) + t).into(), self.inner.read_buf(&mut self.buf))
+                        .await
+                        .map_err(|_| Closed::Failed(match pending {
+                            Some(_) => format!("frame is not received whole within read timeout {:?}, {} bytes pending", t, self.buf.len()),
+                            None => format!("no data within read timeout {:?}", t)
+                        }))?
+                }
+            };
+            match read {
+                Ok(0) if self.buf.is_empty() => return Err(Closed::ByPeer),
+                Ok(0) => return Err(Closed::Failed(format!("connection closed inside a frame, {} bytes pending", self.buf.len()))),
+                Ok(_) => {},
+                Err(e) => return Err(Closed::Failed(e.to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::stats::{PeerState, Stats};
+
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::channel;
+
+    fn frame(kind: Kind, seq: u32, payload: &[u8]) -> Vec<u8> {
+        let h = Header { kind, id: 5, seq, timestamp_us: 0, rate: 8000, channels: 1, format: 0, duration_ms: 1, len: 0 };
+        vproto::encode(&h, payload)
+    }
+
+    #[tokio::test]
+    async fn passes_the_fragments_of_the_peer() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut s, _) = listener.accept().await.unwrap();
+            let mut hello = [0u8; vproto::HEADER_LEN + vproto::CRC_LEN];
+            s.read_exact(&mut hello).await.unwrap();
+            let mut out = vproto::encode(&Header::empty(Kind::Hello), &[]);
+            out.extend(frame(Kind::Audio, 0, &[1; 16]));
+            out.extend(vproto::encode(&Header::empty(Kind::Keepalive), &[]));
+            out.extend(frame(Kind::End, 1, &[2; 16]));
+            s.write_all(&out).await.unwrap();
+            // held open until the test is done
+            let _ = s.read(&mut [0u8; 1]).await;
+        });
+        let cfg = Config::from_sources(&[], &[&format!("input.peers={}", addr)]).unwrap();
+        let peers = cfg.peers();
+        let (tx_stop, rx_stop) = watch::channel(false);
+        let (tx_frag, mut rx_frag) = channel(10);
+        let task = tokio::spawn(run(cfg, Stats::new(&peers), 0, peers[0].clone(), Arc::new(Connects::new(1, 0.0, 1)), rx_stop, tx_frag));
+        for (seq, byte, last) in [(0, 1, false), (1, 2, true)] {
+            match timeout(Duration::from_secs(5), rx_frag.recv()).await.unwrap().unwrap() {
+                Fragment::Data { peer, kind, id, seq: s, audio, last: l, value, .. } => {
+                    memory::release(value.len());
+                    assert_eq!((peer, kind, id, s, l), (0, FragmentKind::Audio, 5, seq, last));
+                    assert_eq!((audio.rate, audio.channels), (8000, 1));
+                    assert_eq!(value, vec![byte; 16]);
+                },
+                _ => panic!("fragment is expected")
+            }
+        }
+        tx_stop.broadcast(true).unwrap();
+        timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+
+    // gives out no more than `step` bytes a read
+    struct Trickle {
+        data: Vec<u8>,
+        step: usize
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    fn reader(data: Vec<u8>, step: usize) -> FrameReader<Trickle> {
+        let checks = Checks { level: Validation::Strict, width: 2 };
+        FrameReader::new(Trickle { data, step }, ByteOrder::Little, checks, MIN_READ_CHUNK, Duration::from_secs(0))
+    }
+
+    #[tokio::test]
+    async fn reads_the_frames_split_across_reads() {
+        let mut data = frame(Kind::Audio, 0, &[1; 16]);
+        data.extend(frame(Kind::Audio, 1, &[2; 16]));
+        for step in [1, 7, 60, 1000] {
+            let mut r = reader(data.clone(), step);
+            assert_eq!(r.next().await.ok().map(|(h, p)| (h.seq, p)), Some((0, vec![1; 16])), "step {}", step);
+            assert_eq!(r.next().await.ok().map(|(h, p)| (h.seq, p)), Some((1, vec![2; 16])), "step {}", step);
+            assert!(matches!(r.next().await, Err(Closed::ByPeer)));
+        }
+    }
+
+    #[tokio::test]
+    async fn reassembles_a_frame_larger_than_the_read_chunk() {
+        // 200 ms of 8 kHz 16-bit audio
+        let payload: Vec<u8> = (0..3200u32).map(|i| i as u8).collect();
+        let h = Header { kind: Kind::Audio, id: 5, seq: 0, timestamp_us: 0, rate: 8000, channels: 1, format: 0, duration_ms: 200, len: 0 };
+        let mut r = reader(vproto::encode(&h, &payload), 100_000);
+        assert!(r.buf.capacity() >= MIN_READ_CHUNK);
+        assert_eq!(r.next().await.ok().map(|(_, p)| p), Some(payload));
+    }
+
+    #[tokio::test]
+    async fn a_frame_cut_off_fails() {
+        let data = frame(Kind::Audio, 0, &[1; 16]);
+        let mut r = reader(data[..40].to_vec(), 16);
+        assert!(matches!(r.next().await, Err(Closed::Failed(e)) if e.contains("inside a frame")));
+    }
+
+    // waits for the peer to come to the state
+    async fn reaches(stats: &SharedStats, state: PeerState) {
+        timeout(Duration::from_secs(5), async {
+            while stats.peer(0).state() != state {
+                delay_for(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap_or_else(|_| panic!("{:?} is not reached, {:?}", state, stats.peer(0).state()));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_failures_until_a_reload() {
+        // nobody listens on the port once the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let cfg = Config::from_sources(&[], &[&format!("input.peers={}", addr), "input.max_reconnects=2", "input.reconnect_delay=0ms"]).unwrap();
+        let peers = cfg.peers();
+        let stats = Stats::new(&peers);
+        let (tx_stop, rx_stop) = watch::channel(false);
+        let (tx_frag, _rx_frag) = channel(10);
+        let task = tokio::spawn(run(cfg.clone(), stats.clone(), 0, peers[0].clone(), Arc::new(Connects::new(1, 0.0, 1)), rx_stop, tx_frag));
+        reaches(&stats, PeerState::Down).await;
+        // the peer is back but silent, so the connection stays at the handshake
+        let mut listener = TcpListener::bind(addr).await.unwrap();
+        delay_for(Duration::from_millis(50)).await;
+        assert_eq!(stats.peer(0).state(), PeerState::Down);
+        cfg.reload().unwrap();
+        let _conn = listener.accept().await.unwrap();
+        reaches(&stats, PeerState::Handshaking).await;
+        tx_stop.broadcast(true).unwrap();
+        timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+        assert_eq!(stats.peer(0).state(), PeerState::Stopped);
+    }
+
+    // never gives out a byte
+    struct Stalled;
+
+    impl AsyncRead for Stalled {
+        fn poll_read(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>, _buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn a_read_past_the_timeout_fails() {
+        let checks = || Checks { level: Validation::Lenient, width: 2 };
+        let mut r = FrameReader::new(Stalled, ByteOrder::Little, checks(), MIN_READ_CHUNK, Duration::from_millis(20));
+        assert!(matches!(r.next().await, Err(Closed::Failed(e)) if e.contains("read timeout")));
+        // no timeout, the read waits on
+        let r = FrameReader::new(Stalled, ByteOrder::Little, checks(), MIN_READ_CHUNK, Duration::from_secs(0));
+        assert!(r.read_timeout.is_none());
+    }
+
+    // gives out a byte a `period`
+    struct Dribble {
+        data: Vec<u8>,
+        period: Duration,
+        next: tokio::time::Delay
+    }
+
+    impl AsyncRead for Dribble {
+        fn poll_read(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+            if std::pin::Pin::new(&mut self.next).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            let at = tokio::time::Instant::now() + self.period;
+            self.next.reset(at);
+            let n = buf.len().min(self.data.len()).min(1);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    #[tokio::test]
+    async fn the_trickled_frame_fails_past_the_timeout() {
+        // a byte each 5 ms is well within the timeout, the whole frame of 52 bytes is not
+        let data = frame(Kind::Audio, 0, &[1; 16]);
+        let dribble = |data: Vec<u8>| Dribble { data, period: Duration::from_millis(5), next: delay_for(Duration::from_millis(0)) };
+        let checks = || Checks { level: Validation::Lenient, width: 2 };
+        let mut r = FrameReader::new(dribble(data.clone()), ByteOrder::Little, checks(), MIN_READ_CHUNK, Duration::from_millis(100));
+        let e = match r.next().await {
+            Err(Closed::Failed(e)) => e,
+            _ => panic!("the trickled frame is accepted")
+        };
+        assert!(e.contains("not received whole within read timeout"), "{}", e);
+        // the frame in time is taken, the one after is timed from its own first byte
+        let mut twice = data.clone();
+        twice.extend(&data);
+        let mut r = FrameReader::new(dribble(twice), ByteOrder::Little, checks(), MIN_READ_CHUNK, Duration::from_millis(400));
+        for _ in 0..2 {
+            assert_eq!(r.next().await.ok().map(|(_, p)| p), Some(vec![1; 16]));
+        }
+    }
+
+    // answers the hello of each connection with the frames and holds it open until the test is done
+    fn serve(mut listener: TcpListener, frames: Vec<u8>) {
+        tokio::spawn(async move {
+            loop {
+                let (mut s, _) = listener.accept().await.unwrap();
+                let frames = frames.clone();
+                tokio::spawn(async move {
+                    let mut hello = [0u8; vproto::HEADER_LEN + vproto::CRC_LEN];
+                    s.read_exact(&mut hello).await.unwrap();
+                    let mut out = vproto::encode(&Header::empty(Kind::Hello), &[]);
+                    out.extend(frames);
+                    s.write_all(&out).await.unwrap();
+                    let _ = s.read(&mut [0u8; 1]).await;
+                });
+            }
+        });
+    }
+
+    // the value of the next fragment passed on
+    async fn received(rx_frag: &mut tokio::sync::mpsc::Receiver<Fragment>) -> Vec<u8> {
+        match timeout(Duration::from_secs(5), rx_frag.recv()).await.unwrap().unwrap() {
+            Fragment::Data { value, .. } => {
+                memory::release(value.len());
+                value
+            },
+            _ => panic!("fragment is expected")
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_the_fragments_while_the_collector_is_full() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut frames = frame(Kind::Audio, 0, &[1; 16]);
+        frames.extend(frame(Kind::Audio, 1, &[2; 16]));
+        frames.extend(frame(Kind::Audio, 2, &[3; 16]));
+        serve(listener, frames);
+        let cfg = Config::from_sources(&[], &[&format!("input.peers={}", addr), "input.on_full=drop"]).unwrap();
+        let peers = cfg.peers();
+        let stats = Stats::new(&peers);
+        let (tx_stop, rx_stop) = watch::channel(false);
+        let (tx_frag, mut rx_frag) = channel(1);
+        let task = tokio::spawn(run(cfg, stats.clone(), 0, peers[0].clone(), Arc::new(Connects::new(1, 0.0, 1)), rx_stop, tx_frag));
+        timeout(Duration::from_secs(5), async {
+            while stats.input.totals().2 < 2 {
+                delay_for(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        assert_eq!(received(&mut rx_frag).await, vec![1; 16]);
+        assert_eq!(stats.input.totals(), (3, 1, 2));
+        tx_stop.broadcast(true).unwrap();
+        timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnects_once_the_full_collector_has_room() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut frames = frame(Kind::Audio, 0, &[1; 16]);
+        frames.extend(frame(Kind::Audio, 1, &[2; 16]));
+        serve(listener, frames);
+        let cfg = Config::from_sources(&[], &[&format!("input.peers={}", addr), "input.on_full=disconnect", "input.full_timeout=50ms", "input.reconnect_delay=0ms"]).unwrap();
+        let peers = cfg.peers();
+        let stats = Stats::new(&peers);
+        let (tx_stop, rx_stop) = watch::channel(false);
+        let (tx_frag, mut rx_frag) = channel(1);
+        let task = tokio::spawn(run(cfg, stats.clone(), 0, peers[0].clone(), Arc::new(Connects::new(1, 0.0, 1)), rx_stop, tx_frag));
+        reaches(&stats, PeerState::Waiting).await;
+        assert_eq!(stats.snapshot()["peers"][0]["overloads"], 1);
+        // the collector takes the fragment, the connection is made again and the peer sends its frames anew
+        assert_eq!(received(&mut rx_frag).await, vec![1; 16]);
+        assert_eq!(received(&mut rx_frag).await, vec![1; 16]);
+        assert_eq!(stats.snapshot()["peers"][0]["connects"], 2);
+        tx_stop.broadcast(true).unwrap();
+        timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn recycles_the_connection_past_its_lifetime() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, Vec::new());
+        // the recycled connection is made again at once, not after the reconnect delay
+        let cfg = Config::from_sources(&[], &[&format!("input.peers={}", addr), "input.max_connection_lifetime=50ms", "input.reconnect_delay=1h"]).unwrap();
+        let peers = cfg.peers();
+        let stats = Stats::new(&peers);
+        let (tx_stop, rx_stop) = watch::channel(false);
+        let (tx_frag, _rx_frag) = channel(1);
+        let task = tokio::spawn(run(cfg, stats.clone(), 0, peers[0].clone(), Arc::new(Connects::new(1, 0.0, 1)), rx_stop, tx_frag));
+        timeout(Duration::from_secs(5), async {
+            while stats.snapshot()["peers"][0]["connects"] != 3 {
+                delay_for(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        tx_stop.broadcast(true).unwrap();
+        timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_stop_goes_ahead_of_the_ready_read() {
+        let (tx, mut rx) = watch::channel(false);
+        assert_eq!(unless_stopped(&mut rx, async { 1 }).await, Some(1));
+        tx.broadcast(true).unwrap();
+        // the read is ready too, the stop wins all the same
+        assert_eq!(unless_stopped(&mut rx, async { 1 }).await, None);
+    }
+
+    #[tokio::test]
+    async fn the_stop_ends_the_pending_read() {
+        let (tx, mut rx) = watch::channel(false);
+        tokio::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            let _ = tx.broadcast(true);
+        });
+        assert_eq!(unless_stopped(&mut rx, std::future::pending::<()>()).await, None);
+    }
+
+    #[tokio::test]
+    async fn checks_the_audio_sizes_under_strict_only() {
+        let data = frame(Kind::Audio, 0, &[1; 15]);
+        let e = match reader(data.clone(), 1000).next().await {
+            Err(Closed::Failed(e)) => e,
+            _ => panic!("half a sample is accepted")
+        };
+        assert!(e.contains("whole samples"), "{}", e);
+        let checks = Checks { level: Validation::Lenient, width: 2 };
+        let mut r = FrameReader::new(Trickle { data, step: 1000 }, ByteOrder::Little, checks, MIN_READ_CHUNK, Duration::from_secs(0));
+        assert_eq!(r.next().await.ok().map(|(_, p)| p.len()), Some(15));
+    }
+}
@@ -0,0 +1,121 @@
+//! Подключение к системе сопряжения с заданными размерами буферов сокета.
+
+use std::io;
+use std::net::SocketAddr;
+
+use crate::config::{Endpoint, SharedConfig};
+
+use log::{debug, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{lookup_host, TcpStream};
+
+/// Наименьший допустимый размер буфера сокета, байтов
+const MIN_BUFFER: usize = 4 * 1024;
+/// Наибольший допустимый размер буфера сокета, байтов
+const MAX_BUFFER: usize = 256 * 1024 * 1024;
+
+/// Размеры буферов приема и передачи сокета подключения к системе сопряжения, 0 - по умолчанию ядра
+pub struct Buffers {
+    recv: usize,
+    send: usize,
+    // the sizes set by the kernel are reported once, not on every reconnect
+    reported: bool
+}
+
+impl Buffers {
+
+    /// Размеры для `peer`: заданные для точки подключения, иначе общие `[input] so_rcvbuf` и `so_sndbuf`.
+    /// Размеры вне допустимых пределов приводятся к ближайшему пределу с предупреждением
+    pub fn new(cfg: &SharedConfig, peer: &Endpoint) -> Buffers {
+        Buffers {
+            recv: clamp(peer.rcvbuf().unwrap_or_else(|| cfg.so_rcvbuf()), "receive", peer),
+            send: clamp(peer.sndbuf().unwrap_or_else(|| cfg.so_sndbuf()), "send", peer),
+            reported: false
+        }
+    }
+
+    /// Подключается к `peer`, буферы сокета задаются до подключения, чтобы учесть их при согласовании окна TCP
+    pub async fn connect(&mut self, peer: &Endpoint) -> io::Result<TcpStream> {
+        if self.recv == 0 && self.send == 0 {
+            return TcpStream::connect((peer.addr(), peer.port())).await;
+        }
+        let addr = lookup_host((peer.addr(), peer.port())).await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", peer.addr())))?;
+        let socket = Socket::new(domain(&addr), Type::stream(), Some(Protocol::tcp()))?;
+        if self.recv > 0 {
+            socket.set_recv_buffer_size(self.recv)?;
+        }
+        if self.send > 0 {
+            socket.set_send_buffer_size(self.send)?;
+        }
+        let stream = TcpStream::connect_std(socket.into_tcp_stream(), &addr).await?;
+        if !self.reported {
+            self.reported = true;
+            self.report(peer, &stream);
+        }
+        Ok(stream)
+    }
+
+    // the kernel silently caps the sizes by its limits, linux also reports twice the size it was given
+    fn report(&self, peer: &Endpoint, stream: &TcpStream) {
+        let actual = [("receive", self.recv, stream.recv_buffer_size()), ("send", self.send, stream.send_buffer_size())];
+        for (name, requested, size) in actual.iter() {
+            match size {
+                Ok(size) if *requested > 0 && size < requested =>
+                    warn!("input: {} socket {} buffer is {} bytes, less than {} requested, the kernel limit applies", peer, name, size, requested),
+                Ok(size) => debug!("input: {} socket {} buffer is {} bytes", peer, name, size),
+                Err(e) => warn!("input: unable to get {} socket {} buffer size: {}", peer, name, e)
+            }
+        }
+    }
+}
+
+fn clamp(size: usize, name: &str, peer: &Endpoint) -> usize {
+    if size == 0 {
+        return 0;
+    }
+    let clamped = size.clamp(MIN_BUFFER, MAX_BUFFER);
+    if clamped != size {
+        warn!("input: {} socket {} buffer of {} bytes is out of {}..{}, {} is used", peer, name, size, MIN_BUFFER, MAX_BUFFER, clamped);
+    }
+    clamped
+}
+
+fn domain(addr: &SocketAddr) -> Domain {
+    match addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn the_peer_sizes_override_the_common_ones() {
+        let cfg = Config::from_sources(&[], &["input.so_rcvbuf=65536", "input.so_sndbuf=1"]).unwrap();
+        let b = Buffers::new(&cfg, &"127.0.0.1:12000".parse().unwrap());
+        assert_eq!((b.recv, b.send), (65536, MIN_BUFFER));
+        let b = Buffers::new(&cfg, &"127.0.0.1:12000/rcvbuf=0/sndbuf=1073741824".parse().unwrap());
+        assert_eq!((b.recv, b.send), (0, MAX_BUFFER));
+    }
+
+    #[tokio::test]
+    async fn connects_with_the_buffer_sizes() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cfg = Config::from_sources(&[], &["input.so_rcvbuf=65536"]).unwrap();
+        let peer: Endpoint = format!("127.0.0.1:{}", addr.port()).parse().unwrap();
+        let mut b = Buffers::new(&cfg, &peer);
+        let (stream, accepted) = tokio::join!(b.connect(&peer), listener.accept());
+        let stream = stream.unwrap();
+        accepted.unwrap();
+        assert!(stream.recv_buffer_size().unwrap() >= 65536);
+        assert!(b.reported);
+    }
+}
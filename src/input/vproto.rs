@@ -0,0 +1,359 @@
+//! Кадры V-протокола обмена с системой сопряжения.
+//!
+//! Кадр состоит из заголовка фиксированной длины, данных и контрольной суммы.
+//! Все многобайтовые поля передаются в порядке little-endian. Для совместимости с вариантами систем сопряжения
+//! порядок байтов поля длины данных задается в настройках `[input] vproto_endian`:
+//!
+//! | смещение | размер | поле |
+//! |---|---|---|
+//! | 0  | 2 | сигнатура `VP` |
+//! | 2  | 1 | версия протокола, 1 |
+//! | 3  | 1 | тип кадра, см. [`Kind`] |
+//! | 4  | 4 | идентификатор абонента |
+//! | 8  | 4 | порядковый номер фрагмента в сеансе абонента |
+//! | 12 | 8 | момент формирования фрагмента, мкс от начала эпохи unix, 0 если не задан |
+//! | 20 | 4 | частота дискретизации звука, Гц |
+//! | 24 | 1 | количество каналов звука |
+//! | 25 | 1 | формат отсчетов звука |
+//! | 26 | 2 | длительность звука во фрагменте, мс |
+//! | 28 | 4 | длина данных N |
+//! | 32 | N | данные |
+//! | 32 + N | 4 | CRC-32 заголовка и данных |
+//!
+//! Строгость проверки кадров задается для каждой системы сопряжения (`[input] validation`, `/validation=<level>`),
+//! см. [`Validation`]
+use bytes::{Buf, BytesMut};
+
+use crate::config::{ByteOrder, Validation};
+
+/// Сигнатура кадра
+pub const MAGIC: [u8; 2] = *b"VP";
+/// Поддерживаемая версия протокола
+pub const VERSION: u8 = 1;
+/// Длина заголовка кадра
+pub const HEADER_LEN: usize = 32;
+/// Длина контрольной суммы в конце кадра
+pub const CRC_LEN: usize = 4;
+/// Наибольшая допустимая длина данных кадра
+pub const MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+/// Тип кадра
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    /// Приветствие при установлении соединения, передается обеими сторонами
+    Hello,
+    /// Фрагмент звука сеанса абонента
+    Audio,
+    /// Завершение сеанса абонента, может содержать последний фрагмент звука
+    End,
+    /// Поддержание соединения, данных не содержит
+    Keepalive,
+    /// Прочие данные смешанного потока
+    Other(u8)
+}
+
+impl Kind {
+
+    fn from_u8(v: u8) -> Kind {
+        match v {
+            0 => Kind::Hello,
+            1 => Kind::Audio,
+            2 => Kind::End,
+            3 => Kind::Keepalive,
+            _ => Kind::Other(v)
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Kind::Hello => 0,
+            Kind::Audio => 1,
+            Kind::End => 2,
+            Kind::Keepalive => 3,
+            Kind::Other(v) => v
+        }
+    }
+}
+
+/// Заголовок кадра
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub kind: Kind,
+    pub id: u32,
+    pub seq: u32,
+    pub timestamp_us: u64,
+    pub rate: u32,
+    pub channels: u8,
+    pub format: u8,
+    pub duration_ms: u16,
+    pub len: usize
+}
+
+impl Header {
+
+    /// Заголовок кадра заданного типа без данных
+    pub fn empty(kind: Kind) -> Header {
+        Header {
+            kind,
+            id: 0,
+            seq: 0,
+            timestamp_us: 0,
+            rate: 0,
+            channels: 0,
+            format: 0,
+            duration_ms: 0,
+            len: 0
+        }
+    }
+
+    /// Разбирает и проверяет со строгостью `level` заголовок кадра, поле длины данных читается в порядке байтов `order`
+    pub fn decode(b: &[u8; HEADER_LEN], order: ByteOrder, level: Validation) -> Result<Header, String> {
+        if b[0..2] != MAGIC {
+            return Err(format!("bad magic {:02x}{:02x}", b[0], b[1]));
+        }
+        if b[2] != VERSION && level != Validation::Permissive {
+            return Err(format!("unsupported version {}", b[2]));
+        }
+        let lb = [b[28], b[29], b[30], b[31]];
+        let len = match order {
+            ByteOrder::Little => u32::from_le_bytes(lb),
+            ByteOrder::Big => u32::from_be_bytes(lb)
+        } as usize;
+        if len > MAX_PAYLOAD {
+            return Err(format!("payload length {} exceeds {}", len, MAX_PAYLOAD));
+        }
+        Ok(Header {
+            kind: Kind::from_u8(b[3]),
+            id: u32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+            seq: u32::from_le_bytes([b[8], b[9], b[10], b[11]]),
+            timestamp_us: u64::from_le_bytes([b[12], b[13], b[14], b[15], b[16], b[17], b[18], b[19]]),
+            rate: u32::from_le_bytes([b[20], b[21], b[22], b[23]]),
+            channels: b[24],
+            format: b[25],
+            duration_ms: u16::from_le_bytes([b[26], b[27]]),
+            len
+        })
+    }
+
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut b = [0u8; HEADER_LEN];
+        b[0..2].copy_from_slice(&MAGIC);
+        b[2] = VERSION;
+        b[3] = self.kind.to_u8();
+        b[4..8].copy_from_slice(&self.id.to_le_bytes());
+        b[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        b[12..20].copy_from_slice(&self.timestamp_us.to_le_bytes());
+        b[20..24].copy_from_slice(&self.rate.to_le_bytes());
+        b[24] = self.channels;
+        b[25] = self.format;
+        b[26..28].copy_from_slice(&self.duration_ms.to_le_bytes());
+        b[28..32].copy_from_slice(&(self.len as u32).to_le_bytes());
+        b
+    }
+}
+
+/// Проверяет контрольную сумму кадра по его заголовку, данным и полученной контрольной сумме
+pub fn check_crc(header: &[u8; HEADER_LEN], payload: &[u8], crc: [u8; CRC_LEN]) -> Result<(), String> {
+    let expected = crc32(header, payload);
+    let got = u32::from_le_bytes(crc);
+    if expected != got {
+        return Err(format!("crc mismatch {:08x} != {:08x}", got, expected));
+    }
+    Ok(())
+}
+
+/// Проверяет размеры звука кадра с данными в отсчетах шириной `width` байтов
+pub fn check_sizes(h: &Header, payload: &[u8], width: usize) -> Result<(), String> {
+    if payload.is_empty() || !matches!(h.kind, Kind::Audio | Kind::End) {
+        return Ok(());
+    }
+    if h.rate == 0 || h.channels == 0 {
+        return Err(format!("audio of {} Hz and {} channels", h.rate, h.channels));
+    }
+    let frame = h.channels as usize * width;
+    if !payload.len().is_multiple_of(frame) {
+        return Err(format!("payload of {} bytes isn't whole samples of {} channels", payload.len(), h.channels));
+    }
+    if h.duration_ms > 0 {
+        let ms = (payload.len() / frame) as u64 * 1000 / h.rate as u64;
+        if ms.abs_diff(h.duration_ms as u64) > 1 {
+            return Err(format!("payload lasts {} ms, duration is {} ms", ms, h.duration_ms));
+        }
+    }
+    Ok(())
+}
+
+/// Извлекает из начала буфера принятых байтов очередной целый кадр и проверяет его со строгостью `level`,
+/// кроме размеров звука, см. [`check_sizes`]. Если кадр получен не полностью, буфер не изменяется и возвращается `None`
+pub fn decode_frame(buf: &mut BytesMut, order: ByteOrder, level: Validation) -> Result<Option<(Header, Vec<u8>)>, String> {
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let mut hb = [0u8; HEADER_LEN];
+    hb.copy_from_slice(&buf[..HEADER_LEN]);
+    let h = Header::decode(&hb, order, level)?;
+    let total = HEADER_LEN + h.len + CRC_LEN;
+    if buf.len() < total {
+        // the whole frame is known to be needed, avoid growing the buffer step by step
+        buf.reserve(total - buf.len());
+        return Ok(None);
+    }
+    let payload = buf[HEADER_LEN..HEADER_LEN + h.len].to_vec();
+    let mut crc = [0u8; CRC_LEN];
+    crc.copy_from_slice(&buf[HEADER_LEN + h.len..total]);
+    // a trusted peer is spared the checksum over every byte
+    if level != Validation::Permissive {
+        check_crc(&hb, &payload, crc)?;
+    }
+    buf.advance(total);
+    Ok(Some((h, payload)))
+}
+
+/// Формирует кадр из заголовка и данных, длина данных в заголовке устанавливается по фактической
+pub fn encode(header: &Header, payload: &[u8]) -> Vec<u8> {
+    let mut h = header.clone();
+    h.len = payload.len();
+    let hb = h.encode();
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+    out.extend_from_slice(&hb);
+    out.extend_from_slice(payload);
+    out.extend_from_slice(&crc32(&hb, payload).to_le_bytes());
+    out
+}
+
+fn crc32(header: &[u8], payload: &[u8]) -> u32 {
+    let mut h = crc32fast::Hasher::new();
+    h.update(header);
+    h.update(payload);
+    h.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio() -> Header {
+        Header { kind: Kind::Audio, id: 7, seq: 3, timestamp_us: 1_600_000_000_000_000, rate: 8000, channels: 1, format: 0,
+            duration_ms: 1, len: 0 }
+    }
+
+    fn decode(frame: &[u8]) -> Result<Option<(Header, Vec<u8>)>, String> {
+        decode_frame(&mut BytesMut::from(frame), ByteOrder::Little, Validation::Strict)
+    }
+
+    #[test]
+    fn decodes_the_encoded_frame() {
+        let frame = encode(&audio(), &[1; 16]);
+        assert_eq!(frame.len(), HEADER_LEN + 16 + CRC_LEN);
+        let (h, payload) = decode(&frame).unwrap().unwrap();
+        assert_eq!((h.kind, h.id, h.seq, h.timestamp_us), (Kind::Audio, 7, 3, 1_600_000_000_000_000));
+        assert_eq!((h.rate, h.channels, h.format, h.duration_ms, h.len), (8000, 1, 0, 1, 16));
+        assert_eq!(payload, vec![1; 16]);
+    }
+
+    #[test]
+    fn keeps_the_unknown_kind() {
+        let frame = encode(&Header::empty(Kind::Other(9)), &[]);
+        assert_eq!(decode(&frame).unwrap().unwrap().0.kind, Kind::Other(9));
+    }
+
+    #[test]
+    fn rejects_the_bad_magic_and_crc() {
+        let mut frame = encode(&audio(), &[1; 16]);
+        frame[HEADER_LEN] ^= 0xff;
+        assert!(decode(&frame).unwrap_err().starts_with("crc mismatch"));
+        frame[0] = b'X';
+        assert!(decode(&frame).unwrap_err().starts_with("bad magic"));
+    }
+
+    #[test]
+    fn waits_for_the_whole_frame() {
+        let frame = encode(&audio(), &[1; 16]);
+        let mut buf = BytesMut::new();
+        for (i, b) in frame.iter().enumerate() {
+            assert!(decode_frame(&mut buf, ByteOrder::Little, Validation::Strict).unwrap().is_none(), "byte {}", i);
+            buf.extend_from_slice(&[*b]);
+        }
+        assert!(decode_frame(&mut buf, ByteOrder::Little, Validation::Strict).unwrap().is_some());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn takes_the_frames_one_by_one() {
+        let mut buf = BytesMut::from(&encode(&audio(), &[1; 16])[..]);
+        buf.extend_from_slice(&encode(&Header::empty(Kind::Keepalive), &[]));
+        buf.extend_from_slice(&encode(&audio(), &[2; 16])[..10]);
+        assert_eq!(decode_frame(&mut buf, ByteOrder::Little, Validation::Strict).unwrap().unwrap().1, vec![1; 16]);
+        assert_eq!(decode_frame(&mut buf, ByteOrder::Little, Validation::Strict).unwrap().unwrap().0.kind, Kind::Keepalive);
+        assert!(decode_frame(&mut buf, ByteOrder::Little, Validation::Strict).unwrap().is_none());
+        assert_eq!(buf.len(), 10);
+    }
+
+    // the frame with the length field in the byte order `order`, the checksum covers the field as sent
+    fn frame_in(order: ByteOrder, payload: &[u8]) -> Vec<u8> {
+        let mut frame = encode(&audio(), payload);
+        if order == ByteOrder::Big {
+            frame[28..32].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+            let crc = crc32(&frame[..HEADER_LEN], payload);
+            let end = frame.len();
+            frame[end - CRC_LEN..].copy_from_slice(&crc.to_le_bytes());
+        }
+        frame
+    }
+
+    #[test]
+    fn reads_the_length_in_either_order_at_every_level() {
+        for level in [Validation::Strict, Validation::Lenient, Validation::Permissive] {
+            for (order, wrong) in [(ByteOrder::Little, ByteOrder::Big), (ByteOrder::Big, ByteOrder::Little)] {
+                let frame = frame_in(order, &[1; 16]);
+                let mut buf = BytesMut::from(&frame[..]);
+                let (h, payload) = decode_frame(&mut buf, order, level).unwrap().unwrap();
+                assert_eq!((h.len, payload), (16, vec![1; 16]), "{:?} {:?}", order, level);
+                assert!(buf.is_empty());
+                // 16 read in the other order is far beyond the payload limit
+                let e = decode_frame(&mut BytesMut::from(&frame[..]), wrong, level).unwrap_err();
+                assert!(e.contains("exceeds"), "{:?} {:?}: {}", order, level, e);
+            }
+        }
+    }
+
+    #[test]
+    fn the_payload_limit_holds_in_either_order() {
+        let mut b = Header::empty(Kind::Audio).encode();
+        b[28..32].copy_from_slice(&(MAX_PAYLOAD as u32 + 1).to_be_bytes());
+        assert!(Header::decode(&b, ByteOrder::Big, Validation::Permissive).is_err());
+        b[28..32].copy_from_slice(&(MAX_PAYLOAD as u32).to_be_bytes());
+        assert_eq!(Header::decode(&b, ByteOrder::Big, Validation::Permissive).unwrap().len, MAX_PAYLOAD);
+    }
+
+    #[test]
+    fn the_permissive_level_skips_the_version_and_the_crc() {
+        let mut frame = encode(&audio(), &[1; 16]);
+        frame[HEADER_LEN] ^= 0xff;
+        let level = |level| decode_frame(&mut BytesMut::from(&frame[..]), ByteOrder::Little, level);
+        assert!(level(Validation::Lenient).unwrap_err().starts_with("crc mismatch"));
+        // the broken byte is passed on as is
+        assert_eq!(level(Validation::Permissive).unwrap().unwrap().1[..2], [0xfe, 1]);
+        let mut b = audio().encode();
+        b[2] = VERSION + 1;
+        assert!(Header::decode(&b, ByteOrder::Little, Validation::Lenient).unwrap_err().starts_with("unsupported version"));
+        assert!(Header::decode(&b, ByteOrder::Little, Validation::Strict).is_err());
+        assert!(Header::decode(&b, ByteOrder::Little, Validation::Permissive).is_ok());
+        // the stream can't be framed without the magic at any level
+        b[0] = b'X';
+        assert!(Header::decode(&b, ByteOrder::Little, Validation::Permissive).unwrap_err().starts_with("bad magic"));
+    }
+
+    #[test]
+    fn checks_the_audio_sizes() {
+        // 1 ms of 8 kHz 16-bit mono is 16 bytes
+        assert!(check_sizes(&audio(), &[0; 16], 2).is_ok());
+        assert!(check_sizes(&audio(), &[0; 15], 2).unwrap_err().contains("whole samples"));
+        assert!(check_sizes(&audio(), &[0; 64], 2).unwrap_err().contains("lasts 4 ms"));
+        assert!(check_sizes(&Header { channels: 0, ..audio() }, &[0; 16], 2).is_err());
+        // no duration, no audio or not an audio frame leave nothing to check
+        assert!(check_sizes(&Header { duration_ms: 0, ..audio() }, &[0; 64], 2).is_ok());
+        assert!(check_sizes(&audio(), &[], 2).is_ok());
+        assert!(check_sizes(&Header { channels: 0, ..Header::empty(Kind::Other(9)) }, &[0; 3], 2).is_ok());
+    }
+}
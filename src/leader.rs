@@ -0,0 +1,257 @@
+//! Выбор ведущего экземпляра приложения для развертывания с горячим резервом.
+//!
+//! Если в настройках `[leader] lock` задан файл блокировки, работает только захвативший его экземпляр.
+//! Резервный экземпляр не подключается к системам сопряжения и не пишет результаты, а с периодом `retry`
+//! пытается захватить блокировку и становится ведущим, как только ведущий ее освобождает или завершается.
+//! Подсистемы дожидаются выбора функцией [`elected`](elected). Способ выбора реализует
+//! [`LeaderElector`](LeaderElector), блокировка удерживается до завершения работы приложения.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::config::SharedConfig;
+use crate::stats::SharedStats;
+use crate::tracker::TaskGuard;
+
+use log::{error, info, warn};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+
+/// Признак выбора ведущим для ожидающих его подсистем, None - выбор не выполняется, экземпляр всегда ведущий
+static ELECTED: Mutex<Option<watch::Receiver<bool>>> = Mutex::new(None);
+
+/// Способ выбора ведущего экземпляра
+pub trait LeaderElector: Send {
+
+    /// Описание для журнала, например путь к файлу блокировки
+    fn name(&self) -> String;
+
+    /// Пытается стать ведущим без ожидания, true - экземпляр ведущий.
+    /// Ведущий остается ведущим, пока жив объект
+    fn try_acquire(&mut self) -> io::Result<bool>;
+}
+
+/// Выбор по исключительной блокировке файла (`flock`, в windows `LockFileEx`), которую ядро снимает и при аварийном завершении.
+/// Файл должен находиться в файловой системе, общей для экземпляров и поддерживающей блокировки
+pub struct FileLock {
+    path: String,
+    file: Option<File>
+}
+
+impl FileLock {
+
+    pub fn new(path: String) -> FileLock {
+        FileLock {
+            path,
+            file: None
+        }
+    }
+}
+
+impl LeaderElector for FileLock {
+
+    fn name(&self) -> String {
+        format!("file lock {}", self.path)
+    }
+
+    fn try_acquire(&mut self) -> io::Result<bool> {
+        if self.file.is_some() {
+            return Ok(true);
+        }
+        let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(&self.path)?;
+        if !platform::try_lock(&file)? {
+            return Ok(false);
+        }
+        // the pid of the leader helps the operators, the lock itself doesn't need it
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        self.file = Some(file);
+        Ok(true)
+    }
+}
+
+/// Выбран ли экземпляр ведущим. Без выбора ведущего экземпляр всегда ведущий
+pub fn is_elected() -> bool {
+    ELECTED.lock().unwrap_or_else(|p| p.into_inner()).as_ref().is_none_or(|rx| *rx.borrow())
+}
+
+/// Дожидается выбора экземпляра ведущим. Без выбора ведущего возвращается сразу
+pub async fn elected() {
+    let rx = ELECTED.lock().unwrap_or_else(|p| p.into_inner()).clone();
+    if let Some(mut rx) = rx {
+        while !*rx.borrow() {
+            if rx.recv().await.is_none() {
+                // the election is stopped before this instance is elected, it is never going to be
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Запускает в асинхронном режиме выбор ведущего экземпляра, если в настройках `[leader] lock` задан файл
+///
+/// Параметры:
+///
+/// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `stats` - счетчики работы подсистем, отмечается резервное состояние
+/// * `rx_stop` - межпоточный канал получения команды на завершение, читатель. Блокировка освобождается по команде
+///
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде,
+/// None - выбор ведущего отключен
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, mut rx_stop: oneshot::Receiver<()>) -> Option<JoinHandle<()>> {
+    let path = cfg.leader_lock();
+    if path.is_empty() {
+        return None;
+    }
+    let mut elector: Box<dyn LeaderElector> = Box::new(FileLock::new(path));
+    let retry = cfg.leader_retry();
+    let (tx, rx) = watch::channel(false);
+    *ELECTED.lock().unwrap_or_else(|p| p.into_inner()) = Some(rx);
+    stats.set_standby(true);
+    info!("start leader election by {}", elector.name());
+
+    Some(tokio::spawn(async move {
+        let _guard = guard;
+
+        let mut attempt = interval(retry);
+        let mut warned = false;
+        loop {
+            tokio::select! {
+                _ = attempt.tick() => match elector.try_acquire() {
+                    Ok(true) => break,
+                    Ok(false) if !warned => {
+                        warn!("leader: {} is held by another instance, standing by", elector.name());
+                        warned = true;
+                    },
+                    Ok(false) => {},
+                    Err(e) => error!("leader: failed to acquire {}: {}", elector.name(), e)
+                },
+                _ = &mut rx_stop => {
+                    info!("leader election is stopped on standby");
+                    return;
+                }
+            }
+        }
+        info!("leader: {} is acquired, this instance is the leader", elector.name());
+        stats.set_standby(false);
+        let _ = tx.broadcast(true);
+        // the lock is held while the stages work, it is released for the standby after they are done
+        let _ = rx_stop.await;
+        let name = elector.name();
+        drop(elector);
+        info!("leader election is stopped, {} is released", name);
+    }))
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    /// Захватывает исключительную блокировку файла без ожидания, false - ее удерживает другой процесс
+    pub fn try_lock(file: &File) -> io::Result<bool> {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::EWOULDBLOCK) => Ok(false),
+                _ => Err(e)
+            };
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+
+    const LOCKFILE_FAIL_IMMEDIATELY: u32 = 1;
+    const LOCKFILE_EXCLUSIVE_LOCK: u32 = 2;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    #[repr(C)]
+    struct Overlapped {
+        internal: usize,
+        internal_high: usize,
+        offset: u32,
+        offset_high: u32,
+        event: *mut c_void
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LockFileEx(file: *mut c_void, flags: u32, reserved: u32, low: u32, high: u32, overlapped: *mut Overlapped) -> i32;
+    }
+
+    /// Захватывает исключительную блокировку файла без ожидания, false - ее удерживает другой процесс
+    pub fn try_lock(file: &File) -> io::Result<bool> {
+        let mut at = Overlapped { internal: 0, internal_high: 0, offset: 0, offset_high: 0, event: std::ptr::null_mut() };
+        // the whole range from the start, the holder still writes its pid through the same handle
+        let flags = LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY;
+        if unsafe { LockFileEx(file.as_raw_handle() as *mut c_void, flags, 0, u32::MAX, u32::MAX, &mut at) } == 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(ERROR_LOCK_VIOLATION) => Ok(false),
+                _ => Err(e)
+            };
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use std::fs::File;
+    use std::io;
+
+    pub fn try_lock(_file: &File) -> io::Result<bool> {
+        Err(io::Error::new(io::ErrorKind::Other, "file locks are not supported on this platform"))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+    use crate::tracker::TaskTracker;
+
+    #[test]
+    fn the_lock_is_held_by_one_until_released() {
+        let path = std::env::temp_dir().join(format!("banshee-leader-{}.lock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut leader = FileLock::new(path.clone());
+        let mut standby = FileLock::new(path.clone());
+        assert!(leader.try_acquire().unwrap());
+        assert!(leader.try_acquire().unwrap());
+        assert!(!standby.try_acquire().unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        drop(leader);
+        assert!(standby.try_acquire().unwrap());
+        drop(standby);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn no_election_without_the_lock() {
+        let cfg = Config::from_sources(&[], &["leader.lock="]).unwrap();
+        let (_tx_stop, rx_stop) = oneshot::channel();
+        assert!(run(cfg, TaskTracker::new().track("leader"), Stats::new(&[]), rx_stop).await.is_none());
+        // the instance is the leader right away
+        elected().await;
+    }
+
+    #[tokio::test]
+    async fn without_the_election_the_instance_leads() {
+        assert!(is_elected());
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(20), elected()).await.is_ok());
+    }
+}
@@ -5,6 +5,12 @@
 //! *  анализировать конфигурацию программы на предмет заданного уровня логирования
 //! *  различать уровни логирования в файл (или системный журнал)и в консоль (stdout)
 //! *  реализовать логирование
+//! *  переопределять уровень логирования отдельных модулей, `[general] log_targets`
+//! *  применять измененные `[general] log_targets` при перечитывании конфигурации
+
+mod target;
+
+use std::sync::Mutex;
 
 use log4rs::append::console::ConsoleAppender;
 use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
@@ -12,17 +18,63 @@ use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
 use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
 use log4rs::append::rolling_file::RollingFileAppender;
 use log4rs::config::{Appender, Config, Root};
+use log4rs::Handle;
 use log4rs::encode::pattern::PatternEncoder;
-use log4rs::filter::threshold::ThresholdFilter;
 
 use crate::config::SharedConfig;
 
+use self::target::TargetFilter;
+
+/// Управление запущенной подсистемой логирования для замены ее настроек, None - логирование не инициализировано
+static HANDLE: Mutex<Option<Handle>> = Mutex::new(None);
+
+/// Признак работы подсистемы логирования, при уничтожении сбрасывает накопленные приемниками записи.
+/// Должен удерживаться до завершения работы приложения
+pub struct LoggerGuard {
+    _private: ()
+}
+
+impl Drop for LoggerGuard {
+
+    fn drop(&mut self) {
+        flush();
+    }
+}
+
+/// Сбрасывает накопленные приемниками записи, в том числе перед принудительным завершением процесса,
+/// при котором [`LoggerGuard`] не уничтожается
+pub fn flush() {
+    log::logger().flush();
+}
+
 /// Инициализирует подсистему логирования в соответствии с настройками в конфигурации.
 /// После инициализации можно в любом метсе программы использовать макросы `error!`, `warn!`, `info!`, `debug!`, `trace!`
 /// Подсистема должна инициализироваться сразу после получения доступа к конфигурации приложения
-pub fn init(conf: SharedConfig) {
+pub fn init(conf: SharedConfig) -> LoggerGuard {
+    let handle = log4rs::init_config(build(&conf)).unwrap();
+    //log::set_boxed_logger(Box::new(log4rs::Logger::new(config))).unwrap();
+    *HANDLE.lock().unwrap_or_else(|p| p.into_inner()) = Some(handle);
+    LoggerGuard {
+        _private: ()
+    }
+}
+
+/// Заменяет настройки подсистемы логирования текущими настройками конфигурации, например после ее перечитывания
+pub fn reload(conf: &SharedConfig) {
+    let config = build(conf);
+    if let Some(handle) = HANDLE.lock().unwrap_or_else(|p| p.into_inner()).as_ref() {
+        handle.set_config(config);
+    }
+}
+
+// the appenders and the filters by the configured levels
+fn build(conf: &SharedConfig) -> Config {
     let lvl_console = conf.log_lvl_console();
     let lvl_file = conf.log_lvl_file();
+    let targets = conf.log_targets();
+    let filter_console = TargetFilter::new(lvl_console, &targets);
+    let filter_file = TargetFilter::new(lvl_file, &targets);
+    let lvl_root = std::cmp::max(filter_console.max_level(), filter_file.max_level());
 
     let stdout = ConsoleAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{m}{n}")))
@@ -42,15 +94,15 @@ pub fn init(conf: SharedConfig) {
         .build("log/log_0.txt", Box::new(compound_policy))
         .unwrap();
 
-    let config = Config::builder()
+    Config::builder()
         .appender(
             Appender::builder()
-                .filter(Box::new(ThresholdFilter::new(lvl_console)))
+                .filter(Box::new(filter_console))
                 .build("stdout", Box::new(stdout)),
         )
         .appender(
             Appender::builder()
-                .filter(Box::new(ThresholdFilter::new(lvl_file)))
+                .filter(Box::new(filter_file))
                 .build("common", Box::new(rolling_logfile)),
         )
         // .logger(Logger::builder()
@@ -61,10 +113,28 @@ pub fn init(conf: SharedConfig) {
             Root::builder()
                 .appender("stdout")
                 .appender("common")
-                .build(std::cmp::max(lvl_console, lvl_file)),
+                .build(lvl_root),
         )
-        .unwrap();
+        .unwrap()
+}
 
-    log4rs::init_config(config).unwrap();
-    //log::set_boxed_logger(Box::new(log4rs::Logger::new(config))).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config as AppConfig;
+
+    // the logger is set once a process, the other tests are kept quiet by the bare level
+    #[test]
+    fn the_guard_leaves_the_records_in_the_file() {
+        let cfg = AppConfig::from_sources(&[], &["general.log_targets=off,banshee::logger=info"]).unwrap();
+        let guard = init(cfg);
+        let marker = format!("logger test {}", std::process::id());
+        log::info!("{}", marker);
+        log::info!(target: "banshee::output", "{} is filtered out", marker);
+        drop(guard);
+        let log = std::fs::read_to_string("log/log_0.txt").unwrap();
+        assert!(log.contains(&format!("{}\n", marker)));
+        assert!(!log.contains("is filtered out"));
+    }
 }
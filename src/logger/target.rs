@@ -0,0 +1,100 @@
+//! Фильтр записей журнала по уровню с учетом заданных для отдельных модулей уровней.
+
+use log::{LevelFilter, Record};
+use log4rs::filter::{Filter, Response};
+
+use crate::config::LogDirective;
+
+/// Пропускает запись, если ее уровень не детальнее уровня наиболее точно совпадающей с целью записи директивы,
+/// а при отсутствии совпадений - общего уровня приемника
+#[derive(Debug)]
+pub struct TargetFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>
+}
+
+impl TargetFilter {
+
+    /// Создает фильтр с общим уровнем `default`, директива без цели заменяет общий уровень
+    pub fn new(default: LevelFilter, directives: &[LogDirective]) -> TargetFilter {
+        let mut default = default;
+        let mut targets = Vec::new();
+        for d in directives {
+            match d.target() {
+                Some(t) => targets.push((t.to_string(), d.level())),
+                None => default = d.level()
+            }
+        }
+        TargetFilter {
+            default,
+            targets
+        }
+    }
+
+    /// Наиболее детальный уровень, который может пропустить фильтр
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, l)| *l).fold(self.default, std::cmp::max)
+    }
+
+    fn level_of(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .filter(|(t, _)| matches(t, target))
+            .max_by_key(|(t, _)| t.len())
+            .map(|(_, l)| *l)
+            .unwrap_or(self.default)
+    }
+}
+
+impl Filter for TargetFilter {
+
+    fn filter(&self, record: &Record) -> Response {
+        if record.level() <= self.level_of(record.target()) {
+            Response::Neutral
+        } else {
+            Response::Reject
+        }
+    }
+}
+
+// the directive target covers the module itself and all of its submodules
+fn matches(directive: &str, target: &str) -> bool {
+    match target.strip_prefix(directive) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use log::Level;
+
+    fn filter(default: LevelFilter, directives: &[&str]) -> TargetFilter {
+        let d: Vec<LogDirective> = directives.iter().map(|d| d.parse().unwrap()).collect();
+        TargetFilter::new(default, &d)
+    }
+
+    fn passes(f: &TargetFilter, level: Level, target: &str) -> bool {
+        let record = Record::builder().level(level).target(target).build();
+        matches!(f.filter(&record), Response::Neutral)
+    }
+
+    #[test]
+    fn the_closest_target_wins() {
+        let f = filter(LevelFilter::Info, &["banshee::input=debug", "banshee::input::peer=warn"]);
+        assert!(passes(&f, Level::Debug, "banshee::input"));
+        assert!(passes(&f, Level::Debug, "banshee::input::vproto"));
+        assert!(!passes(&f, Level::Info, "banshee::input::peer"));
+        assert!(!passes(&f, Level::Debug, "banshee::inputs"));
+        assert!(passes(&f, Level::Info, "banshee::output"));
+        assert_eq!(f.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn a_bare_level_replaces_the_default() {
+        let f = filter(LevelFilter::Info, &["error"]);
+        assert!(!passes(&f, Level::Warn, "banshee::output"));
+        assert_eq!(f.max_level(), LevelFilter::Error);
+    }
+}
@@ -11,7 +11,22 @@
 //! *  processor - выполняет обработку собранных звуковых сессий, получая готовые звуковые сэмплы
 //! *  inference - вычисляет сохраняемый результат для каждого полученного сэмпла
 //! *  output - отправляет на сохранение полученный результат
+//! *  http - вспомогательный служебный HTTP-сервер для контроля работы приложения
+//! *  control - управляющий сокет для диагностики работающего приложения
+//! *  trace - вспомогательный модуль трассировки сеансов через подсистемы для OpenTelemetry
+//! *  quarantine - вспомогательный модуль карантина сеансов систем сопряжения с повторяющимися ошибками обработки
+//! *  events - вспомогательный модуль журнала решений конвейера для аудита
+//! *  leader - выбор ведущего экземпляра при развертывании с горячим резервом
+//! *  stats - вспомогательный модуль счетчиков работы подсистем
+//! *  access - контроль допустимости входящих подключений по адресу удаленной стороны
 //! *  logging - вспомогательная подсистема логирования работы приложения
+//! *  banner - сводка действующих настроек в логе при запуске
+//! *  timer - случайные задержки и периодические таймеры со случайным разбросом
+//! *  drain - режим вывода экземпляра из работы для обслуживания
+//! *  health - признаки неработоспособности подсистем для проверки состояния
+//! *  inflight - ограничение количества сэмплов в работе между processor и inference
+//! *  memory - общий бюджет памяти конвейера, при исчерпании которого прием фрагментов приостанавливается
+//! *  shutdown - остановка приложения по требованию подсистемы при невосстановимом отказе
 //! *  config - вспомогательная  подсистема централизованного доступа к настройкам приложения
 //! *  data - модуль определения структур данных для обмена между подсистемами
 //! 
@@ -24,63 +39,250 @@ mod collector;
 mod processor;
 mod inference;
 mod output;
+mod http;
 mod config;
+mod tracker;
+mod access;
+mod stats;
+mod control;
+mod trace;
+mod quarantine;
+mod events;
+mod leader;
+mod banner;
+mod timer;
+mod drain;
+mod health;
+mod shutdown;
+mod inflight;
+mod memory;
 use config::Config;
+use quarantine::Quarantine;
+use stats::Stats;
+use trace::Tracer;
+use tracker::TaskTracker;
 
-use tokio::sync::oneshot;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{error, info, warn};
+
+use tokio::sync::{broadcast, oneshot};
 use tokio::sync::mpsc::channel;
+use tokio::task::{JoinError, JoinHandle};
+
+/// Емкость каналов обмена между основными подсистемами
+const CHANNEL_CAPACITY: usize = 100;
 
 /// Осуществляет предварительную настройку и запуск подсистем приложения
 /// *  создание подмодуля конфигурации
-/// *  настройку сервиса логирования
+/// *  установку маски прав создаваемых файлов `[general] umask` и настройку сервиса логирования
 /// *  создание каналов обмена между основными подсистемами
-/// *  запуск основных подсистем - input, collector, processor, inference, output
+/// *  одновременный запуск основных подсистем - input, collector, processor, inference, output
+/// *  контроль задач подсистем: завершение задачи до команды на остановку или ее паника записывается в лог
 /// *  запуск обработчика системных сигналов для корректного завершения приложения
-#[tokio::main]
-async fn main() {
+/// *  такое же завершение по требованию подсистемы (`shutdown`), после которого приложение завершается с кодом 1
+/// *  ожидание завершения подсистем в пределах `shutdown_timeout`, по истечении которого приложение завершается принудительно
+///
+/// Среда исполнения tokio строится по настройкам `[general] worker_threads` и `blocking_threads`:
+/// вычислительно емкая обработка выполняется в пуле блокирующих потоков, не занимая потоки асинхронных задач
+fn main() {
 
     println!("Hello, banshee");
 
     // instantiate config
     let cfg_inst = Config::new();
 
+    // an offline model check leaves the pipeline, the log and the peers alone
+    if let Some(model) = cfg_inst.validate_model() {
+        if let Err(e) = inference::validate_model(&cfg_inst, &model) {
+            eprintln!("banshee: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // the log files are created under the mask too
+    if let Some(mask) = cfg_inst.umask() {
+        platform::set_umask(mask);
+    }
+
     // init logger
-    logger::init(cfg_inst.clone());
+    // the guard flushes the log when main returns
+    let _log_guard = logger::init(cfg_inst.clone());
     // now logging is available
+    for note in cfg_inst.notes() {
+        info!("{}", note);
+    }
+    for key in cfg_inst.unknown_keys() {
+        warn!("config: unknown key {} is ignored, misspelled?", key);
+    }
+    inflight::init(cfg_inst.max_in_flight());
+    if cfg_inst.max_in_flight() > 0 {
+        info!("up to {} samples are in flight between processor and inference", cfg_inst.max_in_flight());
+    }
+    memory::init(cfg_inst.memory_budget_bytes());
+    if cfg_inst.memory_budget_bytes() > 0 {
+        info!("memory budget of {} bytes, intake is paused beyond it", cfg_inst.memory_budget_bytes());
+    }
+
+    let (workers, blocking) = threads(&cfg_inst);
+    banner::log(&cfg_inst, workers, blocking, CHANNEL_CAPACITY);
+    let mut runtime = runtime(workers, blocking).unwrap_or_else(|e| panic!("unable to build the runtime: {}", e));
+    // a replay of the dead-lettered results uses the output sinks only, the pipeline isn't started
+    if let Some(dir) = cfg_inst.replay_deadletter() {
+        let code = match runtime.block_on(output::replay(cfg_inst, dir.clone())) {
+            Ok((stored, 0)) => {
+                info!("replay: {} results are stored, {} is clear", stored, dir);
+                0
+            },
+            Ok((stored, left)) => {
+                error!("replay: {} results are stored, {} are left in {}", stored, left, dir);
+                1
+            },
+            Err(e) => {
+                error!("replay: {}", e);
+                1
+            }
+        };
+        logger::flush();
+        std::process::exit(code);
+    }
+    runtime.block_on(run(cfg_inst));
+    if let Some(reason) = shutdown::reason() {
+        error!("banshee is stopped on failure: {}", reason);
+        // exit skips the destructors, the guard won't flush
+        logger::flush();
+        std::process::exit(1);
+    }
+}
+
+// the worker threads, by the cpu count if not set, and the blocking threads of the runtime
+fn threads(cfg: &config::SharedConfig) -> (usize, usize) {
+    let workers = match cfg.worker_threads() {
+        0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        n => n
+    };
+    (workers, cfg.blocking_threads().max(1))
+}
+
+fn runtime(workers: usize, blocking: usize) -> std::io::Result<tokio::runtime::Runtime> {
+    // max_threads covers both the workers and the blocking pool
+    tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .core_threads(workers)
+        .max_threads(workers + blocking)
+        .enable_all()
+        .build()
+}
+
+// launches the subsystems and waits for them to stop
+async fn run(cfg_inst: config::SharedConfig) {
 
     let subsystems = async move {
         // channel to control input is a oneshot
         let (tx_stop, rx_stop) = oneshot::channel();
+        // as well as the one to control http
+        let (tx_http_stop, rx_http_stop) = oneshot::channel();
+        // and control
+        let (tx_control_stop, rx_control_stop) = oneshot::channel();
+        // and trace export
+        let (tx_trace_stop, rx_trace_stop) = oneshot::channel();
+        // and event log
+        let (tx_events_stop, rx_events_stop) = oneshot::channel();
+        // and leader election
+        let (tx_leader_stop, rx_leader_stop) = oneshot::channel();
         
         // other channels are universal        
         // channel to pass fragments: input --> collector
-        let (mut tx_frag, rx_frag) = channel::<data::Fragment>(100);
+        let (mut tx_frag, rx_frag) = channel::<data::Fragment>(CHANNEL_CAPACITY);
         // channel to pass sessions: collector --> processor
-        let (mut tx_sess, rx_sess) = channel::<data::Session>(100);
+        let (mut tx_sess, rx_sess) = channel::<data::Session>(CHANNEL_CAPACITY);
         // channel to pass samples: procesor --> inference
-        let (mut tx_smpl, rx_smpl) = channel::<data::FinalSample>(100);
+        let (mut tx_smpl, rx_smpl) = channel::<data::FinalSample>(CHANNEL_CAPACITY);
         // channel to pass stored results: inference --> output
-        let (mut tx_rslt, rx_rslt) = channel::<data::StoredResult>(100);
+        let (mut tx_rslt, rx_rslt) = channel::<data::StoredResult>(CHANNEL_CAPACITY);
+        // summaries of the stored results for live watching: output --> http clients
+        let (tx_live, _) = broadcast::channel::<String>(cfg_inst.live_queue());
 
         // launch worker submodules
-        input::run(cfg_inst.clone(), rx_stop, tx_frag.clone()).await;
-        collector::run(cfg_inst.clone(), rx_frag, tx_sess.clone()).await;
-        processor::run(cfg_inst.clone(), rx_sess, tx_smpl.clone()).await;
-        inference::run(cfg_inst.clone(), rx_smpl, tx_rslt.clone()).await;
-        output::run(cfg_inst.clone(), rx_rslt).await;
+        let tracker = TaskTracker::new();
+        // the subsystems serving the others, stopped after the others are done
+        let late = TaskTracker::new();
+        let stats = Stats::new(&cfg_inst.peers());
+        let tracer = Tracer::new(&cfg_inst);
+        let quarantine = Quarantine::new(&cfg_inst, stats.clone());
+        // the serving subsystems go first, the stages report to them from the start
+        let trace = trace::run(cfg_inst.clone(), late.track("trace"), tracer.clone(), rx_trace_stop).await;
+        let events = events::run(cfg_inst.clone(), late.track("events"), rx_events_stop).await;
+        // a standby instance starts the stages too, they idle until it is elected
+        let leader = leader::run(cfg_inst.clone(), late.track("leader"), stats.clone(), rx_leader_stop).await;
+        // the stages are started all together, none waits for another to start
+        let (input, collector, processor, inference, output) = tokio::join!(
+            input::run(cfg_inst.clone(), tracker.track("input"), stats.clone(), rx_stop, tx_frag.clone()),
+            collector::run(cfg_inst.clone(), tracker.track("collector"), stats.clone(), tracer.clone(), rx_frag, tx_sess.clone()),
+            processor::run(cfg_inst.clone(), tracker.track("processor"), stats.clone(), tracer.clone(), quarantine.clone(), rx_sess, tx_smpl.clone()),
+            inference::run(cfg_inst.clone(), tracker.track("inference"), stats.clone(), tracer.clone(), quarantine, rx_smpl, tx_rslt.clone()),
+            output::run(cfg_inst.clone(), tracker.track("output"), stats.clone(), tracer.clone(), rx_rslt, tx_live.clone())
+        );
+        let http = http::run(cfg_inst.clone(), tracker.track("http"), tx_live, tx_sess.clone(), rx_http_stop).await;
+        let control = control::run(cfg_inst.clone(), tracker.track("control"), stats.clone(), rx_control_stop).await;
+
+        // a subsystem ending on its own or panicking is reported right away
+        let stopping = Arc::new(AtomicBool::new(false));
+        let started = vec![("input", Some(input)), ("collector", Some(collector)), ("processor", Some(processor)),
+            ("inference", Some(inference)), ("output", Some(output)), ("http", http), ("control", control),
+            ("trace", trace), ("events", events), ("leader", leader)];
+        for (name, handle) in started {
+            if let Some(h) = handle {
+                tokio::spawn(supervise(name, h, stopping.clone()));
+            }
+        }
 
         // launch stop handler, <Enter> in Windows, <Ctrl+C> in others
         let signals = platform::get_system_signals();
         tokio::spawn(async move {
-            for _ in signals.forever() {
+            // waiting for a signal blocks the thread, so keep it away from the runtime workers
+            let signaled = tokio::task::spawn_blocking(move || signals.forever().next().is_some());
+            let stop = tokio::select! {
+                s = signaled => matches!(s, Ok(true)),
+                reason = shutdown::requested() => {
+                    error!("stop is requested: {}", reason);
+                    true
+                }
+            };
+            if stop {
                 println!("\nTrying to stop banshee!\n");
-                // send stop signal to all channels
-                let _ = tx_stop.send(());                               // stops input
-                let _ = tx_frag.send(data::Fragment::Stop).await;       // stops collector
-                let _ = tx_sess.send(data::Session::Stop).await;        // stops processor
-                let _ = tx_smpl.send(data::FinalSample::Stop).await;    // stops inference
-                let _ = tx_rslt.send(data::StoredResult::Stop).await;   // stops output
-                break;
+                stopping.store(true, Ordering::SeqCst);
+                shutdown::set_stopping();
+                // read at the stop, the timeout is changed by a config reload
+                let shutdown_timeout = cfg_inst.shutdown_timeout();
+                let drain = async {
+                    // send stop signal to all channels
+                    let _ = tx_stop.send(());                               // stops input
+                    let _ = tx_frag.send(data::Fragment::Stop).await;       // stops collector
+                    let _ = tx_sess.send(data::Session::Stop).await;        // stops processor
+                    let _ = tx_smpl.send(data::FinalSample::Stop).await;    // stops inference
+                    let _ = tx_rslt.send(data::StoredResult::Stop).await;   // stops output
+                    let _ = tx_http_stop.send(());                          // stops http
+                    let _ = tx_control_stop.send(());                       // stops control
+                    // wait until every subsystem completes its work
+                    tracker.wait().await;
+                    // the trace export sends the spans registered while draining
+                    let _ = tx_trace_stop.send(());
+                    let _ = tx_events_stop.send(());
+                    let _ = tx_leader_stop.send(());
+                    late.wait().await;
+                };
+                // the deadline covers the sends too, a wedged stage won't drain its channel
+                if tokio::time::timeout(shutdown_timeout, drain).await.is_err() {
+                    error!("shutdown timeout {:?} is elapsed, still busy: {}, force exit",
+                        shutdown_timeout, [tracker.busy(), late.busy()].concat().join(", "));
+                    // exit skips the destructors, the guard won't flush
+                    logger::flush();
+                    std::process::exit(1);
+                }
+                info!("all subsystems are stopped");
             }
         }).await.unwrap();
     };
@@ -88,6 +290,24 @@ async fn main() {
     subsystems.await;
 }
 
+// reports the subsystem `name` which task has ended before the stop or failed
+async fn supervise(name: &'static str, handle: JoinHandle<()>, stopping: Arc<AtomicBool>) {
+    let ended = handle.await;
+    if let Some(report) = outcome(name, ended, stopping.load(Ordering::SeqCst)) {
+        error!("{}", report);
+    }
+}
+
+// the report of the ended task of the subsystem `name`, None - it has stopped on the command
+fn outcome(name: &str, ended: Result<(), JoinError>, stopping: bool) -> Option<String> {
+    match ended {
+        Ok(()) if !stopping => Some(format!("{} has stopped unexpectedly", name)),
+        Ok(()) => None,
+        Err(e) if e.is_panic() => Some(format!("{} has panicked", name)),
+        Err(e) => Some(format!("{} has failed: {}", name, e))
+    }
+}
+
 /// Non-windows реализация обработчика Ctrl-C (SIGINT)
 #[cfg(not(windows))]
 mod platform {
@@ -97,7 +317,12 @@ mod platform {
     /// Срабатывание реализовано в виде итератора, который возвращает одно значение на каждый сигнал
     pub fn get_system_signals() -> Signals {
         println!("Banshee has started, press Ctrl+C to stop");
-        Signals::new(&[SIGINT]).unwrap()
+        Signals::new([SIGINT]).unwrap()
+    }
+
+    /// Устанавливает маску прав создаваемых процессом файлов
+    pub fn set_umask(mask: u32) {
+        unsafe { libc::umask(mask as libc::mode_t); }
     }
 }
 
@@ -128,4 +353,38 @@ mod platform {
     pub fn get_system_signals() -> Signals {
         Signals {}
     }
+
+    /// Маска прав файлов unix в windows не применяется
+    pub fn set_umask(_mask: u32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_threads_by_the_config() {
+        let cfg = Config::from_sources(&[], &[]).unwrap();
+        let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        assert_eq!(threads(&cfg), (cpus, 16));
+        let cfg = Config::from_sources(&[], &["general.worker_threads=3", "general.blocking_threads=0"]).unwrap();
+        assert_eq!(threads(&cfg), (3, 1));
+    }
+
+    #[test]
+    fn the_runtime_runs_blocking_work() {
+        let mut rt = runtime(2, 1).unwrap();
+        let n = rt.block_on(async { tokio::task::spawn_blocking(|| 7).await.unwrap() });
+        assert_eq!(n, 7);
+    }
+
+    #[tokio::test]
+    async fn reports_the_task_ended_before_the_stop() {
+        let done = tokio::spawn(async {}).await;
+        assert_eq!(outcome("input", done, false).as_deref(), Some("input has stopped unexpectedly"));
+        let done = tokio::spawn(async {}).await;
+        assert_eq!(outcome("input", done, true), None);
+        let panicked = tokio::spawn(async { panic!("broken") }).await;
+        assert_eq!(outcome("output", panicked, true).as_deref(), Some("output has panicked"));
+    }
 }
@@ -0,0 +1,176 @@
+//! Общий бюджет памяти конвейера `[general] memory_budget_bytes`.
+//!
+//! Подсистемы учитывают объем удерживаемых ими данных: input - фрагменты в канале передачи в collector,
+//! collector - фрагменты незавершенных сеансов и сеансы до их получения processor, processor и inference -
+//! сэмплы в работе от передачи processor до окончания расчета их пакета.
+//! Пока учтенный объем не меньше бюджета, input не читает новые фрагменты из соединений: данные
+//! остаются в буферах сокетов и у систем сопряжения. Чтение возобновляется, как только подсистемы
+//! освобождают память, в том числе передачей незавершенных сеансов на обработку по `[collector] session_timeout`
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+
+/// Бюджет памяти приложения
+static STATE: Budget = Budget::new();
+
+/// Учет удерживаемых данных и ожидание памяти под прием
+struct Budget {
+    /// Бюджет, байтов, 0 - не ограничен
+    limit: AtomicU64,
+    /// Учтенный объем данных, байтов
+    used: AtomicU64,
+    /// Наибольший учтенный объем с запуска
+    peak: AtomicU64,
+    /// Приостановлен ли прием, для записи в журнал только его приостановки и возобновления
+    paused: AtomicBool,
+    /// Количество приостановок приема с запуска
+    pauses: AtomicU64,
+    /// Извещение ожидающих приема об освобождении памяти, None - бюджет не ограничен
+    released: Mutex<Option<Arc<Notify>>>
+}
+
+/// Устанавливает бюджет, 0 - не ограничен. Вызывается до запуска подсистем
+pub fn init(limit: u64) {
+    STATE.init(limit);
+}
+
+/// Учитывает `n` байтов данных, удерживаемых подсистемой
+pub fn charge(n: usize) {
+    STATE.charge(n);
+}
+
+/// Снимает с учета `n` байтов данных, освобожденных подсистемой или переданных ею дальше неучтенными
+pub fn release(n: usize) {
+    STATE.release(n);
+}
+
+/// Дожидается памяти под прием новых данных: возвращается сразу, пока бюджет не исчерпан
+pub async fn admit() {
+    STATE.admit().await;
+}
+
+/// Снимок показателей бюджета памяти в виде JSON
+pub fn snapshot() -> Value {
+    STATE.snapshot()
+}
+
+impl Budget {
+
+    const fn new() -> Budget {
+        Budget {
+            limit: AtomicU64::new(0),
+            used: AtomicU64::new(0),
+            peak: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+            pauses: AtomicU64::new(0),
+            released: Mutex::new(None)
+        }
+    }
+
+    fn init(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+        *self.released() = Some(limit).filter(|l| *l > 0).map(|_| Arc::new(Notify::new()));
+    }
+
+    fn charge(&self, n: usize) {
+        let used = self.used.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+        self.peak.fetch_max(used, Ordering::Relaxed);
+    }
+
+    fn release(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let _ = self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |u| Some(u.saturating_sub(n as u64)));
+        if !self.is_exceeded() {
+            // the waiter may be gone by now, a stopped connection doesn't wait on, so the intake is resumed here
+            if self.paused.swap(false, Ordering::Relaxed) {
+                info!("memory: {} bytes are held, intake is resumed", self.used.load(Ordering::Relaxed));
+            }
+            if let Some(notify) = self.released().as_ref() {
+                notify.notify();
+            }
+        }
+    }
+
+    fn is_exceeded(&self) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed);
+        limit > 0 && self.used.load(Ordering::Relaxed) >= limit
+    }
+
+    async fn admit(&self) {
+        if !self.is_exceeded() {
+            return;
+        }
+        let notify = match self.released().clone() {
+            Some(n) => n,
+            None => return
+        };
+        if !self.paused.swap(true, Ordering::Relaxed) {
+            self.pauses.fetch_add(1, Ordering::Relaxed);
+            warn!("memory: {} bytes are held, the budget of {} is used up, intake is paused",
+                self.used.load(Ordering::Relaxed), self.limit.load(Ordering::Relaxed));
+        }
+        // a release in between the check and the wait leaves the notification stored, it isn't lost
+        while self.is_exceeded() {
+            notify.notified().await;
+        }
+        // a release wakes a single waiter, it passes the turn on to the next one
+        notify.notify();
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "bytes": self.used.load(Ordering::Relaxed),
+            "peak": self.peak.load(Ordering::Relaxed),
+            "limit": self.limit.load(Ordering::Relaxed),
+            "paused": self.paused.load(Ordering::Relaxed),
+            "pauses": self.pauses.load(Ordering::Relaxed)
+        })
+    }
+
+    fn released(&self) -> MutexGuard<'_, Option<Arc<Notify>>> {
+        self.released.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    // the process-wide budget is charged by the tests of the pipeline, these count on their own
+    #[tokio::test]
+    async fn pauses_the_intake_until_the_release() {
+        let b = Budget::new();
+        b.init(100);
+        b.charge(60);
+        assert!(timeout(Duration::from_millis(20), b.admit()).await.is_ok());
+        b.charge(40);
+        assert!(b.is_exceeded());
+        assert!(timeout(Duration::from_millis(20), b.admit()).await.is_err());
+        assert_eq!(b.snapshot()["paused"], true);
+        b.release(50);
+        assert!(timeout(Duration::from_millis(20), b.admit()).await.is_ok());
+        assert_eq!(b.snapshot(), json!({ "bytes": 50, "peak": 100, "limit": 100, "paused": false, "pauses": 1 }));
+    }
+
+    #[tokio::test]
+    async fn unlimited_by_default() {
+        let b = Budget::new();
+        b.init(0);
+        b.charge(1 << 30);
+        assert!(!b.is_exceeded());
+        assert!(timeout(Duration::from_millis(20), b.admit()).await.is_ok());
+        // the release of more than charged leaves no debt
+        b.release(2 << 30);
+        assert_eq!(b.snapshot()["bytes"], 0);
+    }
+}
@@ -7,47 +7,328 @@
 //! *  контролировать канал связи с системой хранения результатов
 //! *  при разрыве канала удерживать неотправленные данные до восстановления канала связи
 //! *  контролировать размер неотправленных данных, не допускать переполнения памяти
+//! *  периодически сбрасывать буферизованные данные открытых файлов на диск (`[output] flush_interval`)
+//! *  не открывать каталоги хранения, пока резервный экземпляр не выбран ведущим (`[leader] lock`)
+//! *  передавать результаты внешнему процессу через именованный канал (`[output] mode = fifo`), только unix
+//! *  передавать результаты внешней системе с подтверждением получения (`[output] mode = http`)
+//! *  не принимать результаты, пока в каталогах хранения недостаточно свободного места (`[output] min_free_bytes`),
+//!    при остановке откладывать или отбрасывать ожидающие места результаты, не задерживая ее
+//! *  записывать в журнал сохраненных файлов сведения о сеансе, переданные от collector (`[output] metadata`)
+//! *  сохранять результат больше допустимого размера объекта хранения частями (`[output] max_result_bytes`)
+//! *  направлять результаты по их признакам в отдельные приемники (`[output] routes`)
+//! *  записывать отметки начала и окончания работы с ее итогами (`[output] run_start_marker`, `run_end_marker`)
+//! *  сохранять результаты, которые не удалось сохранить, для повторной передачи (`[output] deadletter_dir`),
+//!    не сохраненные частью приемников - для повторной передачи только в них
+
+mod sink;
+mod file;
+mod session;
+#[cfg(unix)]
+mod fifo;
+mod fanout;
+mod failover;
+mod digest;
+mod manifest;
+mod marker;
+mod stamp;
+mod session_id;
+mod space;
+mod unique;
+mod route;
+mod ack;
+mod deadletter;
+
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::config::SharedConfig;
+use crate::stats::SharedStats;
+use crate::timer::Jittered;
+use crate::trace::SharedTracer;
+use crate::tracker::TaskGuard;
 use crate::data::StoredResult;
+use crate::events;
+use crate::leader;
+use crate::shutdown;
+use self::deadletter::DeadLetters;
+use self::marker::Markers;
+use self::space::SpaceGate;
+use self::session_id::SessionId;
+use self::sink::Flags;
+use self::stamp::{Clock, Stamp};
+
+pub use self::deadletter::replay;
 
 use log::{error, info};
+use serde_json::json;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Версия формата сведений о сохраненных результатах: строк журнала `[output] manifest` и сводок `/live`.
+/// Передается в каждой из них полем `schema_version`, увеличивается при несовместимом изменении полей
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Период обслуживания приемника результатов (закрытие неактивных сеансов и т.п.)
+const SWEEP_PERIOD: Duration = Duration::from_secs(1);
 
 /// Запускает в асинхронном режиме подсистему отправки итогового результата, полученного от inference, в систему хранения
 /// 
 /// Параметры:
 /// 
 /// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `stats` - счетчики работы подсистем
+/// * `tracer` - регистратор интервалов трасс сеансов
 /// * `rx_rslt` - межпоточный канал получения результата из inference, читатель
+/// * `tx_live` - поток сводок сохраненных результатов для наблюдения, писатель. Сводки строятся, только пока есть читатели
 /// 
-/// Возвращает Future для ожидания в вызывающем коде
-pub async fn run(_cfg: SharedConfig, mut rx_rslt: Receiver<StoredResult>) {
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, tracer: SharedTracer, rx_rslt: Receiver<StoredResult>,
+                 tx_live: broadcast::Sender<String>) -> JoinHandle<()> {
+    serve(cfg, guard, stats, tracer, rx_rslt, tx_live, shutdown::stopping()).await
+}
+
+// the output task, `stopping` completes once the stop begins
+async fn serve<S>(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, tracer: SharedTracer, mut rx_rslt: Receiver<StoredResult>,
+                  tx_live: broadcast::Sender<String>, stopping: S) -> JoinHandle<()>
+    where S: Future<Output = ()> + Send + 'static {
     info!("start output");
 
+    let mut clock = Clock::new(cfg.timestamp_source(), cfg.timestamp_precision());
+    let flush_interval = cfg.flush_interval();
+    let flush_sync = cfg.flush_sync();
+    let flushing = flush_interval > Duration::from_secs(0);
+    if flushing {
+        info!("output: open files are flushed every {:?}{}", flush_interval, if flush_sync { " with fsync" } else { "" });
+    }
+
+    let routes = cfg.output_routes().into_iter().filter(|r| r.mode().has_dir()).map(|r| r.path().to_string());
+    let dirs = std::iter::once(cfg.output_dir()).chain(Some(cfg.fallback_dir()).filter(|d| !d.is_empty())).chain(routes).collect();
+    let mut space = SpaceGate::new(dirs, cfg.min_free_bytes(), cfg.space_check_interval(), stats.clone());
+
     tokio::spawn(async move {
+        let _guard = guard;
+
+        // a standby instance doesn't touch the output directories shared with the leader; the leader
+        // doesn't get here, the select would take its results just as well as the election
+        while !leader::is_elected() {
+            tokio::select! {
+                _ = leader::elected() => break,
+                r = rx_rslt.recv() => match r {
+                    Some(StoredResult::Data { id, .. }) => {
+                        error!("output: result of {} is received on standby and dropped", id);
+                        stats.output.received();
+                        stats.output.dropped();
+                    },
+                    _ => {
+                        info!("output is stopped on standby");
+                        return;
+                    }
+                }
+            }
+        }
+        let mut sink = sink::build(&cfg, stats.clone()).unwrap_or_else(|e| panic!("output: unable to init in {}: {}", cfg.output_dir(), e));
+        let modes: Vec<String> = cfg.output_modes().iter().map(|m| m.to_string()).collect();
+        info!("output: {} mode in {}", modes.join(", "), cfg.output_dir());
+        for r in cfg.output_routes() {
+            info!("output: results routed {}", r);
+        }
+        let mut deadletters = DeadLetters::open(&cfg).unwrap_or_else(|e| panic!("output: unable to open {}: {}", cfg.deadletter_dir(), e));
+        let markers = Markers::start(&cfg);
+        // the end of the run is marked only once the results are drained by the stop command
+        let mut stopped = false;
 
+        // the sessions finalized and the files flushed together by many instances would load the disk at once
+        let mut sweep = Jittered::new(SWEEP_PERIOD, cfg.timer_jitter());
+        // the period can't be zero, the branch is off then anyway
+        let mut flush = Jittered::new(if flushing { flush_interval } else { SWEEP_PERIOD }, cfg.timer_jitter());
+        tokio::pin!(stopping);
+        let mut draining = false;
         loop {
-            match rx_rslt.recv().await {
-                None => {
-                    error!("stored result input channel is broken");
-                    break;
-                },
-                Some(f) => {
-                    match f {
-                        StoredResult::Stop => {
-                            info!("stop output");
-                            break;
-                        },
-                        _ => {
-                            //todo: handle stored result
-                            info!("output: handling results is not implemented yet");
+            // the results wait in the channel while the disk is short of space, backing up the pipeline,
+            // but the stop command waits behind them, so once the stop begins they are received and set aside
+            let accepting = space.open(Instant::now());
+            tokio::select! {
+                r = rx_rslt.recv(), if accepting || draining => match r {
+                    None => {
+                        error!("stored result input channel is broken");
+                        break;
+                    },
+                    Some(StoredResult::Stop) => {
+                        info!("stop output");
+                        stopped = true;
+                        break;
+                    },
+                    Some(r) if !accepting => {
+                        stats.output.received();
+                        stats.output.dropped();
+                        set_aside(&r, deadletters.as_mut());
+                    },
+                    Some(StoredResult::Data { id, prefix, metadata, chunk, trace, peer_time_us, value, low_confidence, partial, last }) => {
+                        stats.output.received();
+                        let started = SystemTime::now();
+                        let stamp = clock.stamp(id, peer_time_us);
+                        let sid = SessionId::new(prefix.clone(), id, metadata.clone());
+                        let flags = Flags { low_confidence, partial, last };
+                        let written = sink.write(&sid, chunk, &value, flags, stamp);
+                        // a result stored by the fanout policy while failed in some sinks is dead-lettered for them
+                        let failed = written.as_ref().err().and_then(sink::failed).map(|f| (f.stored, f.sinks.clone()));
+                        let stored = written.is_ok() || failed.as_ref().is_some_and(|(stored, _)| *stored);
+                        if stored {
+                            stats.output.sent();
+                            // nobody watches most of the time, the summary isn't built then
+                            if tx_live.receiver_count() > 0 {
+                                let _ = tx_live.send(summary(id, chunk, &value, flags, stamp));
+                            }
+                            // the span of the chunk ends with its final result
+                            if !partial {
+                                tracer.stage(&trace, "output", started, id, chunk);
+                            }
+                            if last {
+                                tracer.session(&trace, id);
+                            }
                         }
-                    }                    
+                        if let Err(e) = written {
+                            error!("output: failed to store result of {}: {}", id, e);
+                            events::emit("result_failed", json!({ "id": id, "chunk": chunk, "error": e.to_string() }));
+                            if !stored {
+                                stats.output.dropped();
+                            }
+                            if let Some(d) = deadletters.as_mut() {
+                                let r = StoredResult::Data { id, prefix, metadata, chunk, trace, peer_time_us, value, low_confidence, partial, last };
+                                let put = match &failed {
+                                    Some((_, sinks)) => d.split(&r, sinks),
+                                    None => d.put(&r, None).map(|p| vec![p])
+                                };
+                                match put {
+                                    Ok(paths) => for path in paths {
+                                        info!("output: result of {} chunk {} is dead-lettered to {}", id, chunk, path.display());
+                                    },
+                                    Err(e) => error!("output: failed to dead-letter result of {}: {}", id, e)
+                                }
+                            }
+                        }
+                    }
+                },
+                _ = time::delay_until(space.next_check().into()), if !accepting => {},
+                _ = &mut stopping, if !draining => draining = true,
+                now = sweep.tick() => sink.sweep(now),
+                _ = flush.tick(), if flushing => {
+                    if let Err(e) = sink.flush(flush_sync) {
+                        error!("output: failed to flush: {}", e);
+                    }
                 }
             }
         }    
+        sink.close();
+        if stopped {
+            markers.end(&stats);
+        }
         info!("output is stopped");
 
+    })
+}
+
+// the result the stop doesn't wait the space for, it is dead-lettered if it can be
+fn set_aside(r: &StoredResult, deadletters: Option<&mut DeadLetters>) {
+    let (id, chunk) = match r {
+        StoredResult::Data { id, chunk, .. } => (*id, *chunk),
+        StoredResult::Stop => return
+    };
+    match deadletters.map(|d| d.put(r, None)) {
+        Some(Ok(path)) => info!("output: result of {} chunk {} is dead-lettered to {}, the disk is short of space on stop", id, chunk, path.display()),
+        Some(Err(e)) => error!("output: result of {} chunk {} is dropped, the disk is short of space on stop, failed to dead-letter: {}", id, chunk, e),
+        None => error!("output: result of {} chunk {} is dropped, the disk is short of space on stop", id, chunk)
+    }
+}
+
+// a lightweight view of the stored result for the live watchers
+fn summary(id: u32, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> String {
+    let mut s = json!({
+        "schema_version": SCHEMA_VERSION,
+        "id": id,
+        "chunk": chunk,
+        "time_ms": stamp.ms(),
+        "time_source": stamp.source.to_string(),
+        "bytes": value.len(),
+        "low_confidence": flags.low_confidence
     });
+    if let Some(field) = stamp.field() {
+        s[field] = json!(stamp.value());
+    }
+    if flags.partial {
+        s["partial"] = json!(true);
+    }
+    // the result is a vector of f32 unless stored as is in passthrough mode
+    if !value.is_empty() && value.chunks_exact(4).remainder().is_empty() {
+        let top = value.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .enumerate()
+            .fold((0, f32::MIN), |best, (i, v)| if v > best.1 { (i, v) } else { best });
+        s["top"] = json!({ "index": top.0, "value": top.1 });
+    }
+    s.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::config::{Config, TimestampPrecision, TimestampSource};
+    use crate::stats::Stats;
+    use crate::trace::Tracer;
+    use crate::tracker::TaskTracker;
+    use tokio::sync::{mpsc, oneshot};
+
+    fn stamp() -> Stamp {
+        Stamp { ns: 1_000_000_000, source: TimestampSource::Peer, precision: TimestampPrecision::Ms }
+    }
+
+    #[test]
+    fn the_summary_carries_the_schema_version() {
+        let value: Vec<u8> = [0.1f32, 0.5, 0.25].iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        let s: serde_json::Value = serde_json::from_str(&summary(7, 2, &value, Flags::default(), stamp())).unwrap();
+        assert_eq!(s["schema_version"], SCHEMA_VERSION);
+        assert_eq!((&s["id"], &s["chunk"], &s["time_ms"], &s["time_source"]), (&json!(7), &json!(2), &json!(1000), &json!("peer")));
+        assert_eq!(s["top"], json!({ "index": 1, "value": 0.5 }));
+        assert!(s.get("partial").is_none());
+        let s: serde_json::Value = serde_json::from_str(&summary(7, 2, b"abc", Flags { partial: true, ..Flags::default() }, stamp())).unwrap();
+        assert!(s.get("top").is_none());
+        assert_eq!(s["partial"], true);
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn the_stop_sets_aside_the_results_held_for_the_space() {
+        let root = std::env::temp_dir().join(format!("banshee-output-{}-low-space", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (out, dead) = (root.join("out"), root.join("dead"));
+        std::fs::create_dir_all(&out).unwrap();
+        let cfg = Config::from_sources(&[], &[&format!("output.dir={}", out.display()), &format!("output.deadletter_dir={}", dead.display()),
+            "output.min_free_bytes=18446744073709551615", "output.manifest="]).unwrap();
+        let stats = Stats::new(&cfg.peers());
+        let (mut tx_rslt, rx_rslt) = mpsc::channel(4);
+        let (tx_stopping, rx_stopping) = oneshot::channel::<()>();
+        let stopping = async move { let _ = rx_stopping.await; };
+        let mut task = serve(cfg.clone(), TaskTracker::new().track("output"), stats.clone(), Tracer::new(&cfg), rx_rslt,
+            broadcast::channel(4).0, stopping).await;
+        for id in [1, 2] {
+            let r = StoredResult::Data { id, prefix: "".into(), metadata: Arc::new(HashMap::new()), chunk: 0, trace: Default::default(),
+                peer_time_us: None, value: vec![1, 2, 3], low_confidence: false, partial: false, last: true };
+            assert!(tx_rslt.send(r).await.is_ok());
+        }
+        assert!(tx_rslt.send(StoredResult::Stop).await.is_ok());
+        // the results and the stop behind them wait for the space
+        assert!(time::timeout(Duration::from_millis(100), &mut task).await.is_err());
+        tx_stopping.send(()).unwrap();
+        time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+        assert_eq!(std::fs::read_dir(&dead).unwrap().count(), 2);
+        assert!(std::fs::read_dir(&out).unwrap().all(|e| !e.unwrap().file_name().to_string_lossy().ends_with(".bin")));
+        assert_eq!(stats.snapshot()["stages"]["output"]["dropped"], 2);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
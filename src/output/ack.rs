@@ -0,0 +1,220 @@
+//! Передача результатов внешней системе запросами HTTP с подтверждением получения.
+//!
+//! Каждый результат передается запросом `POST` на адрес `[output] ack_url` с телом `application/octet-stream`
+//! и заголовками `X-Session-Id`, `X-Chunk`, `X-Time-Ms` и `X-Flags` (через запятую `low_confidence`, `partial`,
+//! `last`). Результат считается сохраненным, только когда система ответила кодом 2xx не позже `ack_timeout`,
+//! иначе передача повторяется через `ack_retry_delay` до `ack_retries` раз, 0 - до подтверждения.
+//! Повторы прекращаются с началом остановки приложения, результаты, полученные после нее, передаются один раз.
+//! Результат, подтверждение которого потеряно, передается повторно: доставка не менее одного раза
+
+use std::io;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use log::{info, warn};
+use serde_json::json;
+use tokio::runtime::Handle;
+use tokio::time::{delay_for, timeout};
+
+use crate::events;
+use crate::shutdown;
+
+use super::sink::{Flags, OutputSink};
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+
+/// Передает результаты внешней системе `url`, дожидаясь подтверждения получения
+pub struct AckSink {
+    target: Arc<Target>,
+    /// Среда исполнения запросов: запись в приемник синхронная
+    runtime: Handle
+}
+
+/// Внешняя система и порядок повторов
+struct Target {
+    url: String,
+    client: Client<HttpConnector>,
+    timeout: Duration,
+    retries: u32,
+    delay: Duration
+}
+
+impl AckSink {
+
+    /// Создает приемник в среде исполнения tokio, запросы отправляются при записи результатов
+    pub fn new(url: &str, timeout: Duration, retries: u32, delay: Duration) -> io::Result<AckSink> {
+        let runtime = Handle::try_current().map_err(|e| io::Error::other(e.to_string()))?;
+        info!("output: results are posted to {} until acked, {}", url,
+            if retries == 0 { "with no retry limit".to_string() } else { format!("up to {} retries", retries) });
+        Ok(AckSink {
+            target: Arc::new(Target {
+                url: url.to_string(),
+                client: Client::new(),
+                timeout,
+                retries,
+                delay
+            }),
+            runtime
+        })
+    }
+}
+
+impl Target {
+
+    // posts the result until it is acked or the retries are over
+    async fn deliver(&self, id: SessionId, chunk: u32, value: Vec<u8>, flags: Flags, stamp: Stamp) -> io::Result<()> {
+        let mut attempt = 0;
+        loop {
+            let e = match self.post(&id, chunk, &value, flags, stamp).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e
+            };
+            attempt += 1;
+            if self.retries > 0 && attempt > self.retries {
+                return Err(io::Error::other(format!("{}, given up after {} retries", e, self.retries)));
+            }
+            if shutdown::is_stopping() {
+                return Err(io::Error::other(format!("{}, not retried on shutdown", e)));
+            }
+            warn!("output: result of {} chunk {} is not acked, retry {}: {}", id, chunk, attempt, e);
+            events::emit("output_retry", json!({ "sink": "http", "id": id.to_string(), "chunk": chunk, "attempt": attempt, "error": e }));
+            tokio::select! {
+                _ = delay_for(self.delay) => {},
+                _ = shutdown::stopping() => return Err(io::Error::other(format!("{}, not retried on shutdown", e)))
+            }
+        }
+    }
+
+    // the longest delivery of a result: every post waits for the ack and every retry for its delay,
+    // None - the retries are unlimited
+    fn limit(&self) -> Option<Duration> {
+        if self.retries == 0 {
+            return None;
+        }
+        // a second more for the connection and the request sent before the ack is awaited
+        let posts = (self.timeout + Duration::from_secs(1)).checked_mul(self.retries.saturating_add(1))?;
+        posts.checked_add(self.delay.checked_mul(self.retries)?)
+    }
+
+    // one post of the result, Ok once the service acks it
+    async fn post(&self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> Result<(), String> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.as_str())
+            .header("Content-Type", "application/octet-stream")
+            .header("X-Session-Id", id.to_string())
+            .header("X-Chunk", chunk.to_string())
+            .header("X-Time-Ms", stamp.ms().to_string())
+            .header("X-Flags", flag_names(flags))
+            .body(Body::from(value.to_vec()))
+            .map_err(|e| format!("invalid request to {}: {}", self.url, e))?;
+        match timeout(self.timeout, self.client.request(req)).await {
+            Err(_) => Err(format!("no ack from {} in {:?}", self.url, self.timeout)),
+            Ok(Err(e)) => Err(format!("post to {} failed: {}", self.url, e)),
+            Ok(Ok(rsp)) if rsp.status().is_success() => Ok(()),
+            Ok(Ok(rsp)) => Err(format!("{} replied {}", self.url, rsp.status()))
+        }
+    }
+}
+
+impl OutputSink for AckSink {
+
+    fn write(&mut self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        let target = self.target.clone();
+        let (id, value) = (id.clone(), value.to_vec());
+        let (tx, rx) = mpsc::channel();
+        // the requests are driven by the runtime, this worker only waits, it can't enter the runtime once more;
+        // the delivery is bounded there, the wait ends with it
+        self.runtime.spawn(async move {
+            let delivery = target.deliver(id, chunk, value, flags, stamp);
+            let r = match target.limit() {
+                Some(limit) => timeout(limit, delivery).await
+                    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, format!("not acked in {:?}", limit)))),
+                None => delivery.await
+            };
+            let _ = tx.send(r);
+        });
+        // the output task is the only writer, holding its worker until the ack is the point of the mode
+        tokio::task::block_in_place(|| rx.recv())
+            .unwrap_or_else(|_| Err(io::Error::other("delivery is aborted by the runtime shutdown")))
+    }
+}
+
+fn flag_names(flags: Flags) -> String {
+    let names = [(flags.low_confidence, "low_confidence"), (flags.partial, "partial"), (flags.last, "last")];
+    names.iter().filter(|(set, _)| *set).map(|(_, name)| *name).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server, StatusCode};
+
+    use crate::config::{TimestampPrecision, TimestampSource};
+
+    // the service failing its first `failures` posts, returns its url and the posts counter
+    fn service(failures: u32) -> (String, Arc<AtomicU32>) {
+        let posts = Arc::new(AtomicU32::new(0));
+        let counter = posts.clone();
+        let make = make_service_fn(move |_| {
+            let posts = counter.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let n = posts.fetch_add(1, Ordering::SeqCst);
+                    let ok = n >= failures && req.headers()["X-Flags"] == "last";
+                    async move {
+                        let status = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+                        Ok::<_, Infallible>(Response::builder().status(status).body(Body::empty()).unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make);
+        let url = format!("http://{}/results", server.local_addr());
+        tokio::spawn(server);
+        (url, posts)
+    }
+
+    fn write(sink: &mut AckSink) -> io::Result<()> {
+        let id = SessionId::new("".into(), 7, Default::default());
+        let stamp = Stamp { ns: 0, source: TimestampSource::Local, precision: TimestampPrecision::Ms };
+        sink.write(&id, 0, b"result", Flags { last: true, ..Flags::default() }, stamp)
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn delivers_once_acked() {
+        let (url, posts) = service(2);
+        let mut sink = AckSink::new(&url, Duration::from_secs(5), 3, Duration::from_millis(1)).unwrap();
+        write(&mut sink).unwrap();
+        assert_eq!(posts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn gives_up_after_the_retries() {
+        let (url, posts) = service(5);
+        let mut sink = AckSink::new(&url, Duration::from_secs(5), 1, Duration::from_millis(1)).unwrap();
+        assert!(write(&mut sink).is_err());
+        assert_eq!(posts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn limits_the_bounded_delivery() {
+        let target = |retries| Target { url: String::new(), client: Client::new(), timeout: Duration::from_secs(2), retries,
+            delay: Duration::from_millis(500) };
+        assert_eq!(target(0).limit(), None);
+        assert_eq!(target(2).limit(), Some(Duration::from_millis(3 * 3000 + 2 * 500)));
+    }
+
+    #[test]
+    fn names_the_flags() {
+        assert_eq!(flag_names(Flags { low_confidence: true, partial: false, last: true }), "low_confidence,last");
+        assert_eq!(flag_names(Flags::default()), "");
+    }
+}
@@ -0,0 +1,248 @@
+//! Результаты, которые приемники не смогли сохранить (`[output] deadletter_dir`), и их повторная передача
+//! в приемники (`--replay-deadletter <dir>`).
+//!
+//! Каждый результат хранится файлом `<номер>.res` в формате дисковой очереди inference, номера растут
+//! в порядке поступления. Результат, не сохраненный лишь некоторыми из нескольких приемников, хранится
+//! файлом `<номер>.<приемник>.res` на каждый из них и передается повторно только в этот приемник.
+//! Повторная передача идет в порядке номеров, файл удаляется, как только результат
+//! сохранен, несохраненные остаются в каталоге для следующей попытки. Контекст трассы результата не сохраняется
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::SharedConfig;
+use crate::data::StoredResult;
+use crate::stats::Stats;
+
+use log::{error, info, warn};
+
+use super::sink;
+use super::session_id::SessionId;
+use super::sink::Flags;
+use super::stamp::Clock;
+
+/// Расширение файлов результатов в каталоге
+const EXT: &str = "res";
+
+/// Каталог несохраненных результатов
+pub struct DeadLetters {
+    dir: PathBuf,
+    /// Номер следующего добавляемого результата
+    next: u64
+}
+
+impl DeadLetters {
+
+    /// Открывает каталог `[output] deadletter_dir`, если он задан. Новые результаты нумеруются после оставшихся
+    pub fn open(cfg: &SharedConfig) -> io::Result<Option<DeadLetters>> {
+        let dir = cfg.deadletter_dir();
+        if dir.is_empty() {
+            return Ok(None);
+        }
+        fs::create_dir_all(&dir)?;
+        let left = list(Path::new(&dir))?;
+        if !left.is_empty() {
+            warn!("output: {} dead-lettered results are left in {}, see --replay-deadletter", left.len(), dir);
+        }
+        info!("output: results failed to store are dead-lettered to {}", dir);
+        Ok(Some(DeadLetters {
+            next: next(&left),
+            dir: PathBuf::from(dir)
+        }))
+    }
+
+    // the results left in the directory being replayed are numbered on
+    fn at(dir: &Path, left: &[Letter]) -> DeadLetters {
+        DeadLetters {
+            dir: dir.to_path_buf(),
+            next: next(left)
+        }
+    }
+
+    /// Сохраняет результат в каталог, возвращает имя файла. `sink` - имя единственного приемника
+    /// из нескольких, в который результат нужно передать повторно, None - во все приемники
+    pub fn put(&mut self, r: &StoredResult, sink: Option<&str>) -> io::Result<PathBuf> {
+        let name = match sink {
+            Some(s) => format!("{:020}.{}.{}", self.next, s, EXT),
+            None => format!("{:020}.{}", self.next, EXT)
+        };
+        let path = self.dir.join(name);
+        fs::write(&path, r.encode())?;
+        self.next += 1;
+        Ok(path)
+    }
+
+    /// Сохраняет результат для повторной передачи в каждый из приемников `sinks`, возвращает имена файлов.
+    /// При ошибке уже сохраненные файлы удаляются
+    pub fn split(&mut self, r: &StoredResult, sinks: &[String]) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::with_capacity(sinks.len());
+        for s in sinks {
+            match self.put(r, Some(s)) {
+                Ok(p) => paths.push(p),
+                Err(e) => {
+                    paths.iter().for_each(|p| { let _ = fs::remove_file(p); });
+                    return Err(e);
+                }
+            }
+        }
+        Ok(paths)
+    }
+}
+
+/// Файл результата каталога: номер, имя приемника для повторной передачи и путь
+type Letter = (u64, Option<String>, PathBuf);
+
+fn next(letters: &[Letter]) -> u64 {
+    letters.last().map(|(seq, _, _)| seq + 1).unwrap_or(0)
+}
+
+// the number and the sink of the result file name `<n>[.<sink>].res`
+fn parse(name: &str) -> Option<(u64, Option<String>)> {
+    let name = name.strip_suffix(EXT)?.strip_suffix('.')?;
+    match name.split_once('.') {
+        Some((seq, sink)) if !sink.is_empty() => Some((seq.parse().ok()?, Some(sink.to_string()))),
+        Some(_) => None,
+        None => Some((name.parse().ok()?, None))
+    }
+}
+
+// the numbered result files of the directory in order
+fn list(dir: &Path) -> io::Result<Vec<Letter>> {
+    let mut files: Vec<Letter> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str()
+            .and_then(parse)
+            .map(|(seq, sink)| (seq, sink, e.path())))
+        .collect();
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// Передает результаты каталога `dir` в приемники `[output]` в порядке номеров и удаляет сохраненные.
+/// Выполняется в среде исполнения tokio вместо конвейера. Возвращает количество сохраненных и оставшихся
+/// в каталоге результатов, ошибка - каталог не прочитан или приемники не созданы
+pub async fn replay(cfg: SharedConfig, dir: String) -> Result<(usize, usize), String> {
+    // the sinks block the worker as in the output task, the current thread of the runtime can't do it
+    tokio::spawn(async move {
+        let files = list(Path::new(&dir)).map_err(|e| format!("unable to read {}: {}", dir, e))?;
+        let mut letters = DeadLetters::at(Path::new(&dir), &files);
+        info!("output: {} dead-lettered results in {} are replayed", files.len(), dir);
+        let stats = Stats::new(&cfg.peers());
+        let mut sink = sink::build(&cfg, stats).map_err(|e| format!("unable to init output in {}: {}", cfg.output_dir(), e))?;
+        let mut clock = Clock::new(cfg.timestamp_source(), cfg.timestamp_precision());
+        let (mut stored, mut left) = (0, 0);
+        for (_, target, path) in files {
+            let r = fs::read(&path).map_err(|e| e.to_string()).and_then(|b| StoredResult::decode(&b));
+            let (id, prefix, metadata, chunk, peer_time_us, value, flags) = match &r {
+                Ok(StoredResult::Data { id, prefix, metadata, chunk, peer_time_us, value, low_confidence, partial, last, .. }) =>
+                    (*id, prefix.clone(), metadata.clone(), *chunk, *peer_time_us, value, Flags { low_confidence: *low_confidence, partial: *partial, last: *last }),
+                // decode gives data only, a stop has no record
+                Ok(StoredResult::Stop) => continue,
+                Err(e) => {
+                    error!("output: dead-lettered result {} is not read, left: {}", path.display(), e);
+                    left += 1;
+                    continue;
+                }
+            };
+            let stamp = clock.stamp(id, peer_time_us);
+            let sid = SessionId::new(prefix, id, metadata);
+            let written = match &target {
+                Some(s) => sink.write_to(s, &sid, chunk, value, flags, stamp),
+                None => sink.write(&sid, chunk, value, flags, stamp)
+            };
+            if let Err(e) = written {
+                // the sinks that stored the result don't get it again, it is left for the failed ones only
+                let split = match (&target, sink::failed(&e), &r) {
+                    (None, Some(f), Ok(r)) => letters.split(r, &f.sinks).ok(),
+                    _ => None
+                };
+                match split {
+                    Some(paths) => {
+                        error!("output: dead-lettered result of {} chunk {} is not stored by some sinks, left for them: {}", sid, chunk, e);
+                        left += paths.len();
+                    },
+                    None => {
+                        error!("output: dead-lettered result of {} chunk {} is not stored, left in {}: {}", sid, chunk, path.display(), e);
+                        left += 1;
+                        continue;
+                    }
+                }
+            } else {
+                stored += 1;
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                // stored all the same, the next replay stores it once more
+                error!("output: failed to remove replayed {}: {}", path.display(), e);
+            }
+        }
+        sink.close();
+        Ok((stored, left))
+    }).await.map_err(|e| format!("replay is aborted: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn result(id: u32) -> StoredResult {
+        StoredResult::Data { id, prefix: "".into(), metadata: Arc::new(HashMap::new()), chunk: 2, trace: Default::default(),
+            peer_time_us: Some(5), value: vec![1, 2, 3], low_confidence: true, partial: false, last: true }
+    }
+
+    #[test]
+    fn parses_the_number_and_the_sink() {
+        assert_eq!(parse("00000000000000000007.res"), Some((7, None)));
+        assert_eq!(parse("00000000000000000007.session.res"), Some((7, Some("session".to_string()))));
+        assert_eq!(parse("7..res"), None);
+        assert_eq!(parse("x.res"), None);
+        assert_eq!(parse("7.res.tmp"), None);
+    }
+
+    #[test]
+    fn round_trip_in_order() {
+        let dir = std::env::temp_dir().join(format!("banshee-deadletter-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut d = DeadLetters::at(&dir, &[]);
+        d.put(&result(1), None).unwrap();
+        d.split(&result(2), &["file".to_string(), "http".to_string()]).unwrap();
+        let files = list(&dir).unwrap();
+        let sinks: Vec<(u64, Option<&str>)> = files.iter().map(|(n, s, _)| (*n, s.as_deref())).collect();
+        assert_eq!(sinks, vec![(0, None), (1, Some("file")), (2, Some("http"))]);
+        // a reopened directory numbers on after the results left
+        assert_eq!(DeadLetters::at(&dir, &files).next, 3);
+        match StoredResult::decode(&fs::read(&files[2].2).unwrap()).unwrap() {
+            StoredResult::Data { id, chunk, peer_time_us, value, low_confidence, partial, last, .. } => {
+                assert_eq!((id, chunk, peer_time_us, value), (2, 2, Some(5), vec![1, 2, 3]));
+                assert_eq!((low_confidence, partial, last), (true, false, true));
+            },
+            StoredResult::Stop => panic!("stop is decoded")
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn replays_the_results_and_keeps_the_unread() {
+        let root = std::env::temp_dir().join(format!("banshee-deadletter-{}-replay", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (dir, out) = (root.join("dead"), root.join("out"));
+        fs::create_dir_all(&dir).unwrap();
+        let mut d = DeadLetters::at(&dir, &[]);
+        d.put(&result(1), None).unwrap();
+        d.put(&result(2), Some("file")).unwrap();
+        fs::write(dir.join("00000000000000000002.res"), b"broken").unwrap();
+        let cfg = crate::config::Config::from_sources(&[], &[&format!("output.dir={}", out.display()), "output.manifest="]).unwrap();
+        assert_eq!(replay(cfg, dir.to_str().unwrap().to_string()).await, Ok((2, 1)));
+        // the stored ones are removed, the unread one is left for the next attempt
+        let left: Vec<u64> = list(&dir).unwrap().into_iter().map(|(n, _, _)| n).collect();
+        assert_eq!(left, vec![2]);
+        let mut stored: Vec<String> = fs::read_dir(&out).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        stored.sort();
+        assert_eq!(stored.len(), 2, "{:?}", stored);
+        assert!(stored[0].starts_with("1_2_") && stored[1].starts_with("2_2_"), "{:?}", stored);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
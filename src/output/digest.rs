@@ -0,0 +1,216 @@
+//! Расчет контрольной суммы сохраняемых данных в процессе их записи.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::config::HashAlg;
+
+/// Накопитель контрольной суммы выбранного алгоритма
+#[derive(Clone)]
+pub enum Hasher {
+    None,
+    Sha256(Sha256),
+    Sha512(Sha512)
+}
+
+impl Hasher {
+
+    pub fn new(alg: HashAlg) -> Hasher {
+        match alg {
+            HashAlg::None => Hasher::None,
+            HashAlg::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlg::Sha512 => Hasher::Sha512(Sha512::new())
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::None => {},
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data)
+        }
+    }
+
+    /// Возвращает контрольную сумму в шестнадцатеричном виде, если она рассчитывается
+    pub fn finish(self) -> Option<String> {
+        match self {
+            Hasher::None => None,
+            Hasher::Sha256(h) => Some(hex(&h.finalize())),
+            Hasher::Sha512(h) => Some(hex(&h.finalize()))
+        }
+    }
+}
+
+/// Передает данные в нижележащий writer, учитывая в контрольной сумме только фактически записанные байты.
+/// Данные не буферизуются повторно
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Hasher,
+    written: u64
+}
+
+impl<W: Write> HashingWriter<W> {
+
+    pub fn new(inner: W, alg: HashAlg) -> HashingWriter<W> {
+        HashingWriter {
+            inner,
+            hasher: Hasher::new(alg),
+            written: 0
+        }
+    }
+
+    /// Количество записанных байтов
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Нижележащий writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Возвращает нижележащий writer и контрольную сумму всех записанных данных
+    pub fn finish(self) -> (W, Option<String>) {
+        (self.inner, self.hasher.finish())
+    }
+}
+
+/// Состояние записи, к которому ее можно вернуть
+pub struct Mark {
+    hasher: Hasher,
+    written: u64
+}
+
+impl HashingWriter<BufWriter<File>> {
+
+    /// Отмечает текущее состояние записи файла, записываемого с начала
+    pub fn mark(&self) -> Mark {
+        Mark {
+            hasher: self.hasher.clone(),
+            written: self.written
+        }
+    }
+
+    /// Возвращает запись к отмеченному состоянию: отбрасывает данные, записанные после отметки,
+    /// как уже попавшие в файл, так и оставшиеся в буфере, и продолжает запись с места отметки
+    pub fn rewind(&mut self, mark: Mark) -> io::Result<()> {
+        // the buffer holds the tail of the written bytes, the rest is in the file
+        let buffered = self.inner.buffer();
+        let on_disk = self.written - buffered.len() as u64;
+        let keep = buffered[..mark.written.saturating_sub(on_disk) as usize].to_vec();
+        let file = self.inner.get_ref().try_clone()?;
+        let capacity = self.inner.capacity();
+        // the stale buffer is dropped unwritten, unlike the drop of the writer
+        let _ = std::mem::replace(&mut self.inner, BufWriter::with_capacity(capacity, file)).into_parts();
+        if on_disk > mark.written {
+            let file = self.inner.get_mut();
+            file.set_len(mark.written)?;
+            file.seek(SeekFrom::Start(mark.written))?;
+        }
+        self.inner.write_all(&keep)?;
+        self.hasher = mark.hasher;
+        self.written = mark.written;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+    use std::io::Read;
+
+    fn writer(name: &str, capacity: usize) -> (std::path::PathBuf, HashingWriter<BufWriter<File>>) {
+        let path = std::env::temp_dir().join(format!("banshee-digest-{}-{}", std::process::id(), name));
+        let file = OpenOptions::new().create(true).read(true).write(true).truncate(true).open(&path).unwrap();
+        (path, HashingWriter::new(BufWriter::with_capacity(capacity, file), HashAlg::Sha256))
+    }
+
+    fn finish(path: &std::path::Path, w: HashingWriter<BufWriter<File>>) -> (Vec<u8>, Option<String>) {
+        let (mut inner, digest) = w.finish();
+        inner.flush().unwrap();
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        std::fs::remove_file(path).unwrap();
+        (data, digest)
+    }
+
+    fn sha256(data: &[u8]) -> Option<String> {
+        let mut h = Hasher::new(HashAlg::Sha256);
+        h.update(data);
+        h.finish()
+    }
+
+    #[test]
+    fn rewind_drops_buffered_tail() {
+        let (path, mut w) = writer("buffered", 64);
+        w.write_all(b"first").unwrap();
+        let mark = w.mark();
+        w.write_all(b"second").unwrap();
+        w.rewind(mark).unwrap();
+        assert_eq!(w.written(), 5);
+        w.write_all(b"third").unwrap();
+        assert_eq!(finish(&path, w), (b"firstthird".to_vec(), sha256(b"firstthird")));
+    }
+
+    #[test]
+    fn rewind_truncates_flushed_tail() {
+        let (path, mut w) = writer("flushed", 4);
+        w.write_all(b"first").unwrap();
+        let mark = w.mark();
+        w.write_all(b"second and more").unwrap();
+        w.rewind(mark).unwrap();
+        w.write_all(b"third").unwrap();
+        assert_eq!(finish(&path, w), (b"firstthird".to_vec(), sha256(b"firstthird")));
+    }
+
+    #[test]
+    fn rewind_keeps_buffered_head() {
+        let (path, mut w) = writer("head", 8);
+        w.write_all(b"0123456789").unwrap();
+        w.write_all(b"ab").unwrap();
+        let mark = w.mark();
+        w.write_all(b"cd").unwrap();
+        w.rewind(mark).unwrap();
+        assert_eq!(finish(&path, w), (b"0123456789ab".to_vec(), sha256(b"0123456789ab")));
+    }
+
+    #[test]
+    fn the_known_digests() {
+        assert_eq!(sha256(b"abc").unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let mut h = Hasher::new(HashAlg::Sha512);
+        h.update(b"abc");
+        assert!(h.finish().unwrap().starts_with("ddaf35a193617aba"));
+        assert_eq!(Hasher::new(HashAlg::None).finish(), None);
+    }
+
+    #[test]
+    fn hashes_the_bytes_written() {
+        let mut w = HashingWriter::new(Vec::new(), HashAlg::Sha256);
+        w.write_all(b"ab").unwrap();
+        w.write_all(b"c").unwrap();
+        assert_eq!(w.written(), 3);
+        assert_eq!(w.finish(), (b"abc".to_vec(), sha256(b"abc")));
+    }
+}
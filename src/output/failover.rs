@@ -0,0 +1,209 @@
+//! Переключение сохранения результатов на резервный каталог при недоступности основного.
+
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::{debug, error, info};
+
+use crate::config::SharedConfig;
+use crate::stats::SharedStats;
+
+use super::sink::{self, Flags, OutputSink};
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+
+/// Файл, записью которого проверяется доступность основного каталога
+const PROBE_FILE: &str = ".banshee_probe";
+
+/// Сохраняет результаты в основной каталог, а после неудачной записи в него - в резервный.
+/// При обслуживании проверяется доступность основного каталога, и как только в него снова удается писать,
+/// сохранение возвращается в основной каталог. Результаты сеанса, прерванного переключением,
+/// оказываются в разных каталогах
+pub struct FailoverSink {
+    cfg: SharedConfig,
+    primary_dir: String,
+    fallback_dir: String,
+    /// None, пока основной каталог не удалось подготовить
+    primary: Option<Box<dyn OutputSink>>,
+    fallback: Box<dyn OutputSink>,
+    on_fallback: bool,
+    stats: SharedStats
+}
+
+impl FailoverSink {
+
+    pub fn new(cfg: SharedConfig, primary_dir: String, fallback_dir: String, stats: SharedStats) -> io::Result<FailoverSink> {
+        let fallback = sink::build_in(&cfg, &fallback_dir)?;
+        let primary = match sink::build_in(&cfg, &primary_dir) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                error!("output: {} is unavailable: {}, results are stored to {}", primary_dir, e, fallback_dir);
+                None
+            }
+        };
+        let on_fallback = primary.is_none();
+        stats.set_output_fallback(on_fallback);
+        Ok(FailoverSink {
+            cfg,
+            primary_dir,
+            fallback_dir,
+            primary,
+            fallback,
+            on_fallback,
+            stats
+        })
+    }
+
+    fn switch(&mut self, on_fallback: bool) {
+        self.on_fallback = on_fallback;
+        self.stats.set_output_fallback(on_fallback);
+    }
+
+    // the result is stored by `write` to the primary, to the fallback once the primary fails
+    fn store(&mut self, mut write: impl FnMut(&mut dyn OutputSink) -> io::Result<()>) -> io::Result<()> {
+        if !self.on_fallback {
+            if let Some(p) = self.primary.as_mut() {
+                match write(p.as_mut()) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        error!("output: failed to store to {}: {}, switching to {}", self.primary_dir, e, self.fallback_dir);
+                        self.switch(true);
+                    }
+                }
+            }
+        }
+        write(self.fallback.as_mut())
+    }
+
+    // the primary is writable if a probe file can be created there
+    fn probe(&self) -> io::Result<()> {
+        let path = PathBuf::from(&self.primary_dir).join(PROBE_FILE);
+        std::fs::write(&path, b"")?;
+        std::fs::remove_file(&path)
+    }
+}
+
+impl OutputSink for FailoverSink {
+
+    fn write(&mut self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        self.store(|s| s.write(id, chunk, value, flags, stamp))
+    }
+
+    fn write_to(&mut self, sink: &str, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        self.store(|s| s.write_to(sink, id, chunk, value, flags, stamp))
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        if let Some(p) = self.primary.as_mut() {
+            p.sweep(now);
+        }
+        self.fallback.sweep(now);
+        if !self.on_fallback {
+            return;
+        }
+        if self.primary.is_none() {
+            match sink::build_in(&self.cfg, &self.primary_dir) {
+                Ok(s) => self.primary = Some(s),
+                Err(_) => return
+            }
+        }
+        match self.probe() {
+            Ok(()) => {
+                info!("output: {} is available again, switching back from {}", self.primary_dir, self.fallback_dir);
+                self.switch(false);
+            },
+            Err(e) => debug!("output: {} is still unavailable: {}", self.primary_dir, e)
+        }
+    }
+
+    // the primary may be unavailable, it is not switched away from on a failed flush, the next write tells
+    fn flush(&mut self, sync: bool) -> io::Result<()> {
+        let primary = match self.primary.as_mut() {
+            Some(p) => p.flush(sync),
+            None => Ok(())
+        };
+        self.fallback.flush(sync).and(primary)
+    }
+
+    fn close(&mut self) {
+        if let Some(p) = self.primary.as_mut() {
+            p.close();
+        }
+        self.fallback.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use crate::config::{Config, TimestampPrecision, TimestampSource};
+    use crate::stats::Stats;
+
+    fn dirs(name: &str) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("banshee-failover-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        (root.join("primary"), root.join("fallback"))
+    }
+
+    fn failover(primary: &Path, fallback: &Path) -> (FailoverSink, SharedStats) {
+        let cfg = Config::from_sources(&[], &["output.manifest="]).unwrap();
+        let stats = Stats::new(&[]);
+        let s = FailoverSink::new(cfg, primary.display().to_string(), fallback.display().to_string(), stats.clone()).unwrap();
+        (s, stats)
+    }
+
+    fn write(s: &mut FailoverSink, chunk: u32) -> io::Result<()> {
+        let id = SessionId::new("".into(), 1, Default::default());
+        let stamp = Stamp { ns: 1_000_000_000, source: TimestampSource::Local, precision: TimestampPrecision::Ms };
+        s.write(&id, chunk, b"result", Flags::default(), stamp)
+    }
+
+    fn fallback_active(stats: &SharedStats) -> bool {
+        stats.snapshot()["output_fallback"] == true
+    }
+
+    #[test]
+    fn switches_to_the_fallback_and_back() {
+        let (primary, fallback) = dirs("switch");
+        let (mut s, stats) = failover(&primary, &fallback);
+        write(&mut s, 0).unwrap();
+        assert!(primary.join("1_0_1000.bin").exists());
+        assert!(!fallback_active(&stats));
+        std::fs::remove_dir_all(&primary).unwrap();
+        write(&mut s, 1).unwrap();
+        assert!(fallback.join("1_1_1000.bin").exists());
+        assert!(fallback_active(&stats));
+        // the primary is probed on the sweep only
+        std::fs::create_dir_all(&primary).unwrap();
+        write(&mut s, 2).unwrap();
+        assert!(fallback.join("1_2_1000.bin").exists());
+        s.sweep(Instant::now());
+        assert!(!fallback_active(&stats));
+        write(&mut s, 3).unwrap();
+        assert!(primary.join("1_3_1000.bin").exists());
+        std::fs::remove_dir_all(primary.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn starts_on_the_fallback_if_the_primary_is_not_prepared() {
+        let (primary, fallback) = dirs("start");
+        std::fs::write(&primary, b"not a directory").unwrap();
+        let (mut s, stats) = failover(&primary, &fallback);
+        assert!(fallback_active(&stats));
+        write(&mut s, 0).unwrap();
+        assert!(fallback.join("1_0_1000.bin").exists());
+        s.sweep(Instant::now());
+        assert!(fallback_active(&stats));
+        std::fs::remove_file(&primary).unwrap();
+        s.sweep(Instant::now());
+        assert!(!fallback_active(&stats));
+        write(&mut s, 1).unwrap();
+        assert!(primary.join("1_1_1000.bin").exists());
+        std::fs::remove_dir_all(primary.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! Сохранение каждого результата сразу в несколько приемников.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+use serde_json::json;
+
+use crate::config::FanoutPolicy;
+use crate::events;
+use crate::shutdown;
+
+use super::sink::{Failed, Flags, OutputSink};
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+
+/// Наибольшая пауза перед повтором записи
+const MAX_PAUSE: Duration = Duration::from_secs(10);
+
+// the result of `id` is written to the sink `name` by `write`, the failed write is retried up to `retries` times
+fn store(name: &str, retries: u32, delay: Duration, id: &SessionId, chunk: u32, mut write: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries && !shutdown::is_stopping() => {
+                attempt += 1;
+                warn!("output: {} sink failed to store result of {}, retry {}: {}", name, id, attempt, e);
+                events::emit("output_retry", json!({ "sink": name, "id": id.to_string(), "chunk": chunk, "attempt": attempt, "error": e.to_string() }));
+                pause(delay.checked_mul(2u32.saturating_pow(attempt - 1)).map_or(MAX_PAUSE, |d| d.min(MAX_PAUSE)));
+            },
+            Err(e) => {
+                error!("output: {} sink failed to store result of {}: {}", name, id, e);
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Передает каждый результат во все приемники независимо друг от друга: неудачная запись в один приемник
+/// повторяется до `retries` раз и не мешает записи в остальные. Первый повтор следует через `delay`,
+/// каждый следующий - через вдвое большую паузу, но не больше `MAX_PAUSE`, повторы прекращаются с началом остановки приложения.
+/// Итог записи определяется политикой: при `AllMustSucceed` запись неудачна, если не удалась хотя бы в один
+/// приемник (уже записанное в остальные приемники сохраняется), при `BestEffort` - только если не удалась во все.
+/// Любая неудача возвращает ошибку [`Failed`] с именами не сохранивших результат приемников, при `BestEffort`
+/// с признаком сохраненного результата, если его сохранил хоть один приемник
+pub struct FanoutSink {
+    sinks: Vec<(String, Box<dyn OutputSink>)>,
+    policy: FanoutPolicy,
+    retries: u32,
+    delay: Duration
+}
+
+impl FanoutSink {
+
+    pub fn new(sinks: Vec<(String, Box<dyn OutputSink>)>, policy: FanoutPolicy, retries: u32, delay: Duration) -> FanoutSink {
+        FanoutSink {
+            sinks,
+            policy,
+            retries,
+            delay
+        }
+    }
+}
+
+// the sinks are written by the output task, holding its worker for the pause is fine
+fn pause(d: Duration) {
+    if d > Duration::from_secs(0) {
+        tokio::task::block_in_place(|| std::thread::sleep(d));
+    }
+}
+
+impl OutputSink for FanoutSink {
+
+    fn write(&mut self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        let mut failed = Vec::new();
+        for (name, sink) in self.sinks.iter_mut() {
+            if store(name, self.retries, self.delay, id, chunk, || sink.write(id, chunk, value, flags, stamp)).is_err() {
+                failed.push(name.clone());
+            }
+        }
+        if failed.is_empty() {
+            return Ok(());
+        }
+        let stored = self.policy == FanoutPolicy::BestEffort && failed.len() < self.sinks.len();
+        Err(io::Error::other(Failed { sinks: failed, stored }))
+    }
+
+    fn write_to(&mut self, name: &str, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        match self.sinks.iter_mut().find(|(n, _)| n == name) {
+            Some((_, sink)) => store(name, self.retries, self.delay, id, chunk, || sink.write(id, chunk, value, flags, stamp)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("no {} sink in [output] mode", name)))
+        }
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        for (_, sink) in self.sinks.iter_mut() {
+            sink.sweep(now);
+        }
+    }
+
+    // every sink is flushed even if another one fails
+    fn flush(&mut self, sync: bool) -> io::Result<()> {
+        let mut result = Ok(());
+        for (name, sink) in self.sinks.iter_mut() {
+            if let Err(e) = sink.flush(sync) {
+                error!("output: {} sink failed to flush: {}", name, e);
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn close(&mut self) {
+        for (_, sink) in self.sinks.iter_mut() {
+            sink.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::config::{TimestampPrecision, TimestampSource};
+    use crate::output::sink;
+
+    // the sink failing its first `failures` writes, the writes are counted in `writes`
+    struct Flaky {
+        failures: u32,
+        writes: Arc<Mutex<u32>>
+    }
+
+    impl OutputSink for Flaky {
+        fn write(&mut self, _id: &SessionId, _chunk: u32, _value: &[u8], _flags: Flags, _stamp: Stamp) -> io::Result<()> {
+            *self.writes.lock().unwrap() += 1;
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::other("flaky"));
+            }
+            Ok(())
+        }
+    }
+
+    type Named = (String, Box<dyn OutputSink>);
+
+    fn flaky(name: &str, failures: u32) -> (Named, Arc<Mutex<u32>>) {
+        let writes = Arc::new(Mutex::new(0));
+        ((name.to_string(), Box::new(Flaky { failures, writes: writes.clone() })), writes)
+    }
+
+    fn fanout(sinks: Vec<(u32, &str)>, policy: FanoutPolicy, retries: u32) -> (FanoutSink, Vec<Arc<Mutex<u32>>>) {
+        let (sinks, writes) = sinks.into_iter().map(|(f, n)| flaky(n, f)).unzip();
+        (FanoutSink::new(sinks, policy, retries, Duration::from_secs(0)), writes)
+    }
+
+    fn write(s: &mut FanoutSink, to: Option<&str>) -> io::Result<()> {
+        let id = SessionId::new("".into(), 1, Default::default());
+        let stamp = Stamp { ns: 0, source: TimestampSource::Local, precision: TimestampPrecision::Ms };
+        match to {
+            Some(n) => s.write_to(n, &id, 0, b"result", Flags::default(), stamp),
+            None => s.write(&id, 0, b"result", Flags::default(), stamp)
+        }
+    }
+
+    fn count(writes: &[Arc<Mutex<u32>>]) -> Vec<u32> {
+        writes.iter().map(|w| *w.lock().unwrap()).collect()
+    }
+
+    #[test]
+    fn retries_the_failed_sink_only() {
+        let (mut s, writes) = fanout(vec![(0, "file"), (2, "session")], FanoutPolicy::AllMustSucceed, 2);
+        write(&mut s, None).unwrap();
+        assert_eq!(count(&writes), vec![1, 3]);
+    }
+
+    #[test]
+    fn all_must_succeed_fails_with_the_failed_sinks() {
+        let (mut s, writes) = fanout(vec![(0, "file"), (5, "session")], FanoutPolicy::AllMustSucceed, 1);
+        let e = write(&mut s, None).unwrap_err();
+        let f = sink::failed(&e).unwrap();
+        assert_eq!(f.sinks, vec!["session"]);
+        assert!(!f.stored);
+        assert_eq!(count(&writes), vec![1, 2]);
+    }
+
+    #[test]
+    fn best_effort_is_stored_by_one_sink() {
+        let (mut s, _) = fanout(vec![(5, "file"), (0, "session")], FanoutPolicy::BestEffort, 0);
+        let e = write(&mut s, None).unwrap_err();
+        let f = sink::failed(&e).unwrap();
+        assert_eq!(f.sinks, vec!["file"]);
+        assert!(f.stored);
+    }
+
+    #[test]
+    fn best_effort_fails_in_all_sinks() {
+        let (mut s, _) = fanout(vec![(5, "file"), (5, "session")], FanoutPolicy::BestEffort, 0);
+        let e = write(&mut s, None).unwrap_err();
+        let f = sink::failed(&e).unwrap();
+        assert_eq!(f.sinks, vec!["file", "session"]);
+        assert!(!f.stored);
+    }
+
+    #[test]
+    fn write_to_stores_in_the_named_sink() {
+        let (mut s, writes) = fanout(vec![(0, "file"), (1, "session")], FanoutPolicy::AllMustSucceed, 1);
+        write(&mut s, Some("session")).unwrap();
+        assert_eq!(count(&writes), vec![0, 2]);
+        let e = write(&mut s, Some("fifo")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+}
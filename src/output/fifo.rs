@@ -0,0 +1,213 @@
+//! Передача результатов внешнему процессу через именованный канал (FIFO).
+//!
+//! Каждый результат записывается в канал одной записью: заголовок в порядке байтов little endian
+//! (`id` u32, `chunk` u32, `time_ms` u64, флаги u8: 1 - низкая уверенность, 2 - последний результат сеанса,
+//! 4 - промежуточный результат, длина результата u32), за которым следуют байты результата. Префикс идентификатора сеанса в запись не входит
+//!
+//! Канал открывается при первой записи. Если читатель не подключен, по `[output] fifo_no_reader = block`
+//! запись ожидает его не дольше `[output] fifo_wait`, по `skip` результат сразу считается не сохраненным.
+//! Если читатель отключился, канал открывается заново при следующей записи, ожидая нового читателя так же
+
+use std::fs::{File, OpenOptions};
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::config::NoReader;
+
+use super::sink::{Flags, OutputSink};
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+
+/// Пауза между попытками открыть канал в ожидании читателя
+const REOPEN_PAUSE: Duration = Duration::from_millis(100);
+
+/// Размер заголовка записи результата
+const HEADER: usize = 21;
+
+/// Записывает результаты в именованный канал `path`
+pub struct FifoSink {
+    path: String,
+    no_reader: NoReader,
+    wait: Duration,
+    /// None, пока читатель не подключен
+    pipe: Option<File>
+}
+
+impl FifoSink {
+
+    /// Создает канал `path`, если его нет. Существующий файл другого типа - ошибка
+    pub fn new(path: &str, no_reader: NoReader, wait: Duration) -> io::Result<FifoSink> {
+        match std::fs::metadata(path) {
+            Ok(m) if m.file_type().is_fifo() => {},
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is not a named pipe", path))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => mkfifo(path)?,
+            Err(e) => return Err(e)
+        }
+        info!("output: results are written to pipe {}", path);
+        Ok(FifoSink {
+            path: path.to_string(),
+            no_reader,
+            wait,
+            pipe: None
+        })
+    }
+
+    // the pipe is open once a reader is there, waiting for it as configured
+    fn connect(&mut self) -> io::Result<&mut File> {
+        if self.pipe.is_none() {
+            let pipe = match self.no_reader {
+                NoReader::Skip => open(&self.path)?,
+                // the output task is the only writer, holding its worker for a while is fine
+                NoReader::Block => tokio::task::block_in_place(|| wait_open(&self.path, self.wait))?
+            };
+            match pipe {
+                Some(p) => {
+                    info!("output: reader of pipe {} is connected", self.path);
+                    self.pipe = Some(p);
+                },
+                None => return Err(io::Error::new(io::ErrorKind::NotConnected, format!("no reader of pipe {}", self.path)))
+            }
+        }
+        Ok(self.pipe.as_mut().expect("pipe is open"))
+    }
+}
+
+impl OutputSink for FifoSink {
+
+    fn write(&mut self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        let mut record = Vec::with_capacity(HEADER + value.len());
+        record.extend_from_slice(&id.id.to_le_bytes());
+        record.extend_from_slice(&chunk.to_le_bytes());
+        record.extend_from_slice(&stamp.ms().to_le_bytes());
+        record.push(flags.low_confidence as u8 | (flags.last as u8) << 1 | (flags.partial as u8) << 2);
+        record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        record.extend_from_slice(value);
+
+        // a reader gone between the results is noticed on write, the record goes to the next one
+        for attempt in 0..2 {
+            let pipe = self.connect()?;
+            match pipe.write_all(&record) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe && attempt == 0 => {
+                    warn!("output: reader of pipe {} is gone, waiting for another one", self.path);
+                    self.pipe = None;
+                },
+                Err(e) => {
+                    self.pipe = None;
+                    return Err(e);
+                }
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, format!("reader of pipe {} is gone", self.path)))
+    }
+
+    fn close(&mut self) {
+        self.pipe = None;
+    }
+}
+
+fn mkfifo(path: &str) -> io::Result<()> {
+    let c = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::mkfifo(c.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// opens the pipe for writing without waiting, None - no reader yet
+fn open(path: &str) -> io::Result<Option<File>> {
+    let file = match OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(path) {
+        Ok(f) => f,
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+        Err(e) => return Err(e)
+    };
+    // once open, a write waits for the reader to catch up instead of failing
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(file))
+}
+
+fn wait_open(path: &str, wait: Duration) -> io::Result<Option<File>> {
+    let until = Instant::now() + wait;
+    loop {
+        if let Some(f) = open(path)? {
+            return Ok(Some(f));
+        }
+        if Instant::now() >= until {
+            return Ok(None);
+        }
+        std::thread::sleep(REOPEN_PAUSE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use crate::config::{TimestampPrecision, TimestampSource};
+
+    fn path(name: &str) -> String {
+        let p = std::env::temp_dir().join(format!("banshee-fifo-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&p);
+        p.to_str().unwrap().to_string()
+    }
+
+    fn stamp() -> Stamp {
+        Stamp { ns: 1_000_000_000, source: TimestampSource::Local, precision: TimestampPrecision::Ms }
+    }
+
+    fn id() -> SessionId {
+        SessionId::new("".into(), 7, Default::default())
+    }
+
+    // a reader that doesn't wait for the writer to open
+    fn reader(path: &str) -> File {
+        OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path).unwrap()
+    }
+
+    #[test]
+    fn another_file_is_not_a_pipe() {
+        let p = path("file");
+        std::fs::write(&p, b"").unwrap();
+        assert_eq!(FifoSink::new(&p, NoReader::Skip, Duration::from_secs(0)).err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+        std::fs::remove_file(&p).unwrap();
+    }
+
+    #[test]
+    fn writes_the_record_to_the_reader() {
+        let p = path("record");
+        let mut s = FifoSink::new(&p, NoReader::Skip, Duration::from_secs(0)).unwrap();
+        assert!(std::fs::metadata(&p).unwrap().file_type().is_fifo());
+        let mut r = reader(&p);
+        s.write(&id(), 2, b"abc", Flags { low_confidence: true, partial: false, last: true }, stamp()).unwrap();
+        let mut record = vec![0; HEADER + 3];
+        r.read_exact(&mut record).unwrap();
+        let mut expected = vec![7, 0, 0, 0, 2, 0, 0, 0, 0xe8, 0x03, 0, 0, 0, 0, 0, 0, 3, 3, 0, 0, 0];
+        expected.extend_from_slice(b"abc");
+        assert_eq!(record, expected);
+        std::fs::remove_file(&p).unwrap();
+    }
+
+    #[test]
+    fn fails_without_the_reader() {
+        let p = path("skip");
+        let mut s = FifoSink::new(&p, NoReader::Skip, Duration::from_secs(0)).unwrap();
+        assert_eq!(s.write(&id(), 0, b"a", Flags::default(), stamp()).err().unwrap().kind(), io::ErrorKind::NotConnected);
+        let r = reader(&p);
+        s.write(&id(), 0, b"a", Flags::default(), stamp()).unwrap();
+        // the reader gone, the pipe is opened again for the next one and there is none
+        drop(r);
+        assert_eq!(s.write(&id(), 1, b"b", Flags::default(), stamp()).err().unwrap().kind(), io::ErrorKind::NotConnected);
+        std::fs::remove_file(&p).unwrap();
+    }
+}
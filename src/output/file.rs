@@ -0,0 +1,209 @@
+//! Сохранение каждого результата в отдельный файл.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use super::digest::HashingWriter;
+use super::manifest::{Entry, Manifest};
+use super::sink::{Flags, OutputSink};
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+use super::unique;
+
+/// Записывает каждый результат в отдельный файл `<id>_<chunk>_<unix time ms>.bin` в каталоге результатов,
+/// где `<id>` - идентификатор сеанса с префиксом системы сопряжения, если он задан,
+/// время берется из метки результата. Промежуточные результаты части сеанса нумеруются по порядку с 1,
+/// имя файла промежуточного результата оканчивается на `.partial<номер>.bin`
+/// и регистрирует его в журнале сохраненных файлов.
+/// Результат больше `[output] max_result_bytes` сохраняется частями в файлы `<имя>_part<номер>.bin`,
+/// пронумерованные с 0 по порядку, каждая часть регистрируется в журнале с номером и количеством частей.
+/// Файл с уже занятым именем получает номер `.dup<n>`, см. [`unique`]
+pub struct FileSink {
+    dir: PathBuf,
+    /// Наибольший размер файла, 0 - без ограничения
+    max_bytes: usize,
+    manifest: Manifest,
+    /// Количество промежуточных результатов частей сеансов, ожидающих окончательного результата
+    partials: HashMap<(SessionId, u32), u32>
+}
+
+impl FileSink {
+
+    pub fn new(dir: &str, max_bytes: usize, manifest: Manifest) -> FileSink {
+        FileSink {
+            dir: PathBuf::from(dir),
+            max_bytes,
+            manifest,
+            partials: HashMap::new()
+        }
+    }
+
+    // stores the file `<stem>.bin` of a result or its part under a free name, returns the name, its size and digest
+    fn store(&self, stem: &str, value: &[u8]) -> io::Result<(String, u64, Option<String>)> {
+        let (name, file) = unique::create(&self.dir, stem, "bin")?;
+        let stored = self.manifest.permit(&file).and_then(|_| {
+            let mut w = HashingWriter::new(file, self.manifest.alg());
+            w.write_all(value)?;
+            Ok(w)
+        });
+        match stored {
+            Ok(w) => {
+                let bytes = w.written();
+                let (_, digest) = w.finish();
+                Ok((name, bytes, digest))
+            },
+            Err(e) => {
+                self.manifest.discard(&name);
+                Err(e)
+            }
+        }
+    }
+
+    // registers the stored file, the file unregistered is removed
+    fn record(&mut self, e: Entry<'_>) -> io::Result<()> {
+        let name = e.name;
+        self.manifest.record(e).inspect_err(|_| self.manifest.discard(name))
+    }
+}
+
+impl OutputSink for FileSink {
+
+    fn write(&mut self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        // the partial results of a chunk come within a millisecond or so, the time alone doesn't tell them apart
+        let stem = if flags.partial {
+            let n = self.partials.entry((id.clone(), chunk)).or_insert(0);
+            *n += 1;
+            format!("{}_{}_{}.partial{}", id, chunk, stamp.value(), n)
+        } else {
+            self.partials.remove(&(id.clone(), chunk));
+            format!("{}_{}_{}", id, chunk, stamp.value())
+        };
+        if self.max_bytes == 0 || value.len() <= self.max_bytes {
+            let (name, bytes, digest) = self.store(&stem, value)?;
+            return self.record(Entry { name: &name, id, chunk: Some(chunk), bytes, digest, low_confidence: flags.low_confidence,
+                partial: flags.partial, part: None, stamp });
+        }
+        // a failed part fails the result, the parts are all stored before any is registered, so the parts of a failed
+        // attempt are removed and the retry doesn't leave them doubled; only a failed registration keeps those registered
+        let parts = value.len().div_ceil(self.max_bytes);
+        let mut stored = Vec::with_capacity(parts);
+        for (i, value) in value.chunks(self.max_bytes).enumerate() {
+            match self.store(&format!("{}_part{}", stem, i), value) {
+                Ok(s) => stored.push(s),
+                Err(e) => {
+                    stored.iter().for_each(|(name, _, _)| self.manifest.discard(name));
+                    return Err(e);
+                }
+            }
+        }
+        let mut stored = stored.into_iter().enumerate();
+        while let Some((i, (name, bytes, digest))) = stored.next() {
+            if let Err(e) = self.record(Entry { name: &name, id, chunk: Some(chunk), bytes, digest, low_confidence: flags.low_confidence,
+                partial: flags.partial, part: Some((i, parts)), stamp }) {
+                stored.for_each(|(_, (name, _, _))| self.manifest.discard(&name));
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // the result files are complete once written, only the manifest is left to sync
+    fn flush(&mut self, sync: bool) -> io::Result<()> {
+        self.manifest.flush(sync)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{Config, TimestampPrecision, TimestampSource};
+
+    fn sink(name: &str, sets: &[&str], max_bytes: usize) -> (PathBuf, FileSink) {
+        let dir = std::env::temp_dir().join(format!("banshee-file-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = Manifest::open(&Config::from_sources(&[], sets).unwrap(), &dir).unwrap();
+        (dir.clone(), FileSink::new(dir.to_str().unwrap(), max_bytes, manifest))
+    }
+
+    fn stamp() -> Stamp {
+        Stamp { ns: 1_000_000_000, source: TimestampSource::Local, precision: TimestampPrecision::Ms }
+    }
+
+    fn manifest(dir: &std::path::Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(dir.join("manifest.jsonl")).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn records_the_digest_of_the_file() {
+        let (dir, mut s) = sink("digest", &[], 0);
+        let id = SessionId::new("".into(), 7, Default::default());
+        s.write(&id, 2, b"abc", Flags::default(), stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("7_2_1000.bin")).unwrap(), b"abc");
+        let lines = manifest(&dir);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["file"], "7_2_1000.bin");
+        assert_eq!(lines[0]["sha256"], "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_the_file_by_the_prefixed_id() {
+        let (dir, mut s) = sink("prefix", &["output.manifest="], 0);
+        s.write(&SessionId::new("site-a".into(), 7, Default::default()), 2, b"abc", Flags::default(), stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("site-a-7_2_1000.bin")).unwrap(), b"abc");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbers_the_partial_results_of_the_chunk() {
+        let (dir, mut s) = sink("partial", &[], 0);
+        let id = SessionId::new("".into(), 7, Default::default());
+        let partial = Flags { partial: true, ..Flags::default() };
+        s.write(&id, 2, b"a", partial, stamp()).unwrap();
+        s.write(&id, 2, b"ab", partial, stamp()).unwrap();
+        s.write(&id, 2, b"abc", Flags::default(), stamp()).unwrap();
+        // the next partial results of the chunk are numbered anew
+        s.write(&id, 2, b"x", partial, stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("7_2_1000.partial1.bin")).unwrap(), b"a");
+        assert_eq!(std::fs::read(dir.join("7_2_1000.partial2.bin")).unwrap(), b"ab");
+        assert_eq!(std::fs::read(dir.join("7_2_1000.bin")).unwrap(), b"abc");
+        assert_eq!(std::fs::read(dir.join("7_2_1000.partial1.dup1.bin")).unwrap(), b"x");
+        let partial: Vec<bool> = manifest(&dir).iter().map(|l| l["partial"] == true).collect();
+        assert_eq!(partial, vec![true, true, false, true]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sets_the_file_mode_exactly() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, mut s) = sink("mode", &["output.file_mode=0604", "output.hash_sidecar=true"], 0);
+        s.write(&SessionId::new("".into(), 7, Default::default()), 2, b"abc", Flags::default(), stamp()).unwrap();
+        let mode = |name: &str| std::fs::metadata(dir.join(name)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode("7_2_1000.bin"), 0o604);
+        assert_eq!(mode("7_2_1000.bin.sha256"), 0o604);
+        assert_eq!(mode("manifest.jsonl"), 0o604);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stores_the_large_result_in_parts() {
+        let (dir, mut s) = sink("parts", &[], 4);
+        let id = SessionId::new("".into(), 7, Default::default());
+        s.write(&id, 2, b"abcdefghij", Flags::default(), stamp()).unwrap();
+        s.write(&id, 3, b"abcd", Flags::default(), stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("7_2_1000_part0.bin")).unwrap(), b"abcd");
+        assert_eq!(std::fs::read(dir.join("7_2_1000_part1.bin")).unwrap(), b"efgh");
+        assert_eq!(std::fs::read(dir.join("7_2_1000_part2.bin")).unwrap(), b"ij");
+        // the result fitting the limit stays whole
+        assert_eq!(std::fs::read(dir.join("7_3_1000.bin")).unwrap(), b"abcd");
+        let lines = manifest(&dir);
+        let parts: Vec<(serde_json::Value, serde_json::Value)> = lines.iter().map(|l| (l["part"].clone(), l["parts"].clone())).collect();
+        assert_eq!(parts, vec![(0.into(), 3.into()), (1.into(), 3.into()), (2.into(), 3.into()), (serde_json::Value::Null, serde_json::Value::Null)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
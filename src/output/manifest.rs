@@ -0,0 +1,241 @@
+//! Журнал сохраненных файлов и файлы контрольных сумм.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::config::{HashAlg, SharedConfig};
+
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+use super::SCHEMA_VERSION;
+
+/// Сведения об окончательно сохраненном файле
+pub struct Entry<'a> {
+    /// Имя файла в каталоге результатов
+    pub name: &'a str,
+    /// Идентификатор сеанса
+    pub id: &'a SessionId,
+    /// Часть сеанса, None - файл содержит результаты всего сеанса
+    pub chunk: Option<u32>,
+    pub bytes: u64,
+    pub digest: Option<String>,
+    /// Файл содержит результаты с уверенностью ниже порога
+    pub low_confidence: bool,
+    /// Файл содержит промежуточный результат
+    pub partial: bool,
+    /// Номер части и количество частей результата, сохраненного частями, None - результат в одном файле
+    pub part: Option<(usize, usize)>,
+    /// Момент, к которому относятся результаты в файле
+    pub stamp: Stamp
+}
+
+/// Регистрирует каждый окончательно сохраненный файл: дописывает строку JSON в журнал `[output] manifest`
+/// и, если включено, сохраняет контрольную сумму рядом с файлом. Строка журнала несет версию формата
+/// [`SCHEMA_VERSION`](super::SCHEMA_VERSION), файл контрольной суммы остается в формате sha256sum
+pub struct Manifest {
+    dir: PathBuf,
+    file: Option<File>,
+    alg: HashAlg,
+    sidecar: bool,
+    /// Имена сведений о сеансе, записываемых в строку журнала
+    metadata: Vec<String>,
+    /// Права создаваемых файлов `[output] file_mode`
+    mode: Option<u32>
+}
+
+impl Manifest {
+
+    pub fn open(cfg: &SharedConfig, dir: &Path) -> io::Result<Manifest> {
+        let name = cfg.manifest();
+        let mode = cfg.output_file_mode();
+        let file = if name.is_empty() {
+            None
+        } else {
+            let file = OpenOptions::new().create(true).append(true).open(dir.join(name))?;
+            set_mode(&file, mode)?;
+            Some(file)
+        };
+        Ok(Manifest {
+            dir: dir.to_path_buf(),
+            file,
+            alg: cfg.hash(),
+            sidecar: cfg.hash_sidecar(),
+            metadata: cfg.manifest_metadata(),
+            mode
+        })
+    }
+
+    /// Устанавливает созданному приемником файлу права `[output] file_mode`, если они заданы.
+    /// Права устанавливаются до записи в файл, потребитель не застанет файл с другими правами
+    pub fn permit(&self, file: &File) -> io::Result<()> {
+        set_mode(file, self.mode)
+    }
+
+    /// Алгоритм контрольной суммы, которую должны рассчитывать приемники при записи
+    pub fn alg(&self) -> HashAlg {
+        self.alg
+    }
+
+    /// Дожидается записи зарегистрированных строк журнала на носитель, `sync` = false - ничего не делает,
+    /// строки журнала не буферизуются
+    pub fn flush(&mut self, sync: bool) -> io::Result<()> {
+        match self.file.as_ref() {
+            Some(f) if sync => f.sync_data(),
+            _ => Ok(())
+        }
+    }
+
+    /// Регистрирует сохраненный файл
+    pub fn record(&mut self, e: Entry<'_>) -> io::Result<()> {
+        if let (true, Some(d)) = (self.sidecar, e.digest.as_ref()) {
+            let mut side = File::create(self.dir.join(format!("{}.{}", e.name, self.alg)))?;
+            set_mode(&side, self.mode)?;
+            writeln!(side, "{}  {}", d, e.name)?;
+        }
+        if let Some(f) = self.file.as_mut() {
+            let mut entry = json!({
+                "schema_version": SCHEMA_VERSION,
+                "file": e.name,
+                "id": e.id.id,
+                "bytes": e.bytes,
+                "time_ms": e.stamp.ms(),
+                "time_source": e.stamp.source.to_string()
+            });
+            if let Some(field) = e.stamp.field() {
+                entry[field] = json!(e.stamp.value());
+            }
+            if e.id.is_prefixed() {
+                entry["session"] = json!(e.id.to_string());
+            }
+            if let Some(c) = e.chunk {
+                entry["chunk"] = json!(c);
+            }
+            if let Some(d) = e.digest {
+                entry[self.alg.to_string()] = json!(d);
+            }
+            if e.low_confidence {
+                entry["low_confidence"] = json!(true);
+            }
+            if e.partial {
+                entry["partial"] = json!(true);
+            }
+            // the result is the parts joined in order of the numbers
+            if let Some((part, parts)) = e.part {
+                entry["part"] = json!(part);
+                entry["parts"] = json!(parts);
+            }
+            // the values are passed as they are, a session may lack some of them (no audio, no such peer)
+            let described = &e.id.metadata;
+            let metadata: serde_json::Map<String, serde_json::Value> = self.metadata.iter()
+                .filter_map(|k| described.get(k).map(|v| (k.clone(), json!(v))))
+                .collect();
+            if !metadata.is_empty() {
+                entry["metadata"] = json!(metadata);
+            }
+            // the line goes in one write, a failed one leaves no part of it to be doubled by a retry
+            f.write_all(format!("{}\n", entry).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Удаляет файл, который не удалось зарегистрировать, вместе с его файлом контрольной суммы
+    pub fn discard(&self, name: &str) {
+        let _ = std::fs::remove_file(self.dir.join(name));
+        let _ = std::fs::remove_file(self.dir.join(format!("{}.{}", name, self.alg)));
+    }
+}
+
+// the permissions are set exactly, the umask of the process doesn't apply to them
+#[cfg(unix)]
+fn set_mode(file: &File, mode: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match mode {
+        Some(m) => file.set_permissions(std::fs::Permissions::from_mode(m)),
+        None => Ok(())
+    }
+}
+
+// the unix permissions don't apply
+#[cfg(not(unix))]
+fn set_mode(_file: &File, _mode: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{Config, TimestampPrecision, TimestampSource};
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("banshee-manifest-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry<'a>(name: &'a str, id: &'a SessionId, digest: Option<String>) -> Entry<'a> {
+        Entry { name, id, chunk: Some(2), bytes: 3, digest, low_confidence: false, partial: false, part: None,
+            stamp: Stamp { ns: 1_000_000, source: TimestampSource::Local, precision: TimestampPrecision::Ms } }
+    }
+
+    fn lines(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn records_a_line_per_file() {
+        let dir = dir("lines");
+        let cfg = Config::from_sources(&[], &[]).unwrap();
+        let mut m = Manifest::open(&cfg, &dir).unwrap();
+        let id = SessionId::new("".into(), 7, Default::default());
+        m.record(entry("a.bin", &id, Some("00ff".to_string()))).unwrap();
+        m.record(entry("b.bin", &id, None)).unwrap();
+        let lines = lines(&dir.join("manifest.jsonl"));
+        assert_eq!(lines.len(), 2);
+        assert_eq!((&lines[0]["file"], &lines[0]["id"], &lines[0]["chunk"]), (&json!("a.bin"), &json!(7), &json!(2)));
+        assert_eq!((&lines[0]["bytes"], &lines[0]["sha256"]), (&json!(3), &json!("00ff")));
+        assert_eq!(lines[0]["schema_version"], json!(SCHEMA_VERSION));
+        assert!(lines[1].get("sha256").is_none());
+        assert!(!dir.join("a.bin.sha256").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_the_sidecar() {
+        let dir = dir("sidecar");
+        let cfg = Config::from_sources(&[], &["output.manifest=", "output.hash_sidecar=true"]).unwrap();
+        let mut m = Manifest::open(&cfg, &dir).unwrap();
+        let id = SessionId::new("".into(), 7, Default::default());
+        m.record(entry("a.bin", &id, Some("00ff".to_string()))).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("a.bin.sha256")).unwrap(), "00ff  a.bin\n");
+        assert!(!dir.join("manifest.jsonl").exists());
+        m.discard("a.bin");
+        assert!(!dir.join("a.bin.sha256").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_the_configured_metadata() {
+        let dir = dir("metadata");
+        let cfg = Config::from_sources(&[], &["output.metadata=source,rate"]).unwrap();
+        let mut m = Manifest::open(&cfg, &dir).unwrap();
+        let described: crate::data::Metadata = [("source", "10.0.0.1:12000"), ("channels", "1")].iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let id = SessionId::new("".into(), 7, std::sync::Arc::new(described));
+        m.record(entry("a.bin", &id, None)).unwrap();
+        let bare = SessionId::new("".into(), 8, Default::default());
+        m.record(entry("b.bin", &bare, None)).unwrap();
+        let lines = lines(&dir.join("manifest.jsonl"));
+        // the session lacking a value has none of it, not an empty one
+        assert_eq!(lines[0]["metadata"], json!({ "source": "10.0.0.1:12000" }));
+        assert!(lines[1].get("metadata").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(Config::from_sources(&[], &["output.metadata=caller"]).err().unwrap().contains("output.metadata"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Файлы отметок начала и окончания работы для внешних средств пакетной обработки.
+
+use std::fs;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::SharedConfig;
+use crate::http::VERSION;
+use crate::shutdown;
+use crate::stats::SharedStats;
+
+use super::SCHEMA_VERSION;
+
+use log::{error, info};
+use serde_json::{json, Value};
+
+/// Отметки работы: при открытии каталогов хранения записывается файл `[output] run_start_marker`,
+/// при корректном завершении - `run_end_marker` с итогами работы и признаком ее окончания. Файлы содержат
+/// объект JSON и появляются целиком: сначала записывается временный файл `<path>.tmp`, затем переименовывается.
+/// Оставшаяся от предыдущего запуска отметка окончания удаляется при начале работы,
+/// поэтому ее наличие означает, что текущий запуск окончен
+pub struct Markers {
+    end: String,
+    started_ms: u64
+}
+
+impl Markers {
+
+    /// Записывает отметку начала работы, если она задана
+    pub fn start(cfg: &SharedConfig) -> Markers {
+        let (start, end) = (cfg.run_start_marker(), cfg.run_end_marker());
+        let started_ms = now_ms();
+        if !end.is_empty() {
+            match fs::remove_file(&end) {
+                Ok(()) => info!("output: run end marker {} of the previous run is removed", end),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => error!("output: failed to remove run end marker {}: {}", end, e)
+            }
+        }
+        if !start.is_empty() {
+            let marker = json!({
+                "schema_version": SCHEMA_VERSION,
+                "version": VERSION,
+                "pid": std::process::id(),
+                "started_ms": started_ms
+            });
+            write(&start, &marker);
+        }
+        Markers { end, started_ms }
+    }
+
+    /// Записывает отметку окончания работы с итогами по результатам, полученным и сохраненным output,
+    /// и признаком окончания: `ok` или `failed` при остановке по требованию подсистемы с его причиной
+    pub fn end(&self, stats: &SharedStats) {
+        if self.end.is_empty() {
+            return;
+        }
+        let (received, stored, dropped) = stats.output.totals();
+        let mut marker = json!({
+            "schema_version": SCHEMA_VERSION,
+            "version": VERSION,
+            "pid": std::process::id(),
+            "started_ms": self.started_ms,
+            "ended_ms": now_ms(),
+            "received": received,
+            "stored": stored,
+            "dropped": dropped,
+            "status": "ok"
+        });
+        if let Some(reason) = shutdown::reason() {
+            marker["status"] = json!("failed");
+            marker["reason"] = json!(reason);
+        }
+        write(&self.end, &marker);
+    }
+}
+
+// the marker appears complete or not at all
+fn write(path: &str, marker: &Value) {
+    let tmp = format!("{}.tmp", path);
+    match fs::write(&tmp, format!("{}\n", marker)).and_then(|_| fs::rename(&tmp, path)) {
+        Ok(()) => info!("output: run marker {} is written", path),
+        Err(e) => error!("output: failed to write run marker {}: {}", path, e)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+
+    fn read(path: &std::path::Path) -> Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn marks_the_start_and_the_end_of_the_run() {
+        let dir = std::env::temp_dir().join(format!("banshee-marker-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (start, end) = (dir.join("start.json"), dir.join("end.json"));
+        fs::write(&end, "{}").unwrap();
+        let cfg = Config::from_sources(&[], &[&format!("output.run_start_marker={}", start.display()),
+            &format!("output.run_end_marker={}", end.display())]).unwrap();
+        let markers = Markers::start(&cfg);
+        // the marker of the previous run doesn't tell the current one is over
+        assert!(!end.exists());
+        let s = read(&start);
+        assert_eq!((&s["pid"], &s["started_ms"]), (&json!(std::process::id()), &json!(markers.started_ms)));
+        let stats = Stats::new(&[]);
+        stats.output.add_received(3);
+        stats.output.add_sent(2);
+        stats.output.dropped();
+        markers.end(&stats);
+        let e = read(&end);
+        assert_eq!((&e["received"], &e["stored"], &e["dropped"], &e["status"]), (&json!(3), &json!(2), &json!(1), &json!("ok")));
+        assert!(e["ended_ms"].as_u64().unwrap() >= markers.started_ms);
+        assert!(!dir.join("end.json.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! Направление результатов в отдельные приемники по их признакам.
+
+use std::io;
+use std::time::Instant;
+
+use log::error;
+
+use crate::config::RouteMatch;
+
+use super::sink::{Flags, OutputSink};
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+
+/// Передает результат в приемник первого маршрута, признак которого есть у результата, а не подошедший
+/// ни одному маршруту - в общий приемник `[output] mode`. Каждый результат сохраняет ровно один приемник
+pub struct RouteSink {
+    routes: Vec<(RouteMatch, Box<dyn OutputSink>)>,
+    default: Box<dyn OutputSink>
+}
+
+impl RouteSink {
+
+    pub fn new(routes: Vec<(RouteMatch, Box<dyn OutputSink>)>, default: Box<dyn OutputSink>) -> RouteSink {
+        RouteSink {
+            routes,
+            default
+        }
+    }
+}
+
+impl RouteSink {
+
+    // the sink of the first route matching the result, the default one if none matches
+    fn route(&mut self, id: &SessionId, flags: Flags) -> &mut Box<dyn OutputSink> {
+        match self.routes.iter_mut().find(|(m, _)| matches(m, id, flags)) {
+            Some((_, sink)) => sink,
+            None => &mut self.default
+        }
+    }
+}
+
+/// Есть ли у результата сеанса `id` с признаками `flags` признак маршрута
+fn matches(m: &RouteMatch, id: &SessionId, flags: Flags) -> bool {
+    match m {
+        RouteMatch::LowConfidence => flags.low_confidence,
+        RouteMatch::Partial => flags.partial,
+        RouteMatch::Prefix(p) => *id.prefix == **p,
+        RouteMatch::Metadata(k, v) => id.metadata.get(k) == Some(v)
+    }
+}
+
+impl OutputSink for RouteSink {
+
+    fn write(&mut self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        self.route(id, flags).write(id, chunk, value, flags, stamp)
+    }
+
+    fn write_to(&mut self, sink: &str, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        self.route(id, flags).write_to(sink, id, chunk, value, flags, stamp)
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        for (_, sink) in self.routes.iter_mut() {
+            sink.sweep(now);
+        }
+        self.default.sweep(now);
+    }
+
+    // every sink is flushed even if another one fails
+    fn flush(&mut self, sync: bool) -> io::Result<()> {
+        let mut result = self.default.flush(sync);
+        for (m, sink) in self.routes.iter_mut() {
+            if let Err(e) = sink.flush(sync) {
+                error!("output: sink of route {} failed to flush: {}", m, e);
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn close(&mut self) {
+        for (_, sink) in self.routes.iter_mut() {
+            sink.close();
+        }
+        self.default.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::config::{Route, TimestampPrecision, TimestampSource};
+
+    // the sinks by name and the ids of the sessions they store, in order
+    type Log = Arc<Mutex<Vec<(&'static str, u32)>>>;
+
+    // the sink noting the ids of the sessions it stores under its name
+    struct Named {
+        name: &'static str,
+        log: Log
+    }
+
+    impl OutputSink for Named {
+        fn write(&mut self, id: &SessionId, _chunk: u32, _value: &[u8], _flags: Flags, _stamp: Stamp) -> io::Result<()> {
+            self.log.lock().unwrap().push((self.name, id.id));
+            Ok(())
+        }
+    }
+
+    fn routed(routes: &[&str]) -> (RouteSink, Log) {
+        let log: Log = Arc::new(Mutex::new(Vec::new()));
+        let names = ["first", "second", "third"];
+        let sinks = routes.iter().zip(names)
+            .map(|(r, name)| {
+                let sink: Box<dyn OutputSink> = Box::new(Named { name, log: log.clone() });
+                (r.parse::<Route>().unwrap().matcher().clone(), sink)
+            })
+            .collect();
+        (RouteSink::new(sinks, Box::new(Named { name: "default", log: log.clone() })), log)
+    }
+
+    fn write(s: &mut RouteSink, id: SessionId, flags: Flags) {
+        let stamp = Stamp { ns: 0, source: TimestampSource::Local, precision: TimestampPrecision::Ms };
+        s.write(&id, 0, b"result", flags, stamp).unwrap();
+    }
+
+    #[test]
+    fn the_first_matching_route_stores_the_result() {
+        let (mut s, log) = routed(&["low_confidence -> file:/low", "prefix=site-a -> session:/a", "source=10.0.0.1:12000 -> file:/src"]);
+        let metadata: crate::data::Metadata = vec![("source".to_string(), "10.0.0.1:12000".to_string())].into_iter().collect();
+        write(&mut s, SessionId::new("site-a".into(), 1, Default::default()), Flags { low_confidence: true, ..Flags::default() });
+        write(&mut s, SessionId::new("site-a".into(), 2, Arc::new(metadata.clone())), Flags::default());
+        write(&mut s, SessionId::new("".into(), 3, Arc::new(metadata)), Flags::default());
+        write(&mut s, SessionId::new("site-b".into(), 4, Default::default()), Flags { partial: true, ..Flags::default() });
+        assert_eq!(*log.lock().unwrap(), vec![("first", 1), ("second", 2), ("third", 3), ("default", 4)]);
+    }
+}
@@ -0,0 +1,228 @@
+//! Сохранение всех результатов сеанса абонента в один дописываемый файл.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use log::{debug, error};
+
+use super::digest::HashingWriter;
+use super::manifest::{Entry, Manifest};
+use super::sink::{Flags, OutputSink};
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+use super::unique;
+
+/// Открытый файл сеанса, контрольная сумма рассчитывается по мере дописывания
+struct Open {
+    file: HashingWriter<BufWriter<File>>,
+    touched: Instant,
+    // any result of the session is below the confidence threshold
+    low_confidence: bool,
+    // the time of the first result
+    stamp: Stamp
+}
+
+/// Дописывает результаты сеанса `id` в порядке поступления во временный файл `<id>.bin.part` через буфер,
+/// где `<id>` - идентификатор сеанса с префиксом системы сопряжения, если он задан,
+/// сбрасываемый периодически по `[output] flush_interval`.
+/// По последнему результату сеанса, по истечении таймаута неактивности или при завершении работы
+/// файл закрывается, переименовывается в `<id>.bin`, что означает его готовность для потребителей,
+/// и регистрируется в журнале сохраненных файлов. Если файл прежнего сеанса с тем же идентификатором еще лежит
+/// в каталоге, имя получает номер `.dup<n>`, см. [`unique`].
+/// Неудачно дописанный результат удаляется из файла, так что повтор записи не дублирует его части
+pub struct SessionSink {
+    dir: PathBuf,
+    timeout: Duration,
+    open: HashMap<SessionId, Open>,
+    manifest: Manifest
+}
+
+impl SessionSink {
+
+    pub fn new(dir: &str, timeout: Duration, manifest: Manifest) -> SessionSink {
+        SessionSink {
+            dir: PathBuf::from(dir),
+            timeout,
+            open: HashMap::new(),
+            manifest
+        }
+    }
+
+    fn part_path(&self, id: &SessionId) -> PathBuf {
+        self.dir.join(format!("{}.bin.part", id))
+    }
+
+    // flushes, closes and renames the session file to its final name, then records it in the manifest
+    fn finalize(&mut self, id: &SessionId) -> io::Result<()> {
+        if let Some(open) = self.open.remove(id) {
+            let bytes = open.file.written();
+            let (file, digest) = open.file.finish();
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            drop(file);
+            // a reused subscriber id doesn't overwrite the file of its earlier session
+            let name = unique::free(&self.dir, &id.to_string(), "bin")?;
+            std::fs::rename(self.part_path(id), self.dir.join(&name))?;
+            self.manifest.record(Entry { name: &name, id, chunk: None, bytes, digest, low_confidence: open.low_confidence, partial: false, part: None, stamp: open.stamp })?;
+            debug!("output: session {} file is finalized", id);
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for SessionSink {
+
+    fn write(&mut self, id: &SessionId, _chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        // the file of a session is the sequence of the final results, a partial one is superseded anyway
+        if flags.partial {
+            return Ok(());
+        }
+        if !self.open.contains_key(id) {
+            // a stale part file left by a previous run is overwritten, the digest would not cover it otherwise
+            let file = OpenOptions::new().create(true).write(true).truncate(true).open(self.part_path(id))?;
+            self.manifest.permit(&file)?;
+            let file = HashingWriter::new(BufWriter::new(file), self.manifest.alg());
+            self.open.insert(id.clone(), Open { file, touched: Instant::now(), low_confidence: false, stamp });
+        }
+        if let Some(open) = self.open.get_mut(id) {
+            let mark = open.file.mark();
+            if let Err(e) = open.file.write_all(value) {
+                // a retry appends the result again, the part of it written by now must not stay
+                if let Err(r) = open.file.rewind(mark) {
+                    error!("output: session {} file is dropped, the failed write is not undone: {}", id, r);
+                    self.open.remove(id);
+                    let _ = std::fs::remove_file(self.part_path(id));
+                }
+                return Err(e);
+            }
+            open.touched = Instant::now();
+            open.low_confidence |= flags.low_confidence;
+        }
+        if flags.last {
+            self.finalize(id)?;
+        }
+        Ok(())
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        let expired: Vec<SessionId> = self.open.iter()
+            .filter(|(_, o)| now.duration_since(o.touched) >= self.timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            debug!("output: session {} is timed out", id);
+            if let Err(e) = self.finalize(&id) {
+                error!("output: failed to finalize session {}: {}", id, e);
+            }
+        }
+    }
+
+    fn flush(&mut self, sync: bool) -> io::Result<()> {
+        for open in self.open.values_mut() {
+            open.file.flush()?;
+            if sync {
+                open.file.get_mut().get_ref().sync_data()?;
+            }
+        }
+        self.manifest.flush(sync)
+    }
+
+    fn close(&mut self) {
+        let ids: Vec<SessionId> = self.open.keys().cloned().collect();
+        for id in ids {
+            if let Err(e) = self.finalize(&id) {
+                error!("output: failed to finalize session {}: {}", id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{Config, TimestampPrecision, TimestampSource};
+
+    fn sink(name: &str, timeout: Duration) -> (PathBuf, SessionSink) {
+        let dir = std::env::temp_dir().join(format!("banshee-session-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cfg = Config::from_sources(&[], &["output.manifest="]).unwrap();
+        let manifest = Manifest::open(&cfg, &dir).unwrap();
+        (dir.clone(), SessionSink::new(dir.to_str().unwrap(), timeout, manifest))
+    }
+
+    fn id(n: u32) -> SessionId {
+        SessionId::new("".into(), n, Default::default())
+    }
+
+    fn stamp() -> Stamp {
+        Stamp { ns: 0, source: TimestampSource::Local, precision: TimestampPrecision::Ms }
+    }
+
+    fn flags(last: bool) -> Flags {
+        Flags { last, ..Flags::default() }
+    }
+
+    #[test]
+    fn appends_the_session_until_the_last_result() {
+        let (dir, mut s) = sink("append", Duration::from_secs(30));
+        s.write(&id(1), 0, b"ab", flags(false), stamp()).unwrap();
+        s.write(&id(2), 0, b"x", flags(false), stamp()).unwrap();
+        s.write(&id(1), 1, b"cd", flags(false), stamp()).unwrap();
+        assert!(dir.join("1.bin.part").exists());
+        assert!(!dir.join("1.bin").exists());
+        s.write(&id(1), 2, b"ef", flags(true), stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("1.bin")).unwrap(), b"abcdef");
+        assert!(!dir.join("1.bin.part").exists());
+        s.close();
+        assert_eq!(std::fs::read(dir.join("2.bin")).unwrap(), b"x");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_the_partial_results() {
+        let (dir, mut s) = sink("partial", Duration::from_secs(30));
+        s.write(&id(1), 0, b"draft", Flags { partial: true, ..Flags::default() }, stamp()).unwrap();
+        s.write(&id(1), 0, b"final", flags(true), stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("1.bin")).unwrap(), b"final");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finalizes_the_inactive_session() {
+        let (dir, mut s) = sink("timeout", Duration::from_millis(10));
+        s.write(&id(1), 0, b"ab", flags(false), stamp()).unwrap();
+        s.sweep(Instant::now());
+        assert!(!dir.join("1.bin").exists());
+        s.sweep(Instant::now() + Duration::from_millis(20));
+        assert_eq!(std::fs::read(dir.join("1.bin")).unwrap(), b"ab");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_flush_writes_out_the_open_file() {
+        let (dir, mut s) = sink("flush", Duration::from_secs(30));
+        s.write(&id(1), 0, b"ab", flags(false), stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("1.bin.part")).unwrap(), b"");
+        s.flush(false).unwrap();
+        assert_eq!(std::fs::read(dir.join("1.bin.part")).unwrap(), b"ab");
+        s.write(&id(1), 1, b"cd", flags(false), stamp()).unwrap();
+        s.flush(true).unwrap();
+        assert_eq!(std::fs::read(dir.join("1.bin.part")).unwrap(), b"abcd");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_reused_id_keeps_the_earlier_file() {
+        let (dir, mut s) = sink("reused", Duration::from_secs(30));
+        s.write(&id(1), 0, b"first", flags(true), stamp()).unwrap();
+        s.write(&id(1), 0, b"second", flags(true), stamp()).unwrap();
+        assert_eq!(std::fs::read(dir.join("1.bin")).unwrap(), b"first");
+        assert_eq!(std::fs::read(dir.join("1.dup1.bin")).unwrap(), b"second");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
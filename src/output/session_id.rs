@@ -0,0 +1,83 @@
+//! Идентификатор сеанса в именах файлов и журнале результатов.
+
+use std::fmt::{Display, Formatter, Result};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::data::Metadata;
+
+/// Идентификатор абонента сеанса с префиксом, заданным для системы сопряжения.
+/// Выводится как `<prefix>-<id>`, без префикса - как `<id>`.
+/// Несет сведения о сеансе для журнала сохраненных файлов, они не участвуют в сравнении идентификаторов
+#[derive(Clone, Debug)]
+pub struct SessionId {
+    pub prefix: Arc<str>,
+    pub id: u32,
+    pub metadata: Arc<Metadata>
+}
+
+impl SessionId {
+
+    pub fn new(prefix: Arc<str>, id: u32, metadata: Arc<Metadata>) -> SessionId {
+        SessionId {
+            prefix,
+            id,
+            metadata
+        }
+    }
+
+    /// Задан ли для сеанса префикс
+    pub fn is_prefixed(&self) -> bool {
+        !self.prefix.is_empty()
+    }
+}
+
+impl PartialEq for SessionId {
+
+    fn eq(&self, other: &SessionId) -> bool {
+        self.prefix == other.prefix && self.id == other.id
+    }
+}
+
+impl Eq for SessionId {}
+
+impl Hash for SessionId {
+
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.prefix.hash(state);
+        self.id.hash(state);
+    }
+}
+
+impl Display for SessionId {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if self.is_prefixed() {
+            write!(f, "{}-{}", self.prefix, self.id)
+        } else {
+            write!(f, "{}", self.id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn the_prefix_goes_ahead_of_the_id() {
+        assert_eq!(SessionId::new("site-a".into(), 7, Default::default()).to_string(), "site-a-7");
+        assert_eq!(SessionId::new("".into(), 7, Default::default()).to_string(), "7");
+    }
+
+    #[test]
+    fn the_metadata_is_not_compared() {
+        let mut m = HashMap::new();
+        m.insert("caller".to_string(), "1".to_string());
+        let described = SessionId::new("a".into(), 7, Arc::new(m));
+        assert_eq!(described, SessionId::new("a".into(), 7, Default::default()));
+        assert_ne!(described, SessionId::new("b".into(), 7, Default::default()));
+    }
+}
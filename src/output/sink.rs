@@ -0,0 +1,167 @@
+//! Общий интерфейс приемника результатов и его выбор по конфигурации.
+
+use std::fmt;
+use std::io;
+use std::time::Instant;
+
+use crate::config::{OutputMode, SharedConfig};
+use crate::stats::SharedStats;
+
+use super::ack::AckSink;
+use super::failover::FailoverSink;
+use super::fanout::FanoutSink;
+#[cfg(unix)]
+use super::fifo::FifoSink;
+use super::file::FileSink;
+use super::manifest::Manifest;
+use super::route::RouteSink;
+use super::session_id::SessionId;
+use super::stamp::Stamp;
+use super::session::SessionSink;
+
+/// Признаки сохраняемого результата
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Flags {
+    /// Уверенность результата ниже порога `min_confidence`
+    pub low_confidence: bool,
+    /// Промежуточный результат потокового вычислителя, за ним следуют другие результаты той же части сеанса
+    pub partial: bool,
+    /// Последний результат сеанса
+    pub last: bool
+}
+
+/// Ошибка записи результата в некоторые из нескольких приемников, передается в `io::Error`
+#[derive(Debug)]
+pub struct Failed {
+    /// Имена приемников, не сохранивших результат
+    pub sinks: Vec<String>,
+    /// Результат все же считается сохраненным по политике `[output] fanout`
+    pub stored: bool
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed sinks: {}", self.sinks.join(", "))
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// Сведения о приемниках, не сохранивших результат, если ошибка записи их несет
+pub fn failed(e: &io::Error) -> Option<&Failed> {
+    e.get_ref().and_then(|e| e.downcast_ref::<Failed>())
+}
+
+/// Приемник результатов, сохраняющий их в системе хранения
+pub trait OutputSink: Send {
+
+    /// Сохраняет очередной результат части `chunk` сеанса `id`, относящийся к моменту `stamp`.
+    /// `id` несет префикс идентификатора сеанса, заданный для системы сопряжения.
+    /// `flags` - признаки результата
+    fn write(&mut self, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()>;
+
+    /// Сохраняет результат, как `write`, но только в приемник `sink` из нескольких, ранее не сохранивший его.
+    /// Приемник из одного приемника сохраняет результат как обычно
+    fn write_to(&mut self, _sink: &str, id: &SessionId, chunk: u32, value: &[u8], flags: Flags, stamp: Stamp) -> io::Result<()> {
+        self.write(id, chunk, value, flags, stamp)
+    }
+
+    /// Периодически вызывается для обслуживания открытых ресурсов, например закрытия неактивных сеансов
+    fn sweep(&mut self, _now: Instant) {}
+
+    /// Сбрасывает буферизованные данные открытых файлов в файловую систему,
+    /// `sync` - также дожидается их записи на носитель
+    fn flush(&mut self, _sync: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Завершает работу приемника, корректно закрывая все открытые ресурсы
+    fn close(&mut self) {}
+}
+
+/// Создает приемник результатов в соответствии с настройками `[output]`.
+/// Если задан резервный каталог, создается приемник, переключающийся между основным и резервным каталогами.
+/// Если заданы маршруты, он сохраняет только результаты, не подошедшие ни одному из них
+pub fn build(cfg: &SharedConfig, stats: SharedStats) -> io::Result<Box<dyn OutputSink>> {
+    let fallback = cfg.fallback_dir();
+    let default = if fallback.is_empty() {
+        build_in(cfg, &cfg.output_dir())?
+    } else {
+        Box::new(FailoverSink::new(cfg.clone(), cfg.output_dir(), fallback, stats)?)
+    };
+    let routes = cfg.output_routes();
+    if routes.is_empty() {
+        return Ok(default);
+    }
+    let mut sinks = Vec::new();
+    for r in routes {
+        if r.mode().has_dir() {
+            std::fs::create_dir_all(r.path())?;
+        }
+        // the path of a route is its directory, its pipe or its url
+        sinks.push((r.matcher().clone(), build_mode(cfg, r.mode(), r.path(), r.path())?));
+    }
+    Ok(Box::new(RouteSink::new(sinks, default)))
+}
+
+/// Создает приемник результатов в каталоге `dir`.
+/// Для нескольких заданных приемников создается общий приемник, передающий результаты в каждый из них
+pub fn build_in(cfg: &SharedConfig, dir: &str) -> io::Result<Box<dyn OutputSink>> {
+    std::fs::create_dir_all(dir)?;
+    let mut sinks = Vec::new();
+    for mode in cfg.output_modes() {
+        let target = if mode == OutputMode::Http { cfg.ack_url() } else { cfg.fifo() };
+        sinks.push((mode.to_string(), build_mode(cfg, mode, dir, &target)?));
+    }
+    if sinks.len() == 1 {
+        return Ok(sinks.remove(0).1);
+    }
+    Ok(Box::new(FanoutSink::new(sinks, cfg.fanout(), cfg.write_retries(), cfg.write_retry_delay())))
+}
+
+// the sink of the mode in the existing directory `dir`, writing to the pipe or posting to the url `target`
+fn build_mode(cfg: &SharedConfig, mode: OutputMode, dir: &str, target: &str) -> io::Result<Box<dyn OutputSink>> {
+    Ok(match mode {
+        OutputMode::File => Box::new(FileSink::new(dir, cfg.max_result_bytes(), Manifest::open(cfg, dir.as_ref())?)),
+        OutputMode::Session => Box::new(SessionSink::new(dir, cfg.session_timeout(), Manifest::open(cfg, dir.as_ref())?)),
+        // the pipe is not a file of the directory, it isn't recorded in the manifest
+        #[cfg(unix)]
+        OutputMode::Fifo => Box::new(FifoSink::new(target, cfg.fifo_no_reader(), cfg.fifo_wait())?),
+        #[cfg(not(unix))]
+        OutputMode::Fifo => return Err(io::Error::new(io::ErrorKind::Other, format!("named pipe {} is not supported on this platform", target))),
+        // the service acks a result itself, it isn't recorded in the manifest either
+        OutputMode::Http => Box::new(AckSink::new(target, cfg.ack_timeout(), cfg.ack_retries(), cfg.ack_retry_delay())?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{Config, TimestampPrecision, TimestampSource};
+
+    #[test]
+    fn the_failed_sinks_travel_in_the_error() {
+        let e = io::Error::other(Failed { sinks: vec!["file".to_string()], stored: true });
+        assert_eq!(failed(&e).map(|f| (f.sinks.clone(), f.stored)), Some((vec!["file".to_string()], true)));
+        assert_eq!(e.to_string(), "failed sinks: file");
+        assert!(failed(&io::Error::other("disk full")).is_none());
+        assert!(failed(&io::Error::from(io::ErrorKind::NotFound)).is_none());
+    }
+
+    #[test]
+    fn several_modes_fan_out() {
+        let dir = std::env::temp_dir().join(format!("banshee-sink-{}-fanout", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg = Config::from_sources(&[], &["output.mode=file,session", "output.manifest="]).unwrap();
+        let mut sink = build_in(&cfg, dir.to_str().unwrap()).unwrap();
+        let id = SessionId::new("".into(), 4, Default::default());
+        let stamp = Stamp { ns: 2_000_000, source: TimestampSource::Local, precision: TimestampPrecision::Ms };
+        sink.write(&id, 0, b"abc", Flags { last: true, ..Flags::default() }, stamp).unwrap();
+        assert_eq!(std::fs::read(dir.join("4_0_2.bin")).unwrap(), b"abc");
+        assert_eq!(std::fs::read(dir.join("4.bin")).unwrap(), b"abc");
+        // a sink unknown to the fanout is an error, not a write to all of them
+        assert_eq!(sink.write_to("fifo", &id, 1, b"d", Flags::default(), stamp).unwrap_err().kind(), io::ErrorKind::NotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
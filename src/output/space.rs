@@ -0,0 +1,156 @@
+//! Контроль свободного места в каталогах хранения результатов.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info};
+
+use crate::stats::SharedStats;
+
+/// Разрешает сохранение результатов, пока хотя бы в одном из каталогов хранения свободно не меньше `min_free` байт.
+/// Свободное место проверяется не чаще раза в `period`, в промежутках используется результат последней проверки
+pub struct SpaceGate {
+    dirs: Vec<String>,
+    min_free: u64,
+    period: Duration,
+    checked: Option<Instant>,
+    low: bool,
+    stats: SharedStats
+}
+
+impl SpaceGate {
+
+    /// `min_free` 0 отключает проверку
+    pub fn new(dirs: Vec<String>, min_free: u64, period: Duration, stats: SharedStats) -> SpaceGate {
+        SpaceGate {
+            dirs,
+            min_free,
+            period,
+            checked: None,
+            low: false,
+            stats
+        }
+    }
+
+    /// Признак достаточного свободного места к моменту `now`, при необходимости место проверяется заново
+    pub fn open(&mut self, now: Instant) -> bool {
+        if self.min_free == 0 {
+            return true;
+        }
+        if self.checked.is_some_and(|t| now < t + self.period) {
+            return !self.low;
+        }
+        self.checked = Some(now);
+        let mut best = None;
+        for dir in &self.dirs {
+            match free_bytes(dir) {
+                Ok(free) => best = best.max(Some((free, dir))),
+                Err(e) => debug!("output: unable to get free space of {}: {}", dir, e)
+            }
+        }
+        // the space is unknown, a failing write tells better than a stalled pipeline
+        let (free, dir) = match best {
+            Some(b) => b,
+            None => return true
+        };
+        let low = free < self.min_free;
+        if low && !self.low {
+            error!("output: {} bytes left in {}, less than {} required, results are held until space is freed",
+                free, dir, self.min_free);
+        } else if !low && self.low {
+            info!("output: {} bytes are free in {}, results are accepted again", free, dir);
+        }
+        self.low = low;
+        self.stats.set_output_low_space(low);
+        !low
+    }
+
+    /// Момент следующей проверки, с которого место может оказаться освобожденным
+    pub fn next_check(&self) -> Instant {
+        self.checked.map_or_else(Instant::now, |t| t + self.period)
+    }
+}
+
+#[cfg(unix)]
+fn free_bytes(dir: &str) -> io::Result<u64> {
+    use std::ffi::CString;
+
+    let path = CString::new(dir).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the space available to an unprivileged writer, the reserved blocks don't count
+    Ok(st.f_bavail as u64 * st.f_frsize as u64)
+}
+
+#[cfg(windows)]
+fn free_bytes(dir: &str) -> io::Result<u64> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(dir: *const u16, available: *mut u64, total: *mut u64, free: *mut u64) -> i32;
+    }
+    let path: Vec<u16> = OsStr::new(dir).encode_wide().chain(Some(0)).collect();
+    let mut available = 0u64;
+    // the space available to the caller, the quotas are taken into account
+    if unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+// the space is unknown, the gate stays open
+#[cfg(not(any(unix, windows)))]
+fn free_bytes(_dir: &str) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Other, "free space check is not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::stats::Stats;
+
+    fn gate(dirs: &[&str], min_free: u64) -> (SpaceGate, SharedStats) {
+        let stats = Stats::new(&[]);
+        let dirs = dirs.iter().map(|d| d.to_string()).collect();
+        (SpaceGate::new(dirs, min_free, Duration::from_secs(60), stats.clone()), stats)
+    }
+
+    #[test]
+    fn closes_while_no_directory_has_the_space() {
+        let tmp = std::env::temp_dir().display().to_string();
+        assert!(free_bytes(&tmp).unwrap() > 0);
+        let (mut g, stats) = gate(&[&tmp], u64::MAX);
+        let now = Instant::now();
+        assert!(!g.open(now));
+        assert_eq!(stats.snapshot()["output_low_space"], true);
+        assert_eq!(g.next_check(), now + Duration::from_secs(60));
+        let (mut g, stats) = gate(&["/nonexistent/banshee", &tmp], 1);
+        assert!(g.open(now));
+        assert_eq!(stats.snapshot()["output_low_space"], false);
+    }
+
+    #[test]
+    fn keeps_the_last_check_within_the_period() {
+        let tmp = std::env::temp_dir().display().to_string();
+        let (mut g, _) = gate(&[&tmp], u64::MAX);
+        let now = Instant::now();
+        assert!(!g.open(now));
+        // the lower bound is not checked again before the period
+        g.min_free = 1;
+        assert!(!g.open(now + Duration::from_secs(30)));
+        assert!(g.open(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn an_unknown_space_or_no_limit_keeps_it_open() {
+        let (mut g, _) = gate(&["/nonexistent/banshee"], u64::MAX);
+        assert!(g.open(Instant::now()));
+        let (mut g, _) = gate(&[], 0);
+        assert!(g.open(Instant::now()));
+    }
+}
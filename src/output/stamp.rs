@@ -0,0 +1,140 @@
+//! Метка времени сохраняемого результата.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{TimestampPrecision, TimestampSource};
+
+use log::{debug, warn};
+
+/// Момент, к которому относится сохраняемый результат, и источник, по которому он определен
+#[derive(Clone, Copy, Debug)]
+pub struct Stamp {
+    /// Наносекунды от начала эпохи Unix
+    pub ns: u64,
+    pub source: TimestampSource,
+    /// Точность метки в именах файлов и журнале сохраненных файлов, `[output] timestamp_precision`
+    pub precision: TimestampPrecision
+}
+
+impl Stamp {
+
+    /// Миллисекунды от начала эпохи Unix
+    pub fn ms(&self) -> u64 {
+        self.ns / 1_000_000
+    }
+
+    /// Метка в единицах точности `precision`
+    pub fn value(&self) -> u64 {
+        self.precision.of(self.ns)
+    }
+
+    /// Имя поля сведений о результате с меткой в единицах точности, None - точность миллисекундная
+    /// и метка уже передана полем `time_ms`
+    pub fn field(&self) -> Option<String> {
+        Some(format!("time_{}", self.precision)).filter(|_| self.precision != TimestampPrecision::Ms)
+    }
+}
+
+/// Определяет метку времени результата по настройкам `[output] timestamp_source` и `timestamp_precision`
+pub struct Clock {
+    source: TimestampSource,
+    precision: TimestampPrecision,
+    /// Последняя метка часов приложения, нс
+    last_ns: u64,
+    warned: bool
+}
+
+impl Clock {
+
+    pub fn new(source: TimestampSource, precision: TimestampPrecision) -> Clock {
+        Clock {
+            source,
+            precision,
+            last_ns: 0,
+            warned: false
+        }
+    }
+
+    /// Метка времени результата сеанса `id` с временем системы сопряжения `peer_time_us`.
+    /// Если время системы сопряжения выбрано, но не передано, используются часы приложения.
+    /// С точностью `ns` метки часов приложения строго возрастают
+    pub fn stamp(&mut self, id: u32, peer_time_us: Option<u64>) -> Stamp {
+        match (self.source, peer_time_us) {
+            (TimestampSource::Peer, Some(us)) => Stamp { ns: us * 1000, source: TimestampSource::Peer, precision: self.precision },
+            (TimestampSource::Peer, None) => {
+                // the peer likely never sends the time, a warning per result would flood the log
+                if !self.warned {
+                    warn!("output: no peer time for session {}, local time is used instead", id);
+                    self.warned = true;
+                } else {
+                    debug!("output: no peer time for session {}, local time is used instead", id);
+                }
+                self.local()
+            },
+            (TimestampSource::Local, _) => self.local()
+        }
+    }
+
+    fn local(&mut self) -> Stamp {
+        let mut ns = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        // the clock may not tick between rapid results or may step back, a nanosecond later keeps them apart
+        if self.precision == TimestampPrecision::Ns && ns <= self.last_ns {
+            ns = self.last_ns + 1;
+        }
+        self.last_ns = ns;
+        Stamp { ns, source: TimestampSource::Local, precision: self.precision }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now_ns() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64
+    }
+
+    #[test]
+    fn takes_the_peer_time_when_chosen() {
+        let mut c = Clock::new(TimestampSource::Peer, TimestampPrecision::Ms);
+        let s = c.stamp(1, Some(1_500_000));
+        assert_eq!((s.ns, s.ms(), s.source), (1_500_000_000, 1500, TimestampSource::Peer));
+        let mut c = Clock::new(TimestampSource::Local, TimestampPrecision::Ms);
+        let before = now_ns();
+        let s = c.stamp(1, Some(1_500_000));
+        assert_eq!(s.source, TimestampSource::Local);
+        assert!(s.ns >= before);
+    }
+
+    #[test]
+    fn falls_back_to_the_local_time() {
+        let mut c = Clock::new(TimestampSource::Peer, TimestampPrecision::Ms);
+        let before = now_ns();
+        for _ in 0..2 {
+            let s = c.stamp(1, None);
+            assert_eq!(s.source, TimestampSource::Local);
+            assert!(s.ns >= before);
+        }
+        assert!(c.warned);
+    }
+
+    #[test]
+    fn the_local_nanoseconds_strictly_increase() {
+        let mut c = Clock::new(TimestampSource::Local, TimestampPrecision::Ns);
+        let stamps: Vec<u64> = (0..1000).map(|_| c.stamp(1, None).ns).collect();
+        assert!(stamps.windows(2).all(|w| w[0] < w[1]));
+        // the clock stepped back is not followed
+        let ahead = now_ns() + 1_000_000_000;
+        c.last_ns = ahead;
+        assert_eq!(c.stamp(1, None).ns, ahead + 1);
+    }
+
+    #[test]
+    fn formats_the_stamp_at_the_precision() {
+        let stamp = |precision| Stamp { ns: 1_500_000_123, source: TimestampSource::Local, precision };
+        assert_eq!((stamp(TimestampPrecision::Ms).value(), stamp(TimestampPrecision::Ms).field()), (1500, None));
+        assert_eq!((stamp(TimestampPrecision::S).value(), stamp(TimestampPrecision::S).field()), (1, Some("time_s".to_string())));
+        assert_eq!(stamp(TimestampPrecision::Us).value(), 1_500_000);
+        assert_eq!((stamp(TimestampPrecision::Ns).value(), stamp(TimestampPrecision::Ns).ms()), (1_500_000_123, 1500));
+    }
+}
@@ -0,0 +1,90 @@
+//! Имена файлов результатов, не совпадающие с уже сохраненными.
+//!
+//! Результаты разных сеансов могут получить одно имя: один идентификатор абонента в пределах миллисекунды метки
+//! времени, повторно использованный идентификатор сеанса. Вместо перезаписи сохраненного файла к имени
+//! добавляется номер `<stem>.dup<n>.<ext>`, n от 1
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use log::warn;
+
+/// Наибольший номер, добавляемый к занятому имени
+const MAX_SUFFIX: u32 = 9999;
+
+/// Создает в `dir` файл `<stem>.<ext>` или, если он уже есть, первый свободный `<stem>.dup<n>.<ext>`.
+/// Возвращает имя созданного файла и сам файл
+pub fn create(dir: &Path, stem: &str, ext: &str) -> io::Result<(String, File)> {
+    for n in 0..=MAX_SUFFIX {
+        let name = name(stem, ext, n);
+        match OpenOptions::new().write(true).create_new(true).open(dir.join(&name)) {
+            Ok(file) => {
+                if n > 0 {
+                    warn!("output: {}.{} exists, the result is stored as {}", stem, ext, name);
+                }
+                return Ok((name, file));
+            },
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        }
+    }
+    Err(collision(stem, ext))
+}
+
+/// Имя `<stem>.<ext>` или, если файл с ним уже есть в `dir`, первое свободное `<stem>.dup<n>.<ext>`
+/// для переименования в него готового файла. Файлы каталога создает только приемник, так что имя остается свободным
+pub fn free(dir: &Path, stem: &str, ext: &str) -> io::Result<String> {
+    for n in 0..=MAX_SUFFIX {
+        let name = name(stem, ext, n);
+        match dir.join(&name).symlink_metadata() {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                if n > 0 {
+                    warn!("output: {}.{} exists, the result is stored as {}", stem, ext, name);
+                }
+                return Ok(name);
+            },
+            Err(e) => return Err(e),
+            Ok(_) => continue
+        }
+    }
+    Err(collision(stem, ext))
+}
+
+fn name(stem: &str, ext: &str, n: u32) -> String {
+    if n == 0 { format!("{}.{}", stem, ext) } else { format!("{}.dup{}.{}", stem, n, ext) }
+}
+
+fn collision(stem: &str, ext: &str) -> io::Error {
+    io::Error::new(ErrorKind::AlreadyExists, format!("{}.{} and {} numbered names exist", stem, ext, MAX_SUFFIX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("banshee-unique-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn numbers_the_taken_name() {
+        let dir = dir("create");
+        let names: Vec<String> = (0..3).map(|_| create(&dir, "7_2_1000", "bin").unwrap().0).collect();
+        assert_eq!(names, vec!["7_2_1000.bin", "7_2_1000.dup1.bin", "7_2_1000.dup2.bin"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_free_name_is_not_taken() {
+        let dir = dir("free");
+        assert_eq!(free(&dir, "7", "bin").unwrap(), "7.bin");
+        std::fs::write(dir.join("7.bin"), b"").unwrap();
+        std::fs::write(dir.join("7.dup1.bin"), b"").unwrap();
+        assert_eq!(free(&dir, "7", "bin").unwrap(), "7.dup2.bin");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
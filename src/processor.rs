@@ -2,51 +2,273 @@
 //! 
 //! Обеспечивает последовательную обработку каждого объекта заданным набором фильтров.
 //! Обеспечивает параллельную обработку разных объектов. Задачи по каждому объекту:
-//! *  получить готовый объект от коллектора
+//! *  получить готовый объект от коллектора, по одному или посылкой из нескольких объектов
 //! *  обработать объект цепочкой фильтров
 //! *  передать полученный результат в подмодуль расчета итогового результата (inference)
+//! 
+//! Набор и порядок фильтров задается в настройках `[processor] pipeline`, каждый фильтр реализует [`ProcessStage`](stage::ProcessStage)
+//!
+//! Для сеанса, совпадающего по содержимому с одним из последних `[processor] feature_cache` обработанных,
+//! цепочка не выполняется, в inference передается сохраненный результат обработки
+//!
+//! Сеанс, данные которого не согласуются с объявленными параметрами звука, не обрабатывается: по настройке
+//! `[processor] on_malformed` он пропускается или помещается в карантин
+//!
+//! Обработанный сэмпл передается в inference, только пока сэмплов в работе меньше `[inference] max_in_flight`,
+//! см. [`inflight`](crate::inflight)
+//!
+//! Фильтр `convert` преобразует звук сеанса по формату и порядку байтов отсчетов его системы сопряжения,
+//! переданным collector в сведениях о сеансе (`sample_format`, `endian`), а без них - по `[processor] sample_format`
+//! и `sample_endian`
+//!
+//! Многоканальный сеанс обрабатывается, только если в цепочке есть фильтр `deinterleave`: он разделяет каналы
+//! и передает дальше их среднее или один из них по настройке `[processor] channel`
+
+mod stage;
+mod deinterleave;
+mod cache;
+mod convert;
+mod resample;
+mod normalize;
+mod preemphasis;
+mod trim;
+mod features;
+mod validate;
+
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-use crate::config::SharedConfig;
-use crate::data::{Session, FinalSample};
+use crate::config::{ByteOrder, Malformed, SampleFormat, SharedConfig};
+use crate::quarantine::SharedQuarantine;
+use crate::stats::SharedStats;
+use crate::trace::SharedTracer;
+use crate::tracker::TaskGuard;
+use crate::data::{Metadata, Session, FinalSample};
+use crate::events;
+use crate::inflight;
+use crate::memory;
+use self::cache::{FeatureCache, Processed};
+use self::stage::AudioBuffer;
 
-use log::{info, error};
+pub use self::convert::{decode, silence};
+pub use self::resample::interpolate;
+
+use log::{info, error, warn};
+use serde_json::json;
 use tokio::sync::mpsc::{Sender, Receiver};
+use tokio::task::{self, JoinHandle};
 
 /// Запускает в асинхронном режиме подсистему обработки полученных от коллектора сессий
 /// 
 /// Параметры:
 /// 
 /// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+/// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+/// * `stats` - счетчики работы подсистем
+/// * `tracer` - регистратор интервалов трасс сеансов
+/// * `quarantine` - карантин сеансов систем сопряжения с повторяющимися ошибками обработки
 /// * `rx_sess` - межпоточный канал получения готовых к обработке сессий, читатель
 /// * `tx_smpl` - межпоточный канал передачи обработанных сэмплов в модуль inference, писатель
 /// 
-/// Возвращает Future для ожидания в вызывающем коде
-pub async fn run(_cfg: SharedConfig, mut rx_sess: Receiver<Session>, _tx_smpl: Sender<FinalSample>) {
+/// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде
+pub async fn run(cfg: SharedConfig, guard: TaskGuard, stats: SharedStats, tracer: SharedTracer, quarantine: SharedQuarantine, mut rx_sess: Receiver<Session>, mut tx_smpl: Sender<FinalSample>) -> JoinHandle<()> {
     info!("start processor");
 
+    let chain = Arc::new(stage::build_chain(&cfg).unwrap_or_else(|e| panic!("processor: {}", e)));
+    let names: Vec<&str> = chain.iter().map(|s| s.name()).collect();
+    info!("processor: pipeline [{}]", names.join(", "));
+    let rate = cfg.sample_rate();
+    let multichannel = names.contains(&"deinterleave");
+    let on_malformed = cfg.on_malformed();
+    let formats = (cfg.sample_format(), cfg.sample_endian());
+    let mut cache = FeatureCache::new(cfg.feature_cache(), &chain);
+    if cfg.feature_cache() > 0 {
+        info!("processor: results of {} last sessions are reused", cfg.feature_cache());
+    }
+
     tokio::spawn(async move {
+        let _guard = guard;
 
-        loop {
-            match rx_sess.recv().await {
+        'recv: loop {
+            let batch = match rx_sess.recv().await {
                 None => {
                     error!("sessions input channel is broken");
                     break;
                 },
-                Some(f) => {
-                    match f {
-                        Session::Stop => {
-                            info!("stop processor");
-                            break;
-                        },
-                        _ => {
-                            //todo: handle session
-                            info!("processor: handling sessions is not implemented yet");
+                Some(Session::Stop) => {
+                    info!("stop processor");
+                    break;
+                },
+                Some(Session::Batch(b)) => b,
+                Some(s) => vec![s]
+            };
+            for s in batch {
+                // the session leaves the budget as the processor takes it, the sample is charged once passed on
+                if let Session::Data { value, .. } = &s {
+                    memory::release(value.len());
+                }
+                let (peer, id, prefix, metadata, chunk, trace, peer_time_us, deadline, last, audio, value) = match s {
+                    Session::Data { peer, id, prefix, metadata, chunk, trace, peer_time_us, deadline, last, audio, value } =>
+                        (peer, id, prefix, metadata, chunk, trace, peer_time_us, deadline, last, audio, value),
+                    _ => continue
+                };
+                stats.processor.received();
+                let (format, order) = sample_format(&metadata, formats);
+                if let Err(e) = validate::check(&value, audio, rate, multichannel, format) {
+                    warn!("processor: session {} chunk {} of peer {} is malformed and skipped, {}", id, chunk, peer, e);
+                    events::emit("session_malformed", json!({ "peer": peer, "id": id, "chunk": chunk, "error": e }));
+                    stats.processor.malformed();
+                    if on_malformed == Malformed::Quarantine {
+                        quarantine.hold(peer, id, chunk, "processor", &value);
+                    }
+                    continue;
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    warn!("processor: session {} is dropped, deadline exceeded", id);
+                    events::emit("deadline_exceeded", json!({ "stage": "processor", "peer": peer, "id": id, "chunk": chunk }));
+                    stats.processor.expired();
+                    continue;
+                }
+                if quarantine.is_held(peer) {
+                    quarantine.hold(peer, id, chunk, "processor", &value);
+                    stats.processor.dropped();
+                    continue;
+                }
+                let channels = audio.map_or(1, |a| a.channels as usize);
+                let started = SystemTime::now();
+                let key = cache.key(&value, rate, channels, format, order);
+                let cached = key.as_ref().and_then(|k| cache.get(k));
+                let processed = match cached {
+                    Some(p) => {
+                        stats.feature_cache.hit();
+                        Ok(p)
+                    },
+                    None => {
+                        if key.is_some() {
+                            stats.feature_cache.miss();
+                        }
+                        // the chain is cpu bound, keep it away from the async workers
+                        let c = chain.clone();
+                        let r = task::spawn_blocking(move || stage::run_chain(&c, AudioBuffer::new(value, rate, channels, format, order)))
+                            .await
+                            .unwrap_or_else(|e| Err(format!("processing failed: {}", e)))
+                            .map(|buf| Processed { value: buf.pack(), dim: buf.dim(), rate: buf.rate });
+                        if let (Some(k), Ok(p)) = (key, &r) {
+                            cache.put(k, p.clone());
                         }
-                    }                    
+                        r
+                    }
+                };
+                match processed {
+                    Err(e) => {
+                        warn!("processor: session {} is dropped, {}", id, e);
+                        stats.processor.dropped();
+                        quarantine.failure(peer);
+                    },
+                    Ok(p) => {
+                        quarantine.success(peer);
+                        tracer.stage(&trace, "processor", started, id, chunk);
+                        let bytes = p.value.len();
+                        let smpl = FinalSample::Data { peer, id, prefix, metadata, chunk, trace, peer_time_us, deadline, value: p.value, dim: p.dim, rate: p.rate, last };
+                        inflight::acquire().await;
+                        memory::charge(bytes);
+                        if tx_smpl.send(smpl).await.is_err() {
+                            inflight::release(1);
+                            memory::release(bytes);
+                            error!("final samples output channel is broken");
+                            break 'recv;
+                        }
+                        stats.processor.sent();
+                    }
                 }
             }
-        }    
+        }
         info!("processor is stopped");
     
-    });
+    })
+}
+
+// the audio format of the session peer as the collector tells it, `default` for an older or foreign session
+fn sample_format(metadata: &Metadata, default: (SampleFormat, ByteOrder)) -> (SampleFormat, ByteOrder) {
+    let format = metadata.get("sample_format").and_then(|f| f.parse().ok()).unwrap_or(default.0);
+    let order = metadata.get("endian").and_then(|o| o.parse().ok()).unwrap_or(default.1);
+    (format, order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::config::Config;
+    use crate::data::AudioParams;
+    use crate::quarantine::Quarantine;
+    use crate::stats::Stats;
+    use crate::trace::Tracer;
+    use crate::tracker::TaskTracker;
+    use tokio::sync::mpsc;
+
+    fn session(id: u32, deadline: Option<Instant>) -> Session {
+        Session::Data { peer: 0, id, prefix: "".into(), metadata: Default::default(), chunk: 0, trace: Default::default(),
+            peer_time_us: None, deadline, last: true, audio: Some(AudioParams { rate: 8000, channels: 1, format: 0 }), value: vec![0, 0x40] }
+    }
+
+    // runs the sessions through the processor, gives the ids and the values of the samples passed on
+    async fn process(sets: &[&str], sessions: Vec<Session>) -> (Vec<(u32, Vec<u8>)>, SharedStats) {
+        let mut sets = sets.to_vec();
+        sets.push("input.peers=127.0.0.1:12000");
+        let cfg = Config::from_sources(&[], &sets).unwrap();
+        let stats = Stats::new(&cfg.peers());
+        let quarantine = Quarantine::new(&cfg, stats.clone());
+        let (mut tx_sess, rx_sess) = mpsc::channel(sessions.len() + 1);
+        let (tx_smpl, mut rx_smpl) = mpsc::channel(sessions.len() + 1);
+        let task = run(cfg.clone(), TaskTracker::new().track("processor"), stats.clone(), Tracer::new(&cfg), quarantine, rx_sess, tx_smpl).await;
+        for s in sessions {
+            assert!(tx_sess.send(s).await.is_ok());
+        }
+        assert!(tx_sess.send(Session::Stop).await.is_ok());
+        task.await.unwrap();
+        let mut samples = Vec::new();
+        while let Some(FinalSample::Data { id, value, .. }) = rx_smpl.recv().await {
+            samples.push((id, value));
+        }
+        inflight::release(samples.len());
+        (samples, stats)
+    }
+
+    #[tokio::test]
+    async fn drops_the_session_past_its_deadline() {
+        let past = Instant::now() - Duration::from_millis(1);
+        let sessions = vec![Session::Batch(vec![session(1, Some(past)), session(2, None)]),
+            session(3, Some(Instant::now() + Duration::from_secs(60)))];
+        let (samples, stats) = process(&["processor.pipeline=convert"], sessions).await;
+        assert_eq!(samples.into_iter().map(|(id, _)| id).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(stats.processor.totals(), (3, 2, 1));
+        assert_eq!(stats.snapshot()["stages"]["processor"]["expired"], 1);
+    }
+
+    #[tokio::test]
+    async fn reuses_the_result_of_the_same_content() {
+        let mut other = session(3, None);
+        if let Session::Data { value, .. } = &mut other {
+            *value = vec![0, 0xc0];
+        }
+        let (samples, stats) = process(&["processor.pipeline=convert", "processor.feature_cache=4"],
+            vec![session(1, None), session(2, None), other]).await;
+        assert_eq!(samples, vec![(1, vec![0, 0, 0, 0x3f]), (2, vec![0, 0, 0, 0x3f]), (3, vec![0, 0, 0, 0xbf])]);
+        assert_eq!(stats.snapshot()["feature_cache"], serde_json::json!({ "hits": 1, "misses": 2 }));
+    }
+
+    #[tokio::test]
+    async fn skips_the_session_not_matching_its_audio() {
+        let mut other = session(2, None);
+        if let Session::Data { audio, .. } = &mut other {
+            *audio = Some(AudioParams { rate: 16000, channels: 1, format: 0 });
+        }
+        let (samples, stats) = process(&["processor.pipeline=convert", "processor.on_malformed=skip"], vec![session(1, None), other]).await;
+        assert_eq!(samples.into_iter().map(|(id, _)| id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(stats.snapshot()["stages"]["processor"]["malformed"], 1);
+        assert_eq!(stats.processor.totals(), (2, 1, 1));
+    }
 }
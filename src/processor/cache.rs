@@ -0,0 +1,149 @@
+//! Повторное использование результатов обработки для сеансов с одинаковым содержимым.
+
+use std::collections::{BTreeMap, HashMap};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::{ByteOrder, SampleFormat};
+
+use super::stage::ProcessStage;
+
+/// Хэш содержимого сеанса
+type Key = [u8; 32];
+
+/// Упакованный результат обработки сеанса цепочкой этапов
+#[derive(Clone)]
+pub struct Processed {
+    pub value: Vec<u8>,
+    pub dim: usize,
+    pub rate: u32
+}
+
+/// Результаты обработки последних различных сеансов, не больше `size` штук.
+/// При переполнении вытесняется дольше всех не использовавшийся результат
+pub struct FeatureCache {
+    size: usize,
+    /// Хэш этапов цепочки с их параметрами, результат другой цепочки не подходит
+    chain: Key,
+    // the result and the stamp of its last use
+    entries: HashMap<Key, (Processed, u64)>,
+    // the entries by the stamp of their last use, the first one is evicted
+    used: BTreeMap<u64, Key>,
+    stamp: u64
+}
+
+impl FeatureCache {
+
+    /// Кэш результатов обработки цепочкой `chain`
+    pub fn new(size: usize, chain: &[Box<dyn ProcessStage>]) -> FeatureCache {
+        FeatureCache {
+            size,
+            chain: Sha256::digest(format!("{:?}", chain).as_bytes()).into(),
+            entries: HashMap::new(),
+            used: BTreeMap::new(),
+            stamp: 0
+        }
+    }
+
+    /// Хэш упакованных байтов сеанса с частотой дискретизации `rate`, количеством каналов `channels`
+    /// и форматом отсчетов `format`, `order` вместе с хэшем цепочки, `None` если результаты не сохраняются
+    pub fn key(&self, raw: &[u8], rate: u32, channels: usize, format: SampleFormat, order: ByteOrder) -> Option<Key> {
+        if self.size == 0 {
+            return None;
+        }
+        let mut h = Sha256::new();
+        h.update(self.chain);
+        h.update(rate.to_le_bytes());
+        h.update((channels as u64).to_le_bytes());
+        h.update(format!("{}/{}", format, order));
+        h.update(raw);
+        let mut k = [0u8; 32];
+        k.copy_from_slice(&h.finalize());
+        Some(k)
+    }
+
+    /// Возвращает сохраненный результат обработки сеанса, отмечая его использование
+    pub fn get(&mut self, key: &Key) -> Option<Processed> {
+        self.stamp += 1;
+        let (p, used) = self.entries.get_mut(key)?;
+        self.used.remove(used);
+        *used = self.stamp;
+        self.used.insert(self.stamp, *key);
+        Some(p.clone())
+    }
+
+    /// Сохраняет результат обработки сеанса
+    pub fn put(&mut self, key: Key, p: Processed) {
+        self.stamp += 1;
+        if let Some((_, used)) = self.entries.insert(key, (p, self.stamp)) {
+            self.used.remove(&used);
+        }
+        self.used.insert(self.stamp, key);
+        while self.entries.len() > self.size {
+            match self.used.pop_first() {
+                Some((_, k)) => {
+                    self.entries.remove(&k);
+                },
+                None => break
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::processor::normalize::Normalize;
+    use crate::processor::resample::Resample;
+
+    fn chain(rate: u32) -> Vec<Box<dyn ProcessStage>> {
+        vec![Box::new(Resample::new(rate)), Box::new(Normalize::new(0.9))]
+    }
+
+    fn processed(n: u8) -> Processed {
+        Processed { value: vec![n], dim: 1, rate: 16000 }
+    }
+
+    fn key(c: &FeatureCache, channels: usize) -> Key {
+        c.key(b"session", 16000, channels, SampleFormat::S16, ByteOrder::Little).unwrap()
+    }
+
+    #[test]
+    fn key_depends_on_the_layout() {
+        let c = FeatureCache::new(4, &chain(16000));
+        assert_eq!(key(&c, 1), key(&c, 1));
+        assert_ne!(key(&c, 1), key(&c, 2));
+        assert_ne!(key(&c, 1), c.key(b"session", 8000, 1, SampleFormat::S16, ByteOrder::Little).unwrap());
+        assert_ne!(key(&c, 1), c.key(b"session", 16000, 1, SampleFormat::S16, ByteOrder::Big).unwrap());
+        assert_ne!(key(&c, 1), c.key(b"other", 16000, 1, SampleFormat::S16, ByteOrder::Little).unwrap());
+    }
+
+    #[test]
+    fn key_depends_on_the_chain_params() {
+        let a = FeatureCache::new(4, &chain(16000));
+        let b = FeatureCache::new(4, &chain(8000));
+        let c = FeatureCache::new(4, &chain(16000)[..1]);
+        assert_ne!(key(&a, 1), key(&b, 1));
+        assert_ne!(key(&a, 1), key(&c, 1));
+        assert_eq!(key(&a, 1), key(&FeatureCache::new(4, &chain(16000)), 1));
+    }
+
+    #[test]
+    fn no_key_without_size() {
+        assert!(FeatureCache::new(0, &chain(16000)).key(b"session", 16000, 1, SampleFormat::S16, ByteOrder::Little).is_none());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut c = FeatureCache::new(2, &chain(16000));
+        let keys: Vec<Key> = (1..=3).map(|n| key(&c, n)).collect();
+        c.put(keys[0], processed(1));
+        c.put(keys[1], processed(2));
+        assert_eq!(c.get(&keys[0]).unwrap().value, vec![1]);
+        c.put(keys[2], processed(3));
+        assert!(c.get(&keys[1]).is_none());
+        assert_eq!(c.get(&keys[0]).unwrap().value, vec![1]);
+        assert_eq!(c.get(&keys[2]).unwrap().value, vec![3]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Этап `convert`: преобразование упакованных байтов сессии в отсчеты сигнала.
+
+use crate::config::{ByteOrder, SampleFormat};
+
+use super::stage::{AudioBuffer, ProcessStage};
+
+/// Преобразует отсчеты формата сессии (`AudioBuffer::format`, `order`) в отсчеты f32 в диапазоне [-1, 1]
+#[derive(Debug)]
+pub struct Convert;
+
+impl ProcessStage for Convert {
+
+    fn name(&self) -> &'static str {
+        "convert"
+    }
+
+    fn process(&self, mut buf: AudioBuffer) -> Result<AudioBuffer, String> {
+        let width = buf.format.width();
+        let pcm = buf.raw.chunks_exact(width);
+        if !pcm.remainder().is_empty() {
+            return Err(format!("length {} is not a whole number of {} samples", buf.raw.len(), buf.format));
+        }
+        let (format, order) = (buf.format, buf.order);
+        buf.samples = pcm.map(|b| decode(b, format, order)).collect();
+        buf.raw = Vec::new();
+        Ok(buf)
+    }
+}
+
+/// Преобразует отсчет формата `format` с порядком байтов `order` из `format.width()` байтов `b` в значение диапазона [-1, 1]
+pub fn decode(b: &[u8], format: SampleFormat, order: ByteOrder) -> f32 {
+    match format {
+        SampleFormat::S16 => {
+            let b = [b[0], b[1]];
+            let v = if order == ByteOrder::Little { i16::from_le_bytes(b) } else { i16::from_be_bytes(b) };
+            v as f32 / 32768.0
+        },
+        SampleFormat::S32 => {
+            let b = [b[0], b[1], b[2], b[3]];
+            let v = if order == ByteOrder::Little { i32::from_le_bytes(b) } else { i32::from_be_bytes(b) };
+            v as f32 / 2_147_483_648.0
+        },
+        SampleFormat::F32 => {
+            let b = [b[0], b[1], b[2], b[3]];
+            let v = if order == ByteOrder::Little { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) };
+            // a broken sample would poison every stage after
+            if v.is_finite() { v.clamp(-1.0, 1.0) } else { 0.0 }
+        },
+        SampleFormat::U8 => (b[0] as f32 - 128.0) / 128.0,
+        SampleFormat::Mulaw => mulaw(b[0]) as f32 / 32768.0,
+        SampleFormat::Alaw => alaw(b[0]) as f32 / 32768.0
+    }
+}
+
+/// Отсчет тишины формата `format`: середина шкалы беззнаковых и кодов G.711, ноль остальных в любом порядке байтов
+pub fn silence(format: SampleFormat) -> &'static [u8] {
+    match format {
+        SampleFormat::U8 => &[0x80],
+        SampleFormat::Mulaw => &[0xff],
+        SampleFormat::Alaw => &[0xd5],
+        SampleFormat::S16 => &[0; 2],
+        SampleFormat::S32 | SampleFormat::F32 => &[0; 4]
+    }
+}
+
+// G.711 mu-law to 16-bit linear
+fn mulaw(b: u8) -> i16 {
+    let u = !b;
+    let t = ((((u & 0x0f) as i16) << 3) + 0x84) << ((u & 0x70) >> 4);
+    if u & 0x80 != 0 { 0x84 - t } else { t - 0x84 }
+}
+
+// G.711 A-law to 16-bit linear
+fn alaw(b: u8) -> i16 {
+    let a = b ^ 0x55;
+    let seg = (a & 0x70) >> 4;
+    let t = ((a & 0x0f) as i16) << 4;
+    let t = match seg {
+        0 => t + 8,
+        1 => t + 0x108,
+        _ => (t + 0x108) << (seg - 1)
+    };
+    if a & 0x80 != 0 { t } else { -t }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(raw: Vec<u8>, format: SampleFormat, order: ByteOrder) -> Result<Vec<f32>, String> {
+        Convert.process(AudioBuffer::new(raw, 8000, 1, format, order)).map(|b| b.samples)
+    }
+
+    #[test]
+    fn decodes_s16_in_both_orders() {
+        assert_eq!(convert(vec![0x00, 0x40, 0x00, 0x80], SampleFormat::S16, ByteOrder::Little).unwrap(), vec![0.5, -1.0]);
+        assert_eq!(convert(vec![0x40, 0x00], SampleFormat::S16, ByteOrder::Big).unwrap(), vec![0.5]);
+    }
+
+    #[test]
+    fn rejects_a_partial_sample() {
+        assert!(convert(vec![0; 3], SampleFormat::S16, ByteOrder::Little).is_err());
+    }
+
+    #[test]
+    fn decodes_the_other_formats() {
+        assert_eq!(decode(&[0xc0], SampleFormat::U8, ByteOrder::Little), 0.5);
+        assert_eq!(decode(&0x4000_0000i32.to_be_bytes(), SampleFormat::S32, ByteOrder::Big), 0.5);
+        assert_eq!(decode(&0.25f32.to_le_bytes(), SampleFormat::F32, ByteOrder::Little), 0.25);
+        assert_eq!(decode(&f32::NAN.to_le_bytes(), SampleFormat::F32, ByteOrder::Little), 0.0);
+        assert_eq!(decode(&4.0f32.to_le_bytes(), SampleFormat::F32, ByteOrder::Little), 1.0);
+    }
+
+    #[test]
+    fn decodes_g711_silence_as_zero() {
+        for format in [SampleFormat::U8, SampleFormat::Mulaw, SampleFormat::Alaw, SampleFormat::S16, SampleFormat::S32, SampleFormat::F32] {
+            let s = silence(format);
+            assert_eq!(s.len(), format.width());
+            assert!(decode(s, format, ByteOrder::Big).abs() < 0.001, "{}", format);
+        }
+    }
+
+    #[test]
+    fn decodes_g711_full_scale() {
+        assert!(decode(&[0x80], SampleFormat::Mulaw, ByteOrder::Little) > 0.95);
+        assert!(decode(&[0x00], SampleFormat::Mulaw, ByteOrder::Little) < -0.95);
+        assert!(decode(&[0xaa], SampleFormat::Alaw, ByteOrder::Little) > 0.95);
+        assert!(decode(&[0x2a], SampleFormat::Alaw, ByteOrder::Little) < -0.95);
+    }
+}
@@ -0,0 +1,99 @@
+//! Этап `deinterleave`: разделение чередующихся каналов многоканального сеанса.
+
+use crate::config::ChannelSelect;
+
+use super::stage::{AudioBuffer, ProcessStage};
+
+/// Разделяет отсчеты с чередованием каналов по каналам и передает дальше сигнал, выбранный в `[processor] channel`:
+/// среднее всех каналов или один из них. Монофонический сигнал проходит без изменений
+#[derive(Debug)]
+pub struct Deinterleave {
+    select: ChannelSelect
+}
+
+impl Deinterleave {
+
+    pub fn new(select: ChannelSelect) -> Deinterleave {
+        Deinterleave {
+            select
+        }
+    }
+}
+
+impl ProcessStage for Deinterleave {
+
+    fn name(&self) -> &'static str {
+        "deinterleave"
+    }
+
+    fn process(&self, mut buf: AudioBuffer) -> Result<AudioBuffer, String> {
+        if buf.samples.is_empty() && !buf.raw.is_empty() {
+            return Err("no samples, the stage goes after convert".to_string());
+        }
+        buf.planes = deinterleave(&buf.samples, buf.channels)?;
+        buf.samples = match self.select {
+            ChannelSelect::Mix => mix(&buf.planes),
+            ChannelSelect::Pick(i) => buf.planes.get(i)
+                .ok_or_else(|| format!("channel {} is configured, the session has {}", i, buf.channels))?
+                .clone()
+        };
+        buf.channels = 1;
+        Ok(buf)
+    }
+}
+
+/// Разделяет отсчеты `samples` с чередованием `channels` каналов на отдельные отсчеты каждого канала.
+/// Количество отсчетов, не кратное количеству каналов, является ошибкой
+pub fn deinterleave(samples: &[f32], channels: usize) -> Result<Vec<Vec<f32>>, String> {
+    if channels == 0 {
+        return Err("no channels".to_string());
+    }
+    if !samples.len().is_multiple_of(channels) {
+        return Err(format!("{} samples are not whole frames of {} channels", samples.len(), channels));
+    }
+    let frames = samples.len() / channels;
+    let mut planes = vec![Vec::with_capacity(frames); channels];
+    for frame in samples.chunks_exact(channels) {
+        for (plane, v) in planes.iter_mut().zip(frame) {
+            plane.push(*v);
+        }
+    }
+    Ok(planes)
+}
+
+fn mix(planes: &[Vec<f32>]) -> Vec<f32> {
+    let n = planes.len() as f32;
+    let frames = planes.first().map_or(0, |p| p.len());
+    (0..frames).map(|i| planes.iter().map(|p| p[i]).sum::<f32>() / n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{ByteOrder, SampleFormat};
+
+    fn buffer(samples: Vec<f32>, channels: usize) -> AudioBuffer {
+        let mut buf = AudioBuffer::new(Vec::new(), 8000, channels, SampleFormat::S16, ByteOrder::Little);
+        buf.samples = samples;
+        buf
+    }
+
+    #[test]
+    fn splits_the_frames_by_channel() {
+        assert_eq!(deinterleave(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 2), Ok(vec![vec![1.0, 3.0, 5.0], vec![2.0, 4.0, 6.0]]));
+        assert!(deinterleave(&[1.0, 2.0, 3.0], 2).is_err());
+        assert!(deinterleave(&[1.0], 0).is_err());
+    }
+
+    #[test]
+    fn passes_the_selected_signal() {
+        let mixed = Deinterleave::new(ChannelSelect::Mix).process(buffer(vec![0.5, 0.25, -0.5, 0.75], 2)).unwrap();
+        assert_eq!((mixed.samples, mixed.channels, mixed.planes.len()), (vec![0.375, 0.125], 1, 2));
+        let picked = Deinterleave::new(ChannelSelect::Pick(1)).process(buffer(vec![0.5, 0.25, -0.5, 0.75], 2)).unwrap();
+        assert_eq!(picked.samples, vec![0.25, 0.75]);
+        let mono = Deinterleave::new(ChannelSelect::Mix).process(buffer(vec![0.5, 0.25], 1)).unwrap();
+        assert_eq!(mono.samples, vec![0.5, 0.25]);
+        assert!(Deinterleave::new(ChannelSelect::Pick(2)).process(buffer(vec![0.5, 0.25], 2)).err().unwrap().contains("channel 2"));
+    }
+}
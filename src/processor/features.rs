@@ -0,0 +1,96 @@
+//! Этап `features`: вычисление векторов признаков сигнала.
+
+use std::f32::consts::PI;
+
+use super::stage::{AudioBuffer, ProcessStage};
+
+/// Вычисляет для каждого окна анализа логарифмы энергий в равных по ширине частотных полосах
+#[derive(Debug)]
+pub struct Features {
+    frame_ms: u32,
+    hop_ms: u32,
+    bands: usize
+}
+
+impl Features {
+
+    pub fn new(frame_ms: u32, hop_ms: u32, bands: usize) -> Features {
+        Features {
+            frame_ms,
+            hop_ms,
+            bands
+        }
+    }
+}
+
+impl ProcessStage for Features {
+
+    fn name(&self) -> &'static str {
+        "features"
+    }
+
+    fn process(&self, mut buf: AudioBuffer) -> Result<AudioBuffer, String> {
+        let frame = (buf.rate as usize * self.frame_ms as usize) / 1000;
+        let hop = (buf.rate as usize * self.hop_ms as usize) / 1000;
+        let bins = frame / 2;
+        if self.bands == 0 || hop == 0 || bins < self.bands {
+            return Err(format!("{} bands do not fit {} ms frame at {} Hz", self.bands, self.frame_ms, buf.rate));
+        }
+        let window: Vec<f32> = (0..frame)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / frame as f32).cos())
+            .collect();
+        let mut features = Vec::new();
+        let mut start = 0;
+        while start + frame <= buf.samples.len() {
+            let x = &buf.samples[start..start + frame];
+            let mut energy = vec![0f32; self.bands];
+            for k in 1..=bins {
+                let (mut re, mut im) = (0f32, 0f32);
+                for (n, v) in x.iter().enumerate() {
+                    let phi = 2.0 * PI * (k * n % frame) as f32 / frame as f32;
+                    let w = v * window[n];
+                    re += w * phi.cos();
+                    im -= w * phi.sin();
+                }
+                let band = ((k - 1) * self.bands / bins).min(self.bands - 1);
+                energy[band] += re * re + im * im;
+            }
+            features.extend(energy.iter().map(|e| (e + 1e-10).ln()));
+            start += hop;
+        }
+        buf.features = features;
+        buf.feature_dim = self.bands;
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{ByteOrder, SampleFormat};
+
+    fn buffer(samples: Vec<f32>) -> AudioBuffer {
+        let mut buf = AudioBuffer::new(Vec::new(), 1000, 1, SampleFormat::S16, ByteOrder::Little);
+        buf.samples = samples;
+        buf
+    }
+
+    #[test]
+    fn computes_a_vector_per_hop() {
+        // 1 kHz: a 16 ms frame of 16 samples, an 8 ms hop, so 40 samples give 4 frames
+        let tone: Vec<f32> = (0..40).map(|n| (2.0 * PI * n as f32 / 4.0).sin()).collect();
+        let out = Features::new(16, 8, 4).process(buffer(tone)).unwrap();
+        assert_eq!(out.feature_dim, 4);
+        assert_eq!(out.features.len(), 4 * 4);
+        // the tone of a quarter of the rate is the bin 4 of 8, the second band
+        let first = &out.features[..4];
+        assert!([0, 2, 3].iter().all(|b| first[1] > first[*b]));
+    }
+
+    #[test]
+    fn rejects_the_bands_not_fitting_the_frame() {
+        assert!(Features::new(4, 2, 4).process(buffer(vec![0.0; 16])).is_err());
+        assert!(Features::new(16, 0, 4).process(buffer(vec![0.0; 16])).is_err());
+    }
+}
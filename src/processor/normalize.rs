@@ -0,0 +1,60 @@
+//! Этап `normalize`: нормализация сигнала по пиковому уровню.
+
+use super::stage::{AudioBuffer, ProcessStage};
+
+/// Масштабирует сигнал так, чтобы его максимальная амплитуда стала равна заданному уровню
+#[derive(Debug)]
+pub struct Normalize {
+    peak: f32
+}
+
+impl Normalize {
+
+    pub fn new(peak: f32) -> Normalize {
+        Normalize {
+            peak
+        }
+    }
+}
+
+impl ProcessStage for Normalize {
+
+    fn name(&self) -> &'static str {
+        "normalize"
+    }
+
+    fn process(&self, mut buf: AudioBuffer) -> Result<AudioBuffer, String> {
+        let max = buf.samples.iter().fold(0f32, |m, v| m.max(v.abs()));
+        // digital silence stays as is
+        if max > 0.0 {
+            let k = self.peak / max;
+            for v in buf.samples.iter_mut() {
+                *v *= k;
+            }
+        }
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{ByteOrder, SampleFormat};
+
+    fn normalize(peak: f32, samples: Vec<f32>) -> Vec<f32> {
+        let mut buf = AudioBuffer::new(Vec::new(), 8000, 1, SampleFormat::S16, ByteOrder::Little);
+        buf.samples = samples;
+        Normalize::new(peak).process(buf).unwrap().samples
+    }
+
+    #[test]
+    fn scales_to_the_peak() {
+        assert_eq!(normalize(0.5, vec![0.25, -0.125]), vec![0.5, -0.25]);
+    }
+
+    #[test]
+    fn keeps_the_silence() {
+        assert_eq!(normalize(1.0, vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+}
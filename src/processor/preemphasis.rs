@@ -0,0 +1,61 @@
+//! Этап `preemphasis`: подъем высоких частот сигнала перед выделением признаков.
+
+use super::stage::{AudioBuffer, ProcessStage};
+
+/// Фильтр первого порядка `y[n] = x[n] - a * x[n-1]`, первый отсчет сохраняется как есть.
+/// При `a` = 0 сигнал не изменяется
+#[derive(Debug)]
+pub struct Preemphasis {
+    a: f32
+}
+
+impl Preemphasis {
+
+    pub fn new(a: f32) -> Preemphasis {
+        Preemphasis {
+            a
+        }
+    }
+}
+
+impl ProcessStage for Preemphasis {
+
+    fn name(&self) -> &'static str {
+        "preemphasis"
+    }
+
+    fn process(&self, mut buf: AudioBuffer) -> Result<AudioBuffer, String> {
+        if self.a == 0.0 {
+            return Ok(buf);
+        }
+        // backwards, so every sample still sees the original previous one
+        for n in (1..buf.samples.len()).rev() {
+            buf.samples[n] -= self.a * buf.samples[n - 1];
+        }
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{ByteOrder, SampleFormat};
+
+    fn preemphasis(a: f32, samples: Vec<f32>) -> Vec<f32> {
+        let mut buf = AudioBuffer::new(Vec::new(), 8000, 1, SampleFormat::S16, ByteOrder::Little);
+        buf.samples = samples;
+        Preemphasis::new(a).process(buf).unwrap().samples
+    }
+
+    #[test]
+    fn subtracts_the_original_previous_sample() {
+        assert_eq!(preemphasis(0.5, vec![1.0, 1.0, 0.5, 0.0]), vec![1.0, 0.5, 0.0, -0.25]);
+    }
+
+    #[test]
+    fn keeps_the_signal_when_disabled() {
+        assert_eq!(preemphasis(0.0, vec![1.0, -1.0]), vec![1.0, -1.0]);
+        assert_eq!(preemphasis(0.97, vec![]), Vec::<f32>::new());
+    }
+}
@@ -0,0 +1,107 @@
+//! Этап `resample`: приведение сигнала к заданной частоте дискретизации.
+
+use super::stage::{AudioBuffer, ProcessStage};
+
+/// Передискретизирует сигнал линейной интерполяцией между соседними отсчетами
+#[derive(Debug)]
+pub struct Resample {
+    rate: u32
+}
+
+impl Resample {
+
+    pub fn new(rate: u32) -> Resample {
+        Resample {
+            rate
+        }
+    }
+}
+
+impl ProcessStage for Resample {
+
+    fn name(&self) -> &'static str {
+        "resample"
+    }
+
+    fn process(&self, mut buf: AudioBuffer) -> Result<AudioBuffer, String> {
+        if self.rate == 0 || buf.rate == 0 {
+            return Err("zero sample rate".to_string());
+        }
+        // the raw bytes left unconverted would pass as the empty signal resampled
+        if buf.samples.is_empty() && !buf.raw.is_empty() {
+            return Err("no samples, the stage goes after convert".to_string());
+        }
+        if buf.rate == self.rate || buf.samples.is_empty() {
+            buf.rate = self.rate;
+            return Ok(buf);
+        }
+        buf.samples = interpolate(&buf.samples, buf.rate, self.rate);
+        buf.rate = self.rate;
+        Ok(buf)
+    }
+}
+
+/// Передискретизирует сигнал `src` с частотой `from` к частоте `to` линейной интерполяцией, частоты не нулевые
+pub fn interpolate(src: &[f32], from: u32, to: u32) -> Vec<f32> {
+    let step = from as f64 / to as f64;
+    let len = (src.len() as f64 / step).floor() as usize;
+    let mut out = Vec::with_capacity(len);
+    for i in 0..len {
+        let pos = i as f64 * step;
+        let idx = pos as usize;
+        let frac = (pos - idx as f64) as f32;
+        let a = src[idx];
+        let b = if idx + 1 < src.len() { src[idx + 1] } else { a };
+        out.push(a + (b - a) * frac);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{ByteOrder, SampleFormat};
+
+    fn buffer(raw: Vec<u8>, samples: Vec<f32>, rate: u32) -> AudioBuffer {
+        let mut buf = AudioBuffer::new(raw, rate, 1, SampleFormat::S16, ByteOrder::Little);
+        buf.samples = samples;
+        buf
+    }
+
+    #[test]
+    fn halves_the_rate() {
+        let out = Resample::new(8000).process(buffer(vec![0; 8], vec![0.0, 0.5, 1.0, 0.5], 16000)).unwrap();
+        assert_eq!(out.rate, 8000);
+        assert_eq!(out.samples, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn doubles_the_rate_interpolating() {
+        assert_eq!(interpolate(&[0.0, 1.0], 8000, 16000), vec![0.0, 0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn keeps_the_same_rate() {
+        let out = Resample::new(16000).process(buffer(vec![0; 4], vec![0.25, -0.25], 16000)).unwrap();
+        assert_eq!(out.samples, vec![0.25, -0.25]);
+    }
+
+    #[test]
+    fn passes_the_empty_input() {
+        let out = Resample::new(8000).process(buffer(Vec::new(), Vec::new(), 16000)).unwrap();
+        assert_eq!(out.rate, 8000);
+        assert!(out.samples.is_empty());
+    }
+
+    #[test]
+    fn rejects_the_unconverted_input() {
+        assert!(Resample::new(8000).process(buffer(vec![0; 4], Vec::new(), 16000)).is_err());
+    }
+
+    #[test]
+    fn rejects_a_zero_rate() {
+        assert!(Resample::new(0).process(buffer(Vec::new(), vec![0.0], 16000)).is_err());
+        assert!(Resample::new(8000).process(buffer(Vec::new(), vec![0.0], 0)).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Общий интерфейс этапа обработки и построение цепочки этапов по конфигурации.
+
+use std::fmt;
+
+use crate::config::{ByteOrder, SampleFormat, SharedConfig};
+
+use super::convert::Convert;
+use super::deinterleave::Deinterleave;
+use super::features::Features;
+use super::normalize::Normalize;
+use super::preemphasis::Preemphasis;
+use super::resample::Resample;
+use super::trim::Trim;
+
+/// Обрабатываемый этапами цепочки звуковой буфер вместе с сопутствующими ему параметрами
+pub struct AudioBuffer {
+    /// Упакованные байты сессии, еще не преобразованные в отсчеты
+    pub raw: Vec<u8>,
+    /// Формат и порядок байтов отсчетов в упакованных байтах сессии
+    pub format: SampleFormat,
+    pub order: ByteOrder,
+    /// Отсчеты сигнала в диапазоне [-1, 1]
+    pub samples: Vec<f32>,
+    /// Количество чередующихся каналов в отсчетах, 1 после этапа `deinterleave`
+    pub channels: usize,
+    /// Отсчеты каждого из каналов, разделенные этапом `deinterleave`, пусто до него
+    pub planes: Vec<Vec<f32>>,
+    /// Частота дискретизации отсчетов, Гц
+    pub rate: u32,
+    /// Векторы признаков, уложенные подряд по `feature_dim` значений
+    pub features: Vec<f32>,
+    /// Размерность одного вектора признаков, 0 если признаки не вычислялись
+    pub feature_dim: usize
+}
+
+impl AudioBuffer {
+
+    /// Создает буфер из упакованных байтов сессии с заданными частотой дискретизации, количеством каналов
+    /// и форматом отсчетов
+    pub fn new(raw: Vec<u8>, rate: u32, channels: usize, format: SampleFormat, order: ByteOrder) -> AudioBuffer {
+        AudioBuffer {
+            raw,
+            format,
+            order,
+            samples: Vec::new(),
+            channels,
+            planes: Vec::new(),
+            rate,
+            features: Vec::new(),
+            feature_dim: 0
+        }
+    }
+
+    /// Размерность векторов в упакованном результате обработки
+    pub fn dim(&self) -> usize {
+        if self.feature_dim > 0 { self.feature_dim } else { 1 }
+    }
+
+    /// Упаковывает результат обработки для передачи в inference:
+    /// признаки, если они вычислены, иначе отсчеты сигнала, как последовательность f32 little-endian
+    pub fn pack(&self) -> Vec<u8> {
+        let src = if self.feature_dim > 0 { &self.features } else { &self.samples };
+        let mut out = Vec::with_capacity(src.len() * 4);
+        for v in src {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Этап обработки звукового буфера в цепочке процессора.
+/// Отладочное представление этапа перечисляет все параметры, от которых зависит результат его обработки
+pub trait ProcessStage: Send + Sync + fmt::Debug {
+
+    /// Имя этапа, под которым он указывается в настройках
+    fn name(&self) -> &'static str;
+
+    /// Обрабатывает буфер и возвращает результат для следующего этапа либо описание ошибки
+    fn process(&self, buf: AudioBuffer) -> Result<AudioBuffer, String>;
+}
+
+/// Упорядоченная цепочка этапов обработки
+pub type Chain = Vec<Box<dyn ProcessStage>>;
+
+/// Строит цепочку этапов в порядке, заданном в настройках `[processor] pipeline`.
+/// Этап, отсутствующий в списке, не выполняется. Неизвестное имя этапа является ошибкой
+pub fn build_chain(cfg: &SharedConfig) -> Result<Chain, String> {
+    let mut chain: Chain = Vec::new();
+    for name in cfg.pipeline() {
+        let stage: Box<dyn ProcessStage> = match name.as_str() {
+            "convert" => Box::new(Convert),
+            "deinterleave" => Box::new(Deinterleave::new(cfg.channel())),
+            "resample" => Box::new(Resample::new(cfg.target_rate())),
+            "normalize" => Box::new(Normalize::new(cfg.normalize_peak())),
+            "preemphasis" => Box::new(Preemphasis::new(cfg.preemphasis())),
+            "trim" => Box::new(Trim::new(cfg.trim_threshold())),
+            "features" => Box::new(Features::new(cfg.frame_ms(), cfg.hop_ms(), cfg.feature_bands())),
+            _ => return Err(format!("unknown processing stage '{}'", name))
+        };
+        chain.push(stage);
+    }
+    Ok(chain)
+}
+
+/// Последовательно пропускает буфер через все этапы цепочки
+pub fn run_chain(chain: &[Box<dyn ProcessStage>], buf: AudioBuffer) -> Result<AudioBuffer, String> {
+    let mut buf = buf;
+    for stage in chain {
+        buf = stage.process(buf).map_err(|e| format!("{}: {}", stage.name(), e))?;
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    fn buffer(samples: Vec<f32>) -> AudioBuffer {
+        let mut buf = AudioBuffer::new(Vec::new(), 16000, 1, SampleFormat::S16, ByteOrder::Little);
+        buf.samples = samples;
+        buf
+    }
+
+    #[test]
+    fn builds_the_configured_order() {
+        let cfg = Config::from_sources(&[], &["processor.pipeline=convert, trim, normalize"]).unwrap();
+        let names: Vec<&str> = build_chain(&cfg).unwrap().iter().map(|s| s.name()).collect();
+        assert_eq!(names, vec!["convert", "trim", "normalize"]);
+    }
+
+    #[test]
+    fn rejects_an_unknown_stage() {
+        let cfg = Config::from_sources(&[], &["processor.pipeline=convert, denoise"]).unwrap();
+        assert_eq!(build_chain(&cfg).unwrap_err(), "unknown processing stage 'denoise'");
+    }
+
+    #[test]
+    fn runs_the_stages_in_order() {
+        let chain: Chain = vec![Box::new(Trim::new(0.1)), Box::new(Normalize::new(1.0))];
+        let out = run_chain(&chain, buffer(vec![0.0, 0.25, -0.5, 0.05])).unwrap();
+        assert_eq!(out.samples, vec![0.5, -1.0]);
+    }
+
+    #[test]
+    fn names_the_failed_stage() {
+        let raw = vec![0u8; 3];
+        let chain: Chain = vec![Box::new(Convert)];
+        let e = run_chain(&chain, AudioBuffer::new(raw, 16000, 1, SampleFormat::S16, ByteOrder::Little)).err().unwrap();
+        assert!(e.starts_with("convert: "), "{}", e);
+    }
+
+    #[test]
+    fn packs_features_before_samples() {
+        let mut buf = buffer(vec![0.5]);
+        assert_eq!((buf.pack(), buf.dim()), (0.5f32.to_le_bytes().to_vec(), 1));
+        buf.features = vec![1.0, 2.0];
+        buf.feature_dim = 2;
+        assert_eq!(buf.pack(), [1.0f32.to_le_bytes(), 2.0f32.to_le_bytes()].concat());
+        assert_eq!(buf.dim(), 2);
+    }
+}
@@ -0,0 +1,61 @@
+//! Этап `trim`: отбрасывание тишины в начале и в конце сигнала.
+
+use super::stage::{AudioBuffer, ProcessStage};
+
+/// Удаляет начальные и конечные отсчеты, амплитуда которых не превышает порога
+#[derive(Debug)]
+pub struct Trim {
+    threshold: f32
+}
+
+impl Trim {
+
+    pub fn new(threshold: f32) -> Trim {
+        Trim {
+            threshold
+        }
+    }
+}
+
+impl ProcessStage for Trim {
+
+    fn name(&self) -> &'static str {
+        "trim"
+    }
+
+    fn process(&self, mut buf: AudioBuffer) -> Result<AudioBuffer, String> {
+        let loud = |v: &f32| v.abs() > self.threshold;
+        match buf.samples.iter().position(loud) {
+            None => buf.samples.clear(),
+            Some(first) => {
+                let last = buf.samples.iter().rposition(loud).unwrap_or(first);
+                buf.samples.truncate(last + 1);
+                buf.samples.drain(..first);
+            }
+        }
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{ByteOrder, SampleFormat};
+
+    fn trim(threshold: f32, samples: Vec<f32>) -> Vec<f32> {
+        let mut buf = AudioBuffer::new(Vec::new(), 8000, 1, SampleFormat::S16, ByteOrder::Little);
+        buf.samples = samples;
+        Trim::new(threshold).process(buf).unwrap().samples
+    }
+
+    #[test]
+    fn removes_the_quiet_ends() {
+        assert_eq!(trim(0.1, vec![0.0, 0.05, 0.5, 0.0, -0.3, 0.1]), vec![0.5, 0.0, -0.3]);
+    }
+
+    #[test]
+    fn clears_the_quiet_signal() {
+        assert!(trim(0.1, vec![0.05, -0.1]).is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+//! Проверка согласованности данных сеанса с объявленными параметрами звука до обработки.
+
+use crate::config::SampleFormat;
+use crate::data::AudioParams;
+
+/// Проверяет, что упакованные байты `value` сеанса являются отсчетами формата `format` с частотой дискретизации `rate`,
+/// как их обрабатывает цепочка, согласно объявленным параметрам `audio`. Несколько каналов допустимы
+/// только при `multichannel`, когда цепочка их разделяет. Возвращает описание несоответствия
+pub fn check(value: &[u8], audio: Option<AudioParams>, rate: u32, multichannel: bool, format: SampleFormat) -> Result<(), String> {
+    if value.is_empty() {
+        return Err("no audio".to_string());
+    }
+    let audio = audio.ok_or_else(|| format!("{} bytes without declared audio parameters", value.len()))?;
+    if audio.channels == 0 {
+        return Err("no channels declared".to_string());
+    }
+    if audio.channels != 1 && !multichannel {
+        return Err(format!("{} channels declared, mono is processed without deinterleave", audio.channels));
+    }
+    // a peer not declaring the rate is taken at the configured one
+    if audio.rate != 0 && audio.rate != rate {
+        return Err(format!("{} Hz declared, {} Hz is processed", audio.rate, rate));
+    }
+    if !value.len().is_multiple_of(format.width()) {
+        return Err(format!("length {} is not a whole number of {} samples", value.len(), format));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio(rate: u32, channels: u8) -> Option<AudioParams> {
+        Some(AudioParams { rate, channels, format: 0 })
+    }
+
+    #[test]
+    fn accepts_the_declared_audio() {
+        assert_eq!(check(&[0; 4], audio(8000, 1), 8000, false, SampleFormat::S16), Ok(()));
+        // the rate not declared is the configured one
+        assert_eq!(check(&[0; 4], audio(0, 1), 8000, false, SampleFormat::S16), Ok(()));
+        assert_eq!(check(&[0; 4], audio(8000, 2), 8000, true, SampleFormat::S16), Ok(()));
+    }
+
+    #[test]
+    fn describes_the_mismatch() {
+        assert_eq!(check(&[], audio(8000, 1), 8000, false, SampleFormat::S16), Err("no audio".to_string()));
+        assert!(check(&[0; 4], None, 8000, false, SampleFormat::S16).err().unwrap().contains("without declared audio"));
+        assert!(check(&[0; 4], audio(8000, 0), 8000, false, SampleFormat::S16).err().unwrap().contains("no channels"));
+        assert!(check(&[0; 4], audio(8000, 2), 8000, false, SampleFormat::S16).err().unwrap().contains("2 channels"));
+        assert!(check(&[0; 4], audio(16000, 1), 8000, false, SampleFormat::S16).err().unwrap().contains("16000 Hz"));
+        assert!(check(&[0; 3], audio(8000, 1), 8000, false, SampleFormat::S16).err().unwrap().contains("length 3"));
+    }
+}
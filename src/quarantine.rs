@@ -0,0 +1,139 @@
+//! Карантин сеансов систем сопряжения, обработка которых раз за разом завершается ошибкой.
+//!
+//! После `[quarantine] failures` ошибок обработки или расчета результата подряд для сеансов одной системы
+//! сопряжения ее последующие сеансы не обрабатываются, а сохраняются без изменений в каталог `[quarantine] dir`
+//! для последующего разбора. Остальные системы сопряжения обслуживаются как обычно.
+//! Карантин действует до перезапуска приложения
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::SharedConfig;
+use crate::events;
+use crate::stats::SharedStats;
+
+use log::{debug, error};
+use serde_json::json;
+
+/// Учет ошибок подряд по каждой системе сопряжения и сохранение сеансов, помещенных в карантин
+pub struct Quarantine {
+    limit: u32,
+    dir: PathBuf,
+    /// Имена подкаталогов систем сопряжения в каталоге карантина
+    names: Vec<String>,
+    failures: Vec<AtomicU32>,
+    stats: SharedStats
+}
+
+pub type SharedQuarantine = Arc<Quarantine>;
+
+impl Quarantine {
+
+    pub fn new(cfg: &SharedConfig, stats: SharedStats) -> SharedQuarantine {
+        let peers = cfg.peers();
+        Arc::new(Quarantine {
+            limit: cfg.quarantine_failures(),
+            dir: PathBuf::from(cfg.quarantine_dir()),
+            // ':' is not allowed in the file names everywhere
+            names: peers.iter().map(|p| p.to_string().replace(':', "_")).collect(),
+            failures: peers.iter().map(|_| AtomicU32::new(0)).collect(),
+            stats
+        })
+    }
+
+    /// Проверяет, помещены ли в карантин сеансы системы сопряжения `peer`
+    pub fn is_held(&self, peer: usize) -> bool {
+        self.limit > 0 && self.failures[peer].load(Ordering::Relaxed) >= self.limit
+    }
+
+    /// Учитывает ошибку обработки сеанса системы сопряжения `peer`
+    pub fn failure(&self, peer: usize) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.failures[peer].fetch_add(1, Ordering::Relaxed) + 1 == self.limit {
+            error!("quarantine: {} failures in a row, further sessions of peer {} are quarantined to {}",
+                self.limit, peer, self.dir.join(&self.names[peer]).display());
+        }
+    }
+
+    /// Учитывает успешную обработку сеанса системы сопряжения `peer`, счетчик ошибок подряд сбрасывается.
+    /// Карантин при этом не снимается
+    pub fn success(&self, peer: usize) {
+        if self.limit > 0 && !self.is_held(peer) {
+            self.failures[peer].store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Сохраняет данные части `chunk` сеанса `id` в том виде, в каком их получил этап `stage`,
+    /// в файл `<dir>/<peer addr>_<port>/<id>_<chunk>_<unix time ms>.<stage>.bin`
+    pub fn hold(&self, peer: usize, id: u32, chunk: u32, stage: &str, value: &[u8]) {
+        self.stats.peer(peer).quarantined();
+        events::emit("quarantined", json!({ "stage": stage, "peer": peer, "id": id, "chunk": chunk }));
+        let dir = self.dir.join(&self.names[peer]);
+        let ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let path = dir.join(format!("{}_{}_{}.{}.bin", id, chunk, ms, stage));
+        match std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, value)) {
+            Ok(()) => debug!("quarantine: session {} of peer {} is saved to {}", id, peer, path.display()),
+            Err(e) => error!("quarantine: failed to save session {} of peer {}: {}", id, peer, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::stats::Stats;
+
+    fn quarantine(name: &str, failures: u32) -> (PathBuf, SharedQuarantine) {
+        let dir = std::env::temp_dir().join(format!("banshee-quarantine-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        let sets = [
+            "input.peers=127.0.0.1:12000,127.0.0.1:12001".to_string(),
+            format!("quarantine.failures={}", failures),
+            format!("quarantine.dir={}", dir.display())
+        ];
+        let sets: Vec<&str> = sets.iter().map(|s| s.as_str()).collect();
+        let cfg = Config::from_sources(&[], &sets).unwrap();
+        let stats = Stats::new(&cfg.peers());
+        (dir, Quarantine::new(&cfg, stats))
+    }
+
+    #[test]
+    fn holds_the_peer_after_failures_in_a_row() {
+        let (_, q) = quarantine("row", 2);
+        q.failure(0);
+        q.success(0);
+        q.failure(0);
+        assert!(!q.is_held(0));
+        q.failure(0);
+        assert!(q.is_held(0));
+        assert!(!q.is_held(1));
+        // a success doesn't lift it
+        q.success(0);
+        assert!(q.is_held(0));
+    }
+
+    #[test]
+    fn never_holds_when_disabled() {
+        let (_, q) = quarantine("disabled", 0);
+        (0..10).for_each(|_| q.failure(1));
+        assert!(!q.is_held(1));
+    }
+
+    #[test]
+    fn saves_the_session_as_received() {
+        let (dir, q) = quarantine("hold", 1);
+        q.hold(1, 7, 2, "resample", b"raw");
+        let files: Vec<PathBuf> = std::fs::read_dir(dir.join("127.0.0.1_12001")).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        let name = files[0].file_name().unwrap().to_str().unwrap().to_string();
+        assert!(name.starts_with("7_2_") && name.ends_with(".resample.bin"), "{}", name);
+        assert_eq!(std::fs::read(&files[0]).unwrap(), b"raw");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
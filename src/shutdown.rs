@@ -0,0 +1,86 @@
+//! Остановка приложения по требованию подсистемы, обнаружившей невосстановимый отказ конвейера.
+//!
+//! Остановка выполняется так же, как по сигналу: подсистемы завершают работу по порядку в пределах
+//! `shutdown_timeout`. После нее приложение завершается с ненулевым кодом, чтобы менеджер служб перезапустил его
+
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::sync::watch;
+
+/// Причина требуемой остановки, None - остановка не требовалась
+type Reason = Option<&'static str>;
+
+/// Требование остановки, канал создается при первом обращении
+static STATE: Mutex<Option<(watch::Sender<Reason>, watch::Receiver<Reason>)>> = Mutex::new(None);
+
+/// Признак начатой остановки, по сигналу или по требованию
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Канал признака начатой остановки для ее ожидания, создается при первом обращении
+static STOPPING_STATE: Mutex<Option<(watch::Sender<bool>, watch::Receiver<bool>)>> = Mutex::new(None);
+
+/// Отмечает начало остановки. Подсистемы останавливаются независимо, поэтому после нее завершение
+/// следующей по конвейеру подсистемы раньше предыдущей ожидаемо и не является отказом
+pub fn set_stopping() {
+    STOPPING.store(true, Ordering::SeqCst);
+    let _ = stopping_state().get_or_insert_with(|| watch::channel(false)).0.broadcast(true);
+}
+
+/// Начата ли остановка
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Дожидается начала остановки
+pub async fn stopping() {
+    let mut rx = stopping_state().get_or_insert_with(|| watch::channel(false)).1.clone();
+    // the flag is set before the broadcast, a stop begun after the check is seen by the receiver
+    while !is_stopping() {
+        if rx.recv().await.is_none() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Требует остановки приложения по причине `reason`. Возвращает false, если остановка уже требовалась
+pub fn request(reason: &'static str) -> bool {
+    let mut s = state();
+    let (tx, rx) = s.get_or_insert_with(|| watch::channel(None));
+    if rx.borrow().is_some() {
+        return false;
+    }
+    let _ = tx.broadcast(Some(reason));
+    true
+}
+
+/// Причина требуемой остановки, None - остановка не требовалась
+pub fn reason() -> Reason {
+    *receiver().borrow()
+}
+
+/// Дожидается требования остановки, возвращает его причину
+pub async fn requested() -> &'static str {
+    let mut rx = receiver();
+    loop {
+        if let Some(r) = *rx.borrow() {
+            return r;
+        }
+        if rx.recv().await.is_none() {
+            // the sender lives in the static, it is never dropped
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+fn receiver() -> watch::Receiver<Reason> {
+    state().get_or_insert_with(|| watch::channel(None)).1.clone()
+}
+
+fn stopping_state() -> MutexGuard<'static, Option<(watch::Sender<bool>, watch::Receiver<bool>)>> {
+    STOPPING_STATE.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+fn state() -> MutexGuard<'static, Option<(watch::Sender<Reason>, watch::Receiver<Reason>)>> {
+    STATE.lock().unwrap_or_else(|p| p.into_inner())
+}
@@ -0,0 +1,746 @@
+//! Счетчики работы подсистем конвейера, разделяемые между ними без блокировок.
+//!
+//! Каждая подсистема учитывает полученные, переданные дальше и отброшенные объекты.
+//! Заполненность межпоточного канала вычисляется как разность между количеством переданных в него
+//! вышестоящей подсистемой и полученных из него нижестоящей подсистемой объектов.
+//! Размеры фрагментов и длительности сеансов учитываются распределениями по корзинам.
+//! Для подбора параметров пакетного расчета inference учитывает размеры и частоту пакетов, а также загрузку GPU,
+//! если вычислитель ее сообщает.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::config::Endpoint;
+use crate::inflight;
+use crate::memory;
+
+/// Счетчики одной подсистемы
+#[derive(Default)]
+pub struct Counters {
+    received: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    expired: AtomicU64,
+    late: AtomicU64,
+    spilled: AtomicU64,
+    malformed: AtomicU64
+}
+
+impl Counters {
+
+    /// Учитывает полученный на вход объект
+    pub fn received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает `n` полученных на вход объектов
+    pub fn add_received(&self, n: usize) {
+        self.received.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Учитывает переданный на выход объект
+    pub fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает `n` переданных на выход объектов
+    pub fn add_sent(&self, n: usize) {
+        self.sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Учитывает отброшенный объект
+    pub fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает объект, отброшенный из-за истечения срока обработки, он также учитывается как отброшенный
+    pub fn expired(&self) {
+        self.expired.fetch_add(1, Ordering::Relaxed);
+        self.dropped();
+    }
+
+    /// Учитывает объект, опоздавший к уже переданному дальше объекту, он также учитывается как отброшенный
+    pub fn late(&self) {
+        self.late.fetch_add(1, Ordering::Relaxed);
+        self.dropped();
+    }
+
+    /// Учитывает объект с несогласованными данными, он также учитывается как отброшенный
+    pub fn malformed(&self) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+        self.dropped();
+    }
+
+    /// Учитывает объект, переданный на выход через дисковую очередь, он также учитывается как переданный
+    pub fn spilled(&self) {
+        self.spilled.fetch_add(1, Ordering::Relaxed);
+        self.sent();
+    }
+
+    /// Количество полученных, переданных и отброшенных объектов
+    pub fn totals(&self) -> (u64, u64, u64) {
+        (self.received.load(Ordering::Relaxed), self.sent.load(Ordering::Relaxed), self.dropped.load(Ordering::Relaxed))
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "received": self.received.load(Ordering::Relaxed),
+            "sent": self.sent.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "expired": self.expired.load(Ordering::Relaxed),
+            "late": self.late.load(Ordering::Relaxed),
+            "spilled": self.spilled.load(Ordering::Relaxed),
+            "malformed": self.malformed.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Счетчики результатов inference с уверенностью ниже порога `min_confidence`
+#[derive(Default)]
+pub struct ConfidenceCounters {
+    flagged: AtomicU64,
+    dropped: AtomicU64
+}
+
+impl ConfidenceCounters {
+
+    /// Учитывает результат, сохраненный с отметкой о низкой уверенности
+    pub fn flagged(&self) {
+        self.flagged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает результат, отброшенный из-за низкой уверенности
+    pub fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "flagged": self.flagged.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Счетчики фрагментов без звука, отделенных коллектором от звуковых
+#[derive(Default)]
+pub struct NonAudioCounters {
+    stored: AtomicU64,
+    dropped: AtomicU64
+}
+
+impl NonAudioCounters {
+
+    /// Учитывает сохраненный фрагмент без звука
+    pub fn stored(&self) {
+        self.stored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает отброшенный фрагмент без звука
+    pub fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "stored": self.stored.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Счетчики обращений к сохраненным результатам обработки
+#[derive(Default)]
+pub struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64
+}
+
+impl CacheCounters {
+
+    /// Учитывает сеанс, результат обработки которого взят из сохраненных
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает сеанс, для которого сохраненного результата обработки не нашлось
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "hits": self.hits.load(Ordering::Relaxed),
+            "misses": self.misses.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Счетчики сравнения результатов с теневым вычислителем
+#[derive(Default)]
+pub struct ShadowCounters {
+    matched: AtomicU64,
+    mismatched: AtomicU64,
+    failed: AtomicU64,
+    skipped: AtomicU64
+}
+
+impl ShadowCounters {
+
+    /// Учитывает результат теневого вычислителя, совпавший с основным
+    pub fn matched(&self) {
+        self.matched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает результат теневого вычислителя, расходящийся с основным
+    pub fn mismatched(&self) {
+        self.mismatched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает сэмпл, который рассчитал только один из вычислителей
+    pub fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает `n` сэмплов, теневой расчет которых пропущен из-за нагрузки
+    pub fn add_skipped(&self, n: usize) {
+        self.skipped.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "matched": self.matched.load(Ordering::Relaxed),
+            "mismatched": self.mismatched.load(Ordering::Relaxed),
+            "failed": self.failed.load(Ordering::Relaxed),
+            "skipped": self.skipped.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Границы корзин распределения размеров фрагментов, байтов
+const FRAGMENT_BYTES: &[u64] = &[64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 65536];
+/// Границы корзин распределения длительностей сеансов, мс
+const SESSION_MS: &[u64] = &[100, 250, 500, 1000, 2500, 5000, 10000, 30000, 60000];
+/// Границы корзин распределения количества фрагментов в собранных сеансах
+const SESSION_FRAGMENTS: &[u64] = &[1, 2, 4, 8, 16, 32, 64, 128, 256, 1024];
+/// Границы корзин распределения времени сборки сеансов от первого фрагмента до передачи на обработку, мс
+const ASSEMBLY_MS: &[u64] = &[10, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+/// Границы корзин распределения размеров пакетов расчета, сэмплов
+const BATCH_SIZE: &[u64] = &[1, 2, 4, 8, 16, 32, 64, 128, 256];
+/// Количество полных секунд, по которым усредняется частота пакетов
+const RATE_WINDOW: usize = 10;
+
+/// Распределение значений по корзинам с заданными верхними границами.
+/// Значение попадает в первую корзину, граница которой не меньше него, либо в последнюю неограниченную
+pub struct Histogram {
+    bounds: &'static [u64],
+    /// По счетчику на каждую границу и один для значений больше последней
+    counts: Vec<AtomicU64>,
+    sum: AtomicU64
+}
+
+impl Histogram {
+
+    fn new(bounds: &'static [u64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0)
+        }
+    }
+
+    /// Учитывает значение
+    pub fn record(&self, v: u64) {
+        let i = self.bounds.iter().position(|b| v <= *b).unwrap_or(self.bounds.len());
+        self.counts[i].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(v, Ordering::Relaxed);
+    }
+
+    /// Сводка распределения для лога: количество, среднее и количество значений в каждой корзине.
+    /// В отличие от снимка корзины не накопительные, пустые корзины пропускаются
+    pub fn summary(&self) -> String {
+        let counts: Vec<u64> = self.counts.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return "none".to_string();
+        }
+        let mean = self.sum.load(Ordering::Relaxed) as f64 / total as f64;
+        let buckets: Vec<String> = counts.iter().enumerate()
+            .filter(|(_, c)| **c > 0)
+            .map(|(i, c)| match self.bounds.get(i) {
+                Some(b) => format!("<={}: {}", b, c),
+                None => format!(">{}: {}", self.bounds.last().copied().unwrap_or(0), c)
+            })
+            .collect();
+        format!("{}, mean {:.1} ({})", total, mean, buckets.join(", "))
+    }
+
+    // the buckets are cumulative as in prometheus: each counts the values up to its bound
+    fn snapshot(&self) -> Value {
+        let mut total = 0;
+        let mut buckets = Vec::with_capacity(self.counts.len());
+        for (i, c) in self.counts.iter().enumerate() {
+            total += c.load(Ordering::Relaxed);
+            let le = match self.bounds.get(i) {
+                Some(b) => json!(b),
+                None => json!("+Inf")
+            };
+            buckets.push(json!({ "le": le, "count": total }));
+        }
+        json!({
+            "buckets": buckets,
+            "count": total,
+            "sum": self.sum.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Загрузка GPU, сообщаемая вычислителем
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceLoad {
+    /// Загрузка вычислениями, проценты
+    pub utilization: u8,
+    pub memory_used: u64,
+    pub memory_total: u64
+}
+
+/// Показатели пакетного расчета подсистемы inference
+pub struct InferenceMetrics {
+    batches: AtomicU64,
+    last_batch: AtomicU64,
+    /// Действующий наибольший размер пакета, при подборе - текущий подобранный
+    batch_limit: AtomicU64,
+    /// Распределение размеров пакетов, переданных вычислителю
+    batch_size: Histogram,
+    // a ring of per second counts, each slot remembers the second it counts
+    rate_secs: Vec<AtomicU64>,
+    rate_counts: Vec<AtomicU64>,
+    gpu_known: AtomicBool,
+    gpu_utilization: AtomicU8,
+    gpu_memory_used: AtomicU64,
+    gpu_memory_total: AtomicU64,
+    /// Пакеты, не рассчитанные за `infer_timeout`
+    timeouts: AtomicU64,
+    /// Пересоздания зависшего вычислителя
+    resets: AtomicU64
+}
+
+impl InferenceMetrics {
+
+    fn new() -> InferenceMetrics {
+        InferenceMetrics {
+            batches: AtomicU64::new(0),
+            last_batch: AtomicU64::new(0),
+            batch_limit: AtomicU64::new(0),
+            batch_size: Histogram::new(BATCH_SIZE),
+            rate_secs: (0..=RATE_WINDOW).map(|_| AtomicU64::new(0)).collect(),
+            rate_counts: (0..=RATE_WINDOW).map(|_| AtomicU64::new(0)).collect(),
+            gpu_known: AtomicBool::new(false),
+            gpu_utilization: AtomicU8::new(0),
+            gpu_memory_used: AtomicU64::new(0),
+            gpu_memory_total: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            resets: AtomicU64::new(0)
+        }
+    }
+
+    /// Учитывает пакет из `size` сэмплов, переданный вычислителю
+    pub fn batch(&self, size: usize) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.last_batch.store(size as u64, Ordering::Relaxed);
+        self.batch_size.record(size as u64);
+        let sec = unix_secs();
+        let i = sec as usize % self.rate_secs.len();
+        // the first batch of a second reuses the slot, a batch racing with it may be lost, it's a rate anyway
+        if self.rate_secs[i].swap(sec, Ordering::Relaxed) != sec {
+            self.rate_counts[i].store(0, Ordering::Relaxed);
+        }
+        self.rate_counts[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Запоминает действующий наибольший размер пакета
+    pub fn set_batch_limit(&self, size: usize) {
+        self.batch_limit.store(size as u64, Ordering::Relaxed);
+    }
+
+    /// Запоминает загрузку GPU, сообщенную вычислителем
+    pub fn set_device(&self, load: DeviceLoad) {
+        self.gpu_utilization.store(load.utilization, Ordering::Relaxed);
+        self.gpu_memory_used.store(load.memory_used, Ordering::Relaxed);
+        self.gpu_memory_total.store(load.memory_total, Ordering::Relaxed);
+        self.gpu_known.store(true, Ordering::Relaxed);
+    }
+
+    /// Учитывает пакет, не рассчитанный за отведенное время
+    pub fn timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает пересоздание вычислителя
+    pub fn reset(&self) {
+        self.resets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // the current second is incomplete, the rate is over the complete ones before it
+    fn batches_per_sec(&self) -> f64 {
+        let now = unix_secs();
+        let total: u64 = self.rate_secs.iter().zip(&self.rate_counts)
+            .filter(|(s, _)| {
+                let s = s.load(Ordering::Relaxed);
+                s < now && now - s <= RATE_WINDOW as u64
+            })
+            .map(|(_, c)| c.load(Ordering::Relaxed))
+            .sum();
+        total as f64 / RATE_WINDOW as f64
+    }
+
+    fn snapshot(&self, queue_depth: u64) -> Value {
+        let gpu = if self.gpu_known.load(Ordering::Relaxed) {
+            json!({
+                "utilization": self.gpu_utilization.load(Ordering::Relaxed),
+                "memory_used": self.gpu_memory_used.load(Ordering::Relaxed),
+                "memory_total": self.gpu_memory_total.load(Ordering::Relaxed)
+            })
+        } else {
+            Value::Null
+        };
+        json!({
+            "queue_depth": queue_depth,
+            "batches": self.batches.load(Ordering::Relaxed),
+            "last_batch_size": self.last_batch.load(Ordering::Relaxed),
+            "effective_batch_size": self.batch_limit.load(Ordering::Relaxed),
+            "batches_per_sec": self.batches_per_sec(),
+            "batch_size": self.batch_size.snapshot(),
+            "timeouts": self.timeouts.load(Ordering::Relaxed),
+            "resets": self.resets.load(Ordering::Relaxed),
+            "gpu": gpu
+        })
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Состояние подключения к системе сопряжения
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PeerState {
+    Connecting = 0,
+    Handshaking = 1,
+    Connected = 2,
+    /// Ожидание повторного подключения
+    Waiting = 3,
+    Stopped = 4,
+    /// Подключения прекращены после исчерпания попыток
+    Down = 5,
+    /// Подключение приостановлено режимом вывода экземпляра из работы
+    Drained = 6
+}
+
+impl PeerState {
+
+    fn from_u8(v: u8) -> PeerState {
+        match v {
+            0 => PeerState::Connecting,
+            1 => PeerState::Handshaking,
+            2 => PeerState::Connected,
+            3 => PeerState::Waiting,
+            4 => PeerState::Stopped,
+            6 => PeerState::Drained,
+            _ => PeerState::Down
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PeerState::Connecting => "connecting",
+            PeerState::Handshaking => "handshaking",
+            PeerState::Connected => "connected",
+            PeerState::Waiting => "waiting",
+            PeerState::Stopped => "stopped",
+            PeerState::Down => "down",
+            PeerState::Drained => "drained"
+        }
+    }
+}
+
+/// Счетчики подключения к одной системе сопряжения
+pub struct PeerStats {
+    endpoint: String,
+    state: AtomicU8,
+    connects: AtomicU64,
+    frames: AtomicU64,
+    quarantined: AtomicU64,
+    overloads: AtomicU64
+}
+
+impl PeerStats {
+
+    fn new(endpoint: &Endpoint) -> PeerStats {
+        PeerStats {
+            endpoint: endpoint.to_string(),
+            state: AtomicU8::new(PeerState::Connecting as u8),
+            connects: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+            quarantined: AtomicU64::new(0),
+            overloads: AtomicU64::new(0)
+        }
+    }
+
+    pub fn set_state(&self, state: PeerState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+    }
+
+    pub fn state(&self) -> PeerState {
+        PeerState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    /// Учитывает успешно установленное подключение
+    pub fn connected(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+        self.set_state(PeerState::Connected);
+    }
+
+    /// Учитывает полученный кадр V-протокола
+    pub fn frame(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает сеанс, помещенный в карантин
+    pub fn quarantined(&self) {
+        self.quarantined.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учитывает разрыв соединения из-за заполненного канала передачи фрагментов в коллектор
+    pub fn overloaded(&self) {
+        self.overloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "endpoint": self.endpoint,
+            "state": self.state().name(),
+            "connects": self.connects.load(Ordering::Relaxed),
+            "frames": self.frames.load(Ordering::Relaxed),
+            "quarantined": self.quarantined.load(Ordering::Relaxed),
+            "overloads": self.overloads.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Счетчики всех подсистем конвейера
+pub struct Stats {
+    pub input: Counters,
+    pub collector: Counters,
+    pub processor: Counters,
+    pub inference: Counters,
+    pub output: Counters,
+    pub low_confidence: ConfidenceCounters,
+    pub shadow: ShadowCounters,
+    pub feature_cache: CacheCounters,
+    pub non_audio: NonAudioCounters,
+    pub inference_metrics: InferenceMetrics,
+    output_fallback: AtomicBool,
+    output_low_space: AtomicBool,
+    standby: AtomicBool,
+    /// Размеры звуковых фрагментов, полученных от систем сопряжения, байтов
+    pub fragment_bytes: Histogram,
+    /// Длительности собранных коллектором сеансов, мс
+    pub session_ms: Histogram,
+    /// Количество фрагментов в собранных коллектором сеансах
+    pub session_fragments: Histogram,
+    /// Время сборки сеансов коллектором от первого фрагмента до передачи на обработку, мс
+    pub session_assembly_ms: Histogram,
+    peers: Vec<PeerStats>
+}
+
+pub type SharedStats = Arc<Stats>;
+
+impl Stats {
+
+    pub fn new(peers: &[Endpoint]) -> SharedStats {
+        Arc::new(Stats {
+            input: Counters::default(),
+            collector: Counters::default(),
+            processor: Counters::default(),
+            inference: Counters::default(),
+            output: Counters::default(),
+            low_confidence: ConfidenceCounters::default(),
+            shadow: ShadowCounters::default(),
+            feature_cache: CacheCounters::default(),
+            non_audio: NonAudioCounters::default(),
+            inference_metrics: InferenceMetrics::new(),
+            output_fallback: AtomicBool::new(false),
+            output_low_space: AtomicBool::new(false),
+            standby: AtomicBool::new(false),
+            fragment_bytes: Histogram::new(FRAGMENT_BYTES),
+            session_ms: Histogram::new(SESSION_MS),
+            session_fragments: Histogram::new(SESSION_FRAGMENTS),
+            session_assembly_ms: Histogram::new(ASSEMBLY_MS),
+            peers: peers.iter().map(PeerStats::new).collect()
+        })
+    }
+
+    /// Счетчики подключения к системе сопряжения с порядковым номером `index`
+    pub fn peer(&self, index: usize) -> &PeerStats {
+        &self.peers[index]
+    }
+
+    /// Отмечает, сохраняются ли результаты в резервный каталог
+    pub fn set_output_fallback(&self, active: bool) {
+        self.output_fallback.store(active, Ordering::Relaxed);
+    }
+
+    /// Отмечает, задержаны ли результаты из-за нехватки свободного места в каталогах хранения
+    pub fn set_output_low_space(&self, active: bool) {
+        self.output_low_space.store(active, Ordering::Relaxed);
+    }
+
+    /// Количество сэмплов, переданных процессором и еще не полученных inference
+    pub fn samples_backlog(&self) -> u64 {
+        depth(&self.processor, &self.inference)
+    }
+
+    /// Отмечает, является ли экземпляр резервным, ожидающим выбора ведущим
+    pub fn set_standby(&self, active: bool) {
+        self.standby.store(active, Ordering::Relaxed);
+    }
+
+    /// Снимок всех счетчиков в виде JSON
+    pub fn snapshot(&self) -> Value {
+        json!({
+            "stages": {
+                "input": self.input.snapshot(),
+                "collector": self.collector.snapshot(),
+                "processor": self.processor.snapshot(),
+                "inference": self.inference.snapshot(),
+                "output": self.output.snapshot()
+            },
+            "channels": {
+                "fragments": depth(&self.input, &self.collector),
+                "sessions": depth(&self.collector, &self.processor),
+                "samples": depth(&self.processor, &self.inference),
+                "results": depth(&self.inference, &self.output)
+            },
+            "low_confidence": self.low_confidence.snapshot(),
+            "shadow": self.shadow.snapshot(),
+            "feature_cache": self.feature_cache.snapshot(),
+            "non_audio": self.non_audio.snapshot(),
+            "inference": self.inference_metrics.snapshot(self.samples_backlog()),
+            "in_flight": inflight::snapshot(),
+            "memory": memory::snapshot(),
+            "output_fallback": self.output_fallback.load(Ordering::Relaxed),
+            "output_low_space": self.output_low_space.load(Ordering::Relaxed),
+            "standby": self.standby.load(Ordering::Relaxed),
+            "histograms": {
+                "fragment_bytes": self.fragment_bytes.snapshot(),
+                "session_ms": self.session_ms.snapshot(),
+                "session_fragments": self.session_fragments.snapshot(),
+                "session_assembly_ms": self.session_assembly_ms.snapshot()
+            },
+            "peers": self.peers.iter().map(|p| p.snapshot()).collect::<Vec<_>>()
+        })
+    }
+}
+
+// the amount of objects sent by the upstream but not yet received by the downstream
+fn depth(upstream: &Counters, downstream: &Counters) -> u64 {
+    let sent = upstream.sent.load(Ordering::Relaxed);
+    let received = downstream.received.load(Ordering::Relaxed);
+    sent.saturating_sub(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_channel_depth_is_sent_less_received() {
+        let stats = Stats::new(&[]);
+        stats.input.add_received(3);
+        stats.input.add_sent(3);
+        stats.collector.received();
+        stats.collector.dropped();
+        let s = stats.snapshot();
+        assert_eq!(s["stages"]["input"]["received"], 3);
+        assert_eq!(s["stages"]["collector"]["dropped"], 1);
+        assert_eq!(s["channels"]["fragments"], 2);
+        assert_eq!(s["channels"]["sessions"], 0);
+        assert_eq!(stats.input.totals(), (3, 3, 0));
+    }
+
+    #[test]
+    fn the_peer_state_and_counters() {
+        let stats = Stats::new(&["127.0.0.1:12000".parse().unwrap()]);
+        let p = stats.peer(0);
+        assert_eq!(p.state(), PeerState::Connecting);
+        p.connected();
+        p.frame();
+        p.frame();
+        let s = &stats.snapshot()["peers"][0];
+        assert_eq!((&s["endpoint"], &s["state"]), (&json!("127.0.0.1:12000"), &json!("connected")));
+        assert_eq!((&s["connects"], &s["frames"]), (&json!(1), &json!(2)));
+        for state in [PeerState::Handshaking, PeerState::Waiting, PeerState::Stopped, PeerState::Down, PeerState::Drained] {
+            p.set_state(state);
+            assert_eq!(p.state(), state);
+        }
+    }
+
+    #[test]
+    fn the_histogram_buckets_are_cumulative() {
+        let h = Histogram::new(&[10, 100]);
+        for v in [5, 10, 50, 1000] {
+            h.record(v);
+        }
+        let s = h.snapshot();
+        assert_eq!(s["buckets"], json!([
+            { "le": 10, "count": 2 },
+            { "le": 100, "count": 3 },
+            { "le": "+Inf", "count": 4 }
+        ]));
+        assert_eq!((&s["count"], &s["sum"]), (&json!(4), &json!(1065)));
+        let stats = Stats::new(&[]);
+        stats.fragment_bytes.record(100);
+        assert_eq!(stats.snapshot()["histograms"]["fragment_bytes"]["count"], 1);
+    }
+
+    #[test]
+    fn the_inference_batches_and_the_device_load() {
+        let m = InferenceMetrics::new();
+        m.batch(3);
+        m.batch(8);
+        let s = m.snapshot(2);
+        assert_eq!((&s["queue_depth"], &s["batches"], &s["last_batch_size"]), (&json!(2), &json!(2), &json!(8)));
+        assert_eq!(s["batch_size"]["count"], 2);
+        assert_eq!(s["gpu"], Value::Null);
+        m.set_device(DeviceLoad { utilization: 40, memory_used: 1, memory_total: 4 });
+        assert_eq!(m.snapshot(0)["gpu"], json!({ "utilization": 40, "memory_used": 1, "memory_total": 4 }));
+    }
+
+    #[test]
+    fn the_batch_rate_is_over_the_complete_seconds() {
+        let m = InferenceMetrics::new();
+        let sec = unix_secs() - 1;
+        let i = sec as usize % m.rate_secs.len();
+        m.rate_secs[i].store(sec, Ordering::Relaxed);
+        m.rate_counts[i].store(5, Ordering::Relaxed);
+        // a slot older than the window doesn't count
+        let old = sec - RATE_WINDOW as u64 - 2;
+        let j = old as usize % m.rate_secs.len();
+        m.rate_secs[j].store(old, Ordering::Relaxed);
+        m.rate_counts[j].store(100, Ordering::Relaxed);
+        assert_eq!(m.batches_per_sec(), 0.5);
+    }
+    #[test]
+    fn the_summary_counts_by_bucket() {
+        let h = Histogram::new(&[10, 100]);
+        assert_eq!(h.summary(), "none");
+        for v in [5, 10, 1000] {
+            h.record(v);
+        }
+        assert_eq!(h.summary(), "3, mean 338.3 (<=10: 2, >100: 1)");
+    }
+}
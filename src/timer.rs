@@ -0,0 +1,94 @@
+//! Случайные задержки и периодические таймеры со случайным разбросом моментов срабатывания.
+//!
+//! Разброс `[general] timer_jitter` не дает периодической работе подсистем, например сбросу файлов на диск
+//! и проверке таймаутов сеансов, выполняться в одни и те же моменты и создавать пики нагрузки
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use tokio::time::delay_until;
+
+/// Случайная задержка от 0 до `max`. Хешер `RandomState` получает случайные ключи при каждом создании,
+/// его хеш пустого ввода годится как случайное число
+pub fn jitter(max: Duration) -> Duration {
+    let ms = max.as_millis() as u64;
+    if ms == 0 {
+        return Duration::from_secs(0);
+    }
+    let r = RandomState::new().build_hasher().finish();
+    Duration::from_millis(r % (ms + 1))
+}
+
+/// Периодический таймер, каждый интервал которого случайно отличается от `period` не больше чем на `percent` процентов.
+/// Интервалы отсчитываются от назначенных моментов срабатывания, поэтому в среднем период сохраняется.
+/// В отличие от `tokio::time::interval` первый раз срабатывает не сразу, а через интервал
+pub struct Jittered {
+    period: Duration,
+    percent: u32,
+    next: Instant
+}
+
+impl Jittered {
+
+    pub fn new(period: Duration, percent: u32) -> Jittered {
+        Jittered {
+            period,
+            percent,
+            next: Instant::now() + spread(period, percent)
+        }
+    }
+
+    /// Ожидает очередного срабатывания. Прерванное ожидание не сдвигает назначенный момент
+    pub async fn tick(&mut self) -> Instant {
+        delay_until(self.next.into()).await;
+        let now = Instant::now();
+        // a tick late by more than a period doesn't make up for the missed ones with a burst
+        let from = if now > self.next + self.period { now } else { self.next };
+        self.next = from + spread(self.period, self.percent);
+        now
+    }
+}
+
+// the period changed at random by up to percent of it either way
+fn spread(period: Duration, percent: u32) -> Duration {
+    let d = period * percent.min(100) / 100;
+    period - d + jitter(d * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_jitter_keeps_within_the_max() {
+        assert_eq!(jitter(Duration::from_secs(0)), Duration::from_secs(0));
+        assert_eq!(jitter(Duration::from_micros(900)), Duration::from_secs(0));
+        for _ in 0..100 {
+            assert!(jitter(Duration::from_millis(10)) <= Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn the_spread_keeps_within_the_percent() {
+        let period = Duration::from_millis(1000);
+        assert_eq!(spread(period, 0), period);
+        for _ in 0..100 {
+            let d = spread(period, 10);
+            assert!(d >= Duration::from_millis(900) && d <= Duration::from_millis(1100), "{:?}", d);
+        }
+    }
+
+    #[tokio::test]
+    async fn ticks_after_the_period_without_catching_up() {
+        let started = Instant::now();
+        let mut t = Jittered::new(Duration::from_millis(20), 0);
+        let first = t.tick().await;
+        assert!(first >= started + Duration::from_millis(20));
+        // a late tick schedules the next one a period away from it, not a burst of missed ones
+        std::thread::sleep(Duration::from_millis(50));
+        let late = t.tick().await;
+        let next = t.tick().await;
+        assert!(next >= late + Duration::from_millis(20));
+    }
+}
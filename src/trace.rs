@@ -0,0 +1,416 @@
+//! Трассировка прохождения сеансов через подсистемы конвейера для внешней системы трассировки OpenTelemetry.
+//!
+//! Каждому сеансу при сборке в collector назначается трасса. Подсистемы processor, inference и output
+//! регистрируют свою обработку сеанса (каждой его части) дочерними интервалами трассы, корневой интервал сеанса
+//! регистрируется после сохранения последнего результата. Накопленные интервалы периодически отправляются
+//! по OTLP/HTTP в формате JSON на адрес `[trace] endpoint`, пустой адрес отключает трассировку.
+//! Отправка доступна в сборке с feature `otel`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::SharedConfig;
+
+/// Наибольшее количество ожидающих отправки интервалов, более новые интервалы отбрасываются
+const MAX_PENDING: usize = 10_000;
+
+/// Контекст трассы сеанса, передается вместе с его данными по конвейеру.
+/// Нулевой идентификатор трассы означает, что сеанс не трассируется
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Trace {
+    trace_id: [u8; 16],
+    root: [u8; 8],
+    start_ns: u64
+}
+
+impl Trace {
+
+    fn is_traced(&self) -> bool {
+        self.trace_id != [0u8; 16]
+    }
+}
+
+/// Завершенный интервал трассы
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+#[derive(Clone, Debug)]
+struct Span {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent: Option<[u8; 8]>,
+    name: &'static str,
+    start_ns: u64,
+    end_ns: u64,
+    id: u32,
+    chunk: Option<u32>
+}
+
+/// Регистратор интервалов трасс, разделяемый подсистемами
+pub struct Tracer {
+    enabled: bool,
+    seq: AtomicU64,
+    spans: Mutex<Vec<Span>>
+}
+
+pub type SharedTracer = Arc<Tracer>;
+
+impl Tracer {
+
+    pub fn new(cfg: &SharedConfig) -> SharedTracer {
+        Arc::new(Tracer {
+            enabled: cfg!(feature = "otel") && !cfg.trace_endpoint().is_empty(),
+            seq: AtomicU64::new(0),
+            spans: Mutex::new(Vec::new())
+        })
+    }
+
+    /// Начинает трассу сеанса, начатого в момент `start`
+    pub fn begin(&self, start: SystemTime) -> Trace {
+        if !self.enabled {
+            return Trace::default();
+        }
+        let mut trace_id = [0u8; 16];
+        trace_id.copy_from_slice(&self.random()[..16]);
+        let mut root = [0u8; 8];
+        root.copy_from_slice(&self.random()[..8]);
+        Trace {
+            trace_id,
+            root,
+            start_ns: unix_ns(start)
+        }
+    }
+
+    /// Регистрирует обработку части `chunk` сеанса `id` подсистемой `stage` с момента `start` до текущего момента
+    pub fn stage(&self, trace: &Trace, stage: &'static str, start: SystemTime, id: u32, chunk: u32) {
+        if !trace.is_traced() {
+            return;
+        }
+        let mut span_id = [0u8; 8];
+        span_id.copy_from_slice(&self.random()[..8]);
+        self.push(Span {
+            trace_id: trace.trace_id,
+            span_id,
+            parent: Some(trace.root),
+            name: stage,
+            start_ns: unix_ns(start),
+            end_ns: unix_ns(SystemTime::now()),
+            id,
+            chunk: Some(chunk)
+        });
+    }
+
+    /// Регистрирует корневой интервал сеанса `id` от его начала до текущего момента
+    pub fn session(&self, trace: &Trace, id: u32) {
+        if !trace.is_traced() {
+            return;
+        }
+        self.push(Span {
+            trace_id: trace.trace_id,
+            span_id: trace.root,
+            parent: None,
+            name: "session",
+            start_ns: trace.start_ns,
+            end_ns: unix_ns(SystemTime::now()),
+            id,
+            chunk: None
+        });
+    }
+
+    fn push(&self, span: Span) {
+        let mut spans = self.spans.lock().unwrap();
+        if spans.len() < MAX_PENDING {
+            spans.push(span);
+        }
+    }
+
+    // unique enough ids without a random generator: a digest of the time, the process and a counter
+    fn random(&self) -> [u8; 32] {
+        let mut h = Sha256::new();
+        h.update(unix_ns(SystemTime::now()).to_le_bytes());
+        h.update(std::process::id().to_le_bytes());
+        h.update(self.seq.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+        let mut r = [0u8; 32];
+        r.copy_from_slice(&h.finalize());
+        r
+    }
+}
+
+fn unix_ns(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+#[cfg(feature = "otel")]
+pub use self::export::run;
+
+#[cfg(feature = "otel")]
+mod export {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::time::Duration;
+
+    use hyper::client::HttpConnector;
+    use hyper::{Body, Client, Method, Request};
+    use log::{debug, info, warn};
+
+    use serde_json::{json, Value};
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+    use tokio::time::interval;
+
+    use crate::config::SharedConfig;
+    use crate::tracker::TaskGuard;
+
+    use super::{SharedTracer, Span, Tracer};
+
+    /// Период отправки накопленных интервалов
+    const EXPORT_PERIOD: Duration = Duration::from_secs(1);
+
+    /// Итог отправки интервалов, ошибка - описание неудачи
+    type Exported<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+    /// Получатель накопленных интервалов трасс
+    trait Exporter: Send {
+
+        /// Отправляет интервалы
+        fn export<'a>(&'a mut self, spans: &'a [Span]) -> Exported<'a>;
+    }
+
+    /// Отправка интервалов по OTLP/HTTP в формате JSON
+    struct OtlpHttp {
+        url: String,
+        client: Client<HttpConnector>
+    }
+
+    impl Exporter for OtlpHttp {
+
+        fn export<'a>(&'a mut self, spans: &'a [Span]) -> Exported<'a> {
+            Box::pin(async move {
+                let req = Request::builder()
+                    .method(Method::POST)
+                    .uri(self.url.as_str())
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(export_request(spans).to_string()))
+                    .map_err(|e| format!("invalid export request to {}: {}", self.url, e))?;
+                match self.client.request(req).await {
+                    Ok(rsp) if rsp.status().is_success() => Ok(()),
+                    Ok(rsp) => Err(format!("rejected: {}", rsp.status())),
+                    Err(e) => Err(format!("failed: {}", e))
+                }
+            })
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // the OTLP/JSON ExportTraceServiceRequest with the spans
+    fn export_request(spans: &[Span]) -> Value {
+        let spans: Vec<Value> = spans.iter().map(|s| {
+            let mut attrs = vec![json!({ "key": "session.id", "value": { "intValue": s.id.to_string() } })];
+            if let Some(c) = s.chunk {
+                attrs.push(json!({ "key": "session.chunk", "value": { "intValue": c.to_string() } }));
+            }
+            let mut span = json!({
+                "traceId": hex(&s.trace_id),
+                "spanId": hex(&s.span_id),
+                "name": s.name,
+                "kind": 1,
+                "startTimeUnixNano": s.start_ns.to_string(),
+                "endTimeUnixNano": s.end_ns.to_string(),
+                "attributes": attrs
+            });
+            if let Some(p) = s.parent {
+                span["parentSpanId"] = json!(hex(&p));
+            }
+            span
+        }).collect();
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{ "key": "service.name", "value": { "stringValue": "banshee" } }]
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "banshee", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans
+                }]
+            }]
+        })
+    }
+
+    /// Запускает в асинхронном режиме периодическую отправку накопленных интервалов трасс, если трассировка включена
+    ///
+    /// Параметры:
+    ///
+    /// * `cfg` - доступ к общей разделяемой конфигурации приложения. Потокобезопасность обеспечивается самим объектом
+    /// * `guard` - признак работы подсистемы для контроля ее завершения, удерживается до завершения работы
+    /// * `tracer` - регистратор интервалов трасс
+    /// * `rx_stop` - межпоточный канал получения команды на завершение, читатель
+    ///
+    /// Возвращает JoinHandle задачи подсистемы для контроля ее завершения в вызывающем коде,
+    /// None - трассировка отключена
+    pub async fn run(cfg: SharedConfig, guard: TaskGuard, tracer: SharedTracer, rx_stop: oneshot::Receiver<()>) -> Option<JoinHandle<()>> {
+        if !tracer.enabled {
+            info!("trace is disabled");
+            return None;
+        }
+        let url = format!("{}/v1/traces", cfg.trace_endpoint().trim_end_matches('/'));
+        info!("start trace export to {}", url);
+
+        let exporter = OtlpHttp { url, client: Client::new() };
+        Some(tokio::spawn(async move {
+            let _guard = guard;
+            export_loop(&tracer, exporter, EXPORT_PERIOD, rx_stop).await;
+            info!("trace export is stopped");
+        }))
+    }
+
+    // the spans registered by the tracer are passed to the exporter every `period` and once more on stop
+    async fn export_loop(tracer: &Tracer, mut exporter: impl Exporter, period: Duration, mut rx_stop: oneshot::Receiver<()>) {
+        let mut tick = interval(period);
+        loop {
+            let stop = tokio::select! {
+                _ = tick.tick() => false,
+                _ = &mut rx_stop => true
+            };
+            // the last export on stop sends what the stages registered while draining
+            let spans = std::mem::take(&mut *tracer.spans.lock().unwrap());
+            if !spans.is_empty() {
+                match exporter.export(&spans).await {
+                    Ok(()) => debug!("trace: {} spans are exported", spans.len()),
+                    Err(e) => warn!("trace: export of {} spans {}", spans.len(), e)
+                }
+            }
+            if stop {
+                info!("stop trace export");
+                break;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use std::sync::{Arc, Mutex};
+
+        use crate::trace::tests::tracer;
+
+        // the exporter keeping the spans in memory, the exports fail while `failing`
+        #[derive(Clone, Default)]
+        struct InMemory {
+            spans: Arc<Mutex<Vec<Span>>>,
+            failing: bool
+        }
+
+        impl Exporter for InMemory {
+            fn export<'a>(&'a mut self, spans: &'a [Span]) -> Exported<'a> {
+                Box::pin(async move {
+                    if self.failing {
+                        return Err("failed: unavailable".to_string());
+                    }
+                    self.spans.lock().unwrap().extend_from_slice(spans);
+                    Ok(())
+                })
+            }
+        }
+
+        #[tokio::test]
+        async fn exports_the_session_spans_on_stop() {
+            let t = tracer(true);
+            let trace = t.begin(std::time::SystemTime::now());
+            t.stage(&trace, "processor", std::time::SystemTime::now(), 7, 0);
+            t.stage(&trace, "output", std::time::SystemTime::now(), 7, 0);
+            t.session(&trace, 7);
+            let memory = InMemory::default();
+            let (tx, rx) = oneshot::channel();
+            tx.send(()).unwrap();
+            export_loop(&t, memory.clone(), Duration::from_secs(60), rx).await;
+            let spans = memory.spans.lock().unwrap();
+            let names: Vec<&str> = spans.iter().map(|s| s.name).collect();
+            assert_eq!(names, vec!["processor", "output", "session"]);
+            assert!(spans.iter().all(|s| s.trace_id == spans[2].trace_id && s.id == 7));
+            assert!(spans[..2].iter().all(|s| s.parent == Some(spans[2].span_id) && s.chunk == Some(0)));
+            assert_eq!((spans[2].parent, spans[2].chunk), (None, None));
+            assert!(t.spans.lock().unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn drops_the_spans_of_a_failed_export() {
+            let t = tracer(true);
+            let trace = t.begin(std::time::SystemTime::now());
+            t.session(&trace, 1);
+            let (tx, rx) = oneshot::channel();
+            tx.send(()).unwrap();
+            export_loop(&t, InMemory { failing: true, ..InMemory::default() }, Duration::from_secs(60), rx).await;
+            assert!(t.spans.lock().unwrap().is_empty());
+        }
+
+        #[test]
+        fn builds_the_otlp_request() {
+            let span = Span { trace_id: [1; 16], span_id: [2; 8], parent: Some([3; 8]), name: "output", start_ns: 10, end_ns: 20,
+                id: 7, chunk: Some(1) };
+            let r = export_request(&[span]);
+            let s = &r["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+            assert_eq!(s["traceId"], "01010101010101010101010101010101");
+            assert_eq!(s["spanId"], "0202020202020202");
+            assert_eq!(s["parentSpanId"], "0303030303030303");
+            assert_eq!((s["startTimeUnixNano"].as_str(), s["endTimeUnixNano"].as_str()), (Some("10"), Some("20")));
+            assert_eq!(s["attributes"][1]["value"]["intValue"], "1");
+        }
+    }
+}
+
+/// Заглушка для сборки без feature `otel`: трассировка недоступна
+#[cfg(not(feature = "otel"))]
+pub async fn run(cfg: SharedConfig, _guard: crate::tracker::TaskGuard, _tracer: SharedTracer,
+                 _rx_stop: tokio::sync::oneshot::Receiver<()>) -> Option<tokio::task::JoinHandle<()>> {
+    if !cfg.trace_endpoint().is_empty() {
+        log::warn!("trace: export to {} is not available, the build has no otel feature", cfg.trace_endpoint());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub fn tracer(enabled: bool) -> Tracer {
+        Tracer {
+            enabled,
+            seq: AtomicU64::new(0),
+            spans: Mutex::new(Vec::new())
+        }
+    }
+
+    #[test]
+    fn disabled_tracer_registers_nothing() {
+        let t = tracer(false);
+        let trace = t.begin(SystemTime::now());
+        assert!(!trace.is_traced());
+        t.stage(&trace, "output", SystemTime::now(), 1, 0);
+        t.session(&trace, 1);
+        assert!(t.spans.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn traces_get_distinct_ids() {
+        let t = tracer(true);
+        let (a, b) = (t.begin(SystemTime::now()), t.begin(SystemTime::now()));
+        assert!(a.is_traced() && b.is_traced());
+        assert_ne!(a.trace_id, b.trace_id);
+        assert_ne!(a.root, b.root);
+    }
+
+    #[test]
+    fn pending_spans_are_bounded() {
+        let t = tracer(true);
+        let trace = t.begin(SystemTime::now());
+        for chunk in 0..MAX_PENDING as u32 + 5 {
+            t.stage(&trace, "processor", SystemTime::now(), 1, chunk);
+        }
+        assert_eq!(t.spans.lock().unwrap().len(), MAX_PENDING);
+    }
+}
@@ -0,0 +1,117 @@
+//! Отслеживание работающих подсистем приложения для контроля их завершения.
+//!
+//! Каждая подсистема при запуске получает [`TaskGuard`] и удерживает его в своей асинхронной задаче
+//! до ее завершения. Уничтожение guard-объекта (в том числе при панике в задаче) отмечает подсистему как завершенную.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+struct Inner {
+    busy: Mutex<Vec<&'static str>>,
+    notify: Notify
+}
+
+/// Разделяемый между подсистемами реестр работающих задач
+#[derive(Clone)]
+pub struct TaskTracker {
+    inner: Arc<Inner>
+}
+
+/// Признак работы подсистемы, пока объект существует
+pub struct TaskGuard {
+    name: &'static str,
+    inner: Arc<Inner>
+}
+
+impl TaskTracker {
+
+    pub fn new() -> TaskTracker {
+        TaskTracker {
+            inner: Arc::new(Inner {
+                busy: Mutex::new(Vec::new()),
+                notify: Notify::new()
+            })
+        }
+    }
+
+    /// Регистрирует работающую подсистему `name`, она считается работающей до уничтожения возвращенного объекта
+    pub fn track(&self, name: &'static str) -> TaskGuard {
+        self.inner.busy.lock().unwrap().push(name);
+        TaskGuard {
+            name,
+            inner: self.inner.clone()
+        }
+    }
+
+    /// Список еще не завершившихся подсистем
+    pub fn busy(&self) -> Vec<&'static str> {
+        self.inner.busy.lock().unwrap().clone()
+    }
+
+    /// Ожидает завершения всех зарегистрированных подсистем
+    pub async fn wait(&self) {
+        while !self.busy().is_empty() {
+            self.inner.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for TaskGuard {
+
+    fn drop(&mut self) {
+        // the lock may be poisoned only by a panic inside the tracker itself, the list is still consistent
+        let mut busy = match self.inner.busy.lock() {
+            Ok(b) => b,
+            Err(e) => e.into_inner()
+        };
+        if let Some(pos) = busy.iter().position(|n| *n == self.name) {
+            busy.remove(pos);
+        }
+        drop(busy);
+        self.inner.notify.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn lists_the_busy_subsystems() {
+        let tracker = TaskTracker::new();
+        let input = tracker.track("input");
+        let output = tracker.track("output");
+        assert_eq!(tracker.busy(), vec!["input", "output"]);
+        drop(input);
+        assert_eq!(tracker.busy(), vec!["output"]);
+        drop(output);
+        assert!(tracker.busy().is_empty());
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_last_guard() {
+        let tracker = TaskTracker::new();
+        let guard = tracker.track("output");
+        tokio::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            drop(guard);
+        });
+        tokio::time::timeout(Duration::from_secs(5), tracker.wait()).await.unwrap();
+        assert!(tracker.busy().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_panicked_task_is_done() {
+        let tracker = TaskTracker::new();
+        let guard = tracker.track("processor");
+        let task = tokio::spawn(async move {
+            let _guard = guard;
+            panic!("stage failed");
+        });
+        assert!(task.await.is_err());
+        tokio::time::timeout(Duration::from_secs(5), tracker.wait()).await.unwrap();
+    }
+}